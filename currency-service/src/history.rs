@@ -0,0 +1,158 @@
+use crate::Rates;
+use anyhow::Context as AnyhowContext;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use time::{Date, Month};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Default)]
+pub struct RateHistory {
+    path: PathBuf,
+    days: BTreeMap<Date, Rates>,
+}
+
+fn parse_date(raw: &str) -> Option<Date> {
+    let mut parts = raw.trim().splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+impl RateHistory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            days: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let mut history = Self::new(path);
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(history),
+            Err(err) => return Err(err).context("Unable to open currency rates history"),
+        };
+        // Рядки лише дописуються, тож пізніший курс того ж дня перемагає
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let mut split = line.split(',');
+            let (Some(date), Some(code), Some(rate)) = (split.next(), split.next(), split.next())
+            else {
+                continue;
+            };
+            let date = parse_date(date).context(format!("Unable to parse date {date}"))?;
+            let rate = Decimal::from_str_exact(rate.trim())
+                .context(format!("Unable to parse rate {rate} for currency {code}"))?;
+            history
+                .days
+                .entry(date)
+                .or_default()
+                .insert(code.trim().to_string(), rate);
+        }
+        Ok(history)
+    }
+
+    pub fn get(&self, date: Date) -> Option<&Rates> {
+        self.days.get(&date)
+    }
+
+    pub fn on_or_before(&self, date: Date) -> Option<(Date, &Rates)> {
+        self.days.range(..=date).next_back().map(|(d, r)| (*d, r))
+    }
+
+    pub async fn record(&mut self, date: Date, rates: Rates) -> Result<(), anyhow::Error> {
+        if self.days.get(&date) == Some(&rates) {
+            return Ok(());
+        }
+        let mut codes = rates.iter().collect::<Vec<_>>();
+        codes.sort_by(|a, b| a.0.cmp(b.0));
+        let lines = codes
+            .into_iter()
+            .map(|(code, rate)| format!("{date},{code},{rate}\n"))
+            .collect::<String>();
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context("Unable to open currency rates history")?;
+        file.write_all(lines.as_bytes())
+            .await
+            .context("Unable to write currency rates history")?;
+        file.flush()
+            .await
+            .context("Unable to write currency rates history")?;
+        self.days.insert(date, rates);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::StaticFileProvider;
+    use time::macros::date;
+    use tokio::sync::RwLock;
+
+    fn rates(usd: i64) -> Rates {
+        Rates::from([("USD".to_string(), Decimal::new(usd, 2))])
+    }
+
+    #[tokio::test]
+    async fn history_round_trips_and_resolves_past_days() {
+        let path = std::env::temp_dir().join(format!("rates_history_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut history = RateHistory::new(&path);
+        history
+            .record(date!(2026 - 10 - 09), rates(4100))
+            .await
+            .unwrap();
+        history
+            .record(date!(2026 - 10 - 12), rates(4110))
+            .await
+            .unwrap();
+        history
+            .record(date!(2026 - 10 - 12), rates(4120))
+            .await
+            .unwrap();
+        history
+            .record(date!(2026 - 10 - 12), rates(4120))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        let history = RwLock::new(RateHistory::load(&path).unwrap());
+        let provider = StaticFileProvider::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/static_rates.csv"
+        ));
+        let today = date!(2026 - 10 - 16);
+
+        // Курси з файлу не опубліковані: береться останній відомий день, в історію нічого не йде
+        let current = crate::rates_on(&provider, &history, today, today).await;
+        assert_eq!(current, Some(rates(4120)));
+        assert!(history.read().await.get(today).is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+        std::fs::remove_file(&path).unwrap();
+
+        // Субота бере курс п'ятниці, день до початку історії — нічого
+        let weekend = crate::rates_on(&provider, &history, date!(2026 - 10 - 11), today).await;
+        assert_eq!(weekend, Some(rates(4100)));
+        let exact = crate::rates_on(&provider, &history, date!(2026 - 10 - 12), today).await;
+        assert_eq!(exact, Some(rates(4120)));
+        let before = crate::rates_on(&provider, &history, date!(2026 - 10 - 01), today).await;
+        assert_eq!(before, None);
+
+        let rates_path = path.with_file_name(format!("rates_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&rates_path);
+        let current = RwLock::new(Rates::new());
+        crate::refresh(&provider, &current, &rates_path, &history)
+            .await
+            .unwrap();
+        assert!(!current.read().await.is_empty());
+        assert!(!rates_path.exists());
+        assert!(history.read().await.get(crate::today()).is_none());
+    }
+}
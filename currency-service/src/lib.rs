@@ -1,39 +1,49 @@
 use actix::prelude::*;
 use anyhow::Context as AnyhowContext;
-use once_cell::sync::Lazy;
 use rust_decimal::Decimal;
-use scraper::{Html, Selector};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use time::macros::{offset, time};
-use time::{OffsetDateTime, Time};
+use time::macros::offset;
+use time::{Date, OffsetDateTime};
 use tokio::sync::RwLock;
 
-static DEFAULT_RATES_FILE: &str = "currency_rates.csv";
-static RATES_URL: &str = "https://index.minfin.com.ua/exchange/nbu/curr/";
+pub mod history;
+pub mod provider;
+
+pub use history::RateHistory;
+pub use provider::{
+    FallbackChain, NbuJsonProvider, RateProvider, ScrapedPageProvider, StaticFileProvider,
+};
 
-#[allow(clippy::unwrap_used)]
-static TABLE_SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("table").unwrap());
-#[allow(clippy::unwrap_used)]
-static CURRENCY_SELECTOR: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("tr td:nth-child(2)").unwrap());
-#[allow(clippy::unwrap_used)]
-static MULTIPLIER_SELECTOR: Lazy<Selector> =
-    Lazy::new(|| Selector::parse("tr td:nth-child(3)").unwrap());
-#[allow(clippy::unwrap_used)]
-static RATE_SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("tr td:nth-child(5)").unwrap());
+pub type Rates = HashMap<String, Decimal>;
 
-static UPDATE_RATE: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 4);
+static DEFAULT_RATES_FILE: &str = "currency_rates.csv";
+static DEFAULT_HISTORY_FILE: &str = "currency_rates_history.csv";
 
 pub struct CurrencyService {
     rates: Arc<RwLock<HashMap<String, Decimal>>>,
     rates_path: PathBuf,
+    history: Arc<RwLock<RateHistory>>,
+    provider: Arc<dyn RateProvider>,
 }
 
 impl CurrencyService {
     pub fn new() -> Self {
         let rates_path = rates_path();
+        let history_path = history_path(&rates_path);
+        Self::with_provider(
+            Arc::new(FallbackChain::from_env()),
+            rates_path,
+            history_path,
+        )
+    }
+
+    pub fn with_provider(
+        provider: Arc<dyn RateProvider>,
+        rates_path: PathBuf,
+        history_path: PathBuf,
+    ) -> Self {
         let rates = match read_rates(&rates_path) {
             Ok(rates) => rates,
             Err(err) => {
@@ -44,10 +54,26 @@ impl CurrencyService {
         .map(RwLock::new)
         .map(Arc::new)
         .unwrap_or_default();
-        Self { rates, rates_path }
+        let history = match RateHistory::load(&history_path) {
+            Ok(history) => history,
+            Err(err) => {
+                log::warn!("Unable to read currency rates history: {err}");
+                RateHistory::new(history_path)
+            }
+        };
+        Self {
+            rates,
+            rates_path,
+            history: Arc::new(RwLock::new(history)),
+            provider,
+        }
     }
 }
 
+fn today() -> Date {
+    OffsetDateTime::now_utc().to_offset(offset!(+3)).date()
+}
+
 fn rates_path() -> PathBuf {
     if let Ok(path) = std::env::var("CURRENCY_RATES_PATH") {
         let trimmed = path.trim();
@@ -63,22 +89,16 @@ fn rates_path() -> PathBuf {
     PathBuf::from(DEFAULT_RATES_FILE)
 }
 
+fn history_path(rates_path: &Path) -> PathBuf {
+    match std::env::var("CURRENCY_HISTORY_PATH") {
+        Ok(path) if !path.trim().is_empty() => PathBuf::from(path.trim()),
+        _ => rates_path.with_file_name(DEFAULT_HISTORY_FILE),
+    }
+}
+
 fn read_rates(file: &Path) -> Result<Option<HashMap<String, Decimal>>, anyhow::Error> {
     match std::fs::read_to_string(file) {
-        Ok(file) => file
-            .lines()
-            .filter_map(|l| {
-                let mut split = l.split(',');
-                Some((split.next()?, split.next()?))
-            })
-            .map(|(c, r)| {
-                let r = Decimal::from_str_exact(r)
-                    .context(format!("Unable to parse rate {r} for currency {c}"))?;
-                Ok((c.to_string(), r))
-            })
-            .collect::<Result<_, _>>()
-            .map(Some),
-
+        Ok(file) => provider::parse_rates_csv(&file).map(Some),
         Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
             Err(err).context("Unable to open currency rates file")
         }
@@ -95,41 +115,54 @@ async fn write_rates(file: &Path, rates: &HashMap<String, Decimal>) -> Result<()
     Ok(())
 }
 
-async fn download_rates(url: &str) -> Result<HashMap<String, Decimal>, anyhow::Error> {
-    let resp = reqwest::get(url)
+// У файл курсів та історію пишемо лише опубліковані курси, резервні тримаємо в пам'яті
+async fn refresh(
+    provider: &dyn RateProvider,
+    rates: &RwLock<Rates>,
+    rates_path: &Path,
+    history: &RwLock<RateHistory>,
+) -> Result<(), anyhow::Error> {
+    let today = today();
+    let (fetched, fallback) = provider.fetch_tagged(today, today).await?;
+    *rates.write().await = fetched.clone();
+    if fallback {
+        log::warn!("Using fallback currency rates, rates file and history are left untouched");
+        return Ok(());
+    }
+    if let Err(err) = write_rates(rates_path, &fetched).await {
+        log::error!("Unable to write rates: {err}");
+    }
+    history.write().await.record(today, fetched).await
+}
+
+pub async fn rates_on(
+    provider: &dyn RateProvider,
+    history: &RwLock<RateHistory>,
+    date: Date,
+    today: Date,
+) -> Option<Rates> {
+    if let Some(rates) = history.read().await.get(date) {
+        return Some(rates.clone());
+    }
+    let mut fallback = None;
+    if date <= today && (date == today || provider.supports_history()) {
+        match provider.fetch_tagged(date, today).await {
+            Ok((rates, true)) => fallback = Some(rates),
+            Ok((rates, false)) => {
+                if let Err(err) = history.write().await.record(date, rates.clone()).await {
+                    log::error!("Unable to save currency rates history: {err}");
+                }
+                return Some(rates);
+            }
+            Err(err) => log::warn!("Unable to fetch rates for {date}: {err}"),
+        }
+    }
+    history
+        .read()
         .await
-        .context("Unable to download currency rates")?
-        .text()
-        .await?;
-    let document = Html::parse_document(&resp);
-    let entries = document
-        .select(&TABLE_SELECTOR)
-        .filter(|e| {
-            e.inner_html()
-                .contains("<caption>Официальный валютный курс НБУ")
-        })
-        .map(|e| {
-            e.select(&CURRENCY_SELECTOR)
-                .zip(e.select(&MULTIPLIER_SELECTOR))
-                .zip(e.select(&RATE_SELECTOR))
-                .map(|((c, m), r)| {
-                    let m: Decimal = m
-                        .inner_html()
-                        .replace(",", ".")
-                        .parse()
-                        .context(format!("Unable to parse multiplier {}", m.inner_html()))?;
-                    let r: Decimal = r
-                        .inner_html()
-                        .replace(",", ".")
-                        .parse()
-                        .context(format!("Unable to parse rate {}", r.inner_html()))?;
-                    Ok((c.inner_html().to_uppercase().to_string(), r / m))
-                })
-                .collect()
-        })
-        .next()
-        .ok_or(anyhow::anyhow!("No tables found on page"))?;
-    entries
+        .on_or_before(date)
+        .map(|(_, rates)| rates.clone())
+        .or(fallback)
 }
 
 impl Actor for CurrencyService {
@@ -138,38 +171,12 @@ impl Actor for CurrencyService {
     fn started(&mut self, _ctx: &mut Context<Self>) {
         let rates = self.rates.clone();
         let rates_path = self.rates_path.clone();
+        let history = self.history.clone();
+        let provider = self.provider.clone();
         tokio::spawn(async move {
-            loop {
-                let time_now = OffsetDateTime::now_utc().to_offset(offset!(+3)).time();
-                let delta = (0..=(time::Duration::DAY / UPDATE_RATE) as u32)
-                    .map(|i| Time::MIDNIGHT + (UPDATE_RATE * i))
-                    .filter(|t| t > &time_now)
-                    .map(|t| t - time_now)
-                    .min()
-                    .unwrap_or_else(|| {
-                        time!(23:59) - (time_now - time::Duration::seconds(60 * 10))
-                    });
-                log::info!(
-                    "Next rate update in {}h{}m",
-                    delta.whole_hours(),
-                    delta.whole_minutes() % 60
-                );
-                let sleep = tokio::time::sleep(
-                    delta
-                        .try_into()
-                        .expect("Unable to convert time::Duration to std::time::Duration"),
-                );
-                sleep.await;
-                let mut rates = rates.write().await;
-                *rates = match download_rates(RATES_URL).await {
-                    Ok(r) => r,
-                    Err(err) => {
-                        log::error!("Unable to download rates: {err}");
-                        continue;
-                    }
-                };
-                if let Err(err) = write_rates(&rates_path, &rates).await {
-                    log::error!("Unable to write rates: {err}");
+            if history.read().await.get(today()).is_none() {
+                if let Err(err) = refresh(&*provider, &rates, &rates_path, &history).await {
+                    log::error!("Unable to update currency rates: {err}");
                 }
             }
         });
@@ -180,10 +187,22 @@ impl Actor for CurrencyService {
 #[rtype(result = "Option<Decimal>")]
 pub struct GetRate(pub String);
 
+#[derive(Message)]
+#[rtype(result = "Result<(), anyhow::Error>")]
+pub struct Refresh;
+
 #[derive(Message)]
 #[rtype(result = "HashMap<String, Decimal>")]
 pub struct ListRates;
 
+#[derive(Message)]
+#[rtype(result = "Option<Decimal>")]
+pub struct GetRateOn(pub String, pub Date);
+
+#[derive(Message)]
+#[rtype(result = "Option<HashMap<String, Decimal>>")]
+pub struct ListRatesOn(pub Date);
+
 impl Handler<GetRate> for CurrencyService {
     type Result = ResponseActFuture<Self, Option<Decimal>>;
 
@@ -201,3 +220,52 @@ impl Handler<ListRates> for CurrencyService {
         Box::pin(async move { rates.read().await.clone() }.into_actor(self))
     }
 }
+
+impl Handler<GetRateOn> for CurrencyService {
+    type Result = ResponseActFuture<Self, Option<Decimal>>;
+
+    fn handle(
+        &mut self,
+        GetRateOn(currency, date): GetRateOn,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let history = self.history.clone();
+        let provider = self.provider.clone();
+        Box::pin(
+            async move {
+                rates_on(&*provider, &history, date, today())
+                    .await?
+                    .get(&currency.to_uppercase())
+                    .cloned()
+            }
+            .into_actor(self),
+        )
+    }
+}
+
+impl Handler<ListRatesOn> for CurrencyService {
+    type Result = ResponseActFuture<Self, Option<HashMap<String, Decimal>>>;
+
+    fn handle(&mut self, ListRatesOn(date): ListRatesOn, _: &mut Self::Context) -> Self::Result {
+        let history = self.history.clone();
+        let provider = self.provider.clone();
+        Box::pin(
+            async move { rates_on(&*provider, &history, date, today()).await }.into_actor(self),
+        )
+    }
+}
+
+impl Handler<Refresh> for CurrencyService {
+    type Result = ResponseActFuture<Self, Result<(), anyhow::Error>>;
+
+    fn handle(&mut self, _: Refresh, _: &mut Self::Context) -> Self::Result {
+        let rates = self.rates.clone();
+        let rates_path = self.rates_path.clone();
+        let history = self.history.clone();
+        let provider = self.provider.clone();
+        Box::pin(
+            async move { refresh(&*provider, &rates, &rates_path, &history).await }
+                .into_actor(self),
+        )
+    }
+}
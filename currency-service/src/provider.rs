@@ -0,0 +1,354 @@
+use crate::Rates;
+use anyhow::Context as AnyhowContext;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rust_decimal::Decimal;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use time::Date;
+
+pub static NBU_URL: &str = "https://bank.gov.ua/NBUStatService/v1/statdirectory/exchange";
+pub static MINFIN_URL: &str = "https://index.minfin.com.ua/exchange/nbu/curr/";
+
+#[allow(clippy::unwrap_used)]
+static TABLE_SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("table").unwrap());
+#[allow(clippy::unwrap_used)]
+static CURRENCY_SELECTOR: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("tr td:nth-child(2)").unwrap());
+#[allow(clippy::unwrap_used)]
+static MULTIPLIER_SELECTOR: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("tr td:nth-child(3)").unwrap());
+#[allow(clippy::unwrap_used)]
+static RATE_SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("tr td:nth-child(5)").unwrap());
+
+#[async_trait]
+pub trait RateProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn supports_history(&self) -> bool {
+        false
+    }
+    // Курси, введені вручну: віддаються, але в історію не потрапляють
+    fn is_fallback(&self) -> bool {
+        false
+    }
+    async fn fetch(&self, date: Date, today: Date) -> Result<Rates, anyhow::Error>;
+    async fn fetch_tagged(&self, date: Date, today: Date) -> Result<(Rates, bool), anyhow::Error> {
+        Ok((self.fetch(date, today).await?, self.is_fallback()))
+    }
+}
+
+pub struct NbuJsonProvider {
+    url: String,
+}
+
+impl NbuJsonProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+impl Default for NbuJsonProvider {
+    fn default() -> Self {
+        Self::new(NBU_URL)
+    }
+}
+
+#[derive(Deserialize)]
+struct NbuRate {
+    cc: String,
+    rate: serde_json::Number,
+}
+
+pub fn parse_nbu_json(body: &str) -> Result<Rates, anyhow::Error> {
+    let entries: Vec<NbuRate> =
+        serde_json::from_str(body).context("Unable to parse NBU rates JSON")?;
+    if entries.is_empty() {
+        return Err(anyhow::anyhow!("NBU returned no rates"));
+    }
+    entries
+        .into_iter()
+        .map(|e| {
+            let rate = Decimal::from_str(&e.rate.to_string())
+                .or_else(|_| Decimal::from_scientific(&e.rate.to_string()))
+                .context(format!(
+                    "Unable to parse rate {} for currency {}",
+                    e.rate, e.cc
+                ))?;
+            Ok((e.cc.trim().to_uppercase(), rate))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl RateProvider for NbuJsonProvider {
+    fn name(&self) -> &str {
+        "nbu"
+    }
+
+    fn supports_history(&self) -> bool {
+        true
+    }
+
+    async fn fetch(&self, date: Date, _today: Date) -> Result<Rates, anyhow::Error> {
+        let date = format!(
+            "{:04}{:02}{:02}",
+            date.year(),
+            date.month() as u8,
+            date.day()
+        );
+        let body = reqwest::Client::new()
+            .get(&self.url)
+            .query(&[("json", ""), ("date", date.as_str())])
+            .send()
+            .await
+            .context("Unable to download NBU rates")?
+            .error_for_status()?
+            .text()
+            .await?;
+        parse_nbu_json(&body)
+    }
+}
+
+pub struct ScrapedPageProvider {
+    url: String,
+}
+
+impl ScrapedPageProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+impl Default for ScrapedPageProvider {
+    fn default() -> Self {
+        Self::new(MINFIN_URL)
+    }
+}
+
+pub fn parse_rates_page(html: &str) -> Result<Rates, anyhow::Error> {
+    let document = Html::parse_document(html);
+    let entries = document
+        .select(&TABLE_SELECTOR)
+        .filter(|e| {
+            e.inner_html()
+                .contains("<caption>Официальный валютный курс НБУ")
+        })
+        .map(|e| {
+            e.select(&CURRENCY_SELECTOR)
+                .zip(e.select(&MULTIPLIER_SELECTOR))
+                .zip(e.select(&RATE_SELECTOR))
+                .map(|((c, m), r)| {
+                    let m: Decimal = m
+                        .inner_html()
+                        .replace(",", ".")
+                        .parse()
+                        .context(format!("Unable to parse multiplier {}", m.inner_html()))?;
+                    let r: Decimal = r
+                        .inner_html()
+                        .replace(",", ".")
+                        .parse()
+                        .context(format!("Unable to parse rate {}", r.inner_html()))?;
+                    Ok((c.inner_html().to_uppercase().to_string(), r / m))
+                })
+                .collect()
+        })
+        .next()
+        .ok_or(anyhow::anyhow!("No tables found on page"))?;
+    entries
+}
+
+#[async_trait]
+impl RateProvider for ScrapedPageProvider {
+    fn name(&self) -> &str {
+        "minfin"
+    }
+
+    async fn fetch(&self, date: Date, today: Date) -> Result<Rates, anyhow::Error> {
+        if date != today {
+            return Err(anyhow::anyhow!("Scraped page only provides today's rates"));
+        }
+        let resp = reqwest::get(&self.url)
+            .await
+            .context("Unable to download currency rates")?
+            .text()
+            .await?;
+        parse_rates_page(&resp)
+    }
+}
+
+pub struct StaticFileProvider {
+    path: PathBuf,
+}
+
+impl StaticFileProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+pub fn parse_rates_csv(content: &str) -> Result<Rates, anyhow::Error> {
+    content
+        .lines()
+        .filter_map(|l| {
+            let mut split = l.split(',');
+            Some((split.next()?.trim(), split.next()?.trim()))
+        })
+        .filter(|(c, _)| !c.is_empty())
+        .map(|(c, r)| {
+            let r = Decimal::from_str_exact(r)
+                .context(format!("Unable to parse rate {r} for currency {c}"))?;
+            Ok((c.to_uppercase(), r))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl RateProvider for StaticFileProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn is_fallback(&self) -> bool {
+        true
+    }
+
+    async fn fetch(&self, _date: Date, _today: Date) -> Result<Rates, anyhow::Error> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .context(format!("Unable to read static rates from {:?}", self.path))?;
+        parse_rates_csv(&content)
+    }
+}
+
+pub struct FallbackChain {
+    providers: Vec<Box<dyn RateProvider>>,
+}
+
+impl FallbackChain {
+    pub fn new(providers: Vec<Box<dyn RateProvider>>) -> Self {
+        Self { providers }
+    }
+
+    // minfin лишається джерелом сьогоднішніх курсів, NBU — минулих днів
+    pub fn from_env() -> Self {
+        let names = std::env::var("CURRENCY_RATE_PROVIDERS")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| "minfin,nbu,file".to_string());
+        let providers = names
+            .split(',')
+            .filter_map(|name| -> Option<Box<dyn RateProvider>> {
+                match name.trim() {
+                    "nbu" => Some(Box::new(NbuJsonProvider::default())),
+                    "minfin" => Some(Box::new(ScrapedPageProvider::default())),
+                    "file" => Some(Box::new(StaticFileProvider::new(
+                        std::env::var("CURRENCY_STATIC_RATES_PATH")
+                            .unwrap_or_else(|_| "currency_rates_static.csv".to_string()),
+                    ))),
+                    other => {
+                        log::warn!("Unknown currency rate provider {other}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self::new(providers)
+    }
+}
+
+#[async_trait]
+impl RateProvider for FallbackChain {
+    fn name(&self) -> &str {
+        "chain"
+    }
+
+    fn supports_history(&self) -> bool {
+        self.providers.iter().any(|p| p.supports_history())
+    }
+
+    async fn fetch(&self, date: Date, today: Date) -> Result<Rates, anyhow::Error> {
+        Ok(self.fetch_tagged(date, today).await?.0)
+    }
+
+    async fn fetch_tagged(&self, date: Date, today: Date) -> Result<(Rates, bool), anyhow::Error> {
+        for provider in &self.providers {
+            if date != today && !provider.supports_history() {
+                continue;
+            }
+            match provider.fetch_tagged(date, today).await {
+                Ok((rates, fallback)) if !rates.is_empty() => return Ok((rates, fallback)),
+                Ok(_) => log::warn!("Provider {} returned no rates", provider.name()),
+                Err(err) => log::warn!("Provider {} failed: {err:#}", provider.name()),
+            }
+        }
+        Err(anyhow::anyhow!(
+            "No currency rate provider returned rates for {date}"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use time::macros::date;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("{FIXTURES}/{name}")).unwrap()
+    }
+
+    async fn serve_once(body: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(resp.as_bytes()).await.unwrap();
+        });
+        format!("http://{addr}/exchange")
+    }
+
+    #[tokio::test]
+    async fn nbu_provider_reads_local_stand_in() {
+        let url = serve_once(fixture("nbu.json")).await;
+        let rates = NbuJsonProvider::new(url)
+            .fetch(date!(2026 - 10 - 16), date!(2026 - 10 - 16))
+            .await
+            .unwrap();
+        assert_eq!(rates["USD"], Decimal::new(414123, 4));
+        assert_eq!(rates.len(), 3);
+    }
+
+    #[test]
+    fn parses_scraped_page_with_multiplier() {
+        let rates = parse_rates_page(&fixture("minfin.html")).unwrap();
+        assert_eq!(rates["USD"], Decimal::new(414123, 4));
+        assert_eq!(rates["PLN"], Decimal::new(104560, 4));
+    }
+
+    #[tokio::test]
+    async fn chain_falls_back_and_skips_current_only_providers_for_past_days() {
+        let today = date!(2026 - 10 - 16);
+        let chain = FallbackChain::new(vec![
+            Box::new(NbuJsonProvider::new("http://127.0.0.1:1/exchange")),
+            Box::new(StaticFileProvider::new(format!(
+                "{FIXTURES}/static_rates.csv"
+            ))),
+        ]);
+        let (rates, fallback) = chain.fetch_tagged(today, today).await.unwrap();
+        assert_eq!(rates["EUR"], Decimal::new(4550, 2));
+        assert!(fallback);
+        assert!(chain.fetch(date!(2026 - 10 - 01), today).await.is_err());
+    }
+}
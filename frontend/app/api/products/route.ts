@@ -1,4 +1,5 @@
 import { NextResponse } from 'next/server';
+import { forwardedHeaders } from '../../lib/forwarded';
 import { getCachedValue, setCachedValue } from '../../lib/server-cache';
 
 const apiBase = process.env.NEXT_PUBLIC_API_BASE?.replace(/\/$/, '') || 'http://localhost:8080';
@@ -24,7 +25,7 @@ export async function GET(request: Request) {
     return new NextResponse(cached.value, { status: 200, headers });
   }
   const target = `${apiBase}/api/site/products${url.search}`;
-  const headers: HeadersInit = {};
+  const headers: HeadersInit = forwardedHeaders(request);
   if (siteApiKey) headers['x-api-key'] = siteApiKey;
 
   try {
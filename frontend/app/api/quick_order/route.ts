@@ -1,4 +1,5 @@
 import { NextResponse } from 'next/server';
+import { forwardedHeaders } from '../../lib/forwarded';
 
 const apiBase = process.env.NEXT_PUBLIC_API_BASE?.replace(/\/$/, '') || 'http://localhost:8080';
 const siteApiKey = process.env.SITE_API_KEY || process.env.NEXT_PUBLIC_SITE_API_KEY;
@@ -15,7 +16,7 @@ export async function POST(request: Request) {
     return NextResponse.json({ ok: false, error: 'missing_phone' }, { status: 400 });
   }
 
-  const headers: HeadersInit = { 'Content-Type': 'application/json' };
+  const headers: HeadersInit = { 'Content-Type': 'application/json', ...forwardedHeaders(request) };
   if (siteApiKey) headers['x-api-key'] = siteApiKey;
 
   try {
@@ -1,11 +1,12 @@
 import { NextResponse } from 'next/server';
+import { forwardedHeaders } from '../../lib/forwarded';
 
 const apiBase = process.env.NEXT_PUBLIC_API_BASE?.replace(/\/$/, '') || 'http://localhost:8080';
 const siteApiKey = process.env.SITE_API_KEY || process.env.NEXT_PUBLIC_SITE_API_KEY;
 
 export async function GET(request: Request) {
   const url = new URL(request.url);
-  const headers: HeadersInit = {};
+  const headers: HeadersInit = forwardedHeaders(request);
   if (siteApiKey) headers['x-api-key'] = siteApiKey;
   const target = `${apiBase}/api/site/reviews${url.search}`;
 
@@ -32,7 +33,7 @@ export async function POST(request: Request) {
     return NextResponse.json({ ok: false, error: 'invalid_payload' }, { status: 400 });
   }
 
-  const headers: HeadersInit = { 'Content-Type': 'application/json' };
+  const headers: HeadersInit = { 'Content-Type': 'application/json', ...forwardedHeaders(request) };
   if (siteApiKey) headers['x-api-key'] = siteApiKey;
 
   try {
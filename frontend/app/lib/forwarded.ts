@@ -0,0 +1,9 @@
+// Адреса клієнта для лімітів бекенду, інакше всі запити йдуть від адреси фронтенду.
+export function forwardedHeaders(request: Request): Record<string, string> {
+  const headers: Record<string, string> = {};
+  const forwardedFor = request.headers.get('x-forwarded-for');
+  if (forwardedFor) headers['x-forwarded-for'] = forwardedFor;
+  const realIp = request.headers.get('x-real-ip');
+  if (realIp) headers['x-real-ip'] = realIp;
+  return headers;
+}
@@ -56,6 +56,7 @@ impl Into<rt_types::product::Product> for Product {
             ua_translation: None,
             description: self.description,
             price: Decimal::from(self.price),
+            source_price: None,
             article: self.article,
             in_stock: None,
             currency: "UAH".to_string(),
@@ -140,6 +141,7 @@ impl Into<rt_types::product::Product> for EnrichedProductRef {
             ua_translation: None,
             description: self.product.description,
             price: Decimal::from(self.product.price),
+            source_price: None,
             article: self.product.article,
             in_stock: None,
             currency: "UAH".to_string(),
@@ -353,7 +355,7 @@ pub fn parse_categories(document: &Html) -> Vec<String> {
         .collect()
 }
 
-async fn work_cycle(opts: ParsingOptions) -> Result<(), anyhow::Error> {
+pub async fn work_cycle(opts: ParsingOptions) -> Result<(), anyhow::Error> {
     let body: String = opts
         .client
         .get("https://davi.com.ua/odyag-ta-vzuttya")
@@ -455,6 +457,7 @@ impl Actor for ParserService {
 
 impl SelectBy<AvailableSelector> for Product {}
 
+#[async_trait]
 pub trait ProductRepository:
     Repository<Product, Error = anyhow::Error>
     + Get<Product>
@@ -468,6 +471,32 @@ pub trait ProductRepository:
     + Send
     + Sync
 {
+    async fn list_page(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+        only_available: bool,
+    ) -> Result<(Vec<Product>, Option<String>), anyhow::Error>;
+}
+
+pub fn batches(
+    repo: Arc<dyn ProductRepository>,
+    only_available: bool,
+    batch: usize,
+) -> impl futures::Stream<Item = Result<Vec<Product>, anyhow::Error>> {
+    futures::stream::try_unfold(Some(None), move |cursor| {
+        let repo = repo.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+            let (products, next) = repo.list_page(cursor, batch, only_available).await?;
+            if products.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some((products, next.map(Some))))
+        }
+    })
 }
 
 #[derive(Constructor)]
@@ -624,4 +653,36 @@ impl Select<rt_types::product::Product, AvailableSelector> for PostgresProductRe
     }
 }
 
-impl ProductRepository for PostgresProductRepository {}
+#[async_trait]
+impl ProductRepository for PostgresProductRepository {
+    async fn list_page(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+        only_available: bool,
+    ) -> Result<(Vec<Product>, Option<String>), anyhow::Error> {
+        let p = self
+            .client
+            .query(
+                &format!(
+                    "SELECT * FROM davi_product \
+                    WHERE ($1::TEXT IS NULL OR article > $1) \
+                    AND (NOT $2 OR available = {} OR available = {}) \
+                    ORDER BY article LIMIT $3",
+                    Availability::Available as u8,
+                    Availability::OnOrder as u8
+                ),
+                &[&cursor, &only_available, &(limit as i64)],
+            )
+            .await?
+            .into_iter()
+            .map(Product::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        let next = if p.len() < limit {
+            None
+        } else {
+            p.last().map(|p| p.article.clone())
+        };
+        Ok((p, next))
+    }
+}
@@ -129,21 +129,38 @@ pub fn assign_categories<'a, P: IntoIterator<Item = Product>>(
 where
     <P as IntoIterator>::IntoIter: 'a,
 {
-    let mut categories: Vec<_> = categories
-        .into_iter()
-        .map(|c| (count_parents(categories, &c), c))
-        .collect();
-    categories.sort_by(|(a, _), (b, _)| a.cmp(b));
-    categories.reverse();
-    let categories = categories.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+    let assigner = CategoryAssigner::new(categories);
     dto.into_iter().map(move |mut p| {
-        let category = categories.iter().find(|c| {
+        assigner.assign(&mut p);
+        p
+    })
+}
+
+// Найглибші категорії перевіряються першими
+pub struct CategoryAssigner<'a> {
+    categories: Vec<&'a Category>,
+}
+
+impl<'a> CategoryAssigner<'a> {
+    pub fn new(categories: &'a HashSet<Category, impl BuildHasher>) -> Self {
+        let mut categories: Vec<_> = categories
+            .iter()
+            .map(|c| (count_parents(categories, c), c))
+            .collect();
+        categories.sort_by(|(a, _), (b, _)| a.cmp(b));
+        categories.reverse();
+        Self {
+            categories: categories.into_iter().map(|(_, c)| c).collect(),
+        }
+    }
+
+    pub fn assign(&self, p: &mut Product) {
+        let category = self.categories.iter().find(|c| {
             p.category.as_ref().is_some_and(|ca| ca == &c.id)
                 || c.regex.as_ref().is_some_and(|r| r.is_match(&p.title))
         });
-        p.category = category.map(|c| c.id.clone());
-        p
-    })
+        p.category = category.map(|c| c.id);
+    }
 }
 
 pub fn count_parents<'a, T>(categories: &'a T, category: &Category) -> usize
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 
 pub mod access;
 pub mod category;
+pub mod pricing;
 pub mod product;
 pub mod shop;
 pub mod subscription;
@@ -0,0 +1,287 @@
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Правила застосовуються по черзі, `stop` зупиняє на правилі, що спрацювало
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PricingRule {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, rename = "match")]
+    pub matcher: PriceMatch,
+    #[serde(default)]
+    pub actions: Vec<PriceAction>,
+    #[serde(default)]
+    pub stop: bool,
+}
+
+impl PricingRule {
+    pub fn always(action: PriceAction) -> Self {
+        Self {
+            actions: vec![action],
+            ..Default::default()
+        }
+    }
+}
+
+pub fn with_adjust_price(adjust_price: Option<Decimal>, rules: &[PricingRule]) -> Vec<PricingRule> {
+    adjust_price
+        .map(|factor| PricingRule::always(PriceAction::Multiply(factor)))
+        .into_iter()
+        .chain(rules.iter().cloned())
+        .collect()
+}
+
+pub fn legacy_rules(
+    markup_percent: Option<Decimal>,
+    discount_percent: Option<usize>,
+    round_to_9: bool,
+) -> Vec<PricingRule> {
+    let markup = markup_percent.map(|m| PriceAction::Markup(m.max(Decimal::ZERO)));
+    let discount = discount_percent.map(|d| PriceAction::Markup(-Decimal::from(d.min(100) as u64)));
+    let round = round_to_9.then_some(PriceAction::Round(Rounding::To9));
+    [markup, discount, round]
+        .into_iter()
+        .flatten()
+        .map(PricingRule::always)
+        .collect()
+}
+
+// Умови через AND, `article` приймає `*`, текст без урахування регістру
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PriceMatch {
+    #[serde(default)]
+    pub vendor: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub brand: Option<String>,
+    #[serde(default)]
+    pub min_price: Option<Decimal>,
+    #[serde(default)]
+    pub max_price: Option<Decimal>,
+    #[serde(default)]
+    pub article: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceAction {
+    Markup(Decimal),
+    Multiply(Decimal),
+    Add(Decimal),
+    MinMargin(Decimal),
+    Round(Rounding),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    To9,
+    To10,
+    To50,
+}
+
+impl Rounding {
+    pub fn apply(self, price: Decimal) -> Decimal {
+        let value = price.round();
+        if value <= Decimal::ZERO {
+            return Decimal::ZERO;
+        }
+        match self {
+            Self::To9 => {
+                if value < Decimal::TEN {
+                    return Decimal::from(9);
+                }
+                value - value % Decimal::TEN + Decimal::from(9)
+            }
+            Self::To10 => round_up_to(value, Decimal::TEN),
+            Self::To50 => round_up_to(value, Decimal::from(50)),
+        }
+    }
+}
+
+fn round_up_to(value: Decimal, step: Decimal) -> Decimal {
+    let rest = value % step;
+    if rest.is_zero() {
+        value
+    } else {
+        value - rest + step
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PriceSubject<'a> {
+    pub vendor: &'a str,
+    pub category: Option<&'a str>,
+    pub brand: &'a str,
+    pub article: &'a str,
+}
+
+impl PriceMatch {
+    pub fn matches(&self, subject: &PriceSubject, price: Decimal) -> bool {
+        let eq = |expected: &Option<String>, actual: Option<&str>| match expected {
+            Some(e) if !e.trim().is_empty() => {
+                actual.is_some_and(|a| a.trim().to_lowercase() == e.trim().to_lowercase())
+            }
+            _ => true,
+        };
+        eq(&self.vendor, Some(subject.vendor))
+            && eq(&self.category, subject.category)
+            && eq(&self.brand, Some(subject.brand))
+            && self.min_price.is_none_or(|min| price >= min)
+            && self.max_price.is_none_or(|max| price <= max)
+            && self
+                .article
+                .as_ref()
+                .filter(|p| !p.trim().is_empty())
+                .is_none_or(|p| {
+                    wildcard_match(&p.trim().to_lowercase(), &subject.article.to_lowercase())
+                })
+    }
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+// Діапазони цін перевіряються за ціною на момент правила
+pub fn apply_rules(
+    rules: &[PricingRule],
+    subject: &PriceSubject,
+    source_price: Decimal,
+    mut price: Decimal,
+) -> Decimal {
+    for rule in rules {
+        if !rule.matcher.matches(subject, price) {
+            continue;
+        }
+        for action in &rule.actions {
+            price = match action {
+                PriceAction::Markup(percent) => {
+                    price * (Decimal::ONE_HUNDRED + percent) / Decimal::ONE_HUNDRED
+                }
+                PriceAction::Multiply(factor) => price * factor,
+                PriceAction::Add(amount) => price + amount,
+                PriceAction::MinMargin(percent) => price
+                    .max(source_price * (Decimal::ONE_HUNDRED + percent) / Decimal::ONE_HUNDRED),
+                PriceAction::Round(rounding) => rounding.apply(price),
+            };
+        }
+        if rule.stop {
+            break;
+        }
+    }
+    if price.is_sign_negative() {
+        return Decimal::ZERO;
+    }
+    price
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn subject() -> PriceSubject<'static> {
+        PriceSubject {
+            vendor: "DT",
+            category: Some("Спойлеры"),
+            brand: "Maxton",
+            article: "MX-BM-001",
+        }
+    }
+
+    #[test]
+    fn applies_matching_rules_in_order() {
+        let rules = vec![
+            PricingRule {
+                matcher: PriceMatch {
+                    vendor: Some("dt".to_string()),
+                    article: Some("mx-*-0*".to_string()),
+                    ..Default::default()
+                },
+                actions: vec![PriceAction::Markup(dec!(10)), PriceAction::Add(dec!(5))],
+                ..Default::default()
+            },
+            PricingRule {
+                matcher: PriceMatch {
+                    brand: Some("Other".to_string()),
+                    ..Default::default()
+                },
+                actions: vec![PriceAction::Add(dec!(1000))],
+                ..Default::default()
+            },
+            PricingRule {
+                matcher: PriceMatch {
+                    min_price: Some(dec!(1000)),
+                    ..Default::default()
+                },
+                actions: vec![PriceAction::Round(Rounding::To50)],
+                stop: true,
+                ..Default::default()
+            },
+            PricingRule {
+                actions: vec![PriceAction::Add(dec!(1))],
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            apply_rules(&rules, &subject(), dec!(1000), dec!(1000)),
+            dec!(1150)
+        );
+        assert_eq!(
+            apply_rules(&rules, &subject(), dec!(100), dec!(100)),
+            dec!(116)
+        );
+    }
+
+    #[test]
+    fn min_margin_is_relative_to_source_price() {
+        let rules = vec![PricingRule {
+            actions: vec![
+                PriceAction::Add(dec!(-50)),
+                PriceAction::MinMargin(dec!(20)),
+            ],
+            ..Default::default()
+        }];
+        assert_eq!(
+            apply_rules(&rules, &subject(), dec!(100), dec!(100)),
+            dec!(120)
+        );
+    }
+
+    #[test]
+    fn adjust_price_runs_first_and_keeps_its_scale() {
+        let rules = with_adjust_price(
+            Some(dec!(1.15)),
+            &[PricingRule::always(PriceAction::Add(dec!(5)))],
+        );
+        let price = apply_rules(&rules, &subject(), dec!(1000), dec!(1000));
+        assert_eq!(price.to_string(), "1155.00");
+        assert!(with_adjust_price(None, &[]).is_empty());
+    }
+
+    #[test]
+    fn rounds_prices() {
+        assert_eq!(Rounding::To9.apply(dec!(1234.4)), dec!(1239));
+        assert_eq!(Rounding::To9.apply(dec!(3)), dec!(9));
+        assert_eq!(Rounding::To10.apply(dec!(1234)), dec!(1240));
+        assert_eq!(Rounding::To10.apply(dec!(1230)), dec!(1230));
+        assert_eq!(Rounding::To50.apply(dec!(1234)), dec!(1250));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::category::Category;
 use crate::Availability;
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use typesafe_repository::macros::Id;
 use typesafe_repository::{Identity, IdentityOf, RefIdentity, SelectBy, Selector};
@@ -12,19 +13,20 @@ impl Selector for AvailableSelector {}
 
 impl SelectBy<AvailableSelector> for Product {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UaTranslation {
     pub title: String,
     pub description: Option<String>,
 }
 
-#[derive(Debug, Clone, Id)]
+#[derive(Debug, Clone, Id, Serialize, Deserialize)]
 pub struct Product {
     pub id: String,
     pub title: String,
     pub ua_translation: Option<UaTranslation>,
     pub description: Option<String>,
     pub price: Decimal,
+    pub source_price: Option<Decimal>,
     pub article: String,
     pub in_stock: Option<usize>,
     pub currency: String,
@@ -1,10 +1,12 @@
 use crate::access::UserCredentials;
+use crate::pricing::PricingRule;
 use crate::watermark::WatermarkOptions;
 use crate::{Availability, DescriptionOptions};
 use actix::prelude::*;
 use derive_more::Display;
 use lazy_regex::regex;
 use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use serde::de::Deserializer;
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
@@ -12,7 +14,7 @@ use serde_aux::field_attributes::{
     deserialize_number_from_string, deserialize_option_number_from_string,
 };
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::{Hash, Hasher};
 use std::num::NonZeroU32;
 use std::time::Duration;
@@ -55,6 +57,243 @@ pub struct Shop {
     pub default_custom_options: Option<CustomOptions>,
     #[serde(default)]
     pub image_proxy: bool,
+    #[serde(default)]
+    pub currency: CurrencySettings,
+    #[serde(default)]
+    pub watermark_hosts: Vec<String>,
+    #[serde(default)]
+    pub prom: PromSettings,
+    #[serde(default)]
+    pub reviews: ReviewSettings,
+    #[serde(default)]
+    pub nova_poshta: NovaPoshtaSettings,
+    #[serde(default)]
+    pub feeds: FeedSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FeedSettings {
+    #[serde(default)]
+    pub facebook: bool,
+    #[serde(default)]
+    pub google_merchant: bool,
+    #[serde(default)]
+    pub rozetka: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PromSettings {
+    #[serde(default)]
+    pub api_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReviewSettings {
+    #[serde(default)]
+    pub moderation: ReviewModeration,
+    // Слова, через які відгук іде на модерацію як підозрілий, без урахування регістру
+    #[serde(default)]
+    pub stop_words: Vec<String>,
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, Display, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewModeration {
+    #[default]
+    #[display("Публиковать сразу")]
+    AutoPublish,
+    #[display("Всегда на модерацию")]
+    Always,
+    #[display("На модерацию, если есть ссылки или стоп-слова")]
+    Suspicious,
+}
+
+impl ReviewModeration {
+    pub const ALL: [Self; 3] = [Self::AutoPublish, Self::Always, Self::Suspicious];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AutoPublish => "auto_publish",
+            Self::Always => "always",
+            Self::Suspicious => "suspicious",
+        }
+    }
+}
+
+impl std::str::FromStr for ReviewModeration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|m| m.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown review moderation {s}"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NovaPoshtaSettings {
+    #[serde(default)]
+    pub api_key: Option<String>,
+    // Відділення здачі посилок, з нього ж береться місто відправника
+    #[serde(default)]
+    pub sender_warehouse_ref: Option<String>,
+    #[serde(default)]
+    pub sender_city_ref: Option<String>,
+    #[serde(default)]
+    pub sender_warehouse_name: Option<String>,
+    #[serde(default)]
+    pub sender_phone: Option<String>,
+    #[serde(default)]
+    pub default_weight: Option<Decimal>,
+    #[serde(default)]
+    pub payer: NovaPoshtaPayer,
+}
+
+impl NovaPoshtaSettings {
+    pub fn is_configured(&self) -> bool {
+        self.api_key
+            .as_deref()
+            .is_some_and(|k| !k.trim().is_empty())
+            && self.sender_warehouse_ref.is_some()
+            && self.sender_city_ref.is_some()
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, Display, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NovaPoshtaPayer {
+    #[default]
+    #[display("Получатель")]
+    Recipient,
+    #[display("Отправитель")]
+    Sender,
+}
+
+impl NovaPoshtaPayer {
+    pub const ALL: [Self; 2] = [Self::Recipient, Self::Sender];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Recipient => "recipient",
+            Self::Sender => "sender",
+        }
+    }
+
+    pub fn api_value(&self) -> &'static str {
+        match self {
+            Self::Recipient => "Recipient",
+            Self::Sender => "Sender",
+        }
+    }
+}
+
+impl std::str::FromStr for NovaPoshtaPayer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown Nova Poshta payer {s}"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CurrencySettings {
+    // `None` — історична націнка 7%
+    #[serde(default)]
+    pub default_markup_percent: Option<Decimal>,
+    #[serde(default)]
+    pub currencies: BTreeMap<String, CurrencyRule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CurrencyRule {
+    #[serde(default)]
+    pub markup_percent: Option<Decimal>,
+    #[serde(default)]
+    pub manual_rate: Option<Decimal>,
+    #[serde(default)]
+    pub min_rate: Option<Decimal>,
+    #[serde(default)]
+    pub max_rate: Option<Decimal>,
+}
+
+impl CurrencySettings {
+    pub const DEFAULT_MARKUP_PERCENT: Decimal = dec!(7);
+
+    pub fn rule(&self, code: &str) -> Option<&CurrencyRule> {
+        self.currencies.get(&code.trim().to_uppercase())
+    }
+
+    pub fn markup_percent(&self, code: &str) -> Decimal {
+        self.rule(code)
+            .and_then(|r| r.markup_percent)
+            .or(self.default_markup_percent)
+            .unwrap_or(Self::DEFAULT_MARKUP_PERCENT)
+    }
+
+    pub fn effective_rate(&self, code: &str, base: Option<Decimal>) -> Option<Decimal> {
+        let rule = self.rule(code);
+        let rate = match rule.and_then(|r| r.manual_rate) {
+            Some(manual) => manual,
+            None => {
+                base? * (Decimal::ONE_HUNDRED + self.markup_percent(code)) / Decimal::ONE_HUNDRED
+            }
+        };
+        let Some(rule) = rule else {
+            return Some(rate);
+        };
+        let rate = match rule.min_rate {
+            Some(min) if rate < min => {
+                log::warn!("{code} rate {rate} is below the configured minimum {min}");
+                min
+            }
+            _ => rate,
+        };
+        let rate = match rule.max_rate {
+            Some(max) if rate > max => {
+                log::warn!("{code} rate {rate} is above the configured maximum {max}");
+                max
+            }
+            _ => rate,
+        };
+        Some(rate)
+    }
+
+    pub fn apply(&self, base: HashMap<String, Decimal>) -> HashMap<String, Decimal> {
+        let mut codes = base.keys().cloned().collect::<Vec<_>>();
+        codes.extend(
+            self.currencies
+                .iter()
+                .filter(|(_, r)| r.manual_rate.is_some())
+                .map(|(c, _)| c.clone()),
+        );
+        codes
+            .into_iter()
+            .filter_map(|code| {
+                let rate = self.effective_rate(&code, base.get(&code).copied())?;
+                Some((code, rate))
+            })
+            .collect()
+    }
+
+    // Курси постачальника вже з його маржею, тож націнка лише якщо її задав магазин
+    pub fn apply_to_supplier_rates(
+        &self,
+        base: HashMap<String, Decimal>,
+    ) -> HashMap<String, Decimal> {
+        Self {
+            default_markup_percent: Some(self.default_markup_percent.unwrap_or(Decimal::ZERO)),
+            ..self.clone()
+        }
+        .apply(base)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -200,12 +439,120 @@ pub struct ExportEntry {
     pub davi_parsing: Option<ExportOptions>,
     #[serde(default)]
     pub ddaudio_api: Option<DDAudioExportOptions>,
+    #[serde(default)]
+    pub guards: ExportGuards,
+    #[serde(default)]
+    pub prom: Option<PromUploadOptions>,
     #[serde(deserialize_with = "deserialize_duration_from_string")]
     #[serde(serialize_with = "serialize_duration_into_string")]
     #[serde(default = "default_update_rate")]
     pub update_rate: Duration,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExportGuards {
+    pub max_count_drop_percent: Option<Decimal>,
+    pub max_zero_price_percent: Option<Decimal>,
+    pub max_not_available_percent: Option<Decimal>,
+    pub max_avg_price_change_percent: Option<Decimal>,
+}
+
+impl ExportGuards {
+    pub fn is_enabled(&self) -> bool {
+        self != &Self::default()
+    }
+}
+
+pub const PROM_UPDATABLE_FIELDS: &[(&str, &str)] = &[
+    ("name", "Название"),
+    ("sku", "Артикул"),
+    ("price", "Цена"),
+    ("image_urls", "Изображения"),
+    ("presence", "Наличие"),
+    ("quantity_in_stock", "Остаток"),
+    ("description", "Описание"),
+    ("discount", "Скидка"),
+    ("keywords", "Ключевые слова"),
+    ("attributes", "Характеристики"),
+    ("translations", "Переводы"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PromUploadOptions {
+    #[serde(default = "PromUploadOptions::default_updated_fields")]
+    pub updated_fields: Vec<String>,
+    #[serde(default)]
+    pub mark_missing_product_as: MissingProductAction,
+}
+
+impl PromUploadOptions {
+    pub fn updates(&self, field: &str) -> bool {
+        self.updated_fields.iter().any(|f| f == field)
+    }
+
+    fn default_updated_fields() -> Vec<String> {
+        PROM_UPDATABLE_FIELDS
+            .iter()
+            .map(|(f, _)| f.to_string())
+            .filter(|f| f != "keywords")
+            .collect()
+    }
+}
+
+impl Default for PromUploadOptions {
+    fn default() -> Self {
+        Self {
+            updated_fields: Self::default_updated_fields(),
+            mark_missing_product_as: MissingProductAction::default(),
+        }
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, Display, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingProductAction {
+    #[display("Не изменять")]
+    None,
+    #[default]
+    #[display("Нет в наличии")]
+    NotAvailable,
+    #[display("Скрыть")]
+    NotOnDisplay,
+    #[display("Удалить")]
+    Deleted,
+}
+
+impl MissingProductAction {
+    pub const ALL: [Self; 4] = [
+        Self::None,
+        Self::NotAvailable,
+        Self::NotOnDisplay,
+        Self::Deleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::NotAvailable => "not_available",
+            Self::NotOnDisplay => "not_on_display",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+impl std::str::FromStr for MissingProductAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown missing product action {s}"))
+    }
+}
+
 impl ExportEntry {
     pub fn uses_watermark(&self, watermark: &str) -> bool {
         self.tt_parsing
@@ -505,6 +852,8 @@ impl Default for ExportEntry {
             skm_parsing: None,
             maxton_parsing: None,
             ddaudio_api: None,
+            guards: ExportGuards::default(),
+            prom: None,
             update_rate: default_update_rate(),
         }
     }
@@ -557,6 +906,11 @@ impl ExportEntry {
                         FileFormat::HoroshopCategories.extension()
                     )
                 }
+                Some(format) if format.feed_suffix().is_some() => format!(
+                    "{file_name}_{}.{}.zip",
+                    format.feed_suffix().unwrap_or_default(),
+                    format.extension()
+                ),
                 Some(format) => format!("{file_name}.{}.zip", format.extension()),
                 None => file_name,
             };
@@ -589,10 +943,14 @@ impl ExportEntry {
             }
         }
         let file_format = file_format.into();
+        let feed_suffix = file_format.as_ref().and_then(FileFormat::feed_suffix);
+        if let Some(suffix) = feed_suffix {
+            file_name.push_str(&format!("_{suffix}"));
+        }
         if let Some(file_format) = &file_format {
             file_name.push_str(&format!(".{}", file_format.extension()));
         }
-        if let Some(FileFormat::Csv | FileFormat::Xml) = file_format {
+        if feed_suffix.is_some() || matches!(file_format, Some(FileFormat::Csv | FileFormat::Xml)) {
             file_name.push_str(".zip");
         }
         file_name
@@ -611,9 +969,32 @@ pub enum FileFormat {
     HoroshopCsv,
     #[display("horoshop categories")]
     HoroshopCategories,
+    #[display("facebook csv")]
+    FacebookCsv,
+    #[display("facebook xml")]
+    FacebookXml,
+    #[display("google merchant ua")]
+    GoogleMerchantUa,
+    #[display("google merchant ru")]
+    GoogleMerchantRu,
+    #[display("rozetka yml")]
+    RozetkaYml,
 }
 
 impl FileFormat {
+    pub const ALL: &'static [FileFormat] = &[
+        FileFormat::Csv,
+        FileFormat::Xml,
+        FileFormat::Xlsx,
+        FileFormat::HoroshopCsv,
+        FileFormat::HoroshopCategories,
+        FileFormat::FacebookCsv,
+        FileFormat::FacebookXml,
+        FileFormat::GoogleMerchantUa,
+        FileFormat::GoogleMerchantRu,
+        FileFormat::RozetkaYml,
+    ];
+
     pub fn extension(&self) -> &str {
         match self {
             Self::Xlsx => "xlsx",
@@ -621,6 +1002,22 @@ impl FileFormat {
             Self::Xml => "xml",
             Self::HoroshopCsv => "csv",
             Self::HoroshopCategories => "csv",
+            Self::FacebookCsv => "csv",
+            Self::FacebookXml => "xml",
+            Self::GoogleMerchantUa => "xml",
+            Self::GoogleMerchantRu => "xml",
+            Self::RozetkaYml => "xml",
+        }
+    }
+
+    // Фіди мають ті ж розширення, що й звичайні файли, відрізняються суфіксом
+    pub fn feed_suffix(&self) -> Option<&str> {
+        match self {
+            Self::FacebookCsv | Self::FacebookXml => Some("fb"),
+            Self::GoogleMerchantUa => Some("gmc_ua"),
+            Self::GoogleMerchantRu => Some("gmc_ru"),
+            Self::RozetkaYml => Some("rozetka"),
+            _ => None,
         }
     }
 }
@@ -690,6 +1087,8 @@ pub struct ExportOptions {
     pub description_ua: Option<DescriptionOptions>,
     pub delivery_time: Option<usize>,
     pub adjust_price: Option<Decimal>,
+    #[serde(default)]
+    pub pricing: Vec<PricingRule>,
     #[serde(default = "bool_false")]
     pub categories: bool,
     #[serde(default = "bool_false")]
@@ -706,6 +1105,15 @@ impl ExportOptions {
             None => false,
         }
     }
+    pub fn pricing_rules(&self) -> Vec<PricingRule> {
+        crate::pricing::with_adjust_price(self.adjust_price, &self.pricing)
+    }
+    pub fn pricing_json(&self) -> String {
+        if self.pricing.is_empty() {
+            return String::new();
+        }
+        serde_json::to_string_pretty(&self.pricing).unwrap_or_default()
+    }
 }
 
 impl Default for ExportOptions {
@@ -725,6 +1133,7 @@ impl Default for ExportOptions {
             description_ua: None,
             delivery_time: None,
             adjust_price: None,
+            pricing: Vec::new(),
             categories: false,
             convert_to_uah: false,
             custom_options: None,
@@ -919,3 +1328,52 @@ pub fn serialize_duration_into_string<S: Serializer>(
 ) -> Result<S::Ok, S::Error> {
     serializer.serialize_str(&format!("{}s", duration.as_secs()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn currency_settings_apply_markup_manual_rate_and_band() {
+        let mut settings = CurrencySettings::default();
+        let base = HashMap::from([
+            ("USD".to_string(), dec!(40)),
+            ("EUR".to_string(), dec!(50)),
+            ("PLN".to_string(), dec!(10)),
+        ]);
+        settings.currencies.insert(
+            "EUR".to_string(),
+            CurrencyRule {
+                markup_percent: Some(dec!(2)),
+                max_rate: Some(dec!(50.5)),
+                ..Default::default()
+            },
+        );
+        settings.currencies.insert(
+            "PLN".to_string(),
+            CurrencyRule {
+                manual_rate: Some(dec!(11)),
+                ..Default::default()
+            },
+        );
+        let rates = settings.apply(base.clone());
+        assert_eq!(rates["USD"], dec!(42.8));
+        assert_eq!(rates["EUR"], dec!(50.5));
+        assert_eq!(rates["PLN"], dec!(11));
+        let supplier = settings.apply_to_supplier_rates(base.clone());
+        assert_eq!(supplier["USD"], dec!(40));
+        assert_eq!(supplier["EUR"], dec!(50.5));
+        assert_eq!(supplier["PLN"], dec!(11));
+        assert_eq!(
+            CurrencySettings::default().apply_to_supplier_rates(base.clone()),
+            base
+        );
+
+        settings.default_markup_percent = Some(Decimal::ZERO);
+        assert_eq!(
+            settings.effective_rate("usd", Some(dec!(40))),
+            Some(dec!(40))
+        );
+        assert_eq!(settings.effective_rate("CHF", None), None);
+    }
+}
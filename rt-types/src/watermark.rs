@@ -62,12 +62,167 @@ impl Hash for WatermarkGroup {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
 pub struct WatermarkOptions {
     #[serde(default)]
     pub size: WatermarkSize,
     pub horizontal_position: WatermarkPosition,
     pub vertical_position: WatermarkPosition,
+    #[serde(default)]
+    pub opacity: Option<Decimal>,
+    #[serde(default)]
+    pub repeat: WatermarkRepeat,
+    #[serde(default)]
+    pub output: WatermarkOutput,
+}
+
+impl Hash for WatermarkOptions {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.size.hash(state);
+        self.horizontal_position.hash(state);
+        self.vertical_position.hash(state);
+        // Нові опції хешуються лише коли задані, щоб id наявних груп не змінились
+        if self.opacity.is_some()
+            || self.repeat != WatermarkRepeat::default()
+            || self.output != WatermarkOutput::default()
+        {
+            self.opacity.hash(state);
+            self.repeat.hash(state);
+            self.output.hash(state);
+        }
+    }
+}
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    PartialOrd,
+    Ord,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkRepeat {
+    #[default]
+    #[display("single")]
+    Single,
+    #[display("tiled")]
+    Tiled,
+    #[display("diagonal")]
+    Diagonal,
+}
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    PartialOrd,
+    Ord,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    #[default]
+    #[display("png")]
+    Png,
+    #[display("jpeg")]
+    Jpeg,
+    #[display("webp")]
+    Webp,
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "png" => Ok(Self::Png),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "webp" => Ok(Self::Webp),
+            _ => Err(anyhow!("Unknown image format {s}")),
+        }
+    }
+}
+
+impl ImageFormat {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialOrd, Ord, PartialEq, Eq, Hash)]
+pub struct WatermarkOutput {
+    #[serde(default)]
+    pub format: Option<ImageFormat>,
+    #[serde(default)]
+    pub quality: Option<u8>,
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    #[serde(default)]
+    pub max_height: Option<u32>,
+}
+
+impl WatermarkOutput {
+    pub fn or(self, other: &WatermarkOutput) -> WatermarkOutput {
+        WatermarkOutput {
+            format: self.format.or(other.format),
+            quality: self.quality.or(other.quality),
+            max_width: self.max_width.or(other.max_width),
+            max_height: self.max_height.or(other.max_height),
+        }
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format.unwrap_or_default()
+    }
+
+    pub fn fit(&self, image: PhotonImage) -> PhotonImage {
+        let (width, height) = (image.get_width(), image.get_height());
+        let scale = [
+            self.max_width.map(|w| w as f32 / width as f32),
+            self.max_height.map(|h| h as f32 / height as f32),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(1f32, f32::min);
+        if scale >= 1. || width == 0 || height == 0 {
+            return image;
+        }
+        let width = ((width as f32 * scale).round() as u32).max(1);
+        let height = ((height as f32 * scale).round() as u32).max(1);
+        photon_rs::transform::resize(&image, width, height, SamplingFilter::Lanczos3)
+    }
+
+    pub fn encode(&self, image: &PhotonImage) -> Vec<u8> {
+        match self.format() {
+            ImageFormat::Png => image.get_bytes(),
+            ImageFormat::Jpeg => {
+                image.get_bytes_jpeg(self.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100))
+            }
+            ImageFormat::Webp => image.get_bytes_webp(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
@@ -93,6 +248,9 @@ impl Default for WatermarkOptions {
             size: WatermarkSize::default(),
             horizontal_position: WatermarkPosition::End,
             vertical_position: WatermarkPosition::End,
+            opacity: None,
+            repeat: WatermarkRepeat::default(),
+            output: WatermarkOutput::default(),
         }
     }
 }
@@ -160,38 +318,127 @@ pub fn apply(
             resize(&watermark, width, height)
         }
     };
-    let x = match opts.horizontal_position {
-        WatermarkPosition::Start => 0,
-        WatermarkPosition::Center => image.get_width() / 2 - watermark.get_width() / 2,
-        WatermarkPosition::End => image.get_width() - watermark.get_width(),
-    };
-    let y = match opts.vertical_position {
-        WatermarkPosition::Start => 0,
-        WatermarkPosition::Center => image.get_height() / 2 - watermark.get_height() / 2,
-        WatermarkPosition::End => image.get_height() - watermark.get_height(),
+    let watermark = match opts.opacity {
+        Some(opacity) => with_opacity(
+            watermark,
+            opacity
+                .to_f32()
+                .ok_or(anyhow!("Unable to convert decimal to f32"))?,
+        ),
+        None => watermark,
     };
-    photon_rs::multiple::watermark(&mut image, &watermark, x as i64, y as i64);
+    for (x, y) in placements(&image, &watermark, &opts) {
+        photon_rs::multiple::watermark(&mut image, &watermark, x, y);
+    }
     Ok(image)
 }
 
+fn with_opacity(watermark: PhotonImage, opacity: f32) -> PhotonImage {
+    let factor = (opacity / 100.).clamp(0., 1.);
+    let (width, height) = (watermark.get_width(), watermark.get_height());
+    let mut pixels = watermark.get_raw_pixels();
+    for alpha in pixels.iter_mut().skip(3).step_by(4) {
+        *alpha = (*alpha as f32 * factor).round() as u8;
+    }
+    PhotonImage::new(pixels, width, height)
+}
+
+const MAX_COPIES: i64 = 100;
+
+fn placements(
+    image: &PhotonImage,
+    watermark: &PhotonImage,
+    opts: &WatermarkOptions,
+) -> Vec<(i64, i64)> {
+    let (iw, ih) = (image.get_width() as i64, image.get_height() as i64);
+    let (ww, wh) = (
+        (watermark.get_width() as i64).max(1),
+        (watermark.get_height() as i64).max(1),
+    );
+    match opts.repeat {
+        WatermarkRepeat::Single => {
+            let x = match opts.horizontal_position {
+                WatermarkPosition::Start => 0,
+                WatermarkPosition::Center => iw / 2 - ww / 2,
+                WatermarkPosition::End => iw - ww,
+            };
+            let y = match opts.vertical_position {
+                WatermarkPosition::Start => 0,
+                WatermarkPosition::Center => ih / 2 - wh / 2,
+                WatermarkPosition::End => ih - wh,
+            };
+            vec![(x, y)]
+        }
+        // Крок — половина накладки, але ширше для дуже малих накладок
+        WatermarkRepeat::Tiled => {
+            let (mut step_x, mut step_y) = (ww + ww / 2, wh + wh / 2);
+            let copies = (iw / step_x + 1) * (ih / step_y + 1);
+            if copies > MAX_COPIES {
+                let scale = (copies as f64 / MAX_COPIES as f64).sqrt();
+                step_x = (step_x as f64 * scale).ceil() as i64;
+                step_y = (step_y as f64 * scale).ceil() as i64;
+            }
+            (0..)
+                .map(|row| row * step_y)
+                .take_while(|y| *y < ih)
+                .flat_map(|y| {
+                    (0..)
+                        .map(|col| col * step_x)
+                        .take_while(|x| *x < iw)
+                        .map(move |x| (x, y))
+                })
+                .collect()
+        }
+        WatermarkRepeat::Diagonal => {
+            let count = (iw as f64).hypot(ih as f64) / (ww as f64).hypot(wh as f64) / 1.5;
+            let count = (count.floor() as i64).clamp(1, MAX_COPIES);
+            (0..count)
+                .map(|i| {
+                    let t = (i as f64 + 0.5) / count as f64;
+                    let cx = (iw as f64 * t) as i64;
+                    let cy = (ih as f64 * t) as i64;
+                    (cx - ww / 2, cy - wh / 2)
+                })
+                .collect()
+        }
+    }
+}
+
 pub fn apply_to_product_map<T: BuildHasher + Clone>(
     mut dto: HashMap<ExportOptions, Vec<Product>, T>,
     shop_id: &str,
     addr: &str,
+    query: impl Fn(&str, &str, Option<&WatermarkOptions>) -> String,
 ) -> Result<HashMap<ExportOptions, Vec<Product>, T>, anyhow::Error> {
-    for ((group_id, _), list) in dto
-        .iter_mut()
-        .filter_map(|(o, l)| Some((o.watermarks.as_ref()?, l)))
-    {
+    for (opts, list) in dto.iter_mut() {
         for dto in list.iter_mut() {
-            dto.images.iter_mut().for_each(|i| {
-                *i = format!("{addr}/shop/{shop_id}/watermark/{i}/{group_id}");
-            });
+            apply_to_product(opts, dto, shop_id, addr, &query);
         }
     }
     Ok(dto)
 }
 
+pub fn apply_to_product(
+    opts: &ExportOptions,
+    dto: &mut Product,
+    shop_id: &str,
+    addr: &str,
+    query: impl Fn(&str, &str, Option<&WatermarkOptions>) -> String,
+) {
+    let Some((group_id, opts)) = opts.watermarks.as_ref() else {
+        return;
+    };
+    dto.images.iter_mut().for_each(|i| {
+        let link = format!("{addr}/shop/{shop_id}/watermark/{i}/{group_id}");
+        let query = query(i, group_id, opts.as_ref());
+        *i = if query.is_empty() {
+            link
+        } else {
+            format!("{link}?{query}")
+        };
+    });
+}
+
 pub trait WatermarkGroupRepository:
     Repository<WatermarkGroup, Error = anyhow::Error>
     + Get<WatermarkGroup>
@@ -202,3 +449,58 @@ pub trait WatermarkGroupRepository:
     + Sync
 {
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(v: impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        v.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn output_and_repeat_options() {
+        let opts = WatermarkOptions::default();
+        assert_eq!(
+            hash(&opts),
+            hash((
+                &opts.size,
+                &opts.horizontal_position,
+                &opts.vertical_position
+            ))
+        );
+        let tiled = WatermarkOptions {
+            repeat: WatermarkRepeat::Tiled,
+            ..WatermarkOptions::default()
+        };
+        assert_ne!(hash(&opts), hash(&tiled));
+
+        let image = PhotonImage::new(vec![255; 400 * 200 * 4], 400, 200);
+        let watermark = PhotonImage::new(vec![0; 40 * 20 * 4], 40, 20);
+        assert_eq!(placements(&image, &watermark, &opts), vec![(360, 180)]);
+        assert_eq!(placements(&image, &watermark, &tiled).len(), 7 * 7);
+        let tiny = PhotonImage::new(vec![0; 4], 1, 1);
+        assert!(placements(&image, &tiny, &tiled).len() as i64 <= MAX_COPIES);
+
+        let output = WatermarkOutput {
+            format: Some(ImageFormat::Jpeg),
+            max_width: Some(100),
+            ..Default::default()
+        }
+        .or(&WatermarkOutput {
+            format: Some(ImageFormat::Webp),
+            max_height: Some(80),
+            ..Default::default()
+        });
+        assert_eq!(output.format(), ImageFormat::Jpeg);
+        let image = output.fit(image);
+        assert_eq!((image.get_width(), image.get_height()), (100, 50));
+        assert!(output.encode(&image).starts_with(&[0xff, 0xd8]));
+
+        let opaque = PhotonImage::new(vec![255; 4], 1, 1);
+        assert_eq!(with_opacity(opaque, 50.).get_raw_pixels()[3], 128);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::Url;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::io::ErrorKind;
 use std::time::Duration;
 
@@ -81,3 +82,20 @@ pub fn read_links(path: &str) -> Result<Vec<(Url, String, String)>, anyhow::Erro
 pub fn clean_links(path: &str) -> Result<(), anyhow::Error> {
     Ok(std::fs::remove_file(path)?)
 }
+
+pub fn read_fingerprints(path: &str) -> Result<HashMap<String, u64>, anyhow::Error> {
+    let input = match std::fs::read_to_string(path) {
+        Ok(input) => input,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(serde_yaml::from_str(&input)?)
+}
+
+pub fn write_fingerprints(
+    path: &str,
+    fingerprints: &HashMap<String, u64>,
+) -> Result<(), anyhow::Error> {
+    std::fs::write(path, serde_yaml::to_string(fingerprints)?)?;
+    Ok(())
+}
@@ -0,0 +1,790 @@
+use crate::control::site_api::{
+    check_api_rate_limit, check_login_rate_limit, get_client_ip, load_site_products_cached,
+    resolve_site_shop, ProductDto,
+};
+use crate::control::{ControllerError, Response};
+use crate::customer::{self, Customer, CustomerRepository, OtpCheck, OtpIssue, OtpSender};
+use crate::dt;
+use crate::order;
+use crate::product_category;
+use crate::shop_product;
+use crate::site_publish;
+use actix_web::dev::Payload;
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{get, post, FromRequest, HttpRequest, HttpResponse};
+use rt_types::category::CategoryRepository;
+use rt_types::shop::Shop;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+// Окремий заголовок, щоб сесія покупця не змішувалась з `x-api-key` вітрини
+pub const CUSTOMER_TOKEN_HEADER: &str = "x-customer-token";
+
+fn customer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(CUSTOMER_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+fn error(code: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "ok": false, "error": code }))
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+pub(crate) async fn customer_from_request(
+    req: &HttpRequest,
+    customer_repo: &Arc<dyn CustomerRepository>,
+    shop_id: uuid::Uuid,
+) -> Result<Option<Customer>, ControllerError> {
+    match customer_token(req) {
+        Some(token) => Ok(customer_repo.session(shop_id, &token, now()).await?),
+        None => Ok(None),
+    }
+}
+
+pub struct CustomerSession {
+    pub shop: Shop,
+    pub customer: Customer,
+}
+
+impl FromRequest for CustomerSession {
+    type Error = actix_web::Error;
+    type Future = futures_util::future::LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let missing = |name: &str| {
+                ControllerError::from(anyhow::anyhow!("Unable to extract {name} from request"))
+            };
+            let shop_service =
+                Data::<actix::Addr<rt_types::shop::service::ShopService>>::extract(&req)
+                    .await
+                    .map_err(|_| missing("ShopService"))?;
+            let shop_product_repo =
+                Data::<Arc<dyn shop_product::ShopProductRepository>>::extract(&req)
+                    .await
+                    .map_err(|_| missing("ShopProductRepository"))?;
+            let product_category_repo =
+                Data::<Arc<dyn product_category::ProductCategoryRepository>>::extract(&req)
+                    .await
+                    .map_err(|_| missing("ProductCategoryRepository"))?;
+            let customer_repo = Data::<Arc<dyn CustomerRepository>>::extract(&req)
+                .await
+                .map_err(|_| missing("CustomerRepository"))?;
+            let shop = resolve_site_shop(
+                &req,
+                &shop_service,
+                &shop_product_repo,
+                &product_category_repo,
+            )
+            .await?;
+            if let Err(e) = check_api_rate_limit(&req).await {
+                return Err(ControllerError::TooManyRequests {
+                    retry_after: e.retry_after,
+                    message: e.message,
+                }
+                .into());
+            }
+            let customer = match shop.as_ref() {
+                Some(shop) => customer_from_request(&req, &customer_repo, shop.id).await?,
+                None => None,
+            };
+            match (shop, customer) {
+                (Some(shop), Some(customer)) => Ok(Self { shop, customer }),
+                _ => Err(actix_web::error::InternalError::from_response(
+                    "unauthorized",
+                    HttpResponse::Unauthorized()
+                        .json(serde_json::json!({ "ok": false, "error": "unauthorized" })),
+                )
+                .into()),
+            }
+        })
+    }
+}
+
+async fn public_shop(
+    req: &HttpRequest,
+    shop_service: &actix::Addr<rt_types::shop::service::ShopService>,
+    shop_product_repo: &Arc<dyn shop_product::ShopProductRepository>,
+    product_category_repo: &Arc<dyn product_category::ProductCategoryRepository>,
+) -> Result<Option<Shop>, ControllerError> {
+    let shop =
+        resolve_site_shop(req, shop_service, shop_product_repo, product_category_repo).await?;
+    if let Err(e) = check_api_rate_limit(req).await {
+        return Err(ControllerError::TooManyRequests {
+            retry_after: e.retry_after,
+            message: e.message,
+        });
+    }
+    Ok(shop)
+}
+
+#[derive(Serialize)]
+pub struct CustomerDto {
+    pub id: i64,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub has_password: bool,
+    pub created_at: i64,
+}
+
+impl From<&Customer> for CustomerDto {
+    fn from(c: &Customer) -> Self {
+        Self {
+            id: c.id,
+            phone: c.phone.clone(),
+            email: c.email.clone(),
+            name: c.name.clone(),
+            has_password: c.password.is_some(),
+            created_at: c.created_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AddressDto {
+    pub id: i64,
+    pub label: Option<String>,
+    pub recipient: Option<String>,
+    pub phone: Option<String>,
+    pub delivery: String,
+    pub city_name: Option<String>,
+    pub city_ref: Option<String>,
+    pub branch_name: Option<String>,
+    pub branch_ref: Option<String>,
+    pub street: Option<String>,
+    pub is_default: bool,
+}
+
+impl From<customer::CustomerAddress> for AddressDto {
+    fn from(a: customer::CustomerAddress) -> Self {
+        Self {
+            id: a.id,
+            label: a.label,
+            recipient: a.recipient,
+            phone: a.phone,
+            delivery: a.delivery,
+            city_name: a.city_name,
+            city_ref: a.city_ref,
+            branch_name: a.branch_name,
+            branch_ref: a.branch_ref,
+            street: a.street,
+            is_default: a.is_default,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CustomerOrderDto {
+    pub id: i64,
+    pub status: String,
+    pub total: i64,
+    pub items: Vec<order::OrderItem>,
+    pub delivery: String,
+    pub city_name: Option<String>,
+    pub branch_name: Option<String>,
+    pub payment: String,
+    pub created_at: i64,
+    pub waybill: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FavoriteDto {
+    pub article: String,
+    pub created_at: i64,
+    pub product: Option<ProductDto>,
+}
+
+#[derive(Deserialize)]
+pub struct OtpRequest {
+    pub phone: String,
+}
+
+#[derive(Deserialize)]
+pub struct OtpVerifyRequest {
+    pub phone: String,
+    pub code: String,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    pub email: String,
+    pub password: String,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+// Зміна email чи пароля потребує `current_password` або свіжий `code` для акаунтів без пароля
+#[derive(Deserialize)]
+pub struct ProfileRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub current_password: Option<String>,
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AddressRequest {
+    pub label: Option<String>,
+    pub recipient: Option<String>,
+    pub phone: Option<String>,
+    pub delivery: String,
+    pub city_name: Option<String>,
+    pub city_ref: Option<String>,
+    pub branch_name: Option<String>,
+    pub branch_ref: Option<String>,
+    pub street: Option<String>,
+    pub is_default: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct FavoriteRequest {
+    pub article: String,
+}
+
+#[derive(Deserialize)]
+pub struct CustomerOrdersQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+async fn signed_in(customer_repo: &Arc<dyn CustomerRepository>, customer: &Customer) -> Response {
+    let token = customer_repo.create_session(customer, now()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "ok": true,
+        "token": token,
+        "customer": CustomerDto::from(customer)
+    })))
+}
+
+async fn hash_password(password: String) -> Result<String, ControllerError> {
+    Ok(
+        actix_web::web::block(move || customer::hash_password(&password))
+            .await
+            .map_err(|err| anyhow::anyhow!(err))??,
+    )
+}
+
+fn password_too_short(password: &str) -> bool {
+    password.chars().count() < rt_types::access::MIN_PASSWORD_LENGTH as usize
+}
+
+async fn verify_password(hash: Option<String>, password: String) -> Result<bool, ControllerError> {
+    Ok(actix_web::web::block(move || {
+        customer::verify_password_or_dummy(hash.as_deref(), &password)
+    })
+    .await
+    .map_err(|err| anyhow::anyhow!(err))?)
+}
+
+fn otp_error(check: OtpCheck) -> Option<HttpResponse> {
+    match check {
+        OtpCheck::Valid => None,
+        OtpCheck::Invalid => Some(error("invalid_code")),
+        OtpCheck::Missing => Some(error("code_expired")),
+        OtpCheck::TooManyAttempts => Some(error("too_many_attempts")),
+    }
+}
+
+#[post("/api/site/customer/otp")]
+pub async fn request_otp(
+    req: HttpRequest,
+    payload: Json<OtpRequest>,
+    shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+    otp_sender: Data<Arc<dyn OtpSender>>,
+) -> Response {
+    let shop = match public_shop(
+        &req,
+        &shop_service,
+        &shop_product_repo,
+        &product_category_repo,
+    )
+    .await?
+    {
+        Some(shop) => shop,
+        None => return Ok(error("no_shop")),
+    };
+    let phone = match customer::normalize_phone(&payload.phone) {
+        Some(phone) => phone,
+        None => return Ok(error("invalid_phone")),
+    };
+    let code = customer::generate_otp();
+    match customer_repo
+        .issue_otp(shop.id, &phone, get_client_ip(&req).as_deref(), &code, now())
+        .await?
+    {
+        OtpIssue::Issued => (),
+        OtpIssue::TooSoon => {
+            return Ok(HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", customer::OTP_RESEND_SECS.to_string()))
+                .json(serde_json::json!({ "ok": false, "error": "otp_too_soon" })))
+        }
+        OtpIssue::LimitReached => {
+            return Ok(HttpResponse::TooManyRequests()
+                .json(serde_json::json!({ "ok": false, "error": "otp_limit" })))
+        }
+    }
+    otp_sender.send(&phone, &code).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "ok": true,
+        "expires_in": customer::OTP_TTL_SECS
+    })))
+}
+
+#[post("/api/site/customer/otp/verify")]
+pub async fn verify_otp(
+    req: HttpRequest,
+    payload: Json<OtpVerifyRequest>,
+    shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let shop = match public_shop(
+        &req,
+        &shop_service,
+        &shop_product_repo,
+        &product_category_repo,
+    )
+    .await?
+    {
+        Some(shop) => shop,
+        None => return Ok(error("no_shop")),
+    };
+    let phone = match customer::normalize_phone(&payload.phone) {
+        Some(phone) => phone,
+        None => return Ok(error("invalid_phone")),
+    };
+    let check = customer_repo
+        .check_otp(shop.id, &phone, &payload.code, now())
+        .await?;
+    if let Some(err) = otp_error(check) {
+        return Ok(err);
+    }
+    let customer = match customer_repo.find_by_phone(shop.id, &phone).await? {
+        Some(customer) => customer,
+        None => {
+            let added = customer_repo
+                .add(customer::NewCustomer {
+                    shop_id: shop.id,
+                    phone: Some(phone.clone()),
+                    email: None,
+                    password: None,
+                    name: non_empty(payload.name.as_deref()),
+                    created_at: now(),
+                })
+                .await;
+            match added {
+                Ok(customer) => customer,
+                // Створений паралельним входом з тим самим телефоном
+                Err(err) if customer::is_unique_violation(&err) => customer_repo
+                    .find_by_phone(shop.id, &phone)
+                    .await?
+                    .ok_or(err)?,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    };
+    signed_in(&customer_repo, &customer).await
+}
+
+#[post("/api/site/customer/register")]
+pub async fn register(
+    req: HttpRequest,
+    payload: Json<RegisterRequest>,
+    shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let shop = match public_shop(
+        &req,
+        &shop_service,
+        &shop_product_repo,
+        &product_category_repo,
+    )
+    .await?
+    {
+        Some(shop) => shop,
+        None => return Ok(error("no_shop")),
+    };
+    let email = match customer::normalize_email(&payload.email) {
+        Some(email) => email,
+        None => return Ok(error("invalid_email")),
+    };
+    if password_too_short(&payload.password) {
+        return Ok(error("password_too_short"));
+    }
+    if customer_repo
+        .find_by_email(shop.id, &email)
+        .await?
+        .is_some()
+    {
+        return Ok(error("email_taken"));
+    }
+    let password = hash_password(payload.password.clone()).await?;
+    let customer = customer_repo
+        .add(customer::NewCustomer {
+            shop_id: shop.id,
+            phone: None,
+            email: Some(email),
+            password: Some(password),
+            name: non_empty(payload.name.as_deref()),
+            created_at: now(),
+        })
+        .await;
+    match customer {
+        Ok(customer) => signed_in(&customer_repo, &customer).await,
+        // Зареєстрований паралельним запитом після перевірки вище
+        Err(err) if customer::is_unique_violation(&err) => Ok(error("email_taken")),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[post("/api/site/customer/login")]
+pub async fn login(
+    req: HttpRequest,
+    payload: Json<LoginRequest>,
+    shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let shop = match public_shop(
+        &req,
+        &shop_service,
+        &shop_product_repo,
+        &product_category_repo,
+    )
+    .await?
+    {
+        Some(shop) => shop,
+        None => return Ok(error("no_shop")),
+    };
+    let email = customer::normalize_email(&payload.email);
+    let account = format!("{}:{}", shop.id, email.as_deref().unwrap_or_default());
+    if let Err(e) = check_login_rate_limit(&req, &account).await {
+        return Err(ControllerError::TooManyRequests {
+            retry_after: e.retry_after,
+            message: e.message,
+        });
+    }
+    let customer = match email {
+        Some(email) => customer_repo.find_by_email(shop.id, &email).await?,
+        None => None,
+    };
+    // Невідомі email перевіряються на фіктивному хеші, щоб відповідь займала стільки ж часу
+    let hash = customer.as_ref().and_then(|c| c.password.clone());
+    let valid = verify_password(hash, payload.password.clone()).await?;
+    match customer {
+        Some(customer) if valid => signed_in(&customer_repo, &customer).await,
+        _ => Ok(error("invalid_credentials")),
+    }
+}
+
+#[post("/api/site/customer/logout")]
+pub async fn logout(
+    req: HttpRequest,
+    _session: CustomerSession,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    if let Some(token) = customer_token(&req) {
+        customer_repo.remove_session(&token).await?;
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "ok": true })))
+}
+
+#[get("/api/site/customer/me")]
+pub async fn me(CustomerSession { customer, .. }: CustomerSession) -> Response {
+    Ok(HttpResponse::Ok().json(CustomerDto::from(&customer)))
+}
+
+#[post("/api/site/customer/me")]
+pub async fn update_me(
+    req: HttpRequest,
+    CustomerSession { shop, mut customer }: CustomerSession,
+    payload: Json<ProfileRequest>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    if payload.email.is_some() || payload.password.is_some() {
+        match (customer.password.clone(), customer.phone.as_deref()) {
+            (Some(hash), _) => {
+                let Some(current) = payload.current_password.clone() else {
+                    return Ok(error("current_password_required"));
+                };
+                if !verify_password(Some(hash), current).await? {
+                    return Ok(error("invalid_credentials"));
+                }
+            }
+            (None, Some(phone)) => {
+                let Some(code) = payload.code.as_deref() else {
+                    return Ok(error("code_required"));
+                };
+                let check = customer_repo.check_otp(shop.id, phone, code, now()).await?;
+                if let Some(err) = otp_error(check) {
+                    return Ok(err);
+                }
+            }
+            (None, None) => return Ok(error("unauthorized")),
+        }
+    }
+    if let Some(name) = payload.name.as_deref() {
+        customer.name = non_empty(Some(name));
+    }
+    if let Some(email) = payload.email.as_deref() {
+        let email = match customer::normalize_email(email) {
+            Some(email) => email,
+            None => return Ok(error("invalid_email")),
+        };
+        let taken = customer_repo
+            .find_by_email(shop.id, &email)
+            .await?
+            .is_some_and(|other| other.id != customer.id);
+        if taken {
+            return Ok(error("email_taken"));
+        }
+        customer.email = Some(email);
+    }
+    let password_changed = match payload.password.clone() {
+        Some(password) => {
+            if password_too_short(&password) {
+                return Ok(error("password_too_short"));
+            }
+            customer.password = Some(hash_password(password).await?);
+            true
+        }
+        None => false,
+    };
+    if customer.password.is_some() && customer.email.is_none() {
+        return Ok(error("email_required"));
+    }
+    customer.updated_at = now();
+    let customer = customer_repo.update(customer).await?;
+    if let (true, Some(token)) = (password_changed, customer_token(&req)) {
+        customer_repo
+            .remove_other_sessions(customer.id, &token)
+            .await?;
+    }
+    Ok(HttpResponse::Ok().json(CustomerDto::from(&customer)))
+}
+
+#[get("/api/site/customer/orders")]
+pub async fn orders(
+    CustomerSession { shop, customer }: CustomerSession,
+    params: Query<CustomerOrdersQuery>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+) -> Response {
+    let page = order_repo
+        .list_by_shop(
+            shop.id,
+            order::OrderFilter {
+                customer_id: Some(customer.id),
+                limit: params.limit.unwrap_or(20).clamp(1, 100),
+                offset: params.offset.unwrap_or(0),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let items = page
+        .items
+        .into_iter()
+        .map(|o| CustomerOrderDto {
+            id: o.id,
+            status: o.status.as_str().to_string(),
+            total: o.total,
+            items: o.items().unwrap_or_default(),
+            delivery: o.delivery,
+            city_name: o.city_name,
+            branch_name: o.branch_name,
+            payment: o.payment,
+            created_at: o.created_at,
+            waybill: o.waybill.map(|w| w.number),
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok()
+        .insert_header(("X-Total-Count", page.total.to_string()))
+        .json(items))
+}
+
+#[get("/api/site/customer/addresses")]
+pub async fn addresses(
+    CustomerSession { customer, .. }: CustomerSession,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let items = customer_repo
+        .addresses(customer.id)
+        .await?
+        .into_iter()
+        .map(AddressDto::from)
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(items))
+}
+
+#[post("/api/site/customer/addresses")]
+pub async fn add_address(
+    CustomerSession { customer, .. }: CustomerSession,
+    payload: Json<AddressRequest>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let payload = payload.into_inner();
+    let delivery = payload.delivery.trim().to_string();
+    if !customer::ADDRESS_DELIVERIES.contains(&delivery.as_str()) {
+        return Ok(error("invalid_delivery"));
+    }
+    let city_name = non_empty(payload.city_name.as_deref());
+    let branch_name = non_empty(payload.branch_name.as_deref());
+    let street = non_empty(payload.street.as_deref());
+    let complete = city_name.is_some()
+        && match delivery.as_str() {
+            "nova-poshta-branch" => branch_name.is_some(),
+            _ => street.is_some(),
+        };
+    if !complete {
+        return Ok(error("incomplete_address"));
+    }
+    let phone = match non_empty(payload.phone.as_deref()) {
+        Some(phone) => match customer::normalize_phone(&phone) {
+            Some(phone) => Some(phone),
+            None => return Ok(error("invalid_phone")),
+        },
+        None => None,
+    };
+    let item = customer_repo
+        .add_address(
+            customer.id,
+            customer::NewAddress {
+                label: non_empty(payload.label.as_deref()),
+                recipient: non_empty(payload.recipient.as_deref()),
+                phone,
+                delivery,
+                city_name,
+                city_ref: non_empty(payload.city_ref.as_deref()),
+                branch_name,
+                branch_ref: non_empty(payload.branch_ref.as_deref()),
+                street,
+                is_default: payload.is_default.unwrap_or(false),
+                created_at: now(),
+            },
+        )
+        .await?;
+    Ok(HttpResponse::Ok().json(AddressDto::from(item)))
+}
+
+#[post("/api/site/customer/addresses/{id}/delete")]
+pub async fn remove_address(
+    CustomerSession { customer, .. }: CustomerSession,
+    path: Path<i64>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    if !customer_repo
+        .remove_address(customer.id, path.into_inner())
+        .await?
+    {
+        return Err(ControllerError::NotFound);
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "ok": true })))
+}
+
+#[get("/api/site/customer/favorites")]
+pub async fn favorites(
+    CustomerSession { shop, customer }: CustomerSession,
+    dt_repo: Data<Arc<dyn dt::product::ProductRepository + Send>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    category_repo: Data<Arc<dyn CategoryRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let allowed_suppliers = site_publish::load_site_publish_suppliers(&shop.id);
+    let (products, by_article) = load_site_products_cached(
+        &shop,
+        &allowed_suppliers,
+        &dt_repo,
+        &shop_product_repo,
+        &category_repo,
+        &product_category_repo,
+    )
+    .await;
+    let items = customer_repo
+        .favorites(customer.id)
+        .await?
+        .into_iter()
+        .map(|f| FavoriteDto {
+            product: by_article
+                .get(&f.article.to_lowercase())
+                .and_then(|idx| products.get(*idx))
+                .map(|p| p.dto.clone()),
+            article: f.article,
+            created_at: f.created_at,
+        })
+        .collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(items))
+}
+
+#[post("/api/site/customer/favorites")]
+pub async fn add_favorite(
+    CustomerSession { shop, customer }: CustomerSession,
+    payload: Json<FavoriteRequest>,
+    dt_repo: Data<Arc<dyn dt::product::ProductRepository + Send>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    category_repo: Data<Arc<dyn CategoryRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    let allowed_suppliers = site_publish::load_site_publish_suppliers(&shop.id);
+    let (products, by_article) = load_site_products_cached(
+        &shop,
+        &allowed_suppliers,
+        &dt_repo,
+        &shop_product_repo,
+        &category_repo,
+        &product_category_repo,
+    )
+    .await;
+    let article = match by_article
+        .get(&payload.article.trim().to_lowercase())
+        .and_then(|idx| products.get(*idx))
+    {
+        Some(product) => product.dto.article.clone(),
+        None => return Ok(error("unknown_article")),
+    };
+    customer_repo
+        .add_favorite(customer.id, &article, now())
+        .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "ok": true, "article": article })))
+}
+
+#[post("/api/site/customer/favorites/{article}/delete")]
+pub async fn remove_favorite(
+    CustomerSession { customer, .. }: CustomerSession,
+    path: Path<String>,
+    customer_repo: Data<Arc<dyn CustomerRepository>>,
+) -> Response {
+    customer_repo
+        .remove_favorite(customer.id, path.trim())
+        .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "ok": true })))
+}
@@ -3,9 +3,15 @@ use crate::export::{
     self, AddExportPermission, Export, ExportService, ExportStatus, UpdateExportEntryPermission,
 };
 use crate::category_auto;
+use crate::customer;
+use crate::export_diff;
+use crate::nova_poshta;
+use crate::export_history;
 use crate::product_category;
 use crate::product_category_auto;
 use crate::quick_order;
+use crate::review;
+use crate::scheduler::{self, JobScheduler};
 use crate::seo_page;
 use crate::order;
 use crate::shop_product;
@@ -13,6 +19,8 @@ use crate::site_import;
 use crate::site_publish;
 use crate::ddaudio;
 use crate::ddaudio_import;
+use crate::rozetka;
+use crate::uploader;
 use crate::watermark::WatermarkOptionsDto;
 use crate::{dt, tt};
 use actix::fut::{ready, Ready};
@@ -44,9 +52,11 @@ use rt_types::category::{
 };
 use rt_types::shop::{self, service::ShopService, SiteImportEntry};
 use rt_types::shop::{
-    Discount, DtParsingOptions, ExportEntry, ExportEntryLink, ExportOptions, FileFormat,
-    ParsingCategoriesAction, Shop, TtParsingOptions,
+    Discount, DtParsingOptions, ExportEntry, ExportEntryLink, ExportGuards, ExportOptions,
+    FileFormat, MissingProductAction, ParsingCategoriesAction, PromUploadOptions, Shop,
+    TtParsingOptions, PROM_UPDATABLE_FIELDS,
 };
+use rt_types::pricing::{self, PriceSubject, PricingRule};
 use rt_types::subscription::{self, service::SubscriptionService, Subscription};
 use rt_types::watermark::{WatermarkGroup, WatermarkGroupRepository};
 use rt_types::Availability;
@@ -79,7 +89,9 @@ use url::form_urlencoded;
 use uuid::Uuid;
 
 pub mod catalog;
+pub mod customer_api;
 pub mod landing;
+pub mod nova_poshta_api;
 pub mod product;
 pub mod restal_api;
 pub mod site_api;
@@ -713,6 +725,86 @@ async fn export_status_json(
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(Deserialize)]
+struct PricingPreviewRequest {
+    #[serde(default)]
+    rules: Vec<PricingRule>,
+    adjust_price: Option<Decimal>,
+    supplier: Option<String>,
+    article: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct PricingPreviewRow {
+    article: String,
+    title: String,
+    supplier: Option<String>,
+    brand: String,
+    category: Option<String>,
+    source_price: Decimal,
+    current_price: Decimal,
+    price: Decimal,
+}
+
+#[post("/shop/{shop_id}/pricing/preview")]
+async fn pricing_preview(
+    Json(req): Json<PricingPreviewRequest>,
+    dt_repo: Data<Arc<dyn dt::product::ProductRepository + Send>>,
+    ShopAccess { .. }: ShopAccess,
+) -> Response {
+    let limit = req.limit.unwrap_or(50).clamp(1, 500);
+    let supplier = req
+        .supplier
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_lowercase);
+    let article = req
+        .article
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_lowercase);
+    let rules = pricing::with_adjust_price(req.adjust_price, &req.rules);
+    let rows: Vec<_> = dt_repo
+        .list()
+        .await?
+        .into_iter()
+        .filter(|p| match &supplier {
+            Some(s) => p.supplier.as_ref().is_some_and(|x| x.to_lowercase() == *s),
+            None => true,
+        })
+        .filter(|p| match &article {
+            Some(a) => p.article.to_lowercase().contains(a.as_str()),
+            None => true,
+        })
+        .filter_map(|p| {
+            let current = Decimal::from(p.price? as u64);
+            let source_price = p.source_price.map(Decimal::from).unwrap_or(current);
+            let subject = PriceSubject {
+                vendor: p.supplier.as_deref().unwrap_or_default(),
+                category: p.category.as_deref(),
+                brand: &p.brand,
+                article: &p.article,
+            };
+            let price = pricing::apply_rules(&rules, &subject, source_price, current);
+            Some(PricingPreviewRow {
+                article: p.article,
+                title: p.title,
+                supplier: p.supplier,
+                brand: p.brand,
+                category: p.category,
+                source_price,
+                current_price: current,
+                price,
+            })
+        })
+        .take(limit)
+        .collect();
+    Ok(HttpResponse::Ok().json(rows))
+}
+
 #[get("/shop/{shop_id}")]
 async fn index(
     export_service: Data<Arc<Addr<export::ExportService>>>,
@@ -729,14 +821,7 @@ async fn index(
         .into_iter()
         .map(|(h, e)| {
             let mut f = vec![];
-            let formats = [
-                FileFormat::Csv,
-                FileFormat::Xml,
-                FileFormat::Xlsx,
-                FileFormat::HoroshopCsv,
-                FileFormat::HoroshopCategories,
-            ];
-            for format in formats {
+            for format in FileFormat::ALL.iter().cloned() {
                 let info = file_info(format!(
                     "export/{shop_id}/{}",
                     e.entry.file_name(format.clone())
@@ -911,6 +996,122 @@ async fn control_panel_dt_products(
     })
 }
 
+#[derive(Template)]
+#[template(path = "control_panel/dt_product_history.html")]
+pub struct DtProductHistoryPage {
+    article: String,
+    title: Option<String>,
+    from: String,
+    to: String,
+    rows: Vec<DtHistoryRow>,
+    chart_points: String,
+    user: UserCredentials,
+}
+
+pub struct DtHistoryRow {
+    changed_at: String,
+    price: Option<usize>,
+    price_delta: Option<i64>,
+    source_price: Option<usize>,
+    available: String,
+    available_changed: bool,
+    quantity: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct DtProductHistoryQuery {
+    article: String,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+fn parse_date_param(value: Option<&str>) -> Option<time::Date> {
+    let value = value.map(str::trim).filter(|v| !v.is_empty())?;
+    let format = time::format_description::parse("[year]-[month]-[day]").ok()?;
+    time::Date::parse(value, &format).ok()
+}
+
+fn price_chart_points(prices: &[Option<usize>], width: f64, height: f64) -> String {
+    let known = prices.iter().flatten().copied().collect::<Vec<_>>();
+    let (Some(min), Some(max)) = (known.iter().min(), known.iter().max()) else {
+        return String::new();
+    };
+    let span = (max - min).max(1) as f64;
+    let step = if prices.len() > 1 {
+        width / (prices.len() - 1) as f64
+    } else {
+        0.
+    };
+    prices
+        .iter()
+        .enumerate()
+        .filter_map(|(i, price)| {
+            let y = height - (price.as_ref()? - min) as f64 / span * height;
+            Some(format!("{:.1},{:.1}", i as f64 * step, y))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[get("/control_panel/dt/history")]
+async fn control_panel_dt_product_history(
+    dt_repo: Data<Arc<dyn dt::product::ProductRepository + Send>>,
+    ControlPanelAccess { user }: ControlPanelAccess,
+    query: Query<DtProductHistoryQuery>,
+) -> Response {
+    let params = query.into_inner();
+    let article = params.article.trim().to_string();
+    if article.is_empty() {
+        return Err(ControllerError::InvalidInput {
+            field: "article".to_string(),
+            msg: "Вкажіть артикул".to_string(),
+        });
+    }
+    let from = parse_date_param(params.from.as_deref());
+    let to = parse_date_param(params.to.as_deref());
+    let entries = dt_repo
+        .history_by_article(
+            &article,
+            from.map(|d| d.midnight().assume_utc()),
+            to.map(|d| d.midnight().assume_utc() + time::Duration::days(1)),
+        )
+        .await?;
+    let title = dt_repo.get_one(&article).await?.map(|p| p.title);
+
+    let now = OffsetDateTime::now_utc();
+    let mut rows = Vec::with_capacity(entries.len());
+    let mut previous: Option<&dt::product::ProductHistoryEntry> = None;
+    for entry in entries.iter() {
+        let price_delta = match (previous.and_then(|p| p.price), entry.price) {
+            (Some(before), Some(after)) if before != after => Some(after as i64 - before as i64),
+            _ => None,
+        };
+        rows.push(DtHistoryRow {
+            changed_at: format_dt_last_visited(entry.changed_at, now),
+            price: entry.price,
+            price_delta,
+            source_price: entry.source_price,
+            available: entry.available.to_string(),
+            available_changed: previous.is_some_and(|p| p.available != entry.available),
+            quantity: entry.quantity,
+        });
+        previous = Some(entry);
+    }
+    let prices = entries.iter().map(|e| e.price).collect::<Vec<_>>();
+    let chart_points = price_chart_points(&prices, 600., 120.);
+    rows.reverse();
+
+    render_template(DtProductHistoryPage {
+        article,
+        title,
+        from: from.map(|d| d.to_string()).unwrap_or_default(),
+        to: to.map(|d| d.to_string()).unwrap_or_default(),
+        rows,
+        chart_points,
+        user,
+    })
+}
+
 #[derive(Template)]
 #[template(path = "shop/products.html")]
 pub struct ShopProductsPage {
@@ -1007,18 +1208,84 @@ struct OrderItemView {
 
 struct OrderView {
     id: i64,
+    status: order::OrderStatus,
     customer_name: String,
     phone: String,
     email: Option<String>,
     delivery: String,
     city_name: Option<String>,
     branch_name: Option<String>,
+    has_branch_ref: bool,
     payment: String,
     total: i64,
     items_count: usize,
     items: Vec<OrderItemView>,
     comment: Option<String>,
     created_at: String,
+    waybill: Option<WaybillView>,
+}
+
+struct WaybillView {
+    number: String,
+    status: Option<String>,
+    updated_at: String,
+}
+
+impl OrderView {
+    fn from_order(item: order::Order) -> Self {
+        let parsed_items = match item.items() {
+            Ok(items) => items,
+            Err(err) => {
+                log::warn!("Unable to parse order items for {}: {}", item.id, err);
+                Vec::new()
+            }
+        };
+        let items = parsed_items
+            .into_iter()
+            .map(|order_item| OrderItemView {
+                article: order_item.article,
+                title: order_item.title,
+                price: order_item.price,
+                quantity: order_item.quantity,
+            })
+            .collect::<Vec<_>>();
+        OrderView {
+            id: item.id,
+            status: item.status,
+            customer_name: item.customer_name,
+            phone: item.phone,
+            email: item.email,
+            delivery: format_delivery(&item.delivery),
+            city_name: item.city_name,
+            branch_name: item.branch_name,
+            has_branch_ref: item.city_ref.is_some() && item.branch_ref.is_some(),
+            payment: format_payment(&item.payment),
+            total: item.total,
+            items_count: item.items_count,
+            items,
+            comment: item.comment,
+            created_at: format_unix_timestamp(item.created_at),
+            waybill: item.waybill.map(|w| WaybillView {
+                number: w.number,
+                status: w.status,
+                updated_at: format_unix_timestamp(w.updated_at),
+            }),
+        }
+    }
+}
+
+struct OrderHistoryView {
+    from_status: Option<order::OrderStatus>,
+    to_status: order::OrderStatus,
+    author: String,
+    comment: Option<String>,
+    created_at: String,
+}
+
+struct OrderEventView {
+    author: String,
+    comment: String,
+    created_at: String,
 }
 
 #[derive(Template)]
@@ -1035,6 +1302,25 @@ struct ShopOrdersPage {
     shop: Shop,
     user: UserCredentials,
     items: Vec<OrderView>,
+    statuses: Vec<order::OrderStatus>,
+    status_filter: String,
+    query: String,
+    customer_filter: String,
+    total_items: usize,
+    page_links: Vec<PageLink>,
+}
+
+#[derive(Template)]
+#[template(path = "shop/order.html")]
+struct ShopOrderPage {
+    shop: Shop,
+    user: UserCredentials,
+    item: OrderView,
+    history: Vec<OrderHistoryView>,
+    events: Vec<OrderEventView>,
+    next_statuses: Vec<order::OrderStatus>,
+    items_editable: bool,
+    payers: [shop::NovaPoshtaPayer; 2],
 }
 
 #[derive(Template)]
@@ -1042,6 +1328,71 @@ struct ShopOrdersPage {
 struct ShopUsersPage {
     shop: Shop,
     user: UserCredentials,
+    items: Vec<CustomerView>,
+    query: String,
+    total_items: usize,
+    page_links: Vec<PageLink>,
+}
+
+struct CustomerView {
+    id: i64,
+    name: Option<String>,
+    phone: Option<String>,
+    email: Option<String>,
+    orders: usize,
+    total: i64,
+    last_order_at: Option<String>,
+    created_at: String,
+}
+
+struct ReviewView {
+    id: i64,
+    status: review::ReviewStatus,
+    product_key: Option<String>,
+    name: String,
+    text: String,
+    rating: i64,
+    photos: Vec<String>,
+    reply: Option<String>,
+    created_at: String,
+}
+
+impl From<review::Review> for ReviewView {
+    fn from(item: review::Review) -> Self {
+        Self {
+            id: item.id,
+            status: item.status,
+            product_key: item.product_key,
+            name: item.name,
+            text: item.text,
+            rating: item.rating,
+            photos: item.photos,
+            reply: item.reply,
+            created_at: format_unix_timestamp(item.created_at),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "shop/reviews.html")]
+struct ShopReviewsPage {
+    shop: Shop,
+    user: UserCredentials,
+    items: Vec<ReviewView>,
+    statuses: Vec<review::ReviewStatus>,
+    status_filter: String,
+    query: String,
+    current_page: usize,
+    total_items: usize,
+    page_links: Vec<PageLink>,
+}
+
+#[derive(Template)]
+#[template(path = "shop/review.html")]
+struct ShopReviewPage {
+    shop: Shop,
+    user: UserCredentials,
+    item: ReviewView,
 }
 
 pub struct PageLink {
@@ -1682,67 +2033,704 @@ async fn shop_quick_order_delete(
     Ok(see_other(&format!("/shop/{}/crm/quick_orders", shop.id)))
 }
 
+#[derive(Deserialize)]
+pub struct ShopOrdersQuery {
+    pub status: Option<String>,
+    pub q: Option<String>,
+    pub page: Option<String>,
+    pub customer: Option<String>,
+}
+
 #[get("/shop/{shop_id}/crm/orders")]
 async fn shop_orders_page(
     ShopAccess { shop, user }: ShopAccess,
+    params: Query<ShopOrdersQuery>,
     order_repo: Data<Arc<dyn order::OrderRepository>>,
 ) -> Response {
-    let items = order_repo
-        .list_by_shop(shop.id)
+    const PER_PAGE: usize = 50;
+    let status = params
+        .status
+        .as_deref()
+        .and_then(|s| s.parse::<order::OrderStatus>().ok());
+    let query = normalize_string(params.q.clone());
+    let customer_id = params
+        .customer
+        .as_deref()
+        .and_then(|c| c.trim().parse::<i64>().ok());
+    let mut page = parse_usize_param(params.page.as_deref()).unwrap_or(1).max(1);
+    let filter = |page: usize| order::OrderFilter {
+        status,
+        query: query.clone(),
+        customer_id,
+        limit: PER_PAGE,
+        offset: (page - 1) * PER_PAGE,
+        ..Default::default()
+    };
+    let mut result = order_repo.list_by_shop(shop.id, filter(page)).await?;
+    let total_pages = result.total.div_ceil(PER_PAGE).max(1);
+    if page > total_pages {
+        page = total_pages;
+        result = order_repo.list_by_shop(shop.id, filter(page)).await?;
+    }
+
+    let build_url = |p: usize| {
+        let mut qs = form_urlencoded::Serializer::new(String::new());
+        if let Some(status) = status {
+            qs.append_pair("status", status.as_str());
+        }
+        if let Some(q) = query.as_ref() {
+            qs.append_pair("q", q);
+        }
+        if let Some(customer_id) = customer_id {
+            qs.append_pair("customer", &customer_id.to_string());
+        }
+        qs.append_pair("page", &p.to_string());
+        format!("/shop/{}/crm/orders?{}", shop.id, qs.finish())
+    };
+    let page_links = build_pagination_items(page, total_pages)
+        .into_iter()
+        .map(|item| match item {
+            PaginationItem::Page(p) => PageLink {
+                label: p.to_string(),
+                url: Some(build_url(p)),
+                current: p == page,
+            },
+            PaginationItem::Gap => PageLink {
+                label: "...".to_string(),
+                url: None,
+                current: false,
+            },
+        })
+        .collect();
+
+    let items = result.items.into_iter().map(OrderView::from_order).collect();
+    render_template(ShopOrdersPage {
+        shop,
+        user,
+        items,
+        statuses: order::OrderStatus::ALL.to_vec(),
+        status_filter: status.map(|s| s.as_str().to_string()).unwrap_or_default(),
+        query: query.unwrap_or_default(),
+        customer_filter: customer_id.map(|c| c.to_string()).unwrap_or_default(),
+        total_items: result.total,
+        page_links,
+    })
+}
+
+#[get("/shop/{shop_id}/crm/orders/{id}")]
+async fn shop_order_page(
+    ShopAccess { shop, user }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    let item = order_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    let history = order_repo
+        .history(shop.id, id)
         .await?
         .into_iter()
-        .map(|item| {
-            let parsed_items = match serde_json::from_str::<Vec<order::OrderItem>>(&item.items_json)
-            {
-                Ok(items) => items,
-                Err(err) => {
-                    log::warn!("Unable to parse order items for {}: {}", item.id, err);
-                    Vec::new()
-                }
-            };
-            let items = parsed_items
-                .into_iter()
-                .map(|order_item| OrderItemView {
-                    article: order_item.article,
-                    title: order_item.title,
-                    price: order_item.price,
-                    quantity: order_item.quantity,
-                })
-                .collect::<Vec<_>>();
-            OrderView {
-                id: item.id,
-                customer_name: item.customer_name,
-                phone: item.phone,
-                email: item.email,
-                delivery: format_delivery(&item.delivery),
-                city_name: item.city_name,
-                branch_name: item.branch_name,
-                payment: format_payment(&item.payment),
-                total: item.total,
-                items_count: item.items_count,
-                items,
-                comment: item.comment,
-                created_at: format_unix_timestamp(item.created_at),
+        .map(|h| OrderHistoryView {
+            from_status: h.from_status,
+            to_status: h.to_status,
+            author: h.author,
+            comment: h.comment,
+            created_at: format_unix_timestamp(h.created_at),
+        })
+        .collect();
+    let events = order_repo
+        .events(shop.id, id)
+        .await?
+        .into_iter()
+        .map(|e| OrderEventView {
+            author: e.author,
+            comment: e.comment,
+            created_at: format_unix_timestamp(e.created_at),
+        })
+        .collect();
+    let next_statuses = item.status.next().to_vec();
+    let items_editable = item.status.items_editable();
+    render_template(ShopOrderPage {
+        shop,
+        user,
+        item: OrderView::from_order(item),
+        history,
+        events,
+        next_statuses,
+        items_editable,
+        payers: shop::NovaPoshtaPayer::ALL,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct OrderStatusForm {
+    pub status: String,
+    pub comment: Option<String>,
+}
+
+#[post("/shop/{shop_id}/crm/orders/{id}/status")]
+async fn shop_order_status(
+    ShopAccess { shop, user }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    Form(form): Form<OrderStatusForm>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    let status = form
+        .status
+        .parse::<order::OrderStatus>()
+        .map_err(|err| ControllerError::InvalidInput {
+            field: "status".to_string(),
+            msg: err.to_string(),
+        })?;
+    let current = order_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    if !current.status.can_transition_to(status) {
+        return Err(ControllerError::InvalidInput {
+            field: "status".to_string(),
+            msg: order::OrderError::InvalidTransition {
+                from: current.status,
+                to: status,
+            }
+            .to_string(),
+        });
+    }
+    order_repo
+        .change_status(
+            shop.id,
+            id,
+            order::StatusChange {
+                status,
+                author: user.login.to_string(),
+                comment: normalize_string(form.comment),
+                created_at: OffsetDateTime::now_utc().unix_timestamp(),
+            },
+        )
+        .await?;
+    Ok(see_other(&format!("/shop/{}/crm/orders/{id}", shop.id)))
+}
+
+#[derive(Deserialize)]
+pub struct OrderWaybillForm {
+    pub weight: Option<String>,
+    pub seats: Option<usize>,
+    pub declared_cost: Option<i64>,
+    pub description: Option<String>,
+    pub payer: Option<String>,
+}
+
+#[post("/shop/{shop_id}/crm/orders/{id}/waybill")]
+async fn shop_order_waybill(
+    ShopAccess { shop, user }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    Form(form): Form<OrderWaybillForm>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+    np_client: Data<nova_poshta::NovaPoshtaClient>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    let invalid = |msg: &str| ControllerError::InvalidInput {
+        field: "waybill".to_string(),
+        msg: msg.to_string(),
+    };
+    let current = order_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    if current.waybill.is_some() {
+        return Err(invalid("ТТН для замовлення вже створено"));
+    }
+    if !current.status.shippable() {
+        return Err(invalid(&format!(
+            "Замовлення у статусі «{}» не можна відправити",
+            current.status.label()
+        )));
+    }
+    let settings = &shop.nova_poshta;
+    let api_key = match settings.api_key.as_deref() {
+        Some(key) if settings.is_configured() => key,
+        _ => return Err(invalid("Нову Пошту не налаштовано для магазину")),
+    };
+    let weight = form
+        .weight
+        .map(|w| w.trim().replace(',', "."))
+        .filter(|w| !w.is_empty())
+        .map(|w| Decimal::from_str(&w))
+        .transpose()
+        .map_err(|_| invalid("Некоректна вага"))?
+        .or(settings.default_weight)
+        .unwrap_or(Decimal::ONE);
+    let payer = match normalize_string(form.payer) {
+        Some(payer) => payer.parse().map_err(|_| invalid("Невідомий платник"))?,
+        None => settings.payer,
+    };
+    let parcel = nova_poshta::ParcelOptions {
+        weight,
+        seats: form.seats.unwrap_or(1),
+        declared_cost: form.declared_cost.unwrap_or(current.total),
+        description: normalize_string(form.description)
+            .unwrap_or_else(|| format!("Замовлення #{id}")),
+        payer,
+    };
+    let client = np_client.with_key(api_key);
+    let waybill = nova_poshta::create_waybill(&client, settings, &current, &parcel)
+        .await
+        .map_err(|err| invalid(&err.to_string()))?;
+    let document_ref = waybill.document_ref.clone();
+    // Замовлення могли відправити чи дати йому ТТН паралельно, тоді нову не зберігаємо
+    if let Err(err) = order_repo
+        .set_waybill(shop.id, id, waybill, user.login.to_string())
+        .await
+    {
+        client
+            .delete_waybill(&document_ref)
+            .await
+            .log_error("Unable to delete unused waybill");
+        return Err(match order::order_error(&err) {
+            Some(order::OrderError::WaybillExists(_)) => invalid("ТТН для замовлення вже створено"),
+            Some(order::OrderError::NotShippable(status)) => invalid(&format!(
+                "Замовлення у статусі «{}» не можна відправити",
+                status.label()
+            )),
+            _ => ControllerError::InternalServerError(err),
+        });
+    }
+    Ok(see_other(&format!("/shop/{}/crm/orders/{id}", shop.id)))
+}
+
+#[post("/shop/{shop_id}/crm/orders/{id}/waybill/refresh")]
+async fn shop_order_waybill_refresh(
+    ShopAccess { shop, .. }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+    np_client: Data<nova_poshta::NovaPoshtaClient>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    let current = order_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    let (Some(waybill), Some(api_key)) = (&current.waybill, &shop.nova_poshta.api_key) else {
+        return Err(ControllerError::NotFound);
+    };
+    let phone = nova_poshta::api_phone(&current.phone).unwrap_or_default();
+    let statuses = np_client
+        .with_key(api_key)
+        .track(&[(waybill.number.clone(), phone)])
+        .await
+        .map_err(|err| ControllerError::InvalidInput {
+            field: "waybill".to_string(),
+            msg: err.to_string(),
+        })?;
+    if let Some(status) = statuses.first() {
+        nova_poshta::apply_status(order_repo.get_ref().as_ref(), &current, status).await?;
+    }
+    Ok(see_other(&format!("/shop/{}/crm/orders/{id}", shop.id)))
+}
+
+fn parse_order_items_form(body: &[u8]) -> Result<Vec<order::OrderItem>, ControllerError> {
+    let mut params = HashMap::<String, Vec<String>>::new();
+    for (key, value) in form_urlencoded::parse(body) {
+        params.entry(key.into_owned()).or_default().push(value.into_owned());
+    }
+    let column = |key: &str| params.get(key).cloned().unwrap_or_default();
+    let (articles, titles, prices, quantities) = (
+        column("article"),
+        column("title"),
+        column("price"),
+        column("quantity"),
+    );
+    let mut items = Vec::new();
+    for (idx, article) in articles.iter().enumerate() {
+        let article = article.trim();
+        let quantity = quantities
+            .get(idx)
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<usize>())
+            .transpose()
+            .map_err(|err| ControllerError::InvalidInput {
+                field: format!("quantity[{idx}]"),
+                msg: err.to_string(),
+            })?
+            .unwrap_or(1);
+        if article.is_empty() || quantity == 0 {
+            continue;
+        }
+        if quantity > order::MAX_QUANTITY {
+            return Err(ControllerError::InvalidInput {
+                field: format!("quantity[{idx}]"),
+                msg: format!("Quantity can't be more than {}", order::MAX_QUANTITY),
+            });
+        }
+        let price = prices
+            .get(idx)
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<usize>())
+            .transpose()
+            .map_err(|err| ControllerError::InvalidInput {
+                field: format!("price[{idx}]"),
+                msg: err.to_string(),
+            })?;
+        items.push(order::OrderItem {
+            article: article.to_string(),
+            title: titles
+                .get(idx)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| article.to_string()),
+            price,
+            quantity,
+        });
+    }
+    Ok(items)
+}
+
+#[post("/shop/{shop_id}/crm/orders/{id}/items")]
+async fn shop_order_items(
+    ShopAccess { shop, user }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    body: Bytes,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    let items = parse_order_items_form(&body)?;
+    if items.is_empty() {
+        return Err(ControllerError::InvalidInput {
+            field: "article".to_string(),
+            msg: "Order must contain at least one item".to_string(),
+        });
+    }
+    if order::items_total(&items).is_none() {
+        return Err(ControllerError::InvalidInput {
+            field: "price".to_string(),
+            msg: "Order total is too large".to_string(),
+        });
+    }
+    let current = order_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    if !current.status.items_editable() {
+        return Err(ControllerError::InvalidInput {
+            field: "status".to_string(),
+            msg: order::OrderError::NotEditable(current.status).to_string(),
+        });
+    }
+    order_repo
+        .update_items(
+            shop.id,
+            id,
+            items,
+            user.login.to_string(),
+            OffsetDateTime::now_utc().unix_timestamp(),
+        )
+        .await?;
+    Ok(see_other(&format!("/shop/{}/crm/orders/{id}", shop.id)))
+}
+
+#[post("/shop/{shop_id}/crm/orders/{id}/delete")]
+async fn shop_order_delete(
+    ShopAccess { shop, .. }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    order_repo.remove(shop.id, id).await?;
+    Ok(see_other(&format!("/shop/{}/crm/orders", shop.id)))
+}
+
+#[derive(Deserialize)]
+pub struct ShopUsersQuery {
+    pub q: Option<String>,
+    pub page: Option<String>,
+}
+
+#[get("/shop/{shop_id}/crm/users")]
+async fn shop_users_page(
+    ShopAccess { shop, user }: ShopAccess,
+    params: Query<ShopUsersQuery>,
+    customer_repo: Data<Arc<dyn customer::CustomerRepository>>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
+) -> Response {
+    const PER_PAGE: usize = 50;
+    let query = normalize_string(params.q.clone());
+    let mut page = parse_usize_param(params.page.as_deref()).unwrap_or(1).max(1);
+    let filter = |page: usize| customer::CustomerFilter {
+        query: query.clone(),
+        limit: PER_PAGE,
+        offset: (page - 1) * PER_PAGE,
+    };
+    let mut result = customer_repo.list_by_shop(shop.id, filter(page)).await?;
+    let total_pages = result.total.div_ceil(PER_PAGE).max(1);
+    if page > total_pages {
+        page = total_pages;
+        result = customer_repo.list_by_shop(shop.id, filter(page)).await?;
+    }
+    let stats = order_repo.customer_stats(shop.id).await?;
+
+    let build_url = |p: usize| {
+        let mut qs = form_urlencoded::Serializer::new(String::new());
+        if let Some(q) = query.as_ref() {
+            qs.append_pair("q", q);
+        }
+        qs.append_pair("page", &p.to_string());
+        format!("/shop/{}/crm/users?{}", shop.id, qs.finish())
+    };
+    let page_links = build_pagination_items(page, total_pages)
+        .into_iter()
+        .map(|item| match item {
+            PaginationItem::Page(p) => PageLink {
+                label: p.to_string(),
+                url: Some(build_url(p)),
+                current: p == page,
+            },
+            PaginationItem::Gap => PageLink {
+                label: "...".to_string(),
+                url: None,
+                current: false,
+            },
+        })
+        .collect();
+
+    let items = result
+        .items
+        .into_iter()
+        .map(|c| {
+            let stats = stats.get(&c.id).cloned().unwrap_or_default();
+            CustomerView {
+                id: c.id,
+                name: c.name,
+                phone: c.phone,
+                email: c.email,
+                orders: stats.orders,
+                total: stats.total,
+                last_order_at: stats.last_order_at.map(format_unix_timestamp),
+                created_at: format_unix_timestamp(c.created_at),
             }
         })
         .collect();
-    render_template(ShopOrdersPage { shop, user, items })
+    render_template(ShopUsersPage {
+        shop,
+        user,
+        items,
+        query: query.unwrap_or_default(),
+        total_items: result.total,
+        page_links,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ShopReviewsQuery {
+    pub status: Option<String>,
+    pub q: Option<String>,
+    pub page: Option<String>,
+}
+
+fn reviews_url(
+    shop_id: Uuid,
+    status: Option<&str>,
+    query: Option<&str>,
+    page: Option<usize>,
+) -> String {
+    let mut qs = form_urlencoded::Serializer::new(String::new());
+    if let Some(status) = status.filter(|s| !s.is_empty()) {
+        qs.append_pair("status", status);
+    }
+    if let Some(q) = query.filter(|q| !q.is_empty()) {
+        qs.append_pair("q", q);
+    }
+    if let Some(page) = page {
+        qs.append_pair("page", &page.to_string());
+    }
+    let qs = qs.finish();
+    if qs.is_empty() {
+        format!("/shop/{shop_id}/reviews")
+    } else {
+        format!("/shop/{shop_id}/reviews?{qs}")
+    }
+}
+
+#[get("/shop/{shop_id}/reviews")]
+async fn shop_reviews_page(
+    ShopAccess { shop, user }: ShopAccess,
+    params: Query<ShopReviewsQuery>,
+    review_repo: Data<Arc<dyn review::ReviewRepository>>,
+) -> Response {
+    const PER_PAGE: usize = 50;
+    let status = params
+        .status
+        .as_deref()
+        .filter(|s| !s.trim().is_empty())
+        .map(review::ReviewStatus::from_str);
+    let query = normalize_string(params.q.clone());
+    let mut page = parse_usize_param(params.page.as_deref()).unwrap_or(1).max(1);
+    let filter = |page: usize| review::ReviewFilter {
+        status,
+        query: query.clone(),
+        limit: PER_PAGE,
+        offset: (page - 1) * PER_PAGE,
+    };
+    let mut result = review_repo.list_by_shop(shop.id, filter(page)).await?;
+    let total_pages = result.total.div_ceil(PER_PAGE).max(1);
+    if page > total_pages {
+        page = total_pages;
+        result = review_repo.list_by_shop(shop.id, filter(page)).await?;
+    }
+    let page_links = build_pagination_items(page, total_pages)
+        .into_iter()
+        .map(|item| match item {
+            PaginationItem::Page(p) => PageLink {
+                label: p.to_string(),
+                url: Some(reviews_url(
+                    shop.id,
+                    status.map(|s| s.as_str()),
+                    query.as_deref(),
+                    Some(p),
+                )),
+                current: p == page,
+            },
+            PaginationItem::Gap => PageLink {
+                label: "...".to_string(),
+                url: None,
+                current: false,
+            },
+        })
+        .collect();
+    render_template(ShopReviewsPage {
+        shop,
+        user,
+        items: result.items.into_iter().map(Into::into).collect(),
+        statuses: review::ReviewStatus::ALL.to_vec(),
+        status_filter: status.map(|s| s.as_str().to_string()).unwrap_or_default(),
+        query: query.unwrap_or_default(),
+        current_page: page,
+        total_items: result.total,
+        page_links,
+    })
+}
+
+#[post("/shop/{shop_id}/reviews/bulk")]
+async fn shop_reviews_bulk(
+    ShopAccess { shop, .. }: ShopAccess,
+    body: Bytes,
+    review_repo: Data<Arc<dyn review::ReviewRepository>>,
+) -> Response {
+    let mut params = HashMap::<String, Vec<String>>::new();
+    for (key, value) in form_urlencoded::parse(&body) {
+        params.entry(key.into_owned()).or_default().push(value.into_owned());
+    }
+    let first = |key: &str| {
+        params
+            .get(key)
+            .and_then(|v| v.first())
+            .map(|v| v.trim().to_string())
+    };
+    let redirect = reviews_url(
+        shop.id,
+        first("status").as_deref(),
+        first("q").as_deref(),
+        parse_usize_param(first("page").as_deref()),
+    );
+    let ids = params
+        .get("id")
+        .map(|ids| {
+            ids.iter()
+                .filter_map(|id| id.trim().parse::<i64>().ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let action = first("action").unwrap_or_default();
+    let status = match action.as_str() {
+        "publish" => Some(review::ReviewStatus::Published),
+        "reject" => Some(review::ReviewStatus::Rejected),
+        "pending" => Some(review::ReviewStatus::Pending),
+        "delete" => None,
+        _ => {
+            return Err(ControllerError::InvalidInput {
+                field: "action".to_string(),
+                msg: format!("Unknown review action {action}"),
+            })
+        }
+    };
+    match status {
+        Some(status) => review_repo.set_status(shop.id, ids, status).await?,
+        None => review_repo.remove(shop.id, ids).await?,
+    };
+    Ok(see_other(&redirect))
+}
+
+#[get("/shop/{shop_id}/reviews/{id}")]
+async fn shop_review_page(
+    ShopAccess { shop, user }: ShopAccess,
+    path: Path<(Uuid, i64)>,
+    review_repo: Data<Arc<dyn review::ReviewRepository>>,
+) -> Response {
+    let (_, id) = path.into_inner();
+    let item = review_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    render_template(ShopReviewPage {
+        shop,
+        user,
+        item: item.into(),
+    })
 }
 
-#[post("/shop/{shop_id}/crm/orders/{id}/delete")]
-async fn shop_order_delete(
+#[derive(Deserialize)]
+pub struct ReviewEditForm {
+    pub name: String,
+    pub text: String,
+    pub rating: i64,
+    pub reply: Option<String>,
+}
+
+#[post("/shop/{shop_id}/reviews/{id}")]
+async fn shop_review_update(
     ShopAccess { shop, .. }: ShopAccess,
     path: Path<(Uuid, i64)>,
-    order_repo: Data<Arc<dyn order::OrderRepository>>,
+    Form(form): Form<ReviewEditForm>,
+    review_repo: Data<Arc<dyn review::ReviewRepository>>,
 ) -> Response {
     let (_, id) = path.into_inner();
-    order_repo.remove(shop.id, id).await?;
-    Ok(see_other(&format!("/shop/{}/crm/orders", shop.id)))
-}
-
-#[get("/shop/{shop_id}/crm/users")]
-async fn shop_users_page(ShopAccess { shop, user }: ShopAccess) -> Response {
-    render_template(ShopUsersPage { shop, user })
+    let current = review_repo
+        .get(shop.id, id)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    let name = review::normalize_name(&form.name);
+    let text = review::normalize_text(&form.text);
+    if name.is_empty() || text.is_empty() {
+        return Err(ControllerError::InvalidInput {
+            field: "text".to_string(),
+            msg: "Review must have an author and a text".to_string(),
+        });
+    }
+    let reply = normalize_string(form.reply).map(|r| review::normalize_text(&r));
+    // Дата відповіді змінюється лише коли змінилась сама відповідь
+    let replied_at = match &reply {
+        Some(reply) if current.reply.as_ref() == Some(reply) => current.replied_at,
+        Some(_) => Some(OffsetDateTime::now_utc().unix_timestamp()),
+        None => None,
+    };
+    review_repo
+        .update(
+            shop.id,
+            id,
+            review::ReviewEdit {
+                name,
+                text,
+                rating: review::clamp_rating(form.rating),
+                reply,
+                replied_at,
+            },
+        )
+        .await?;
+    Ok(see_other(&format!("/shop/{}/reviews/{id}", shop.id)))
 }
 
 async fn perform_bulk_visibility_update(
@@ -3570,19 +4558,128 @@ struct ExportInfoPage {
     ddaudio_api_selected_warehouses: HashSet<String>,
     ddaudio_api_warehouse_statuses: HashMap<String, String>,
     ddaudio_api_warehouse_views: Vec<DDAudioWarehouseView>,
+    diff: Option<ExportDiffView>,
+    prom_token_set: bool,
+    prom_fields: &'static [(&'static str, &'static str)],
+    prom_missing_actions: [MissingProductAction; 4],
+    runs: Vec<ExportRunView>,
+}
+
+const EXPORT_RUNS_PAGE_LIMIT: usize = 20;
+
+struct ExportRunView {
+    id: i64,
+    started: String,
+    duration: Option<String>,
+    outcome: scheduler::RunOutcome,
+    stage: Option<String>,
+    error: Option<String>,
+    counts: String,
+    timings: String,
+    artifacts: Vec<(String, String)>,
+}
+
+impl ExportRunView {
+    async fn new(run: export_history::ExportRun, files: &[String], now: OffsetDateTime) -> Self {
+        let join = |counts: &scheduler::RunCounts, unit: &str| {
+            counts
+                .iter()
+                .map(|(k, v)| format!("{k}: {v}{unit}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let artifacts = export_history::artifacts(&run, files).await;
+        Self {
+            id: run.id,
+            started: format_dt_last_visited(run.started, now),
+            duration: run
+                .finished
+                .map(|f| crate::format_duration_short(&(f - run.started).unsigned_abs())),
+            outcome: run.outcome,
+            stage: run.stage,
+            error: run.error,
+            counts: join(&run.counts, ""),
+            timings: join(&run.timings, "ms"),
+            artifacts,
+        }
+    }
+}
+
+const EXPORT_DIFF_PAGE_LIMIT: usize = 200;
+
+struct ExportDiffView {
+    previous: String,
+    current: String,
+    total: usize,
+    counts: Vec<(&'static str, usize)>,
+    changes: Vec<export_diff::Change>,
+}
+
+impl From<export_diff::DiffReport> for ExportDiffView {
+    fn from(mut report: export_diff::DiffReport) -> Self {
+        use export_diff::ChangeKind;
+        let format = |t: Option<OffsetDateTime>| {
+            t.map(|t| format_unix_timestamp(t.unix_timestamp()))
+                .unwrap_or_default()
+        };
+        let counts = [
+            ChangeKind::Added,
+            ChangeKind::Removed,
+            ChangeKind::Price,
+            ChangeKind::Availability,
+            ChangeKind::Title,
+            ChangeKind::Description,
+        ]
+        .iter()
+        .map(|k| (k.label(), report.count(k)))
+        .collect();
+        report.changes.truncate(EXPORT_DIFF_PAGE_LIMIT);
+        Self {
+            previous: format(report.previous),
+            current: format(report.current),
+            total: report.total,
+            counts,
+            changes: report.changes,
+        }
+    }
 }
 
 #[derive(Serialize, Clone)]
 struct ExportViewDto {
     status: String,
+    guard_blocked: bool,
     entry: ExportEntry,
+    prom_import: Option<PromImportView>,
+    rozetka_issues: Vec<rozetka::OfferIssues>,
 }
 
 impl From<export::Export> for ExportViewDto {
     fn from(e: export::Export) -> Self {
         Self {
             status: e.status().to_string(),
+            guard_blocked: e.guard_blocked,
             entry: e.entry().clone(),
+            prom_import: e.prom_import.clone().map(Into::into),
+            rozetka_issues: e.rozetka_issues.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct PromImportView {
+    id: Option<String>,
+    started: String,
+    status: Option<uploader::ImportStatus>,
+    error: Option<String>,
+}
+
+impl From<export::PromImport> for PromImportView {
+    fn from(i: export::PromImport) -> Self {
+        Self {
+            id: i.id,
+            started: format_unix_timestamp(i.started.unix_timestamp()),
+            status: i.status,
+            error: i.error,
         }
     }
 }
@@ -3631,6 +4728,7 @@ async fn export_info(
     q: Query<ExportInfoQuery>,
     export_service: Data<Arc<Addr<export::ExportService>>>,
     watermark_group_repository: Data<Arc<dyn WatermarkGroupRepository>>,
+    export_runs: Data<Arc<dyn export_history::ExportRunRepository>>,
     ShopAccess { shop, user }: ShopAccess,
 ) -> Response {
     let (shop_id, hash) = path.into_inner();
@@ -3705,6 +4803,11 @@ async fn export_info(
         .map_err(ShopControllerError::with(&user, &shop))?;
     match export {
         Some(export) => {
+            let diff = export_diff::read_report(&shop_id.to_string(), &export.entry.file_name(None))
+                .await
+                .log_error("Unable to read export diff")
+                .flatten()
+                .map(Into::into);
             let mut ddaudio_api_categories = Vec::new();
             let mut ddaudio_api_warehouses = Vec::new();
             let mut ddaudio_api_error = None;
@@ -3844,6 +4947,21 @@ async fn export_info(
                     })
                     .collect::<Vec<_>>();
             }
+            let prom_token_set = shop.prom.api_token.is_some();
+            let files = export_history::output_files(&export.entry);
+            let now = OffsetDateTime::now_utc();
+            let mut runs = vec![];
+            for run in export_runs
+                .list_runs(
+                    shop_id,
+                    &export.entry.file_name(None),
+                    EXPORT_RUNS_PAGE_LIMIT,
+                )
+                .await
+                .map_err(ControllerError::InternalServerError)?
+            {
+                runs.push(ExportRunView::new(run, &files, now).await);
+            }
             render_template(ExportInfoPage {
                 export: export.into(),
                 hash,
@@ -3860,12 +4978,82 @@ async fn export_info(
                 ddaudio_api_selected_warehouses,
                 ddaudio_api_warehouse_statuses,
                 ddaudio_api_warehouse_views,
+                diff,
+                prom_token_set,
+                prom_fields: PROM_UPDATABLE_FIELDS,
+                prom_missing_actions: MissingProductAction::ALL,
+                runs,
             })
         }
         None => Ok(see_other(&format!("/shop/{shop_id}"))),
     }
 }
 
+#[get("/shop/{shop_id}/export_info/{hash}/diff.{format}")]
+async fn export_diff_download(
+    path: Path<(IdentityOf<Shop>, String, String)>,
+    export_service: Data<Arc<Addr<export::ExportService>>>,
+    ShopAccess { .. }: ShopAccess,
+) -> Response {
+    let (shop_id, hash, format) = path.into_inner();
+    let export = export_service
+        .send(export::GetStatus(hash))
+        .await
+        .context("Unable to send message to ExportService")?
+        .ok_or(ControllerError::NotFound)?;
+    let file_name = export.entry().file_name(None);
+    let report = export_diff::read_report(&shop_id.to_string(), &file_name)
+        .await?
+        .ok_or(ControllerError::NotFound)?;
+    let (body, content_type) = match format.as_str() {
+        "csv" => (export_diff::report_to_csv(&report)?, "text/csv; charset=utf-8"),
+        "xlsx" => (
+            export_diff::report_to_xlsx(&report)?,
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+        _ => return Err(ControllerError::NotFound),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{file_name}_diff.{format}\""),
+        ))
+        .body(body))
+}
+
+#[post("/shop/{shop_id}/export_info/{hash}/runs/{run_id}/rollback")]
+async fn export_run_rollback(
+    path: Path<(IdentityOf<Shop>, String, i64)>,
+    export_service: Data<Arc<Addr<export::ExportService>>>,
+    export_runs: Data<Arc<dyn export_history::ExportRunRepository>>,
+    ShopAccess { .. }: ShopAccess,
+) -> Response {
+    let (shop_id, hash, run_id) = path.into_inner();
+    let export = export_service
+        .send(export::GetStatus(hash.clone()))
+        .await
+        .context("Unable to send message to ExportService")?
+        .ok_or(ControllerError::NotFound)?;
+    let run = export_runs
+        .get_run(run_id)
+        .await
+        .map_err(ControllerError::InternalServerError)?
+        .filter(|r| r.shop == shop_id && r.file_name == export.entry().file_name(None))
+        .ok_or(ControllerError::NotFound)?;
+    let restored = export_history::rollback(&run, &export_history::output_files(export.entry()))
+        .await
+        .map_err(ControllerError::InternalServerError)?;
+    if restored == 0 {
+        return Err(ControllerError::NotFound);
+    }
+    log::info!(
+        "Restored {restored} files of {} from export run {run_id}",
+        run.file_name
+    );
+    Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
+}
+
 #[post("/shop/{shop_id}/export_info/{hash}/remove")]
 async fn remove_export(
     hash: Path<(IdentityOf<Shop>, String)>,
@@ -3904,6 +5092,14 @@ pub struct ExportEntryDto {
     pub file_name: Option<String>,
     #[serde(deserialize_with = "deserialize_parse_form::<_, u64>")]
     pub update_rate: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_decimal_form")]
+    pub max_count_drop_percent: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_decimal_form")]
+    pub max_zero_price_percent: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_decimal_form")]
+    pub max_not_available_percent: Option<Decimal>,
+    #[serde(default, deserialize_with = "deserialize_decimal_form")]
+    pub max_avg_price_change_percent: Option<Decimal>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -3949,6 +5145,7 @@ pub struct ExportEntryLinkDto {
     pub adjust_price: bool,
     #[serde(deserialize_with = "deserialize_decimal_form")]
     pub adjust_price_by: Option<Decimal>,
+    pub pricing_rules: Option<String>,
     #[serde(default, deserialize_with = "deserialize_bool_form")]
     pub categories: bool,
     #[serde(default, deserialize_with = "deserialize_bool_form")]
@@ -4049,6 +5246,7 @@ impl Into<ExportOptions> for ExportEntryLinkDto {
                 .zip(self.description_action_ua)
                 .and_then(|(path, action)| DescriptionOptions::try_from(action, path)),
             adjust_price: self.adjust_price_by.filter(|_| self.adjust_price),
+            pricing: parse_pricing_rules(self.pricing_rules.as_deref()).unwrap_or_default(),
             delivery_time: self.delivery_time_duration.filter(|_| self.delivery_time),
             categories: self.categories,
             convert_to_uah: self.convert_to_uah,
@@ -4068,7 +5266,22 @@ impl Into<ExportOptions> for ExportEntryLinkDto {
     }
 }
 
+fn parse_pricing_rules(raw: Option<&str>) -> Result<Vec<PricingRule>, ControllerError> {
+    let Some(raw) = raw.filter(|r| !r.trim().is_empty()) else {
+        return Ok(Vec::new());
+    };
+    serde_json::from_str(raw).map_err(|err| ControllerError::InvalidInput {
+        field: "pricing_rules".to_string(),
+        msg: format!("Некорректные правила ценообразования: {err}"),
+    })
+}
+
 impl ExportEntryLinkDto {
+    // Після перетворення в ExportOptions помилку правил уже не показати
+    fn check_pricing_rules(&self) -> Result<(), ControllerError> {
+        parse_pricing_rules(self.pricing_rules.as_deref()).map(|_| ())
+    }
+
     fn publish_value(&self) -> bool {
         self.publish
             .iter()
@@ -4279,7 +5492,7 @@ where
         .map_err(D::Error::custom)
 }
 
-fn deserialize_parse_form<'de, D, RT>(deserializer: D) -> Result<Option<RT>, D::Error>
+pub fn deserialize_parse_form<'de, D, RT>(deserializer: D) -> Result<Option<RT>, D::Error>
 where
     D: de::Deserializer<'de>,
     RT: FromStr,
@@ -4308,6 +5521,12 @@ async fn update_export(
             if let Some(rate) = dto.update_rate {
                 export_entry.update_rate = Duration::from_secs(rate * 60 * 60);
             }
+            export_entry.guards = ExportGuards {
+                max_count_drop_percent: dto.max_count_drop_percent,
+                max_zero_price_percent: dto.max_zero_price_percent,
+                max_not_available_percent: dto.max_not_available_percent,
+                max_avg_price_change_percent: dto.max_avg_price_change_percent,
+            };
         })
         .await?;
 
@@ -4352,6 +5571,7 @@ async fn update_export_link(
 ) -> Response {
     let (shop_id, _, link_hash) = path.into_inner();
     let new_link = form.into_inner();
+    new_link.check_pricing_rules()?;
     let (mut export_entry, g) = export_entry.into_inner();
     let link = export_entry
         .entry
@@ -4387,7 +5607,9 @@ async fn add_export_link(
     ShopAccess { .. }: ShopAccess,
 ) -> Response {
     let (shop_id, _) = path.into_inner();
-    let new_link = form.into_inner().try_into()?;
+    let new_link = form.into_inner();
+    new_link.check_pricing_rules()?;
+    let new_link = new_link.try_into()?;
 
     let hash = export_entry
         .map(|export_entry| {
@@ -4573,6 +5795,23 @@ async fn add_export_ddaudio_api(
     Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
 }
 
+#[post("/shop/{shop_id}/export_info/{export_hash}/add_prom")]
+async fn add_export_prom(
+    path: Path<(IdentityOf<Shop>, String)>,
+    ShopAccess { .. }: ShopAccess,
+    export_entry: Record<Export>,
+) -> Response {
+    let (shop_id, _) = path.into_inner();
+    let hash = export_entry
+        .map(|export_entry| {
+            if export_entry.entry.prom.is_none() {
+                export_entry.entry.prom = Some(PromUploadOptions::default());
+            }
+        })
+        .await?;
+    Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
+}
+
 #[post("/shop/{shop_id}/export_info/{export_hash}/remove_dt")]
 async fn remove_export_dt(
     path: Path<(IdentityOf<Shop>, String)>,
@@ -4723,6 +5962,58 @@ async fn remove_export_ddaudio_api(
     Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
 }
 
+#[post("/shop/{shop_id}/export_info/{export_hash}/remove_prom")]
+async fn remove_export_prom(
+    path: Path<(IdentityOf<Shop>, String)>,
+    ShopAccess { .. }: ShopAccess,
+    export_entry: Record<Export>,
+) -> Response {
+    let (shop_id, _) = path.into_inner();
+    let hash = export_entry
+        .map(|export_entry| {
+            export_entry.entry.prom = None;
+        })
+        .await?;
+    Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
+}
+
+#[post("/shop/{shop_id}/export_info/{export_hash}/prom")]
+async fn update_export_prom(
+    body: Bytes,
+    path: Path<(IdentityOf<Shop>, String)>,
+    ShopAccess { .. }: ShopAccess,
+    export_entry: Record<ExportEntry>,
+) -> Response {
+    let (shop_id, _) = path.into_inner();
+    let mut opts = PromUploadOptions {
+        updated_fields: vec![],
+        mark_missing_product_as: Default::default(),
+    };
+    for (key, value) in form_urlencoded::parse(&body) {
+        match key.as_ref() {
+            "updated_fields" if PROM_UPDATABLE_FIELDS.iter().any(|(f, _)| *f == value) => {
+                opts.updated_fields.push(value.into_owned());
+            }
+            "mark_missing_product_as" => {
+                opts.mark_missing_product_as =
+                    value
+                        .parse()
+                        .map_err(|err: anyhow::Error| ControllerError::InvalidInput {
+                            field: "mark_missing_product_as".to_string(),
+                            msg: err.to_string(),
+                        })?;
+            }
+            _ => (),
+        }
+    }
+    let hash = export_entry
+        .map(move |export_entry| {
+            export_entry.prom = Some(opts);
+        })
+        .await?;
+    Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
+}
+
 #[post("/shop/{shop_id}/export_info/{export_hash}/{link_hash}/remove")]
 async fn remove_export_link(
     path: Path<(IdentityOf<Shop>, String, String)>,
@@ -4749,6 +6040,7 @@ async fn update_export_dt(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -4786,6 +6078,7 @@ async fn update_export_op_tuning(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -4823,6 +6116,7 @@ async fn update_export_maxton(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -4860,6 +6154,7 @@ async fn update_export_pl(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -4897,6 +6192,7 @@ async fn update_export_skm(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -4934,6 +6230,7 @@ async fn update_export_dt_tt(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -4971,6 +6268,7 @@ async fn update_export_jgd(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -5008,6 +6306,7 @@ async fn update_export_tt(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.options.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -5045,6 +6344,7 @@ async fn update_export_davi(
     export_entry: Record<ExportEntry>,
 ) -> Response {
     let opts = form.into_inner();
+    opts.check_pricing_rules()?;
     let (shop_id, _) = path.into_inner();
 
     let description = opts
@@ -5093,6 +6393,7 @@ async fn update_export_ddaudio_api(
     }
     let opts: ExportEntryLinkDto =
         serde_urlencoded::from_bytes(&body).map_err(anyhow::Error::new)?;
+    opts.check_pricing_rules()?;
 
     let description = opts
         .description_path
@@ -5245,6 +6546,19 @@ async fn start_export(
     Ok(see_other(&format!("/shop/{shop_id}")))
 }
 
+#[post("/shop/{shop_id}/export_info/{hash}/publish_anyway")]
+async fn publish_export_anyway(
+    path: Path<(IdentityOf<Shop>, String)>,
+    addr: Data<Arc<Addr<export::ExportService>>>,
+    ShopAccess { .. }: ShopAccess,
+) -> Response {
+    let (shop_id, hash) = path.into_inner();
+    addr.send(export::PublishAnyway(hash.clone()))
+        .await
+        .context("Unable to send message to ExportService")?;
+    Ok(see_other(&format!("/shop/{shop_id}/export_info/{hash}")))
+}
+
 #[post("/shop/{shop_id}/start_export_all")]
 async fn start_export_all(
     path: Path<IdentityOf<Shop>>,
@@ -5354,6 +6668,7 @@ pub struct SitePublishPage {
     pub suppliers: Vec<crate::site_publish::XmlSupplier>,
     pub categories: Vec<Category>,
     pub restal_key: Option<String>,
+    pub site_api: crate::site_publish::SiteApiConfig,
     pub xml_error: Option<String>,
     pub supplier_options: Vec<SupplierOption>,
     pub allow_restal: bool,
@@ -5696,6 +7011,16 @@ pub struct SitePublishForm {
     pub restal_key: Option<String>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct SiteApiForm {
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub hosts: String,
+    #[serde(default)]
+    pub slug: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct PurgeSupplierForm {
     pub supplier: String,
@@ -5728,6 +7053,7 @@ async fn site_publish_page(
 ) -> Response {
     let suppliers = site_publish::load_site_publish_configs(&shop.id).unwrap_or_default();
     let restal_key = site_publish::load_restal_key(&shop.id);
+    let site_api = site_publish::load_site_api_config(&shop.id);
     let allowed_suppliers = site_publish::load_site_publish_suppliers(&shop.id);
     let allow_restal = allowed_suppliers.contains(&"restal".to_string());
     let ddaudio_config = site_publish::load_ddaudio_config(&shop.id);
@@ -5948,6 +7274,7 @@ async fn site_publish_page(
         suppliers,
         categories,
         restal_key,
+        site_api,
         xml_error: None,
         supplier_options,
         allow_restal,
@@ -6005,6 +7332,7 @@ async fn site_publish_ddaudio_save(
     shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
     category_repo: Data<Arc<dyn CategoryRepository>>,
     product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    jobs: Data<Arc<JobScheduler>>,
 ) -> Response {
     let mut params = HashMap::<String, Vec<String>>::new();
     for (key, value) in form_urlencoded::parse(&body) {
@@ -6098,6 +7426,7 @@ async fn site_publish_ddaudio_save(
         shop_product_repo.get_ref().clone(),
         category_repo.get_ref().clone(),
         product_category_repo.get_ref().clone(),
+        jobs.get_ref().clone(),
     )
     .await;
     Ok(see_other(&format!("/shop/{}/site_publish", shop.id)))
@@ -6116,6 +7445,7 @@ async fn ddaudio_import_start(
     shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
     category_repo: Data<Arc<dyn CategoryRepository>>,
     product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    jobs: Data<Arc<JobScheduler>>,
 ) -> Response {
     let _ = params;
     let target = site_publish::DDAudioTarget::Site;
@@ -6126,6 +7456,7 @@ async fn ddaudio_import_start(
         shop_product_repo.get_ref().clone(),
         category_repo.get_ref().clone(),
         product_category_repo.get_ref().clone(),
+        jobs.get_ref().clone(),
         true,
     )
     .await
@@ -6197,6 +7528,58 @@ async fn site_publish_allowed(
     Ok(see_other(&format!("/shop/{}/site_publish", shop.id)))
 }
 
+#[post("/shop/{shop_id}/site_publish/api")]
+async fn site_publish_api_save(
+    ShopAccess { shop, .. }: ShopAccess,
+    Form(form): Form<SiteApiForm>,
+    shop_service: Data<Addr<ShopService>>,
+) -> Response {
+    let api_key = normalize_string(form.api_key);
+    if let Some(key) = &api_key {
+        if crate::control::site_api::site_api_key_taken(&shop_service, shop.id, key).await? {
+            return Err(ControllerError::InvalidInput {
+                field: "api_key".to_string(),
+                msg: "Цей API ключ уже використовує інший магазин".to_string(),
+            });
+        }
+    }
+    let slug = normalize_string(form.slug).map(|s| s.to_lowercase());
+    let hosts = form
+        .hosts
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|h| h.trim().to_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect::<Vec<_>>();
+    let conflict =
+        site_api::site_address_taken(&shop_service, shop.id, slug.as_deref(), &hosts).await?;
+    match conflict {
+        Some(site_api::SiteAddressConflict::Slug(slug)) => {
+            return Err(ControllerError::InvalidInput {
+                field: "slug".to_string(),
+                msg: format!("Slug {slug} уже використовує інший магазин"),
+            })
+        }
+        Some(site_api::SiteAddressConflict::Host(host)) => {
+            return Err(ControllerError::InvalidInput {
+                field: "hosts".to_string(),
+                msg: format!("Домен {host} уже використовує інший магазин"),
+            })
+        }
+        None => (),
+    }
+    site_publish::save_site_api_config(
+        &shop.id,
+        &site_publish::SiteApiConfig {
+            api_key,
+            hosts,
+            slug,
+        },
+    )
+    .map_err(ControllerError::InternalServerError)?;
+    crate::control::site_api::invalidate_site_shops_cache().await;
+    Ok(see_other(&format!("/shop/{}/site_publish", shop.id)))
+}
+
 #[post("/shop/{shop_id}/site_publish/purge_supplier")]
 async fn site_publish_purge_supplier(
     ShopAccess { shop, .. }: ShopAccess,
@@ -6527,6 +7910,7 @@ async fn site_import_update(
         }
     };
 
+    transform.check_pricing_rules()?;
     let transform: ExportOptions = transform.into();
     if let Some(desc) = transform.description.as_ref() {
         check_description(shop_id, desc.value()).await?;
@@ -7771,6 +9155,180 @@ async fn control_panel_shops(
     render_template(ControlPanelShopsPage { user, shops })
 }
 
+pub struct JobRow {
+    id: String,
+    title: String,
+    shop: Option<IdentityOf<Shop>>,
+    schedule: String,
+    default_schedule: String,
+    paused: bool,
+    running_since: Option<String>,
+    next_run: Option<String>,
+}
+
+pub struct JobRunRow {
+    job: String,
+    started: String,
+    duration: Option<String>,
+    outcome: scheduler::RunOutcome,
+    error: Option<String>,
+    counts: String,
+}
+
+#[derive(Template)]
+#[template(path = "control_panel/jobs.html")]
+pub struct ControlPanelJobsPage {
+    user: UserCredentials,
+    jobs: Vec<JobRow>,
+    runs: Vec<JobRunRow>,
+    selected: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct JobsQuery {
+    job: Option<String>,
+}
+
+#[get("/control_panel/jobs")]
+async fn control_panel_jobs(
+    ControlPanelAccess { user }: ControlPanelAccess,
+    jobs: Data<Arc<JobScheduler>>,
+    query: Query<JobsQuery>,
+) -> Response {
+    let selected = query.into_inner().job.filter(|j| !j.is_empty());
+    let now = OffsetDateTime::now_utc();
+    let list = jobs.list().await;
+    let titles = list
+        .iter()
+        .map(|j| (j.definition.id.clone(), j.definition.title.clone()))
+        .collect::<HashMap<_, _>>();
+    let runs = jobs
+        .runs(selected.clone(), 100)
+        .await
+        .map_err(ControllerError::InternalServerError)?
+        .into_iter()
+        .map(|r| JobRunRow {
+            job: titles.get(&r.job).cloned().unwrap_or(r.job),
+            started: format_dt_last_visited(r.started, now),
+            duration: r
+                .finished
+                .map(|f| crate::format_duration_short(&(f - r.started).unsigned_abs())),
+            outcome: r.outcome,
+            error: r.error,
+            counts: r
+                .counts
+                .iter()
+                .map(|(k, v)| format!("{k}: {v}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        })
+        .collect();
+    let jobs = list
+        .into_iter()
+        .map(|j| JobRow {
+            id: j.definition.id,
+            title: j.definition.title,
+            shop: j.definition.shop,
+            schedule: j.schedule.to_string(),
+            default_schedule: j.default_schedule.to_string(),
+            paused: j.definition.paused,
+            running_since: j.running_since.map(|s| format_dt_last_visited(s, now)),
+            next_run: j.next_run.map(|n| {
+                let left = crate::format_duration_short(
+                    &(n - now).max(time::Duration::ZERO).unsigned_abs(),
+                );
+                format!("через {left}")
+            }),
+        })
+        .collect();
+    render_template(ControlPanelJobsPage {
+        user,
+        jobs,
+        runs,
+        selected,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct JobActionDto {
+    id: String,
+}
+
+#[post("/control_panel/jobs/run")]
+async fn control_panel_job_run(
+    ControlPanelAccess { .. }: ControlPanelAccess,
+    jobs: Data<Arc<JobScheduler>>,
+    dto: Form<JobActionDto>,
+) -> Response {
+    if !jobs.trigger(&dto.id).await {
+        return Err(ControllerError::NotFound);
+    }
+    Ok(see_other("/control_panel/jobs"))
+}
+
+#[post("/control_panel/jobs/cancel")]
+async fn control_panel_job_cancel(
+    ControlPanelAccess { .. }: ControlPanelAccess,
+    jobs: Data<Arc<JobScheduler>>,
+    dto: Form<JobActionDto>,
+) -> Response {
+    jobs.cancel(&dto.id).await;
+    Ok(see_other("/control_panel/jobs"))
+}
+
+#[post("/control_panel/jobs/pause")]
+async fn control_panel_job_pause(
+    ControlPanelAccess { .. }: ControlPanelAccess,
+    jobs: Data<Arc<JobScheduler>>,
+    dto: Form<JobActionDto>,
+) -> Response {
+    jobs.set_paused(&dto.id, true)
+        .await
+        .map_err(ControllerError::InternalServerError)?;
+    Ok(see_other("/control_panel/jobs"))
+}
+
+#[post("/control_panel/jobs/resume")]
+async fn control_panel_job_resume(
+    ControlPanelAccess { .. }: ControlPanelAccess,
+    jobs: Data<Arc<JobScheduler>>,
+    dto: Form<JobActionDto>,
+) -> Response {
+    jobs.set_paused(&dto.id, false)
+        .await
+        .map_err(ControllerError::InternalServerError)?;
+    Ok(see_other("/control_panel/jobs"))
+}
+
+#[derive(Deserialize)]
+pub struct JobScheduleDto {
+    id: String,
+    #[serde(default)]
+    schedule: String,
+}
+
+// Порожній розклад повертає той, з яким задачу зареєстровано
+#[post("/control_panel/jobs/schedule")]
+async fn control_panel_job_schedule(
+    ControlPanelAccess { .. }: ControlPanelAccess,
+    jobs: Data<Arc<JobScheduler>>,
+    dto: Form<JobScheduleDto>,
+) -> Response {
+    let JobScheduleDto { id, schedule } = dto.into_inner();
+    let schedule = Some(schedule.trim())
+        .filter(|s| !s.is_empty())
+        .map(str::parse::<scheduler::Schedule>)
+        .transpose()
+        .map_err(|err| ControllerError::InvalidInput {
+            field: "schedule".to_string(),
+            msg: err.to_string(),
+        })?;
+    jobs.set_schedule(&id, schedule)
+        .await
+        .map_err(ControllerError::InternalServerError)?;
+    Ok(see_other("/control_panel/jobs"))
+}
+
 #[derive(Template)]
 #[template(path = "control_panel/settings.html")]
 pub struct ControlPanelSettingsPage {
@@ -0,0 +1,71 @@
+use crate::control::site_api::check_api_rate_limit;
+use crate::control::{ControllerError, Response};
+use crate::nova_poshta::NovaPoshtaRepository;
+use actix_web::web::{Data, Query};
+use actix_web::{get, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct CitiesQuery {
+    pub q: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct WarehousesQuery {
+    pub city: String,
+    pub q: Option<String>,
+    pub limit: Option<usize>,
+}
+
+async fn rate_limit(req: &HttpRequest) -> Result<(), ControllerError> {
+    check_api_rate_limit(req)
+        .await
+        .map_err(|e| ControllerError::TooManyRequests {
+            retry_after: e.retry_after,
+            message: e.message,
+        })
+}
+
+fn cached_json(body: impl serde::Serialize) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=3600"))
+        .json(body)
+}
+
+#[get("/api/site/np/cities")]
+pub async fn cities(
+    req: HttpRequest,
+    params: Query<CitiesQuery>,
+    np_repo: Data<Arc<dyn NovaPoshtaRepository>>,
+) -> Response {
+    rate_limit(&req).await?;
+    let query = params.q.as_deref().unwrap_or_default().trim();
+    if query.is_empty() {
+        return Ok(cached_json(Vec::<()>::new()));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    Ok(cached_json(np_repo.search_cities(query, limit).await?))
+}
+
+#[get("/api/site/np/warehouses")]
+pub async fn warehouses(
+    req: HttpRequest,
+    params: Query<WarehousesQuery>,
+    np_repo: Data<Arc<dyn NovaPoshtaRepository>>,
+) -> Response {
+    rate_limit(&req).await?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let items = np_repo
+        .search_warehouses(
+            params.city.trim(),
+            params.q.as_deref().unwrap_or_default(),
+            limit,
+        )
+        .await?;
+    Ok(cached_json(items))
+}
@@ -1,5 +1,8 @@
+use crate::control::customer_api::customer_from_request;
 use crate::control::{Record, Response};
+use crate::customer;
 use crate::category_auto;
+use crate::nova_poshta;
 use crate::dt;
 use crate::product_category;
 use crate::product_category_auto;
@@ -9,10 +12,13 @@ use crate::order;
 use crate::seo_page;
 use crate::shop_product;
 use crate::site_publish;
+use crate::site_search;
 use actix_web::{get, post};
 use actix_web::web::{Data, Json, Path, Query};
 use actix_web::HttpRequest;
 use anyhow::anyhow;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use regex::Regex;
 use rt_types::category::{By, Category, CategoryRepository};
 use serde::Deserialize;
@@ -22,11 +28,13 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use time::OffsetDateTime;
 use tokio::sync::RwLock;
+use typesafe_repository::IdentityOf;
 use once_cell::sync::Lazy;
 
 mod rate_limit {
     use actix_web::HttpRequest;
     use std::collections::HashMap;
+    use std::net::IpAddr;
     use std::sync::Arc;
     use std::time::{Duration, Instant};
     use tokio::sync::RwLock;
@@ -112,36 +120,86 @@ mod rate_limit {
         RateLimiter::new(max, window)
     });
 
-    fn get_client_ip(req: &HttpRequest) -> String {
-        // Перевіряємо заголовки для отримання реального IP
-        if let Some(forwarded) = req.headers().get("x-forwarded-for") {
-            if let Ok(forwarded_str) = forwarded.to_str() {
-                if let Some(first_ip) = forwarded_str.split(',').next() {
-                    return first_ip.trim().to_string();
+    // Проксі, яким довіряємо заголовки X-Forwarded-For та X-Real-IP,
+    // `TRUSTED_PROXIES` через кому
+    static TRUSTED_PROXIES: Lazy<Vec<IpAddr>> = Lazy::new(|| {
+        std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .filter_map(|v| match v.parse() {
+                Ok(ip) => Some(ip),
+                Err(_) => {
+                    log::warn!("Invalid address {v} in TRUSTED_PROXIES");
+                    None
                 }
-            }
+            })
+            .collect()
+    });
+
+    // Спроби входу: 10 на 15 хвилин на обліковий запис і 50 на IP
+    static LOGIN_ACCOUNT_LIMITER: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(10, 900));
+    static LOGIN_IP_LIMITER: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(50, 900));
+
+    // `None`, якщо довірений проксі не передав адресу клієнта
+    pub fn get_client_ip(req: &HttpRequest) -> Option<String> {
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+        client_ip(
+            req.peer_addr().map(|addr| addr.ip()),
+            header("x-forwarded-for"),
+            header("x-real-ip"),
+            &TRUSTED_PROXIES,
+        )
+    }
+
+    // Адресу самого проксі не повертаємо, щоб його клієнти не ділили одні ліміти
+    pub(super) fn client_ip(
+        peer: Option<IpAddr>,
+        forwarded_for: Option<&str>,
+        real_ip: Option<&str>,
+        trusted: &[IpAddr],
+    ) -> Option<String> {
+        let peer = peer?;
+        if !trusted.contains(&peer) {
+            return Some(peer.to_string());
         }
-        
-        if let Some(real_ip) = req.headers().get("x-real-ip") {
-            if let Ok(ip_str) = real_ip.to_str() {
-                return ip_str.to_string();
-            }
+        // Справа наліво: перша адреса, додана не нашим проксі
+        let forwarded = forwarded_for
+            .into_iter()
+            .flat_map(|v| v.rsplit(','))
+            .map(|v| v.trim().parse::<IpAddr>())
+            .find(|ip| !matches!(ip, Ok(ip) if trusted.contains(ip)));
+        match forwarded {
+            Some(Ok(ip)) => Some(ip.to_string()),
+            // Зіпсований ланцюжок не довіряємо
+            Some(Err(_)) => None,
+            None => real_ip
+                .and_then(|v| v.trim().parse::<IpAddr>().ok())
+                .filter(|ip| !trusted.contains(ip))
+                .map(|ip| ip.to_string()),
         }
-        
-        // Fallback на peer address
-        req.peer_addr()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or_else(|| "unknown".to_string())
     }
 
     pub async fn check_api_rate_limit(req: &HttpRequest) -> Result<(), RateLimitError> {
-        let ip = get_client_ip(req);
-        let key = format!("api:{}", ip);
-        API_RATE_LIMITER.check(&key).await
+        match get_client_ip(req) {
+            Some(ip) => API_RATE_LIMITER.check(&format!("api:{ip}")).await,
+            None => Ok(()),
+        }
+    }
+
+    pub async fn check_login_rate_limit(
+        req: &HttpRequest,
+        account: &str,
+    ) -> Result<(), RateLimitError> {
+        if let Some(ip) = get_client_ip(req) {
+            LOGIN_IP_LIMITER.check(&format!("login:{ip}")).await?;
+        }
+        LOGIN_ACCOUNT_LIMITER.check(&format!("login:{account}")).await
     }
 }
 
-use rate_limit::check_api_rate_limit;
+pub(crate) use rate_limit::{check_api_rate_limit, check_login_rate_limit, get_client_ip};
 
 #[derive(Clone, Debug)]
 struct SeoTemplates {
@@ -166,6 +224,7 @@ pub struct QuickOrderRequest {
 #[derive(Deserialize)]
 pub struct OrderItemRequest {
     pub article: String,
+    // Лише для відображення, рядки рахуються за цінами каталогу
     pub title: String,
     pub price: Option<usize>,
     pub quantity: Option<usize>,
@@ -181,6 +240,9 @@ pub struct OrderRequest {
     pub delivery: String,
     pub city_name: Option<String>,
     pub branch_name: Option<String>,
+    // Ref-и з `/api/site/np/*` мають перевагу над назвами
+    pub city_ref: Option<String>,
+    pub branch_ref: Option<String>,
     pub comment: Option<String>,
     pub payment: String,
     pub news: Option<bool>,
@@ -231,6 +293,7 @@ pub(crate) struct CachedProduct {
     pub(crate) brand_slug: String,
     pub(crate) model_slug: String,
     pub(crate) category_slug: String,
+    pub(crate) supplier: String,
     pub(crate) search_blob: String,
     pub(crate) article_lower: String,
     pub(crate) category_id: Option<uuid::Uuid>,
@@ -240,21 +303,33 @@ pub(crate) struct CachedProduct {
 
 struct SiteProductsCache {
     cached_at: Instant,
-    shop_id: String,
     allowed_suppliers: Vec<String>,
     items: Arc<Vec<CachedProduct>>,
     by_article: Arc<HashMap<String, usize>>,
     by_brand_slug: Arc<HashMap<String, Vec<usize>>>,
     by_model_slug: Arc<HashMap<String, Vec<usize>>>,
     by_category_slug: Arc<HashMap<String, Vec<usize>>>,
+    by_supplier: Arc<HashMap<String, Vec<usize>>>,
     hit_indices: Arc<HashSet<usize>>,
+    search: Arc<site_search::SearchIndex>,
+    sort_ranks: Arc<HashMap<ProductSort, Vec<usize>>>,
 }
 
 static SITE_PRODUCTS_CACHE_TTL: Lazy<Duration> =
     Lazy::new(|| cache_ttl_from_env("SITE_PRODUCTS_CACHE_TTL_SECS", 600));
 
-static SITE_PRODUCTS_CACHE: Lazy<RwLock<Option<SiteProductsCache>>> =
-    Lazy::new(|| RwLock::new(None));
+// Окремо для кожного магазину, щоб вітрини не бачили чужих товарів
+static SITE_PRODUCTS_CACHE: Lazy<RwLock<HashMap<uuid::Uuid, SiteProductsCache>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+struct SoldQuantitiesCache {
+    cached_at: Instant,
+    sold: Arc<HashMap<String, usize>>,
+}
+
+// Продані одиниці за артикулом, лише для `sort=popularity`
+static SOLD_QUANTITIES_CACHE: Lazy<RwLock<HashMap<uuid::Uuid, SoldQuantitiesCache>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
 
 static PRIMARY_SHOP_CACHE_TTL: Lazy<Duration> =
     Lazy::new(|| cache_ttl_from_env("PRIMARY_SHOP_CACHE_TTL_SECS", 60));
@@ -262,6 +337,15 @@ static PRIMARY_SHOP_CACHE_TTL: Lazy<Duration> =
 static PRIMARY_SHOP_CACHE: Lazy<RwLock<Option<(Instant, rt_types::shop::Shop)>>> =
     Lazy::new(|| RwLock::new(None));
 
+struct SiteShop {
+    shop: rt_types::shop::Shop,
+    config: site_publish::SiteApiConfig,
+}
+
+type CachedSiteShops = (Instant, Arc<Vec<SiteShop>>);
+
+static SITE_SHOPS_CACHE: Lazy<RwLock<Option<CachedSiteShops>>> = Lazy::new(|| RwLock::new(None));
+
 async fn load_dt_products_cached(
     dt_repo: &Arc<dyn dt::product::ProductRepository + Send>,
 ) -> Arc<Vec<dt::product::Product>> {
@@ -302,9 +386,8 @@ pub(crate) async fn load_site_products_cached(
 
     {
         let cache = SITE_PRODUCTS_CACHE.read().await;
-        if let Some(entry) = cache.as_ref() {
+        if let Some(entry) = cache.get(&shop.id) {
             if entry.cached_at.elapsed() < *SITE_PRODUCTS_CACHE_TTL
-                && entry.shop_id == shop.id.to_string()
                 && entry.allowed_suppliers == allowed_key
             {
                 return (entry.items.clone(), entry.by_article.clone());
@@ -417,10 +500,8 @@ pub(crate) async fn load_site_products_cached(
         let mut available = o
             .and_then(|x| x.available.clone())
             .unwrap_or_else(|| p.available.clone());
-        if matches!(
-            site_publish::detect_supplier(&p).as_deref(),
-            Some("maxton") | Some("jgd") | Some("skm")
-        ) {
+        let supplier = site_publish::detect_supplier(p).unwrap_or_default();
+        if matches!(supplier.as_str(), "maxton" | "jgd" | "skm") {
             available = rt_types::Availability::OnOrder;
         }
         let images = o
@@ -620,6 +701,7 @@ pub(crate) async fn load_site_products_cached(
             brand_slug,
             model_slug,
             category_slug,
+            supplier,
             search_blob,
             article_lower,
             category_id,
@@ -635,6 +717,7 @@ pub(crate) async fn load_site_products_cached(
     let mut by_brand_slug: HashMap<String, Vec<usize>> = HashMap::new();
     let mut by_model_slug: HashMap<String, Vec<usize>> = HashMap::new();
     let mut by_category_slug: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut by_supplier: HashMap<String, Vec<usize>> = HashMap::new();
     let mut hit_indices: HashSet<usize> = HashSet::new();
     
     for (idx, item) in items.iter().enumerate() {
@@ -647,6 +730,9 @@ pub(crate) async fn load_site_products_cached(
         if !item.category_slug.is_empty() {
             by_category_slug.entry(item.category_slug.clone()).or_default().push(idx);
         }
+        if !item.supplier.is_empty() {
+            by_supplier.entry(item.supplier.clone()).or_default().push(idx);
+        }
         if item.is_hit {
             hit_indices.insert(idx);
         }
@@ -655,19 +741,34 @@ pub(crate) async fn load_site_products_cached(
     let by_brand_slug = Arc::new(by_brand_slug);
     let by_model_slug = Arc::new(by_model_slug);
     let by_category_slug = Arc::new(by_category_slug);
+    let by_supplier = Arc::new(by_supplier);
     let hit_indices = Arc::new(hit_indices);
+    // search_blob повторює інші поля, тож лише опис лишається з найменшою вагою
+    let search = Arc::new(site_search::SearchIndex::build(items.iter().map(|item| {
+        site_search::SearchDoc {
+            article: &item.dto.article,
+            title: &item.dto.title,
+            brand: &item.dto.brand,
+            model: &item.dto.model,
+            category: item.dto.category.as_deref(),
+            description: &item.search_blob,
+        }
+    })));
+    let sort_ranks = Arc::new(sort_ranks(&items));
     
     let mut cache = SITE_PRODUCTS_CACHE.write().await;
-    *cache = Some(SiteProductsCache {
+    cache.insert(shop.id, SiteProductsCache {
         cached_at: Instant::now(),
-        shop_id: shop.id.to_string(),
         allowed_suppliers: allowed_key,
         items: items.clone(),
         by_article: by_article.clone(),
         by_brand_slug: by_brand_slug.clone(),
         by_model_slug: by_model_slug.clone(),
         by_category_slug: by_category_slug.clone(),
+        by_supplier: by_supplier.clone(),
         hit_indices: hit_indices.clone(),
+        search,
+        sort_ranks,
     });
     (items, by_article)
 }
@@ -761,14 +862,131 @@ fn apply_seo_templates(dto: &mut ProductDto, templates: &SeoTemplates) {
 #[derive(Deserialize)]
 pub struct ProductsQuery {
     pub limit: Option<usize>,
+    // Для старих вітрин, `cursor` має перевагу
     pub offset: Option<usize>,
+    pub cursor: Option<String>,
+    // Бренд, модель, категорія і постачальник приймають значення через кому
     pub brand: Option<String>,
     pub model: Option<String>,
     pub category: Option<String>,
+    pub supplier: Option<String>,
     pub q: Option<String>,
+    pub sort: Option<String>,
+    pub price_min: Option<usize>,
+    pub price_max: Option<usize>,
+    pub available_only: Option<bool>,
     pub include_total: Option<bool>,
     pub compact: Option<bool>,
     pub hit: Option<bool>,
+    pub facets: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct ProductsPage {
+    pub items: Vec<ProductDto>,
+    pub total: usize,
+    pub facets: site_search::Facets,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductSort {
+    Relevance,
+    PriceAsc,
+    PriceDesc,
+    Newest,
+    Popularity,
+    Title,
+}
+
+impl std::str::FromStr for ProductSort {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "" | "relevance" => Ok(Self::Relevance),
+            "price_asc" => Ok(Self::PriceAsc),
+            "price_desc" => Ok(Self::PriceDesc),
+            "newest" => Ok(Self::Newest),
+            "popularity" => Ok(Self::Popularity),
+            "title" => Ok(Self::Title),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum SortValue {
+    Number(i64),
+    Text(String),
+}
+
+impl ProductSort {
+    // Релевантність і популярність залежать від запиту, тож ранжуються посторінково
+    const PRESORTED: [ProductSort; 4] =
+        [Self::PriceAsc, Self::PriceDesc, Self::Newest, Self::Title];
+
+    fn value(self, item: &CachedProduct) -> SortValue {
+        let price = item.dto.price.map(|p| p as i64);
+        match self {
+            Self::Relevance | Self::Popularity => SortValue::Number(0),
+            // products without a price go last in both directions
+            Self::PriceAsc => SortValue::Number(price.unwrap_or(i64::MAX)),
+            Self::PriceDesc => SortValue::Number(price.map(|p| -p).unwrap_or(i64::MAX)),
+            Self::Newest => SortValue::Number(-item.lastmod.unix_timestamp()),
+            Self::Title => SortValue::Text(item.dto.title.to_lowercase()),
+        }
+    }
+}
+
+fn sort_ranks(items: &[CachedProduct]) -> HashMap<ProductSort, Vec<usize>> {
+    ProductSort::PRESORTED
+        .into_iter()
+        .map(|sort| {
+            let mut order = (0..items.len()).collect::<Vec<_>>();
+            order.sort_by_cached_key(|idx| (sort.value(&items[*idx]), *idx));
+            let mut ranks = vec![0; items.len()];
+            for (rank, idx) in order.into_iter().enumerate() {
+                ranks[idx] = rank;
+            }
+            (sort, ranks)
+        })
+        .collect()
+}
+
+fn select_page(
+    mut keys: Vec<(i64, usize)>,
+    start: usize,
+    limit: usize,
+) -> (Vec<(i64, usize)>, bool) {
+    let end = keys.len().min(start.saturating_add(limit));
+    let has_more = end < keys.len();
+    if has_more {
+        keys.select_nth_unstable(end);
+        keys.truncate(end);
+    }
+    keys.sort_unstable();
+    (keys.split_off(start.min(end)), has_more)
+}
+
+// На відміну від `offset`, наступна сторінка йде одразу після ключа, навіть якщо товар зник
+#[derive(Serialize, Deserialize)]
+struct ProductsCursor {
+    sort: ProductSort,
+    value: SortValue,
+    index: usize,
+}
+
+impl ProductsCursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(value.trim()).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
 }
 
 #[derive(Deserialize)]
@@ -784,6 +1002,11 @@ pub struct ReviewsQuery {
     pub offset: Option<usize>,
 }
 
+#[derive(Deserialize)]
+pub struct ReviewRatingsQuery {
+    pub product: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct ReviewCreateRequest {
     pub product: Option<String>,
@@ -879,6 +1102,18 @@ pub struct ReviewDto {
     pub photos: Vec<String>,
     #[serde(rename = "createdAt")]
     pub created_at: i64,
+    pub reply: Option<String>,
+    #[serde(rename = "repliedAt")]
+    pub replied_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ReviewRatingDto {
+    pub product: String,
+    #[serde(rename = "ratingValue")]
+    pub rating_value: f64,
+    #[serde(rename = "reviewCount")]
+    pub review_count: usize,
 }
 
 #[derive(Serialize)]
@@ -928,37 +1163,214 @@ fn default_product_source() -> crate::shop_product::SourceType {
     crate::shop_product::SourceType::Parsing
 }
 
-fn ensure_api_key(req: &HttpRequest) -> Result<(), crate::control::ControllerError> {
-    let expected = std::env::var("SITE_API_KEY")
-        .map_err(|_| crate::control::ControllerError::Forbidden)?;
-    if expected.trim().is_empty() {
-        return Err(crate::control::ControllerError::Forbidden);
-    }
-    let provided = req
-        .headers()
+fn provided_api_key(req: &HttpRequest) -> Option<String> {
+    req.headers()
         .get("x-api-key")
         .and_then(|v| v.to_str().ok())
-        .map(str::to_string);
-    if Some(expected) != provided {
-        return Err(crate::control::ControllerError::Forbidden);
-    }
-    Ok(())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
 }
 
-fn api_key_valid(req: &HttpRequest) -> bool {
-    let expected = match std::env::var("SITE_API_KEY") {
-        Ok(value) => value,
-        Err(_) => return false,
+// Спільний SITE_API_KEY відкриває лише магазини без власного ключа
+fn legacy_api_key() -> Option<String> {
+    std::env::var("SITE_API_KEY")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn requested_shop_slug(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("x-shop")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            url::form_urlencoded::parse(req.query_string().as_bytes())
+                .find(|(key, _)| key == "shop")
+                .map(|(_, value)| value.into_owned())
+        })
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+fn normalize_host(input: &str) -> String {
+    let host = input.trim().trim_end_matches('.').to_lowercase();
+    let host = host
+        .strip_prefix("https://")
+        .or_else(|| host.strip_prefix("http://"))
+        .unwrap_or(&host);
+    let host = host.split('/').next().unwrap_or_default();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
     };
-    if expected.trim().is_empty() {
-        return false;
+    host.strip_prefix("www.").unwrap_or(host).to_string()
+}
+
+fn site_shop_slug(entry: &SiteShop) -> String {
+    entry
+        .config
+        .slug
+        .as_deref()
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| slugify_latin(&entry.shop.name))
+}
+
+fn match_site_shop<'a>(
+    shops: &'a [SiteShop],
+    slug: Option<&str>,
+    host: Option<&str>,
+) -> Option<&'a SiteShop> {
+    if let Some(slug) = slug {
+        return shops
+            .iter()
+            .find(|s| site_shop_slug(s) == slug || s.shop.id.to_string() == slug);
+    }
+    let host = normalize_host(host?);
+    if host.is_empty() {
+        return None;
+    }
+    shops
+        .iter()
+        .find(|s| s.config.hosts.iter().any(|h| normalize_host(h) == host))
+}
+
+async fn load_site_shops(
+    shop_service: &actix::Addr<rt_types::shop::service::ShopService>,
+) -> Result<Vec<SiteShop>, crate::control::ControllerError> {
+    Ok(shop_service
+        .send(rt_types::shop::service::List)
+        .await??
+        .into_iter()
+        .map(|shop| SiteShop {
+            config: site_publish::load_site_api_config(&shop.id),
+            shop,
+        })
+        .collect())
+}
+
+fn api_key_owner<'a>(shops: &'a [SiteShop], provided: &str) -> Option<&'a SiteShop> {
+    shops
+        .iter()
+        .find(|s| s.config.api_key.as_deref().map(str::trim) == Some(provided))
+}
+
+// Ключ вибирає магазин, тож двом магазинам один ключ не можна
+pub(crate) async fn site_api_key_taken(
+    shop_service: &actix::Addr<rt_types::shop::service::ShopService>,
+    shop_id: uuid::Uuid,
+    api_key: &str,
+) -> Result<bool, crate::control::ControllerError> {
+    let shops = load_site_shops(shop_service).await?;
+    Ok(api_key_owner(&shops, api_key.trim()).is_some_and(|owner| owner.shop.id != shop_id))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SiteAddressConflict {
+    Slug(String),
+    Host(String),
+}
+
+// Запит іде до першого магазину з таким slug чи host, тож другий їх не отримає
+fn site_address_conflict(
+    shops: &[SiteShop],
+    shop_id: uuid::Uuid,
+    slug: Option<&str>,
+    hosts: &[String],
+) -> Option<SiteAddressConflict> {
+    let others = || shops.iter().filter(|s| s.shop.id != shop_id);
+    if let Some(slug) = slug.map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty()) {
+        if others().any(|s| site_shop_slug(s) == slug || s.shop.id.to_string() == slug) {
+            return Some(SiteAddressConflict::Slug(slug));
+        }
+    }
+    hosts
+        .iter()
+        .map(|h| normalize_host(h))
+        .filter(|h| !h.is_empty())
+        .find(|host| others().any(|s| s.config.hosts.iter().any(|h| normalize_host(h) == *host)))
+        .map(SiteAddressConflict::Host)
+}
+
+pub(crate) async fn site_address_taken(
+    shop_service: &actix::Addr<rt_types::shop::service::ShopService>,
+    shop_id: uuid::Uuid,
+    slug: Option<&str>,
+    hosts: &[String],
+) -> Result<Option<SiteAddressConflict>, crate::control::ControllerError> {
+    let shops = load_site_shops(shop_service).await?;
+    Ok(site_address_conflict(&shops, shop_id, slug, hosts))
+}
+
+async fn load_site_shops_cached(
+    shop_service: &actix::Addr<rt_types::shop::service::ShopService>,
+) -> Result<Arc<Vec<SiteShop>>, crate::control::ControllerError> {
+    {
+        let cache = SITE_SHOPS_CACHE.read().await;
+        if let Some((cached_at, shops)) = cache.as_ref() {
+            if cached_at.elapsed() < *PRIMARY_SHOP_CACHE_TTL {
+                return Ok(shops.clone());
+            }
+        }
+    }
+    let shops = Arc::new(load_site_shops(shop_service).await?);
+    let mut cache = SITE_SHOPS_CACHE.write().await;
+    *cache = Some((Instant::now(), shops.clone()));
+    Ok(shops)
+}
+
+pub(crate) async fn invalidate_site_shops_cache() {
+    *SITE_SHOPS_CACHE.write().await = None;
+    *PRIMARY_SHOP_CACHE.write().await = None;
+}
+
+// Ключ магазину вибирає лише свій магазин, SITE_API_KEY — за slug чи host або основний
+pub(crate) async fn resolve_site_shop(
+    req: &HttpRequest,
+    shop_service: &actix::Addr<rt_types::shop::service::ShopService>,
+    shop_product_repo: &Arc<dyn shop_product::ShopProductRepository>,
+    product_category_repo: &Arc<dyn product_category::ProductCategoryRepository>,
+) -> Result<Option<rt_types::shop::Shop>, crate::control::ControllerError> {
+    let provided = provided_api_key(req).ok_or(crate::control::ControllerError::Forbidden)?;
+    let shops = load_site_shops_cached(shop_service).await?;
+    let slug = requested_shop_slug(req);
+    let host = req.connection_info().host().to_string();
+    let requested = match_site_shop(&shops, slug.as_deref(), Some(&host));
+    if slug.is_some() && requested.is_none() {
+        return Err(crate::control::ControllerError::NotFound);
+    }
+
+    if let Some(owner) = api_key_owner(&shops, &provided) {
+        if requested.is_some_and(|r| r.shop.id != owner.shop.id) {
+            return Err(crate::control::ControllerError::Forbidden);
+        }
+        return Ok(Some(owner.shop.clone()));
+    }
+
+    if legacy_api_key().as_deref() != Some(provided.as_str()) {
+        return Err(crate::control::ControllerError::Forbidden);
+    }
+    match requested {
+        Some(entry) if entry.config.api_key.is_some() => {
+            Err(crate::control::ControllerError::Forbidden)
+        }
+        Some(entry) => Ok(Some(entry.shop.clone())),
+        None => {
+            let primary =
+                get_primary_shop_cached(shop_service, shop_product_repo, product_category_repo)
+                    .await;
+            let scoped = primary.as_ref().is_some_and(|p| {
+                shops
+                    .iter()
+                    .any(|s| s.shop.id == p.id && s.config.api_key.is_some())
+            });
+            if scoped {
+                return Err(crate::control::ControllerError::Forbidden);
+            }
+            Ok(primary)
+        }
     }
-    let provided = req
-        .headers()
-        .get("x-api-key")
-        .and_then(|v| v.to_str().ok())
-        .map(str::to_string);
-    Some(expected) == provided
 }
 
 fn slugify(input: &str) -> String {
@@ -1041,6 +1453,18 @@ fn normalize_filter(value: Option<&str>) -> Option<String> {
         .filter(|v| !v.is_empty())
 }
 
+fn normalize_filters(value: Option<&str>, normalize: fn(&str) -> String) -> Vec<String> {
+    let mut values = value
+        .unwrap_or_default()
+        .split(',')
+        .map(normalize)
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    values.sort();
+    values.dedup();
+    values
+}
+
 fn sanitize_review_photos(input: Option<Vec<String>>) -> Vec<String> {
     const MAX_PHOTOS: usize = 3;
     const MAX_LEN: usize = 1_000_000;
@@ -1118,7 +1542,7 @@ pub(crate) fn product_path_from_slug(slug: &str, article: &str) -> String {
     format!("/item/{slug}-{encoded_article}")
 }
 
-fn product_path(title: &str, brand: &str, model: &str, article: &str) -> String {
+pub(crate) fn product_path(title: &str, brand: &str, model: &str, article: &str) -> String {
     let slug = build_product_slug(title, brand, model, article);
     product_path_from_slug(&slug, article)
 }
@@ -1134,6 +1558,20 @@ pub(crate) fn canonical_from_path(path: &str) -> Option<String> {
     site_base().map(|base| format!("{base}{path}"))
 }
 
+// Фідам потрібні абсолютні посилання, без хоста їх пропускаємо
+pub(crate) fn shop_site_base(shop_id: &IdentityOf<rt_types::shop::Shop>) -> Option<String> {
+    let host = site_publish::load_site_api_config(shop_id)
+        .hosts
+        .into_iter()
+        .map(|h| h.trim().trim_end_matches('/').to_string())
+        .find(|h| !h.is_empty())?;
+    if host.starts_with("http://") || host.starts_with("https://") {
+        Some(host)
+    } else {
+        Some(format!("https://{host}"))
+    }
+}
+
 fn trim_to(input: &str, max: usize) -> String {
     if input.chars().count() <= max {
         return input.to_string();
@@ -1246,6 +1684,18 @@ fn validate_seo_ready(
     }
 }
 
+fn union_indices(index: &HashMap<String, Vec<usize>>, values: &[String]) -> Vec<usize> {
+    let mut result = values
+        .iter()
+        .filter_map(|value| index.get(value))
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
 fn intersect_indices(indices_list: Vec<&[usize]>) -> Vec<usize> {
     if indices_list.is_empty() {
         return Vec::new();
@@ -1327,10 +1777,12 @@ pub async fn list_products(
     category_repo: Data<Arc<dyn CategoryRepository>>,
     product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    order_repo: Data<Arc<dyn order::OrderRepository>>,
     params: Query<ProductsQuery>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -1338,13 +1790,7 @@ pub async fn list_products(
         });
     }
 
-    let shop = match get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    {
+    let shop = match shop {
         Some(s) => s,
         None => return Ok(actix_web::HttpResponse::Ok().json(Vec::<ProductDto>::new())),
     };
@@ -1361,19 +1807,43 @@ pub async fn list_products(
 
     // Get indexes from cache for efficient filtering
     let cache = SITE_PRODUCTS_CACHE.read().await;
-    let indexes = cache.as_ref().map(|c| (
+    let indexes = cache.get(&shop.id).map(|c| (
         c.by_brand_slug.clone(),
         c.by_model_slug.clone(),
         c.by_category_slug.clone(),
+        c.by_supplier.clone(),
         c.hit_indices.clone(),
+        c.search.clone(),
+        c.sort_ranks.clone(),
     ));
     drop(cache);
 
+    let sort = params
+        .sort
+        .as_deref()
+        .unwrap_or_default()
+        .parse::<ProductSort>()
+        .map_err(|_| crate::control::ControllerError::InvalidInput {
+            field: "sort".to_string(),
+            msg: "Unknown sort order".to_string(),
+        })?;
+    let cursor = match params.cursor.as_deref().filter(|c| !c.trim().is_empty()) {
+        Some(value) => Some(
+            ProductsCursor::decode(value)
+                .filter(|c| c.sort == sort)
+                .ok_or_else(|| crate::control::ControllerError::InvalidInput {
+                    field: "cursor".to_string(),
+                    msg: "Cursor is invalid or belongs to another sort order".to_string(),
+                })?,
+        ),
+        None => None,
+    };
     let offset = params.offset.unwrap_or(0);
     let limit = params.limit.unwrap_or(24).max(1).min(30);
-    let brand_filter = normalize_filter(params.brand.as_deref());
-    let model_filter = normalize_filter(params.model.as_deref());
-    let category_filter = normalize_filter(params.category.as_deref());
+    let brand_filter = normalize_filters(params.brand.as_deref(), slugify_latin);
+    let model_filter = normalize_filters(params.model.as_deref(), slugify_latin);
+    let category_filter = normalize_filters(params.category.as_deref(), slugify_latin);
+    let supplier_filter = normalize_filters(params.supplier.as_deref(), |s| s.trim().to_lowercase());
     let query = params
         .q
         .as_ref()
@@ -1382,55 +1852,36 @@ pub async fn list_products(
     let want_total = params.include_total.unwrap_or(false);
     let compact = params.compact.unwrap_or(false);
     let hit_only = params.hit.unwrap_or(false);
+    let available_only = params.available_only.unwrap_or(false);
+    let with_facets = params.facets.unwrap_or(false);
 
+    let mut scores = HashMap::<usize, f32>::new();
+    let mut ranks = None;
     // Build candidate indices using indexes
-    let candidate_indices = if let Some((by_brand_slug, by_model_slug, by_category_slug, hit_indices)) = indexes {
-        let mut index_sets: Vec<&[usize]> = Vec::new();
-        let mut hit_vec: Option<Vec<usize>> = None;
-        
-        if let Some(ref brand_filter) = brand_filter {
-            if let Some(indices) = by_brand_slug.get(brand_filter) {
-                index_sets.push(indices.as_slice());
-            } else {
-                // No matches for brand, return empty result
-                return Ok(actix_web::HttpResponse::Ok().json(Vec::<ProductDto>::new()));
-            }
-        }
-        
-        if let Some(ref model_filter) = model_filter {
-            if let Some(indices) = by_model_slug.get(model_filter) {
-                index_sets.push(indices.as_slice());
-            } else {
-                // No matches for model, return empty result
-                return Ok(actix_web::HttpResponse::Ok().json(Vec::<ProductDto>::new()));
-            }
-        }
-        
-        if let Some(ref category_filter) = category_filter {
-            if let Some(indices) = by_category_slug.get(category_filter) {
-                index_sets.push(indices.as_slice());
-            } else {
-                // No matches for category, return empty result
-                return Ok(actix_web::HttpResponse::Ok().json(Vec::<ProductDto>::new()));
+    let mut candidate_indices = if let Some((by_brand_slug, by_model_slug, by_category_slug, by_supplier, hit_indices, search, sort_ranks)) = indexes {
+        ranks = Some(sort_ranks);
+        // Values of one filter are alternatives, different filters narrow down
+        let mut index_sets: Vec<Vec<usize>> = Vec::new();
+        for (index, values) in [
+            (&by_brand_slug, &brand_filter),
+            (&by_model_slug, &model_filter),
+            (&by_category_slug, &category_filter),
+            (&by_supplier, &supplier_filter),
+        ] {
+            if !values.is_empty() {
+                index_sets.push(union_indices(index, values));
             }
         }
         
         if hit_only {
-            let collected: Vec<usize> = hit_indices.iter().copied().collect();
-            if collected.is_empty() {
-                // No hits, return empty result
-                return Ok(actix_web::HttpResponse::Ok().json(Vec::<ProductDto>::new()));
-            }
-            hit_vec = Some(collected);
-        }
-
-        if let Some(ref vec) = hit_vec {
-            index_sets.push(vec.as_slice());
+            let mut collected: Vec<usize> = hit_indices.iter().copied().collect();
+            collected.sort_unstable();
+            index_sets.push(collected);
         }
         
         // Intersect all index sets
         let mut candidates = if !index_sets.is_empty() {
-            intersect_indices(index_sets)
+            intersect_indices(index_sets.iter().map(|v| v.as_slice()).collect())
         } else {
             // No filters, use all indices
             (0..items.len()).collect()
@@ -1438,10 +1889,13 @@ pub async fn list_products(
         
         // Apply query filter if present
         if let Some(ref query) = query {
-            candidates.retain(|&idx| {
-                let item = &items[idx];
-                item.search_blob.contains(query) || item.article_lower.contains(query)
-            });
+            let allowed = candidates.into_iter().collect::<HashSet<_>>();
+            scores = search
+                .search(query)
+                .into_iter()
+                .filter(|(idx, _)| allowed.contains(idx))
+                .collect();
+            candidates = scores.keys().copied().collect();
         }
         
         candidates
@@ -1449,16 +1903,81 @@ pub async fn list_products(
         // Fallback to linear scan if cache not available
         (0..items.len()).collect()
     };
+    if params.price_min.is_some() || params.price_max.is_some() || available_only {
+        candidate_indices.retain(|&idx| {
+            let dto = &items[idx].dto;
+            let price_ok = match (params.price_min, params.price_max) {
+                (None, None) => true,
+                (min, max) => dto.price.is_some_and(|price| {
+                    min.is_none_or(|min| price >= min) && max.is_none_or(|max| price <= max)
+                }),
+            };
+            price_ok && (!available_only || dto.available == rt_types::Availability::Available)
+        });
+    }
 
     let total_matched = candidate_indices.len();
-    let paginated_indices: Vec<usize> = candidate_indices
+    let facets = with_facets.then(|| {
+        site_search::facets(candidate_indices.iter().map(|idx| {
+            let item = &items[*idx];
+            site_search::FacetDoc {
+                brand: (&item.brand_slug, &item.dto.brand),
+                model: (&item.model_slug, &item.dto.model),
+                category: (&item.category_slug, item.dto.category.as_deref()),
+                price: item.dto.price,
+                available: &item.dto.available,
+            }
+        }))
+    });
+
+    let sold = if sort == ProductSort::Popularity {
+        load_sold_quantities_cached(shop.id, &order_repo).await
+    } else {
+        Default::default()
+    };
+    let ranks = ranks.unwrap_or_else(|| Arc::new(sort_ranks(&items)));
+    let key = |idx: usize| -> Option<(i64, usize)> {
+        let primary = match sort {
+            // scores are never negative, so their bits order like them
+            ProductSort::Relevance if query.is_some() => -(scores.get(&idx)?.to_bits() as i64),
+            ProductSort::Relevance => 0,
+            ProductSort::Popularity => {
+                -(sold.get(&items[idx].article_lower).copied().unwrap_or(0) as i64)
+            }
+            _ => *ranks.get(&sort)?.get(idx)? as i64,
+        };
+        Some((primary, idx))
+    };
+    let sort_value = |idx: usize| -> SortValue {
+        match sort {
+            ProductSort::Relevance | ProductSort::Popularity => {
+                SortValue::Number(key(idx).map_or(i64::MAX, |(primary, _)| primary))
+            }
+            _ => sort.value(&items[idx]),
+        }
+    };
+    let ordered = candidate_indices
         .into_iter()
-        .skip(offset)
-        .take(if want_total { usize::MAX } else { limit })
-        .collect();
+        .filter(|idx| {
+            cursor
+                .as_ref()
+                .is_none_or(|c| (sort_value(*idx), *idx) > (c.value.clone(), c.index))
+        })
+        .filter_map(key)
+        .collect::<Vec<_>>();
+    let start = if cursor.is_some() { 0 } else { offset };
+    let (page, has_more) = select_page(ordered, start, limit);
+    let next_cursor = page.last().filter(|_| has_more).map(|(_, idx)| {
+        ProductsCursor {
+            sort,
+            value: sort_value(*idx),
+            index: *idx,
+        }
+        .encode()
+    });
 
-    let mut slice = Vec::with_capacity(paginated_indices.len().min(limit));
-    for idx in paginated_indices.iter().take(limit) {
+    let mut slice = Vec::with_capacity(page.len());
+    for (_, idx) in &page {
         let item = &items[*idx];
         let mut dto = item.dto.clone();
         if compact {
@@ -1485,9 +2004,50 @@ pub async fn list_products(
     if want_total {
         resp.insert_header(("X-Total-Count", total_matched.to_string()));
     }
+    if let Some(next_cursor) = next_cursor.as_ref() {
+        resp.insert_header(("X-Next-Cursor", next_cursor.clone()));
+    }
+    if let Some(facets) = facets {
+        return Ok(resp.json(ProductsPage {
+            items: slice,
+            total: total_matched,
+            facets,
+            next_cursor,
+        }));
+    }
     Ok(resp.json(slice))
 }
 
+async fn load_sold_quantities_cached(
+    shop_id: uuid::Uuid,
+    order_repo: &Arc<dyn order::OrderRepository>,
+) -> Arc<HashMap<String, usize>> {
+    {
+        let cache = SOLD_QUANTITIES_CACHE.read().await;
+        if let Some(entry) = cache.get(&shop_id) {
+            if entry.cached_at.elapsed() < *SITE_PRODUCTS_CACHE_TTL {
+                return entry.sold.clone();
+            }
+        }
+    }
+    let sold = match order_repo.sold_quantities(shop_id).await {
+        Ok(sold) => Arc::new(sold),
+        Err(err) => {
+            log::error!("Unable to count sold products: {err}");
+            return Default::default();
+        }
+    };
+    let mut cache = SOLD_QUANTITIES_CACHE.write().await;
+    cache.insert(
+        shop_id,
+        SoldQuantitiesCache {
+            cached_at: Instant::now(),
+            sold: sold.clone(),
+        },
+    );
+    sold
+}
+
 #[get("/api/site/categories")]
 pub async fn list_categories(
     dt_repo: Data<Arc<dyn dt::product::ProductRepository + Send>>,
@@ -1497,7 +2057,8 @@ pub async fn list_categories(
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -1505,13 +2066,7 @@ pub async fn list_categories(
         });
     }
 
-    let shop = match get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    {
+    let shop = match shop {
         Some(s) => s,
         None => {
             let mut resp = actix_web::HttpResponse::Ok();
@@ -1696,7 +2251,8 @@ pub async fn list_car_categories(
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -1704,13 +2260,7 @@ pub async fn list_car_categories(
         });
     }
 
-    let shop = match get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    {
+    let shop = match shop {
         Some(s) => s,
         None => {
             let mut resp = actix_web::HttpResponse::Ok();
@@ -1957,7 +2507,8 @@ pub async fn list_model_categories(
     params: Query<ModelCategoriesQuery>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -1965,13 +2516,7 @@ pub async fn list_model_categories(
         });
     }
 
-    let shop = match get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    {
+    let shop = match shop {
         Some(s) => s,
         None => {
             let mut resp = actix_web::HttpResponse::Ok();
@@ -2024,9 +2569,12 @@ pub async fn create_quick_order(
     req: HttpRequest,
     payload: Json<QuickOrderRequest>,
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
     quick_order_repo: Data<Arc<dyn quick_order::QuickOrderRepository>>,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     let phone = payload.phone.trim();
     let re = Regex::new(r"^\+380\d{9}$").unwrap();
     if !re.is_match(phone) {
@@ -2035,13 +2583,8 @@ pub async fn create_quick_order(
             "error": "invalid_phone"
         })));
     }
-    let shops = shop_service
-        .send(rt_types::shop::service::List)
-        .await??
-        .into_iter()
-        .collect::<Vec<_>>();
-    let shop = match shops.get(0) {
-        Some(s) => s.clone(),
+    let shop = match shop {
+        Some(s) => s,
         None => {
             return Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
                 "ok": false,
@@ -2065,10 +2608,17 @@ pub async fn create_quick_order(
 pub async fn create_order(
     req: HttpRequest,
     payload: Json<OrderRequest>,
+    dt_repo: Data<Arc<dyn dt::product::ProductRepository + Send>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    category_repo: Data<Arc<dyn CategoryRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
     order_repo: Data<Arc<dyn order::OrderRepository>>,
+    customer_repo: Data<Arc<dyn customer::CustomerRepository>>,
+    np_repo: Data<Arc<dyn nova_poshta::NovaPoshtaRepository>>,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     let phone = payload.phone.trim();
     if phone.is_empty() {
         return Ok(actix_web::HttpResponse::BadRequest().json(serde_json::json!({
@@ -2083,13 +2633,8 @@ pub async fn create_order(
         })));
     }
 
-    let shops = shop_service
-        .send(rt_types::shop::service::List)
-        .await??
-        .into_iter()
-        .collect::<Vec<_>>();
-    let shop = match shops.get(0) {
-        Some(s) => s.clone(),
+    let shop = match shop {
+        Some(s) => s,
         None => {
             return Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
                 "ok": false,
@@ -2106,24 +2651,76 @@ pub async fn create_order(
     }
     let customer_name = name_parts.join(" ").trim().to_string();
 
-    let items = payload
-        .items
-        .iter()
-        .map(|item| {
-            let quantity = item.quantity.unwrap_or(1).max(1);
-            order::OrderItem {
-                article: item.article.clone(),
-                title: item.title.clone(),
-                price: item.price,
-                quantity,
+    let non_empty = |v: &Option<String>| {
+        v.as_ref()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let mut city_name = non_empty(&payload.city_name);
+    let mut branch_name = non_empty(&payload.branch_name);
+    let mut city_ref = non_empty(&payload.city_ref);
+    let branch_ref = non_empty(&payload.branch_ref);
+    if let Some(branch_ref) = branch_ref.as_deref() {
+        let warehouse = match np_repo.warehouse(branch_ref).await? {
+            Some(w) if city_ref.as_ref().is_none_or(|c| *c == w.city_ref) => w,
+            _ => {
+                return Ok(actix_web::HttpResponse::BadRequest().json(serde_json::json!({
+                    "ok": false,
+                    "error": "unknown_branch"
+                })))
             }
-        })
-        .collect::<Vec<_>>();
+        };
+        city_ref = Some(warehouse.city_ref);
+        branch_name = Some(warehouse.name);
+    }
+    if let Some(city_ref) = city_ref.as_deref() {
+        match np_repo.city(city_ref).await? {
+            Some(city) => city_name = Some(city.name),
+            None => {
+                return Ok(actix_web::HttpResponse::BadRequest().json(serde_json::json!({
+                    "ok": false,
+                    "error": "unknown_city"
+                })))
+            }
+        }
+    }
 
-    let total = items
+    let allowed_suppliers = site_publish::load_site_publish_suppliers(&shop.id);
+    let (products, by_article) = load_site_products_cached(
+        &shop,
+        &allowed_suppliers,
+        &dt_repo,
+        &shop_product_repo,
+        &category_repo,
+        &product_category_repo,
+    )
+    .await;
+    let lines = payload
+        .items
         .iter()
-        .map(|item| item.price.unwrap_or(0) as i64 * item.quantity as i64)
-        .sum::<i64>();
+        .map(|item| (item.article.clone(), item.quantity.unwrap_or(1)))
+        .collect::<Vec<_>>();
+    let hidden = shop_product::Visibility::Hidden.as_str();
+    let priced = order::price_order_lines(&lines, |article| {
+        let idx = by_article.get(&article.to_lowercase())?;
+        let dto = &products.get(*idx)?.dto;
+        Some(order::CatalogItem {
+            title: dto.title.clone(),
+            price: dto.price,
+            available: dto.available.clone(),
+            visible: dto.visibility_on_site.as_deref() != Some(hidden),
+        })
+    });
+    let (items, total) = match priced {
+        Ok(priced) => priced,
+        Err(issues) => {
+            return Ok(actix_web::HttpResponse::BadRequest().json(serde_json::json!({
+                "ok": false,
+                "error": "invalid_items",
+                "items": issues
+            })))
+        }
+    };
 
     let items_json = serde_json::to_string(&items).map_err(|err| anyhow!(err))?;
     let created_at = OffsetDateTime::now_utc().unix_timestamp();
@@ -2133,16 +2730,8 @@ pub async fn create_order(
         phone: phone.to_string(),
         email: payload.email.clone().map(|v| v.trim().to_string()).filter(|v| !v.is_empty()),
         delivery: payload.delivery.trim().to_string(),
-        city_name: payload
-            .city_name
-            .clone()
-            .map(|v| v.trim().to_string())
-            .filter(|v| !v.is_empty()),
-        branch_name: payload
-            .branch_name
-            .clone()
-            .map(|v| v.trim().to_string())
-            .filter(|v| !v.is_empty()),
+        city_name,
+        branch_name,
         payment: payload.payment.trim().to_string(),
         total,
         items_count: items.len(),
@@ -2153,11 +2742,17 @@ pub async fn create_order(
             .map(|v| v.trim().to_string())
             .filter(|v| !v.is_empty()),
         created_at,
+        customer_id: customer_from_request(&req, &customer_repo, shop.id)
+            .await?
+            .map(|c| c.id),
+        city_ref,
+        branch_ref,
     };
     let order = order_repo.add(item).await?;
     Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
         "ok": true,
-        "id": order.id
+        "id": order.id,
+        "total": order.total
     })))
 }
 
@@ -2165,10 +2760,13 @@ pub async fn create_order(
 pub async fn list_reviews(
     review_repo: Data<Arc<dyn review::ReviewRepository>>,
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
     params: Query<ReviewsQuery>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -2176,13 +2774,8 @@ pub async fn list_reviews(
         });
     }
 
-    let shops = shop_service
-        .send(rt_types::shop::service::List)
-        .await??
-        .into_iter()
-        .collect::<Vec<_>>();
-    let shop = match shops.get(0) {
-        Some(s) => s.clone(),
+    let shop = match shop {
+        Some(s) => s,
         None => {
             let mut resp = actix_web::HttpResponse::Ok();
             resp.insert_header(("Cache-Control", "public, max-age=300"));
@@ -2223,6 +2816,8 @@ pub async fn list_reviews(
             rating: item.rating,
             photos: item.photos,
             created_at: item.created_at.saturating_mul(1000),
+            reply: item.reply,
+            replied_at: item.replied_at.map(|t| t.saturating_mul(1000)),
         })
         .collect::<Vec<_>>();
 
@@ -2231,14 +2826,55 @@ pub async fn list_reviews(
     Ok(resp.json(dtos))
 }
 
+#[get("/api/site/reviews/rating")]
+pub async fn review_ratings(
+    review_repo: Data<Arc<dyn review::ReviewRepository>>,
+    shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
+    params: Query<ReviewRatingsQuery>,
+    req: HttpRequest,
+) -> Response {
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
+    if let Err(e) = check_api_rate_limit(&req).await {
+        return Err(crate::control::ControllerError::TooManyRequests {
+            retry_after: e.retry_after,
+            message: e.message,
+        });
+    }
+
+    let mut resp = actix_web::HttpResponse::Ok();
+    resp.insert_header(("Cache-Control", "public, max-age=300"));
+    let shop = match shop {
+        Some(s) => s,
+        None => return Ok(resp.json(Vec::<ReviewRatingDto>::new())),
+    };
+    let product_key = review::normalize_product_key(params.product.as_deref());
+    let dtos = review_repo
+        .ratings(shop.id, product_key)
+        .await?
+        .into_iter()
+        .map(|r| ReviewRatingDto {
+            product: r.product_key,
+            rating_value: (r.average * 10.0).round() / 10.0,
+            review_count: r.count,
+        })
+        .collect::<Vec<_>>();
+    Ok(resp.json(dtos))
+}
+
 #[post("/api/site/reviews")]
 pub async fn create_review(
     review_repo: Data<Arc<dyn review::ReviewRepository>>,
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
+    shop_product_repo: Data<Arc<dyn shop_product::ShopProductRepository>>,
+    product_category_repo: Data<Arc<dyn product_category::ProductCategoryRepository>>,
     payload: Json<ReviewCreateRequest>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -2246,13 +2882,8 @@ pub async fn create_review(
         });
     }
 
-    let shops = shop_service
-        .send(rt_types::shop::service::List)
-        .await??
-        .into_iter()
-        .collect::<Vec<_>>();
-    let shop = match shops.get(0) {
-        Some(s) => s.clone(),
+    let shop = match shop {
+        Some(s) => s,
         None => {
             return Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
                 "ok": false,
@@ -2273,6 +2904,7 @@ pub async fn create_review(
     let product_key = review::normalize_product_key(payload.product.as_deref());
     let photos = sanitize_review_photos(payload.photos.clone());
     let created_at = OffsetDateTime::now_utc().unix_timestamp();
+    let status = review::initial_status(&shop.reviews, &name, &text);
 
     let item = review_repo
         .add(review::NewReview {
@@ -2282,7 +2914,7 @@ pub async fn create_review(
             text: text.clone(),
             rating,
             photos: photos.clone(),
-            status: review::ReviewStatus::Published,
+            status,
             created_at,
         })
         .await?;
@@ -2294,10 +2926,13 @@ pub async fn create_review(
         rating,
         photos,
         created_at: created_at.saturating_mul(1000),
+        reply: None,
+        replied_at: None,
     };
 
     Ok(actix_web::HttpResponse::Ok().json(serde_json::json!({
         "ok": true,
+        "status": item.status.as_str(),
         "review": dto
     })))
 }
@@ -2313,7 +2948,8 @@ pub async fn list_seo_pages(
     params: Query<SeoPagesQuery>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -2321,13 +2957,7 @@ pub async fn list_seo_pages(
         });
     }
 
-    let shop = match get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    {
+    let shop = match shop {
         Some(s) => s,
         None => {
             let mut resp = actix_web::HttpResponse::Ok();
@@ -2425,7 +3055,8 @@ pub async fn get_seo_page(
     path: Path<(String, String)>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
@@ -2440,13 +3071,7 @@ pub async fn get_seo_page(
         return Err(crate::control::ControllerError::NotFound);
     }
 
-    let shop = get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    .ok_or(crate::control::ControllerError::NotFound)?;
+    let shop = shop.ok_or(crate::control::ControllerError::NotFound)?;
 
     let mut page = seo_page_repo
         .get_by_slug(shop.id, &slug)
@@ -2543,20 +3168,15 @@ pub async fn get_product(
     article: Path<String>,
     req: HttpRequest,
 ) -> Response {
-    ensure_api_key(&req)?;
+    let shop = resolve_site_shop(&req, &shop_service, &shop_product_repo, &product_category_repo)
+        .await?;
     if let Err(e) = check_api_rate_limit(&req).await {
         return Err(crate::control::ControllerError::TooManyRequests {
             retry_after: e.retry_after,
             message: e.message,
         });
     }
-    let shop = get_primary_shop_cached(
-        &shop_service,
-        &shop_product_repo,
-        &product_category_repo,
-    )
-    .await
-    .ok_or(crate::control::ControllerError::NotFound)?;
+    let shop = shop.ok_or(crate::control::ControllerError::NotFound)?;
     let article = article.into_inner();
     let article = article.trim().to_string();
     if article.is_empty() {
@@ -2598,17 +3218,21 @@ pub async fn sitemap(
     shop_service: Data<actix::Addr<rt_types::shop::service::ShopService>>,
     req: HttpRequest,
 ) -> Response {
-    if !api_key_valid(&req) {
-        return Ok(actix_web::HttpResponse::Unauthorized().finish());
-    }
-
-    let shop = match get_primary_shop_cached(
+    let shop = match resolve_site_shop(
+        &req,
         &shop_service,
         &shop_product_repo,
         &product_category_repo,
     )
     .await
     {
+        Ok(shop) => shop,
+        Err(crate::control::ControllerError::Forbidden) => {
+            return Ok(actix_web::HttpResponse::Unauthorized().finish())
+        }
+        Err(err) => return Err(err),
+    };
+    let shop = match shop {
         Some(s) => s,
         None => {
             let mut resp = actix_web::HttpResponse::Ok();
@@ -2834,3 +3458,164 @@ pub async fn sitemap(
     resp.insert_header(("Cache-Control", "public, max-age=300"));
     Ok(resp.json(entries))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        api_key_owner, match_site_shop, normalize_filters, normalize_host, rate_limit,
+        select_page, site_address_conflict, slugify_latin, ProductSort, ProductsCursor,
+        SiteAddressConflict, SiteShop, SortValue,
+    };
+    use crate::site_publish::SiteApiConfig;
+
+    fn site_shop(name: &str, api_key: Option<&str>, hosts: &[&str]) -> SiteShop {
+        SiteShop {
+            shop: rt_types::shop::Shop {
+                id: uuid::Uuid::new_v4(),
+                is_suspended: false,
+                name: name.to_string(),
+                owner: rt_types::access::Login("owner".to_string()),
+                export_entries: vec![],
+                site_import_entries: vec![],
+                limits: None,
+                default_custom_options: None,
+                image_proxy: false,
+                currency: Default::default(),
+                watermark_hosts: vec![],
+                prom: Default::default(),
+                reviews: Default::default(),
+                nova_poshta: Default::default(),
+                feeds: Default::default(),
+            },
+            config: SiteApiConfig {
+                api_key: api_key.map(str::to_string),
+                hosts: hosts.iter().map(|h| h.to_string()).collect(),
+                slug: None,
+            },
+        }
+    }
+
+    #[test]
+    fn trusts_forwarded_addresses_only_from_proxies() {
+        let proxy: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let client = Some("203.0.113.7".parse().unwrap());
+        let trusted = [proxy];
+        let forwarded = Some("1.1.1.1, 198.51.100.2, 10.0.0.1");
+        let ip = |peer, forwarded, real_ip, trusted: &[std::net::IpAddr]| {
+            rate_limit::client_ip(peer, forwarded, real_ip, trusted)
+        };
+        assert_eq!(
+            ip(client, forwarded, Some("1.1.1.1"), &trusted).as_deref(),
+            Some("203.0.113.7")
+        );
+        assert_eq!(
+            ip(Some(proxy), forwarded, None, &trusted).as_deref(),
+            Some("198.51.100.2")
+        );
+        assert_eq!(
+            ip(Some(proxy), None, Some("198.51.100.3"), &trusted).as_deref(),
+            Some("198.51.100.3")
+        );
+        assert_eq!(ip(Some(proxy), Some("bogus"), None, &trusted), None);
+        // Проксі, що не передав адресу клієнта, не стає спільним ключем лімітів
+        assert_eq!(ip(Some(proxy), None, None, &trusted), None);
+        assert_eq!(
+            ip(Some(proxy), forwarded, None, &[]).as_deref(),
+            Some("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn resolves_shops_by_key_slug_and_host() {
+        let shops = vec![
+            site_shop("Legacy", None, &[]),
+            site_shop("Авто Дім", Some(" key-a "), &["shop-a.com"]),
+            site_shop("Second", Some("key-b"), &[]),
+        ];
+        let owner = api_key_owner(&shops, "key-b").expect("shop by key");
+        assert_eq!(owner.shop.id, shops[2].shop.id);
+        let owner = api_key_owner(&shops, "key-a").expect("trimmed stored key");
+        assert_eq!(owner.shop.id, shops[1].shop.id);
+        assert!(api_key_owner(&shops, "key-c").is_none());
+        assert!(api_key_owner(&shops, "").is_none());
+
+        let by_host = match_site_shop(&shops, None, Some("www.shop-a.com:443"));
+        assert_eq!(by_host.map(|s| s.shop.id), Some(shops[1].shop.id));
+        let by_slug = match_site_shop(&shops, Some("avto-dim"), None);
+        assert_eq!(by_slug.map(|s| s.shop.id), Some(shops[1].shop.id));
+
+        let own = shops[1].shop.id;
+        let other = shops[2].shop.id;
+        let hosts = |h: &[&str]| h.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            site_address_conflict(&shops, other, Some("Avto-Dim"), &[]),
+            Some(SiteAddressConflict::Slug("avto-dim".to_string()))
+        );
+        assert_eq!(
+            site_address_conflict(&shops, other, None, &hosts(&["https://WWW.shop-a.com/"])),
+            Some(SiteAddressConflict::Host("shop-a.com".to_string()))
+        );
+        assert_eq!(
+            site_address_conflict(&shops, own, Some("avto-dim"), &hosts(&["shop-a.com"])),
+            None
+        );
+    }
+
+    #[test]
+    fn normalizes_hosts_for_matching() {
+        assert_eq!(normalize_host("Shop.Example.com:8080"), "shop.example.com");
+        assert_eq!(normalize_host("https://www.example.com/catalog"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("[::1]:3000"), "[::1]");
+    }
+
+    #[test]
+    fn selects_pages_in_key_order() {
+        let keys = (0..50usize)
+            .map(|idx| (((idx * 37) % 7) as i64, idx))
+            .collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        let mut pages = vec![];
+        let mut after = None;
+        loop {
+            let rest = keys
+                .iter()
+                .copied()
+                .filter(|k| after.is_none_or(|after| *k > after))
+                .collect();
+            let (page, has_more) = select_page(rest, 0, 12);
+            after = page.last().copied();
+            pages.extend(page);
+            if !has_more {
+                break;
+            }
+        }
+        assert_eq!(pages, sorted);
+        assert_eq!(select_page(keys.clone(), 48, 12), (sorted[48..].to_vec(), false));
+        assert_eq!(select_page(keys, 60, 12), (vec![], false));
+    }
+
+    #[test]
+    fn parses_sorts_filters_and_cursors() {
+        assert_eq!("price_desc".parse::<ProductSort>(), Ok(ProductSort::PriceDesc));
+        assert_eq!("".parse::<ProductSort>(), Ok(ProductSort::Relevance));
+        assert!("cheapest".parse::<ProductSort>().is_err());
+        assert_eq!(
+            normalize_filters(Some("Audi, BMW,,audi"), slugify_latin),
+            vec!["audi".to_string(), "bmw".to_string()]
+        );
+        assert!(SortValue::Number(-5) < SortValue::Number(3));
+
+        let cursor = ProductsCursor {
+            sort: ProductSort::Title,
+            value: SortValue::Text("бампер".to_string()),
+            index: 7,
+        };
+        let decoded = ProductsCursor::decode(&cursor.encode()).expect("valid cursor");
+        assert_eq!(decoded.sort, ProductSort::Title);
+        assert_eq!(decoded.value, SortValue::Text("бампер".to_string()));
+        assert_eq!(decoded.index, 7);
+        assert!(ProductsCursor::decode("not a cursor").is_none());
+    }
+}
@@ -0,0 +1,1202 @@
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rand::Rng;
+use rt_types::access::{generate_salt, DEFAULT_ARGON_CONFIG};
+use rusqlite::{params, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio_rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::SqlWrapper;
+
+pub const OTP_TTL_SECS: i64 = 10 * 60;
+pub const OTP_RESEND_SECS: i64 = 60;
+pub const OTP_MAX_ATTEMPTS: i64 = 5;
+pub const OTP_PHONE_LIMIT: i64 = 5;
+pub const OTP_IP_LIMIT: i64 = 20;
+pub const OTP_LIMIT_WINDOW_SECS: i64 = 24 * 60 * 60;
+pub const SESSION_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+pub const ADDRESS_DELIVERIES: [&str; 2] = ["nova-poshta-branch", "nova-poshta-courier"];
+
+// Покупець вітрини, не пов'язаний з `UserCredentials` персоналу
+#[derive(Debug, Clone)]
+pub struct Customer {
+    pub id: i64,
+    pub shop_id: Uuid,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub name: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewCustomer {
+    pub shop_id: Uuid,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub name: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomerFilter {
+    pub query: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl Default for CustomerFilter {
+    fn default() -> Self {
+        Self {
+            query: None,
+            limit: 50,
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomerPage {
+    pub items: Vec<Customer>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomerAddress {
+    pub id: i64,
+    pub customer_id: i64,
+    pub label: Option<String>,
+    pub recipient: Option<String>,
+    pub phone: Option<String>,
+    pub delivery: String,
+    pub city_name: Option<String>,
+    pub city_ref: Option<String>,
+    pub branch_name: Option<String>,
+    pub branch_ref: Option<String>,
+    pub street: Option<String>,
+    pub is_default: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAddress {
+    pub label: Option<String>,
+    pub recipient: Option<String>,
+    pub phone: Option<String>,
+    pub delivery: String,
+    pub city_name: Option<String>,
+    pub city_ref: Option<String>,
+    pub branch_name: Option<String>,
+    pub branch_ref: Option<String>,
+    pub street: Option<String>,
+    pub is_default: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Favorite {
+    pub article: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpIssue {
+    Issued,
+    TooSoon,
+    LimitReached,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpCheck {
+    Valid,
+    Invalid,
+    Missing,
+    TooManyAttempts,
+}
+
+#[async_trait]
+pub trait CustomerRepository: Send + Sync {
+    async fn add(&self, item: NewCustomer) -> anyhow::Result<Customer>;
+    async fn get(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Option<Customer>>;
+    async fn find_by_phone(&self, shop_id: Uuid, phone: &str) -> anyhow::Result<Option<Customer>>;
+    async fn find_by_email(&self, shop_id: Uuid, email: &str) -> anyhow::Result<Option<Customer>>;
+    async fn update(&self, item: Customer) -> anyhow::Result<Customer>;
+    async fn list_by_shop(
+        &self,
+        shop_id: Uuid,
+        filter: CustomerFilter,
+    ) -> anyhow::Result<CustomerPage>;
+
+    async fn issue_otp(
+        &self,
+        shop_id: Uuid,
+        phone: &str,
+        ip: Option<&str>,
+        code: &str,
+        now: i64,
+    ) -> anyhow::Result<OtpIssue>;
+    async fn check_otp(
+        &self,
+        shop_id: Uuid,
+        phone: &str,
+        code: &str,
+        now: i64,
+    ) -> anyhow::Result<OtpCheck>;
+
+    async fn create_session(&self, customer: &Customer, now: i64) -> anyhow::Result<String>;
+    async fn session(
+        &self,
+        shop_id: Uuid,
+        token: &str,
+        now: i64,
+    ) -> anyhow::Result<Option<Customer>>;
+    async fn remove_session(&self, token: &str) -> anyhow::Result<()>;
+    async fn remove_other_sessions(&self, customer_id: i64, token: &str) -> anyhow::Result<()>;
+
+    async fn addresses(&self, customer_id: i64) -> anyhow::Result<Vec<CustomerAddress>>;
+    async fn add_address(
+        &self,
+        customer_id: i64,
+        item: NewAddress,
+    ) -> anyhow::Result<CustomerAddress>;
+    async fn remove_address(&self, customer_id: i64, id: i64) -> anyhow::Result<bool>;
+
+    async fn favorites(&self, customer_id: i64) -> anyhow::Result<Vec<Favorite>>;
+    async fn add_favorite(&self, customer_id: i64, article: &str, now: i64) -> anyhow::Result<()>;
+    async fn remove_favorite(&self, customer_id: i64, article: &str) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+pub trait OtpSender: Send + Sync {
+    async fn send(&self, phone: &str, code: &str) -> anyhow::Result<()>;
+}
+
+pub struct LogOtpSender;
+
+#[async_trait]
+impl OtpSender for LogOtpSender {
+    async fn send(&self, phone: &str, code: &str) -> anyhow::Result<()> {
+        log::info!("Customer login code for {phone}: {code}");
+        Ok(())
+    }
+}
+
+pub struct FileOtpSender {
+    path: PathBuf,
+}
+
+impl FileOtpSender {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl OtpSender for FileOtpSender {
+    async fn send(&self, phone: &str, code: &str) -> anyhow::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{phone} {code}\n").as_bytes())
+            .await?;
+        Ok(())
+    }
+}
+
+pub fn normalize_phone(raw: &str) -> Option<String> {
+    let digits = raw
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<String>();
+    let digits = match digits.len() {
+        9 => format!("380{digits}"),
+        10 if digits.starts_with('0') => format!("38{digits}"),
+        11..=15 => digits,
+        _ => return None,
+    };
+    Some(format!("+{digits}"))
+}
+
+pub fn normalize_email(raw: &str) -> Option<String> {
+    let email = raw.trim().to_lowercase();
+    let (name, domain) = email.split_once('@')?;
+    let valid = !name.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.contains(char::is_whitespace);
+    valid.then_some(email)
+}
+
+pub fn generate_otp() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// Коди й токени зберігаються лише як цей дайджест
+fn digest(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+// Повільно навмисно, виконувати поза async
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    Ok(argon2::hash_encoded(
+        password.as_bytes(),
+        &generate_salt(),
+        &DEFAULT_ARGON_CONFIG,
+    )?)
+}
+
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
+
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| hash_password("dummy password").unwrap_or_default());
+
+// Без хеша перевіряє фіктивний, щоб невідомі акаунти займали стільки ж часу
+pub fn verify_password_or_dummy(hash: Option<&str>, password: &str) -> bool {
+    match hash {
+        Some(hash) => verify_password(hash, password),
+        None => {
+            verify_password(&DUMMY_HASH, password);
+            false
+        }
+    }
+}
+
+pub struct SqliteCustomerRepository {
+    conn: Connection,
+}
+
+impl SqliteCustomerRepository {
+    pub async fn init(conn: Connection) -> Result<Self, tokio_rusqlite::Error> {
+        conn.call(|conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS customer (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    shop_id TEXT NOT NULL,
+                    phone TEXT,
+                    email TEXT,
+                    password TEXT,
+                    name TEXT,
+                    created_at INTEGER NOT NULL,
+                    updated_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE UNIQUE INDEX IF NOT EXISTS customer_shop_phone_idx
+                 ON customer(shop_id, phone) WHERE phone IS NOT NULL",
+                [],
+            )?;
+            conn.execute(
+                "CREATE UNIQUE INDEX IF NOT EXISTS customer_shop_email_idx
+                 ON customer(shop_id, email) WHERE email IS NOT NULL",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS customer_otp (
+                    shop_id TEXT NOT NULL,
+                    phone TEXT NOT NULL,
+                    code TEXT NOT NULL,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    sent_at INTEGER NOT NULL,
+                    expires_at INTEGER NOT NULL,
+                    PRIMARY KEY (shop_id, phone)
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS customer_otp_request (
+                    shop_id TEXT NOT NULL,
+                    phone TEXT NOT NULL,
+                    ip TEXT NOT NULL,
+                    sent_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS customer_otp_request_sent_idx
+                 ON customer_otp_request(sent_at)",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS customer_session (
+                    token TEXT PRIMARY KEY,
+                    customer_id INTEGER NOT NULL,
+                    shop_id TEXT NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    expires_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS customer_address (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    customer_id INTEGER NOT NULL,
+                    label TEXT,
+                    recipient TEXT,
+                    phone TEXT,
+                    delivery TEXT NOT NULL,
+                    city_name TEXT,
+                    city_ref TEXT,
+                    branch_name TEXT,
+                    branch_ref TEXT,
+                    street TEXT,
+                    is_default INTEGER NOT NULL DEFAULT 0,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS customer_address_customer_idx
+                 ON customer_address(customer_id)",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS customer_favorite (
+                    customer_id INTEGER NOT NULL,
+                    article TEXT NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    PRIMARY KEY (customer_id, article)
+                )",
+                [],
+            )?;
+            Ok(())
+        })
+        .await?;
+        Ok(Self { conn })
+    }
+}
+
+const CUSTOMER_COLUMNS: &str = "id, shop_id, phone, email, password, name, created_at, updated_at";
+
+fn customer_from_row(row: &rusqlite::Row) -> rusqlite::Result<Customer> {
+    let shop_id: String = row.get(1)?;
+    Ok(Customer {
+        id: row.get(0)?,
+        shop_id: Uuid::parse_str(&shop_id).unwrap_or(Uuid::nil()),
+        phone: row.get(2)?,
+        email: row.get(3)?,
+        password: row.get(4)?,
+        name: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+fn select_customer(
+    conn: &rusqlite::Connection,
+    shop_id: Uuid,
+    column: &str,
+    value: rusqlite::types::Value,
+) -> rusqlite::Result<Option<Customer>> {
+    conn.query_row(
+        &format!("SELECT {CUSTOMER_COLUMNS} FROM customer WHERE shop_id = ?1 AND {column} = ?2"),
+        params![shop_id.to_string(), value],
+        customer_from_row,
+    )
+    .optional()
+}
+
+const ADDRESS_COLUMNS: &str = "id, customer_id, label, recipient, phone, delivery,
+    city_name, city_ref, branch_name, branch_ref, street, is_default, created_at";
+
+fn address_from_row(row: &rusqlite::Row) -> rusqlite::Result<CustomerAddress> {
+    Ok(CustomerAddress {
+        id: row.get(0)?,
+        customer_id: row.get(1)?,
+        label: row.get(2)?,
+        recipient: row.get(3)?,
+        phone: row.get(4)?,
+        delivery: row.get(5)?,
+        city_name: row.get(6)?,
+        city_ref: row.get(7)?,
+        branch_name: row.get(8)?,
+        branch_ref: row.get(9)?,
+        street: row.get(10)?,
+        is_default: row.get(11)?,
+        created_at: row.get(12)?,
+    })
+}
+
+pub fn is_unique_violation(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<tokio_rusqlite::Error>(),
+        Some(tokio_rusqlite::Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _)))
+            if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+    )
+}
+
+#[async_trait]
+impl CustomerRepository for SqliteCustomerRepository {
+    async fn add(&self, item: NewCustomer) -> anyhow::Result<Customer> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO customer (shop_id, phone, email, password, name, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+                    params![
+                        item.shop_id.to_string(),
+                        item.phone,
+                        item.email,
+                        item.password,
+                        item.name,
+                        item.created_at
+                    ],
+                )?;
+                Ok(SqlWrapper(Customer {
+                    id: conn.last_insert_rowid(),
+                    shop_id: item.shop_id,
+                    phone: item.phone,
+                    email: item.email,
+                    password: item.password,
+                    name: item.name,
+                    created_at: item.created_at,
+                    updated_at: item.created_at,
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn get(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Option<Customer>> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| Ok(SqlWrapper(select_customer(conn, shop_id, "id", id.into())?)))
+            .await?;
+        Ok(out)
+    }
+
+    async fn find_by_phone(&self, shop_id: Uuid, phone: &str) -> anyhow::Result<Option<Customer>> {
+        let phone = phone.to_string();
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                Ok(SqlWrapper(select_customer(
+                    conn,
+                    shop_id,
+                    "phone",
+                    phone.into(),
+                )?))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn find_by_email(&self, shop_id: Uuid, email: &str) -> anyhow::Result<Option<Customer>> {
+        let email = email.to_string();
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                Ok(SqlWrapper(select_customer(
+                    conn,
+                    shop_id,
+                    "email",
+                    email.into(),
+                )?))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn update(&self, item: Customer) -> anyhow::Result<Customer> {
+        self.conn
+            .call({
+                let item = item.clone();
+                move |conn| {
+                    conn.execute(
+                        "UPDATE customer
+                         SET phone = ?1, email = ?2, password = ?3, name = ?4, updated_at = ?5
+                         WHERE shop_id = ?6 AND id = ?7",
+                        params![
+                            item.phone,
+                            item.email,
+                            item.password,
+                            item.name,
+                            item.updated_at,
+                            item.shop_id.to_string(),
+                            item.id
+                        ],
+                    )?;
+                    Ok(())
+                }
+            })
+            .await?;
+        Ok(item)
+    }
+
+    async fn list_by_shop(
+        &self,
+        shop_id: Uuid,
+        filter: CustomerFilter,
+    ) -> anyhow::Result<CustomerPage> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let mut clause = String::from("WHERE shop_id = ?");
+                let mut args: Vec<rusqlite::types::Value> = vec![shop_id.to_string().into()];
+                if let Some(query) = filter
+                    .query
+                    .as_ref()
+                    .map(|q| q.trim())
+                    .filter(|q| !q.is_empty())
+                {
+                    let pattern = crate::order::like_contains(&query.to_lowercase());
+                    clause.push_str(
+                        " AND (lower(name) LIKE ? ESCAPE '\\' OR phone LIKE ? ESCAPE '\\'
+                         OR email LIKE ? ESCAPE '\\')",
+                    );
+                    args.push(pattern.clone().into());
+                    args.push(pattern.clone().into());
+                    args.push(pattern.into());
+                }
+                let total: i64 = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM customer {clause}"),
+                    rusqlite::params_from_iter(args.iter()),
+                    |row| row.get(0),
+                )?;
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {CUSTOMER_COLUMNS} FROM customer {clause}
+                     ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?"
+                ))?;
+                args.push((filter.limit as i64).into());
+                args.push((filter.offset as i64).into());
+                let items = stmt
+                    .query_map(rusqlite::params_from_iter(args.iter()), customer_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(CustomerPage {
+                    items,
+                    total: total.max(0) as usize,
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn issue_otp(
+        &self,
+        shop_id: Uuid,
+        phone: &str,
+        ip: Option<&str>,
+        code: &str,
+        now: i64,
+    ) -> anyhow::Result<OtpIssue> {
+        let phone = phone.to_string();
+        let ip = ip.map(str::to_string);
+        let code = digest(code);
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let shop_id = shop_id.to_string();
+                let sent_at: Option<i64> = conn
+                    .query_row(
+                        "SELECT sent_at FROM customer_otp WHERE shop_id = ?1 AND phone = ?2",
+                        params![shop_id, phone],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                if sent_at.is_some_and(|sent_at| now - sent_at < OTP_RESEND_SECS) {
+                    return Ok(SqlWrapper(OtpIssue::TooSoon));
+                }
+                conn.execute(
+                    "DELETE FROM customer_otp_request WHERE sent_at <= ?1",
+                    params![now - OTP_LIMIT_WINDOW_SECS],
+                )?;
+                let (by_phone, by_ip): (i64, i64) = conn.query_row(
+                    "SELECT
+                        COUNT(CASE WHEN shop_id = ?1 AND phone = ?2 THEN 1 END),
+                        COUNT(CASE WHEN ip = ?3 THEN 1 END)
+                     FROM customer_otp_request",
+                    params![shop_id, phone, ip],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                if by_phone >= OTP_PHONE_LIMIT || (ip.is_some() && by_ip >= OTP_IP_LIMIT) {
+                    return Ok(SqlWrapper(OtpIssue::LimitReached));
+                }
+                conn.execute(
+                    "INSERT INTO customer_otp_request (shop_id, phone, ip, sent_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![shop_id, phone, ip.unwrap_or_default(), now],
+                )?;
+                conn.execute(
+                    "INSERT OR REPLACE INTO customer_otp (shop_id, phone, code, attempts, sent_at, expires_at)
+                     VALUES (?1, ?2, ?3, 0, ?4, ?5)",
+                    params![shop_id, phone, code, now, now + OTP_TTL_SECS],
+                )?;
+                Ok(SqlWrapper(OtpIssue::Issued))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn check_otp(
+        &self,
+        shop_id: Uuid,
+        phone: &str,
+        code: &str,
+        now: i64,
+    ) -> anyhow::Result<OtpCheck> {
+        let phone = phone.to_string();
+        let code = digest(code.trim());
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let shop_id = shop_id.to_string();
+                let stored: Option<(String, i64, i64)> = conn
+                    .query_row(
+                        "SELECT code, attempts, expires_at FROM customer_otp
+                         WHERE shop_id = ?1 AND phone = ?2",
+                        params![shop_id, phone],
+                        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                    )
+                    .optional()?;
+                let result = match stored {
+                    None => OtpCheck::Missing,
+                    Some((_, _, expires_at)) if expires_at <= now => OtpCheck::Missing,
+                    Some((_, attempts, _)) if attempts >= OTP_MAX_ATTEMPTS => {
+                        OtpCheck::TooManyAttempts
+                    }
+                    Some((stored, _, _)) if stored == code => OtpCheck::Valid,
+                    Some(_) => OtpCheck::Invalid,
+                };
+                match result {
+                    OtpCheck::Valid => {
+                        // sent_at лишається, тож новий код однаково чекає затримки
+                        conn.execute(
+                            "UPDATE customer_otp SET expires_at = ?1 WHERE shop_id = ?2 AND phone = ?3",
+                            params![now, shop_id, phone],
+                        )?;
+                    }
+                    OtpCheck::Invalid => {
+                        conn.execute(
+                            "UPDATE customer_otp SET attempts = attempts + 1
+                             WHERE shop_id = ?1 AND phone = ?2",
+                            params![shop_id, phone],
+                        )?;
+                    }
+                    OtpCheck::Missing | OtpCheck::TooManyAttempts => (),
+                }
+                Ok(SqlWrapper(result))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn create_session(&self, customer: &Customer, now: i64) -> anyhow::Result<String> {
+        let token = generate_token();
+        let customer_id = customer.id;
+        let shop_id = customer.shop_id.to_string();
+        self.conn
+            .call({
+                let token = digest(&token);
+                move |conn| {
+                    conn.execute(
+                        "DELETE FROM customer_session WHERE expires_at <= ?1",
+                        params![now],
+                    )?;
+                    conn.execute(
+                        "INSERT INTO customer_session (token, customer_id, shop_id, created_at, expires_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![token, customer_id, shop_id, now, now + SESSION_TTL_SECS],
+                    )?;
+                    Ok(())
+                }
+            })
+            .await?;
+        Ok(token)
+    }
+
+    async fn session(
+        &self,
+        shop_id: Uuid,
+        token: &str,
+        now: i64,
+    ) -> anyhow::Result<Option<Customer>> {
+        let token = digest(token);
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let customer_id: Option<i64> = conn
+                    .query_row(
+                        "SELECT customer_id FROM customer_session
+                         WHERE token = ?1 AND shop_id = ?2 AND expires_at > ?3",
+                        params![token, shop_id.to_string(), now],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let customer = match customer_id {
+                    Some(id) => select_customer(conn, shop_id, "id", id.into())?,
+                    None => None,
+                };
+                Ok(SqlWrapper(customer))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn remove_session(&self, token: &str) -> anyhow::Result<()> {
+        let token = digest(token);
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM customer_session WHERE token = ?1",
+                    params![token],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_other_sessions(&self, customer_id: i64, token: &str) -> anyhow::Result<()> {
+        let token = digest(token);
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM customer_session WHERE customer_id = ?1 AND token != ?2",
+                    params![customer_id, token],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn addresses(&self, customer_id: i64) -> anyhow::Result<Vec<CustomerAddress>> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {ADDRESS_COLUMNS} FROM customer_address
+                     WHERE customer_id = ?1
+                     ORDER BY is_default DESC, created_at DESC, id DESC"
+                ))?;
+                let items = stmt
+                    .query_map(params![customer_id], address_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn add_address(
+        &self,
+        customer_id: i64,
+        item: NewAddress,
+    ) -> anyhow::Result<CustomerAddress> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let first: bool = tx.query_row(
+                    "SELECT COUNT(*) = 0 FROM customer_address WHERE customer_id = ?1",
+                    params![customer_id],
+                    |row| row.get(0),
+                )?;
+                let is_default = item.is_default || first;
+                if is_default {
+                    tx.execute(
+                        "UPDATE customer_address SET is_default = 0 WHERE customer_id = ?1",
+                        params![customer_id],
+                    )?;
+                }
+                tx.execute(
+                    "INSERT INTO customer_address (
+                        customer_id, label, recipient, phone, delivery, city_name,
+                        city_ref, branch_name, branch_ref, street, is_default, created_at
+                    )
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    params![
+                        customer_id,
+                        item.label,
+                        item.recipient,
+                        item.phone,
+                        item.delivery,
+                        item.city_name,
+                        item.city_ref,
+                        item.branch_name,
+                        item.branch_ref,
+                        item.street,
+                        is_default,
+                        item.created_at
+                    ],
+                )?;
+                let id = tx.last_insert_rowid();
+                tx.commit()?;
+                Ok(SqlWrapper(CustomerAddress {
+                    id,
+                    customer_id,
+                    label: item.label,
+                    recipient: item.recipient,
+                    phone: item.phone,
+                    delivery: item.delivery,
+                    city_name: item.city_name,
+                    city_ref: item.city_ref,
+                    branch_name: item.branch_name,
+                    branch_ref: item.branch_ref,
+                    street: item.street,
+                    is_default,
+                    created_at: item.created_at,
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn remove_address(&self, customer_id: i64, id: i64) -> anyhow::Result<bool> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let removed = conn.execute(
+                    "DELETE FROM customer_address WHERE customer_id = ?1 AND id = ?2",
+                    params![customer_id, id],
+                )?;
+                Ok(SqlWrapper(removed > 0))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn favorites(&self, customer_id: i64) -> anyhow::Result<Vec<Favorite>> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT article, created_at FROM customer_favorite
+                     WHERE customer_id = ?1
+                     ORDER BY created_at DESC",
+                )?;
+                let items = stmt
+                    .query_map(params![customer_id], |row| {
+                        Ok(Favorite {
+                            article: row.get(0)?,
+                            created_at: row.get(1)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn add_favorite(&self, customer_id: i64, article: &str, now: i64) -> anyhow::Result<()> {
+        let article = article.to_string();
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR IGNORE INTO customer_favorite (customer_id, article, created_at)
+                     VALUES (?1, ?2, ?3)",
+                    params![customer_id, article, now],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_favorite(&self, customer_id: i64, article: &str) -> anyhow::Result<()> {
+        let article = article.to_string();
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "DELETE FROM customer_favorite
+                     WHERE customer_id = ?1 AND lower(article) = lower(?2)",
+                    params![customer_id, article],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_phones_and_emails() {
+        assert_eq!(
+            normalize_phone("067 123-45-67").as_deref(),
+            Some("+380671234567")
+        );
+        assert_eq!(
+            normalize_phone("+38 (067) 123 45 67").as_deref(),
+            Some("+380671234567")
+        );
+        assert_eq!(
+            normalize_phone("671234567").as_deref(),
+            Some("+380671234567")
+        );
+        assert_eq!(normalize_phone("12345"), None);
+        assert_eq!(
+            normalize_email(" Ivan@Example.com ").as_deref(),
+            Some("ivan@example.com")
+        );
+        assert_eq!(normalize_email("ivan@localhost"), None);
+        assert_eq!(normalize_email("@example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_one_time_code() {
+        let repo = SqliteCustomerRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let shop = Uuid::new_v4();
+        let phone = "+380671234567";
+        let now = 1_700_000_000;
+
+        let ip = Some("203.0.113.7");
+
+        assert_eq!(
+            repo.issue_otp(shop, phone, ip, "111111", now)
+                .await
+                .unwrap(),
+            OtpIssue::Issued
+        );
+        assert_eq!(
+            repo.issue_otp(shop, phone, ip, "222222", now + 10)
+                .await
+                .unwrap(),
+            OtpIssue::TooSoon
+        );
+        assert_eq!(
+            repo.check_otp(shop, phone, "000000", now + 20)
+                .await
+                .unwrap(),
+            OtpCheck::Invalid
+        );
+        assert_eq!(
+            repo.check_otp(Uuid::new_v4(), phone, "111111", now + 20)
+                .await
+                .unwrap(),
+            OtpCheck::Missing
+        );
+        assert_eq!(
+            repo.check_otp(shop, phone, "111111", now + 30)
+                .await
+                .unwrap(),
+            OtpCheck::Valid
+        );
+        assert_eq!(
+            repo.check_otp(shop, phone, "111111", now + 40)
+                .await
+                .unwrap(),
+            OtpCheck::Missing
+        );
+
+        assert_eq!(
+            repo.issue_otp(shop, phone, ip, "333333", now + 100)
+                .await
+                .unwrap(),
+            OtpIssue::Issued
+        );
+        for _ in 0..OTP_MAX_ATTEMPTS {
+            repo.check_otp(shop, phone, "000000", now + 110)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            repo.check_otp(shop, phone, "333333", now + 120)
+                .await
+                .unwrap(),
+            OtpCheck::TooManyAttempts
+        );
+
+        let customer = repo
+            .add(NewCustomer {
+                shop_id: shop,
+                phone: Some(phone.to_string()),
+                email: None,
+                password: None,
+                name: Some("Іван".to_string()),
+                created_at: now,
+            })
+            .await
+            .unwrap();
+        let err = repo
+            .add(NewCustomer {
+                shop_id: shop,
+                phone: Some(phone.to_string()),
+                email: None,
+                password: None,
+                name: None,
+                created_at: now,
+            })
+            .await
+            .unwrap_err();
+        assert!(is_unique_violation(&err));
+        let token = repo.create_session(&customer, now).await.unwrap();
+        let found = repo.session(shop, &token, now + 60).await.unwrap().unwrap();
+        assert_eq!(found.id, customer.id);
+        let other = repo.create_session(&customer, now).await.unwrap();
+        repo.remove_other_sessions(customer.id, &token)
+            .await
+            .unwrap();
+        assert!(repo.session(shop, &other, now).await.unwrap().is_none());
+        assert!(repo.session(shop, &token, now).await.unwrap().is_some());
+        assert!(repo
+            .session(Uuid::new_v4(), &token, now)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(repo
+            .session(shop, &token, now + SESSION_TTL_SECS)
+            .await
+            .unwrap()
+            .is_none());
+        repo.remove_session(&token).await.unwrap();
+        assert!(repo.session(shop, &token, now).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn limits_codes_per_phone_and_ip_and_stores_digests() {
+        let repo = SqliteCustomerRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let shop = Uuid::new_v4();
+        let now = 1_700_000_000;
+        let mut at = now;
+        for _ in 0..OTP_PHONE_LIMIT {
+            assert_eq!(
+                repo.issue_otp(shop, "+380671234567", Some("ip-1"), "123456", at)
+                    .await
+                    .unwrap(),
+                OtpIssue::Issued
+            );
+            at += OTP_RESEND_SECS;
+        }
+        assert_eq!(
+            repo.issue_otp(shop, "+380671234567", Some("ip-2"), "123456", at)
+                .await
+                .unwrap(),
+            OtpIssue::LimitReached
+        );
+        for i in OTP_PHONE_LIMIT..OTP_IP_LIMIT {
+            let phone = format!("+38067000{i:04}");
+            assert_eq!(
+                repo.issue_otp(shop, &phone, Some("ip-1"), "123456", at)
+                    .await
+                    .unwrap(),
+                OtpIssue::Issued
+            );
+        }
+        assert_eq!(
+            repo.issue_otp(shop, "+380501234567", Some("ip-1"), "123456", at)
+                .await
+                .unwrap(),
+            OtpIssue::LimitReached
+        );
+        // Невідома адреса обмежується лише за телефоном
+        assert_eq!(
+            repo.issue_otp(shop, "+380501234567", None, "123456", at)
+                .await
+                .unwrap(),
+            OtpIssue::Issued
+        );
+        assert_eq!(
+            repo.issue_otp(
+                shop,
+                "+380671234567",
+                Some("ip-1"),
+                "654321",
+                now + OTP_LIMIT_WINDOW_SECS
+            )
+            .await
+            .unwrap(),
+            OtpIssue::Issued
+        );
+
+        let customer = repo
+            .add(NewCustomer {
+                shop_id: shop,
+                phone: Some("+380671234567".to_string()),
+                email: None,
+                password: None,
+                name: None,
+                created_at: now,
+            })
+            .await
+            .unwrap();
+        let token = repo.create_session(&customer, now).await.unwrap();
+        let stored = repo
+            .conn
+            .call(|conn| {
+                Ok(SqlWrapper(conn.query_row(
+                    "SELECT (SELECT code FROM customer_otp WHERE phone = '+380671234567'),
+                        (SELECT token FROM customer_session)",
+                    [],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                )?))
+            })
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(stored, (digest("654321"), digest(&token)));
+    }
+
+    #[tokio::test]
+    async fn keeps_one_default_address_and_unique_favorites() {
+        let repo = SqliteCustomerRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let address = |label: &str, is_default, created_at| NewAddress {
+            label: Some(label.to_string()),
+            recipient: None,
+            phone: None,
+            delivery: ADDRESS_DELIVERIES[0].to_string(),
+            city_name: Some("Київ".to_string()),
+            city_ref: None,
+            branch_name: Some("Відділення №1".to_string()),
+            branch_ref: None,
+            street: None,
+            is_default,
+            created_at,
+        };
+        let home = repo
+            .add_address(1, address("Дім", false, 10))
+            .await
+            .unwrap();
+        assert!(home.is_default);
+        let work = repo
+            .add_address(1, address("Робота", true, 20))
+            .await
+            .unwrap();
+        let list = repo.addresses(1).await.unwrap();
+        assert_eq!(
+            list.iter()
+                .map(|a| (a.id, a.is_default))
+                .collect::<Vec<_>>(),
+            vec![(work.id, true), (home.id, false)]
+        );
+        assert!(!repo.remove_address(2, home.id).await.unwrap());
+        assert!(repo.remove_address(1, home.id).await.unwrap());
+
+        repo.add_favorite(1, "DT-1", 10).await.unwrap();
+        repo.add_favorite(1, "DT-1", 20).await.unwrap();
+        repo.add_favorite(1, "DT-2", 30).await.unwrap();
+        repo.remove_favorite(1, "DT-2").await.unwrap();
+        let favorites = repo.favorites(1).await.unwrap();
+        assert_eq!(favorites.len(), 1);
+        assert_eq!(favorites[0].created_at, 10);
+    }
+}
@@ -2,8 +2,8 @@ use crate::category_auto;
 use crate::ddaudio;
 use crate::export::{Export, ProgressInfo};
 use anyhow::anyhow;
-use rust_decimal::prelude::ToPrimitive;
-use rust_decimal::Decimal;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
@@ -15,6 +15,7 @@ use tokio::sync::RwLock;
 use tokio::time::sleep;
 
 use rt_types::category::Category;
+use rt_types::pricing;
 use rt_types::product::{generate_id, Product, UaTranslation};
 use rt_types::shop::{DDAudioCategoryRule, DDAudioExportOptions, DDAudioPriceType, ZeroStockPolicy};
 use rt_types::Availability;
@@ -54,6 +55,7 @@ struct ImportedProduct {
     images: Vec<String>,
     attributes: HashMap<String, String>,
     price: Option<usize>,
+    source_price: Option<usize>,
     available: Availability,
     quantity: Option<usize>,
     title_ru: Option<String>,
@@ -73,6 +75,7 @@ impl Default for ImportedProduct {
             images: Vec::new(),
             attributes: HashMap::new(),
             price: None,
+            source_price: None,
             available: Availability::NotAvailable,
             quantity: None,
             title_ru: None,
@@ -273,20 +276,18 @@ fn append_model_to_title(title: String, model: &str) -> String {
     format!("{} {}", title.trim_end(), model)
 }
 
-fn round_price_to_9(value: usize) -> usize {
-    if value == 0 {
-        return 0;
-    }
-    if value < 10 {
-        return 9;
-    }
-    let base = value - (value % 10);
-    base + 9
-}
-
 #[cfg(test)]
 mod tests {
-    use super::round_price_to_9;
+    use rt_types::pricing::Rounding;
+    use rust_decimal::prelude::ToPrimitive;
+    use rust_decimal::Decimal;
+
+    fn round_price_to_9(value: usize) -> usize {
+        Rounding::To9
+            .apply(Decimal::from(value))
+            .to_usize()
+            .unwrap_or(0)
+    }
 
     #[test]
     fn round_price_to_9_rounds_to_ending_nine() {
@@ -345,10 +346,6 @@ fn should_include(
     false
 }
 
-fn markup_to_f64(markup: Option<Decimal>) -> Option<f64> {
-    markup.and_then(|m| m.to_f64())
-}
-
 fn price_from_item(
     item: &AggregatedItem,
     rule: &DDAudioCategoryRule,
@@ -363,27 +360,29 @@ fn price_from_item(
         return (None, None);
     }
     let base_uah = base.round().max(0.0) as usize;
-    let mut price = base;
-    if let Some(markup) = markup_to_f64(rule.markup_percent) {
-        price *= 1.0 + (markup.max(0.0) / 100.0);
-    }
-    if let Some(discount) = rule.discount_percent {
-        let mut apply_discount = true;
-        if let Some(hours) = rule.discount_hours {
+    let Some(price) = Decimal::from_f64(base) else {
+        return (None, None);
+    };
+    let discount = rule.discount_percent.filter(|_| match rule.discount_hours {
+        Some(hours) => {
             let now = OffsetDateTime::now_utc().to_timezone(KYIV);
             let start = now.replace_time(Time::MIDNIGHT);
             let end = start + TimeDuration::hours(hours.max(1) as i64);
-            apply_discount = now >= start && now < end;
-        }
-        if apply_discount {
-            let percent = discount.min(100);
-            price *= (100.0 - percent as f64) / 100.0;
-        }
-    }
-    let mut final_price = price.round().max(0.0) as usize;
-    if rule.round_to_9 {
-        final_price = round_price_to_9(final_price);
-    }
+            now >= start && now < end
+        }
+        None => true,
+    });
+    let rules = pricing::legacy_rules(rule.markup_percent, discount, rule.round_to_9);
+    let price = pricing::apply_rules(
+        &rules,
+        &pricing::PriceSubject::default(),
+        Decimal::from(base_uah),
+        price,
+    );
+    let final_price = price
+        .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+        .to_usize()
+        .unwrap_or(0);
     (Some(final_price), Some(base_uah))
 }
 
@@ -573,6 +572,7 @@ fn merge_images(existing: &[String], incoming: &[String]) -> Vec<String> {
 pub async fn fetch_products(
     config: &DDAudioExportOptions,
     categories: &[Category],
+    currency: &rt_types::shop::CurrencySettings,
     progress: Option<Arc<RwLock<Export>>>,
 ) -> anyhow::Result<Vec<Product>> {
     let token = config.token.trim();
@@ -583,7 +583,7 @@ pub async fn fetch_products(
     if languages.is_empty() {
         languages = vec!["ru".to_string()];
     }
-    let rates = load_currency_rates();
+    let rates = crate::shop::apply_currency_settings_f64(currency, load_currency_rates());
 
     let price_types = selected_price_types(config);
     let mut imported: HashMap<String, ImportedProduct> = HashMap::new();
@@ -685,8 +685,9 @@ pub async fn fetch_products(
                     qty,
                     &config.default_rule,
                 );
-                let (price, _source) = price_from_item(price_item, rule, &rates);
+                let (price, source_price) = price_from_item(price_item, rule, &rates);
                 entry.price = price;
+                entry.source_price = source_price;
                 entry.base_ready = true;
             }
             let mut title = price_item.title.clone();
@@ -786,6 +787,7 @@ pub async fn fetch_products(
             ua_translation,
             description: item.description_ru.clone(),
             price,
+            source_price: item.source_price.map(Decimal::from),
             article: item.article.clone(),
             in_stock: item.quantity,
             currency: "UAH".to_string(),
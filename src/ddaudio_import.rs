@@ -3,6 +3,7 @@ use crate::ddaudio;
 use crate::dt;
 use crate::product_category;
 use crate::product_category_auto;
+use crate::scheduler::{JobClass, JobScheduler, RunCounts, Schedule};
 use crate::shop_product;
 use crate::site_publish::{self, DDAudioCategoryRule, DDAudioConfig, DDAudioPriceType, DDAudioTarget, ZeroStockPolicy};
 use crate::import_throttle;
@@ -19,10 +20,14 @@ use time_tz::OffsetDateTimeExt;
 use tokio::sync::{Notify, RwLock};
 use tokio::time::sleep;
 use uuid::Uuid;
+use rt_types::pricing;
 use rt_types::shop::MissingProductPolicy;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
 use rt_types::Availability;
 
 const PAGE_LIMIT: usize = 10_000;
+const IN_PROGRESS_POLL: std::time::Duration = std::time::Duration::from_secs(60);
 static REQUEST_DELAY_SECS: Lazy<u64> = Lazy::new(|| {
     std::env::var("DDAUDIO_REQUEST_DELAY_SECS")
         .ok()
@@ -122,6 +127,7 @@ pub async fn trigger_import(
     shop_product_repo: Arc<dyn shop_product::ShopProductRepository>,
     category_repo: Arc<dyn rt_types::category::CategoryRepository>,
     product_category_repo: Arc<dyn product_category::ProductCategoryRepository>,
+    jobs: Arc<JobScheduler>,
     manual_start: bool,
 ) -> anyhow::Result<()> {
     let config = site_publish::load_ddaudio_config(&shop_id);
@@ -148,23 +154,31 @@ pub async fn trigger_import(
 
     tokio::spawn(async move {
         let _permit = import_throttle::acquire_import_permit().await;
-        let res = run_import(
-            shop_id,
-            target,
-            config,
-            dt_repo,
-            shop_product_repo,
-            category_repo,
-            product_category_repo,
-        )
-        .await;
+        let res = jobs
+            .run(&job_id(shop_id), |_| async {
+                run_import(
+                    shop_id,
+                    target,
+                    config,
+                    dt_repo,
+                    shop_product_repo,
+                    category_repo,
+                    product_category_repo,
+                )
+                .await
+                .map(|msg| (msg, RunCounts::new()))
+            })
+            .await;
         let mut state = get_status(shop_id).await;
         match res {
-            Ok(msg) => {
+            None => {
+                state.status = ImportStatus::Failure("Імпорт скасовано".to_string());
+            }
+            Some(Ok(msg)) => {
                 state.status = ImportStatus::Success;
                 state.last_log = msg;
             }
-            Err(err) => {
+            Some(Err(err)) => {
                 state.status = ImportStatus::Failure(err.to_string());
                 state.last_error = Some(err.to_string());
             }
@@ -176,14 +190,27 @@ pub async fn trigger_import(
     Ok(())
 }
 
+fn job_id(shop_id: Uuid) -> String {
+    format!("ddaudio_import:{shop_id}")
+}
+
 pub async fn sync_scheduler(
     shop_id: Uuid,
     dt_repo: Arc<dyn dt::product::ProductRepository + Send>,
     shop_product_repo: Arc<dyn shop_product::ShopProductRepository>,
     category_repo: Arc<dyn rt_types::category::CategoryRepository>,
     product_category_repo: Arc<dyn product_category::ProductCategoryRepository>,
+    jobs: Arc<JobScheduler>,
 ) {
     let config = site_publish::load_ddaudio_config(&shop_id);
+    jobs.register(
+        job_id(shop_id),
+        "Импорт DD Audio",
+        Some(shop_id),
+        JobClass::Task,
+        Schedule::Interval(config.update_rate),
+    )
+    .await;
     let mut tasks = IMPORT_TASKS.write().await;
     if !config.auto_update {
         AUTO_UPDATE_ARMED.write().await.remove(&shop_id);
@@ -206,6 +233,7 @@ pub async fn sync_scheduler(
         },
     );
     tokio::spawn(async move {
+        let job_id = job_id(shop_id);
         loop {
             let cfg = site_publish::load_ddaudio_config(&shop_id);
             let armed = AUTO_UPDATE_ARMED.read().await.contains(&shop_id);
@@ -221,25 +249,14 @@ pub async fn sync_scheduler(
                 tokio::select! {
                     _ = stop.notified() => break,
                     _ = notify.notified() => continue,
-                    _ = sleep(cfg.update_rate) => (),
+                    _ = sleep(IN_PROGRESS_POLL.min(cfg.update_rate)) => (),
                 }
                 continue;
             }
-            if let Some(last_started) = state.last_started {
-                let elapsed = OffsetDateTime::now_utc() - last_started;
-                let elapsed_secs = elapsed.as_seconds_f64();
-                if elapsed_secs >= 0.0 {
-                    let elapsed = std::time::Duration::from_secs_f64(elapsed_secs);
-                    if elapsed < cfg.update_rate {
-                        let wait_for = cfg.update_rate - elapsed;
-                        tokio::select! {
-                            _ = stop.notified() => break,
-                            _ = notify.notified() => continue,
-                            _ = sleep(wait_for) => (),
-                        }
-                        continue;
-                    }
-                }
+            tokio::select! {
+                _ = stop.notified() => break,
+                _ = notify.notified() => continue,
+                _ = jobs.wait_due(&job_id) => (),
             }
 
             if cfg.token.trim().is_empty() {
@@ -255,15 +272,11 @@ pub async fn sync_scheduler(
                     shop_product_repo.clone(),
                     category_repo.clone(),
                     product_category_repo.clone(),
+                    jobs.clone(),
                     false,
                 )
                 .await;
             }
-            tokio::select! {
-                _ = stop.notified() => break,
-                _ = notify.notified() => continue,
-                _ = sleep(cfg.update_rate) => (),
-            }
         }
     });
 }
@@ -532,17 +545,6 @@ fn append_model_to_title(title: String, model: &str) -> String {
     format!("{} {}", title.trim_end(), model)
 }
 
-fn round_price_to_9(value: usize) -> usize {
-    if value == 0 {
-        return 0;
-    }
-    if value < 10 {
-        return 9;
-    }
-    let base = value - (value % 10);
-    base + 9
-}
-
 fn resolve_rule<'a>(
     config: &'a DDAudioConfig,
     category_id: Option<&str>,
@@ -601,29 +603,36 @@ fn price_from_item(
         return (None, None, None);
     }
     let base_uah = base.round().max(0.0) as usize;
-    let mut price = base;
-    if let Some(markup) = rule.markup_percent {
-        price *= 1.0 + (markup.max(0.0) / 100.0);
-    }
-    let mut discount_percent = None;
-    if let Some(discount) = rule.discount_percent {
-        let mut apply_discount = true;
-        if let Some(hours) = rule.discount_hours {
-            let now = OffsetDateTime::now_utc().to_timezone(KYIV);
-            let start = now.replace_time(Time::MIDNIGHT);
-            let end = start + TimeDuration::hours(hours.max(1) as i64);
-            apply_discount = now >= start && now < end;
-        }
-        if apply_discount {
-            let percent = discount.min(100);
-            discount_percent = Some(percent);
-            price *= (100.0 - percent as f64) / 100.0;
-        }
-    }
-    let mut final_price = price.round().max(0.0) as usize;
-    if rule.round_to_9 {
-        final_price = round_price_to_9(final_price);
-    }
+    let Some(price) = Decimal::from_f64(base) else {
+        return (None, None, None);
+    };
+    let discount_percent = rule
+        .discount_percent
+        .filter(|_| match rule.discount_hours {
+            Some(hours) => {
+                let now = OffsetDateTime::now_utc().to_timezone(KYIV);
+                let start = now.replace_time(Time::MIDNIGHT);
+                let end = start + TimeDuration::hours(hours.max(1) as i64);
+                now >= start && now < end
+            }
+            None => true,
+        })
+        .map(|d| d.min(100));
+    let rules = pricing::legacy_rules(
+        rule.markup_percent.and_then(Decimal::from_f64),
+        discount_percent,
+        rule.round_to_9,
+    );
+    let price = pricing::apply_rules(
+        &rules,
+        &pricing::PriceSubject::default(),
+        Decimal::from(base_uah),
+        price,
+    );
+    let final_price = price
+        .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+        .to_usize()
+        .unwrap_or(0);
     (Some(final_price), Some(base_uah), discount_percent)
 }
 
@@ -926,7 +935,10 @@ async fn run_import(
     if languages.is_empty() {
         return Err(anyhow!("No languages selected"));
     }
-    let rates = load_currency_rates();
+    let rates = crate::shop::apply_currency_settings_f64(
+        &crate::shop::currency_settings(&shop_id),
+        load_currency_rates(),
+    );
 
     let categories = category_repo.select(&rt_types::category::By(shop_id)).await?;
     let product_categories = product_category_repo
@@ -1234,6 +1246,14 @@ async fn run_import(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rt_types::pricing::Rounding;
+
+    fn round_price_to_9(value: usize) -> usize {
+        Rounding::To9
+            .apply(Decimal::from(value))
+            .to_usize()
+            .unwrap_or(0)
+    }
 
     #[test]
     fn test_round_price_to_9() {
@@ -1,5 +1,6 @@
 pub mod parser;
 pub mod product;
+pub mod schedule;
 
 pub mod selectors {
     #![allow(clippy::unwrap_used)]
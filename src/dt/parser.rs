@@ -1,8 +1,10 @@
 use crate::cache;
 use crate::dt::{
     product::{Product, ProductRepository},
+    schedule::{self, CrawlSchedule},
     selectors,
 };
+use crate::scheduler::{JobClass, JobScheduler, RunCounts, Schedule};
 use crate::{format_raw_html, Model, Url};
 use actix::prelude::*;
 use actix_broker::BrokerSubscribe;
@@ -13,15 +15,16 @@ use futures::{stream, FutureExt, StreamExt, TryStreamExt};
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
 use lazy_regex::regex;
+use log_error::LogError;
 use reqwest_middleware::ClientWithMiddleware;
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use rt_types::shop::ConfigurationChanged;
 use rt_types::{Availability, Pause, Resume};
 use scraper::{node::Node, Html};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ops::ControlFlow;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
 use time::OffsetDateTime;
 use tokio::signal;
@@ -128,8 +131,14 @@ pub struct ParsingOptions {
     pub progress_bar: Option<Arc<ProgressBar>>,
     pub parallel_downloads: usize,
     pub stage: ParsingStage,
+    pub schedule: Arc<CrawlSchedule>,
+    pub list_fingerprints: Arc<Mutex<HashMap<String, u64>>>,
+    // Переносяться в `list_fingerprints`, коли всі їх посилання збережені
+    pub pending_fingerprints: Arc<Mutex<HashMap<String, PendingList>>>,
 }
 
+pub type PendingList = (u64, Vec<String>);
+
 impl ParsingOptions {
     pub fn new(
         url: String,
@@ -145,6 +154,13 @@ impl ParsingOptions {
             progress_bar,
             parallel_downloads,
             stage: ParsingStage::Pause,
+            schedule: Arc::new(CrawlSchedule::default()),
+            list_fingerprints: Arc::new(Mutex::new(
+                cache::read_fingerprints(FINGERPRINTS_PATH)
+                    .log_error("Unable to read list fingerprints")
+                    .unwrap_or_default(),
+            )),
+            pending_fingerprints: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -348,7 +364,7 @@ pub async fn parse_product_lists<'a>(
     models: &'a [(Url, String, &String)],
     options: Arc<RwLock<ParsingOptions>>,
 ) -> Result<Vec<(Url, &'a String, &'a String)>, ParsingError> {
-    let (client, url, repo, pb, parallel_downloads) = {
+    let (client, url, repo, pb, parallel_downloads, schedule, fingerprints, pending_fingerprints) = {
         let opts = options.read().await;
         (
             opts.client.clone(),
@@ -356,6 +372,9 @@ pub async fn parse_product_lists<'a>(
             opts.repo.clone(),
             opts.progress_bar.clone(),
             opts.parallel_downloads,
+            opts.schedule.clone(),
+            opts.list_fingerprints.clone(),
+            opts.pending_fingerprints.clone(),
         )
     };
     let url = if url.ends_with('/') {
@@ -385,7 +404,7 @@ pub async fn parse_product_lists<'a>(
                 if is_browser_check(&body) {
                     return Err(ParsingError::BrowserCheck(link.clone()));
                 }
-                let mut res = vec![(link.clone(), body.clone(), model, brand)];
+                let mut res = vec![(link.clone(), link.clone(), body.clone(), model, brand)];
                 let regex =
                     regex!(r"a href=.([a-z|0-9|\-\/_]*).( target=._self.)? aria-label=.Next");
                 loop {
@@ -415,7 +434,7 @@ pub async fn parse_product_lists<'a>(
                             continue;
                         }
                     };
-                    res.push((link.clone(), body.clone(), model, brand));
+                    res.push((link.clone(), n, body.clone(), model, brand));
                 }
                 Ok(res)
             }
@@ -428,14 +447,26 @@ pub async fn parse_product_lists<'a>(
         .map(|res| {
             let repo = repo.clone();
             let pb = pb.clone();
+            let schedule = schedule.clone();
+            let fingerprints = fingerprints.clone();
+            let pending_fingerprints = pending_fingerprints.clone();
             async move {
-                let (link, body, model, brand) = res?;
-                let document = Html::parse_document(&body);
-                let items: Vec<_> = document
-                    .select(&selectors::PRODUCT_ITEM)
-                    .map(|e| (e.attr("href").map(str::to_string), e.inner_html()))
-                    .map(|(url, v)| (url, v.replace('\n', "").trim().to_string()))
-                    .collect();
+                let (link, page, body, model, brand) = res?;
+                let items: Vec<_> = {
+                    let document = Html::parse_document(&body);
+                    document
+                        .select(&selectors::PRODUCT_ITEM)
+                        .map(|e| (e.attr("href").map(str::to_string), e.inner_html()))
+                        .map(|(url, v)| (url, v.replace('\n', "").trim().to_string()))
+                        .collect()
+                };
+                let fingerprint =
+                    schedule::list_fingerprint(items.iter().map(|(_, v)| v.as_str()));
+                let list_unchanged = fingerprints
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(&page)
+                    == Some(&fingerprint);
                 let urls: Vec<(Url, &String, &String)> = items
                     .into_iter()
                     .map(|(url, _)| {
@@ -457,19 +488,18 @@ pub async fn parse_product_lists<'a>(
                         )
                     })
                     .collect();
-                urls.sort_by(|a, b| {
-                    a.3.map(|x| x.last_visited)
-                        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
-                        .partial_cmp(
-                            &b.3.map(|x| x.last_visited)
-                                .unwrap_or(OffsetDateTime::UNIX_EPOCH),
-                        )
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                });
+                urls.sort_by_key(|(_, _, _, product)| schedule.priority(*product));
+                let now = OffsetDateTime::now_utc();
                 let urls: Vec<_> = urls
                     .into_iter()
                     .filter_map(|(url, model, brand, product)| match product {
-                        Some(product) if product.is_outdated() => Some((url, model, brand)),
+                        Some(product) if schedule.is_due(product, now) => Some((url, model, brand)),
+                        Some(product)
+                            if !list_unchanged
+                                && product.last_visited + schedule::MIN_REVISIT <= now =>
+                        {
+                            Some((url, model, brand))
+                        }
                         None => Some((url, model, brand)),
                         Some(product) => {
                             log::info!("Skipping up to date product parsing: {}", product.article);
@@ -477,6 +507,13 @@ pub async fn parse_product_lists<'a>(
                         }
                     })
                     .collect();
+                if !list_unchanged {
+                    let links = urls.iter().map(|(url, _, _)| url.0.clone()).collect();
+                    pending_fingerprints
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(page, (fingerprint, links));
+                }
                 if let Some(pb) = pb {
                     pb.inc(1);
                 }
@@ -701,14 +738,18 @@ pub struct ParserService {
     stop_notify: Arc<Notify>,
     start_notify: Arc<Notify>,
     start_paused: bool,
+    jobs: Arc<JobScheduler>,
 }
 
+const JOB_ID: &str = "dt_parser";
+
 impl ParserService {
     pub fn new(
         opts: ParsingOptions,
         pb_style: Option<ProgressStyle>,
         token: CancellationToken,
         start_paused: bool,
+        jobs: Arc<JobScheduler>,
     ) -> Self {
         Self {
             opts: Arc::new(RwLock::new(opts)),
@@ -717,6 +758,7 @@ impl ParserService {
             stop_notify: Arc::new(Notify::new()),
             start_notify: Arc::new(Notify::new()),
             start_paused,
+            jobs,
         }
     }
 }
@@ -732,15 +774,26 @@ impl Actor for ParserService {
         let stop_notify = self.stop_notify.clone();
         let start_notify = self.start_notify.clone();
         let start_paused = self.start_paused;
+        let jobs = self.jobs.clone();
         tokio::task::spawn_local(async move {
+            jobs.register(
+                JOB_ID,
+                "Парсинг DT",
+                None,
+                JobClass::Parser,
+                Schedule::Interval(Duration::ZERO),
+            )
+            .await;
             let stop = Arc::new(AtomicBool::new(start_paused));
             tokio::task::spawn({
                 let stop = stop.clone();
                 let stop_notify = stop_notify.clone();
+                let jobs = jobs.clone();
                 async move {
                     loop {
                         stop_notify.notified().await;
                         stop.store(true, Ordering::SeqCst);
+                        jobs.cancel(JOB_ID).await;
                     }
                 }
             });
@@ -753,7 +806,12 @@ impl Actor for ParserService {
                             stop.store(false, Ordering::SeqCst);
                         }
                         let res = tokio::select! {
-                            res = products_parsing(res.clone(), pb_style.clone(), opts.clone()) => res,
+                            res = products_parsing(
+                                res.clone(),
+                                pb_style.clone(),
+                                opts.clone(),
+                                token.clone(),
+                            ) => res,
                             _ = stop_notify.notified() => continue,
                         };
                         if let Err(err) = res {
@@ -772,14 +830,33 @@ impl Actor for ParserService {
                     start_notify.notified().await;
                     stop.store(false, Ordering::SeqCst);
                 }
-                let res = tokio::select! {
-                    res = work_cycle(opts.clone(), pb_style.clone(), token.clone()) => res,
-                    _ = stop_notify.notified() => continue,
-                };
+                jobs.wait_due(JOB_ID).await;
+                if stop.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let res = jobs
+                    .run(JOB_ID, |cancel| {
+                        // Скасування задачі зупиняє цикл так само, як вимкнення
+                        let cycle = token.child_token();
+                        let fut = work_cycle(opts.clone(), pb_style.clone(), cycle.clone());
+                        async move {
+                            tokio::pin!(fut);
+                            let res = tokio::select! {
+                                res = &mut fut => res,
+                                _ = cancel.cancelled() => {
+                                    cycle.cancel();
+                                    fut.await
+                                }
+                            };
+                            res.map(|flow| (flow, RunCounts::new()))
+                        }
+                    })
+                    .await;
                 match res {
-                    Ok(ControlFlow::Continue(())) => continue,
-                    Ok(ControlFlow::Break(())) => break,
-                    Err(err) => {
+                    None => continue,
+                    Some(Ok(ControlFlow::Continue(()))) => continue,
+                    Some(Ok(ControlFlow::Break(()))) => break,
+                    Some(Err(err)) => {
                         log::error!("Unable to parse dt products: {err}");
                         continue;
                     }
@@ -924,7 +1001,18 @@ pub async fn work_cycle(
     token: CancellationToken,
 ) -> Result<ControlFlow<(), ()>, anyhow::Error> {
     {
+        let repo = options.read().await.repo.clone();
+        let schedule = CrawlSchedule::load(repo.as_ref())
+            .await
+            .log_error("Unable to load crawl schedule")
+            .unwrap_or_default();
         let mut options = options.write().await;
+        options.schedule = Arc::new(schedule);
+        options
+            .pending_fingerprints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
         options.stage = ParsingStage::Brands;
     }
     let brands = tokio::select! {
@@ -1044,9 +1132,9 @@ pub async fn work_cycle(
     if let Some(pb) = pb.clone() {
         pb.finish_and_clear();
     }
-    let repo = {
+    let (repo, schedule) = {
         let opts = options.read().await;
-        opts.repo.clone()
+        (opts.repo.clone(), opts.schedule.clone())
     };
     let mut res = res
         .into_iter()
@@ -1056,24 +1144,33 @@ pub async fn work_cycle(
     for (url, _, _) in &res {
         seen.insert(url.0.clone());
     }
-    let stale_products = repo
-        .list()
-        .await?
-        .into_iter()
-        .filter(|p| p.is_outdated())
+    let products = repo.list().await?;
+    let now = OffsetDateTime::now_utc();
+    let stale_products = products
+        .iter()
+        .filter(|p| schedule.is_due(p, now))
         .filter(|p| !p.url.0.trim().is_empty());
     for product in stale_products {
         if seen.insert(product.url.0.clone()) {
             res.push((product.url.clone(), product.model.0.clone(), product.brand.clone()));
         }
     }
+    let by_url: HashMap<_, _> = products.iter().map(|p| (p.url.0.as_str(), p)).collect();
+    res.sort_by_key(|(url, _, _)| schedule.priority(by_url.get(url.0.as_str()).copied()));
+    drop(by_url);
+    drop(products);
     let fut = async {
+        let started = OffsetDateTime::now_utc();
         let categories = parse_categories(options.clone()).await?;
         let subcategories = parse_subcategories(&categories, options.clone()).await;
         match subcategories {
             Ok(r) => match parse_product_lists(&r, options.clone()).await {
                 Ok(res) => {
-                    products_parsing(res, pb_style.clone(), options.clone()).await?;
+                    if !products_parsing(res, pb_style.clone(), options.clone(), token.clone())
+                        .await?
+                    {
+                        commit_list_fingerprints(&options, started).await?;
+                    }
                 }
                 Err(err) => {
                     log::error!("Unable to parse product lists in subcategory: {err:?}");
@@ -1084,27 +1181,32 @@ pub async fn work_cycle(
             }
         };
         log::info!("{} total links", res.len());
-        if products_parsing(res, pb_style.clone(), options).await? {
+        if products_parsing(res, pb_style.clone(), options.clone(), token.clone()).await? {
             return Ok(ControlFlow::Break(()));
         }
+        commit_list_fingerprints(&options, started).await?;
         Ok(ControlFlow::Continue(()))
     };
+    // Парсинг товарів зупиняється сам, щоб зберегти решту посилань
     tokio::select! {
+        biased;
         r = fut => r,
         _ = token.cancelled() => Ok(ControlFlow::Break(())),
     }
 }
 
+// При скасуванні решта посилань іде в кеш посилань і повертається `true`
 pub async fn products_parsing<M, B>(
     res: Vec<(Url, M, B)>,
     pb_style: Option<ProgressStyle>,
     options: Arc<RwLock<ParsingOptions>>,
+    token: CancellationToken,
 ) -> Result<bool, anyhow::Error>
 where
     M: AsRef<str> + std::fmt::Display + Into<String> + Clone,
     B: AsRef<str> + std::fmt::Display + Into<String> + Clone,
 {
-    let token = CancellationToken::new();
+    let token = token.child_token();
     let (tx, mut rx) = mpsc::channel(100);
     let r = res
         .iter()
@@ -1189,7 +1291,44 @@ where
     Ok(false)
 }
 
+// Сторінки з невдалими товарами лишаються і наступного разу читаються повністю
+async fn commit_list_fingerprints(
+    options: &RwLock<ParsingOptions>,
+    started: OffsetDateTime,
+) -> Result<(), anyhow::Error> {
+    let (repo, fingerprints, pending) = {
+        let opts = options.read().await;
+        (
+            opts.repo.clone(),
+            opts.list_fingerprints.clone(),
+            opts.pending_fingerprints.clone(),
+        )
+    };
+    let visited: HashSet<_> = repo
+        .list()
+        .await?
+        .into_iter()
+        .filter(|p| p.last_visited >= started)
+        .map(|p| p.url.0)
+        .collect();
+    let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut fingerprints = fingerprints.lock().unwrap_or_else(PoisonError::into_inner);
+    pending.retain(|page, (fingerprint, links)| {
+        if links.iter().all(|l| visited.contains(l)) {
+            fingerprints.insert(page.clone(), *fingerprint);
+            false
+        } else {
+            true
+        }
+    });
+    if let Err(err) = cache::write_fingerprints(FINGERPRINTS_PATH, &fingerprints) {
+        log::error!("Unable to write list fingerprints: {err}");
+    }
+    Ok(())
+}
+
 static MODELS_PATH: &str = "models.yml";
+static FINGERPRINTS_PATH: &str = "list_fingerprints.yml";
 static LINKS_PATH: &str = "links.yml";
 static CHUNK_SIZE: usize = 50;
 
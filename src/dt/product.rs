@@ -3,7 +3,7 @@
 use crate::{Model, Url};
 use async_trait::async_trait;
 use rt_types::Availability;
-use rusqlite::{Transaction, TransactionBehavior};
+use rusqlite::{OptionalExtension, Transaction, TransactionBehavior};
 use std::collections::HashMap;
 use time::{Duration, OffsetDateTime};
 use tokio_rusqlite::Connection;
@@ -149,16 +149,113 @@ pub trait ProductRepository:
     + Select<Product, FromDateAvailableSelector>
     + Select<Product, AvailableSelector>
     + DeleteProducts
+    + ProductHistory
+    + ListPage
     + Send
     + Sync
 {
 }
 
+#[async_trait]
+pub trait ListPage {
+    // Збережений повторно товар зберігає артикул, тож сторінка не повториться
+    async fn list_page(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+        only_available: bool,
+    ) -> Result<(Vec<Product>, Option<String>), anyhow::Error>;
+}
+
+pub fn batches(
+    repo: std::sync::Arc<dyn ProductRepository>,
+    only_available: bool,
+    batch: usize,
+) -> impl futures::Stream<Item = Result<Vec<Product>, anyhow::Error>> {
+    futures::stream::try_unfold(Some(None), move |cursor| {
+        let repo = repo.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+            let (products, next) = repo.list_page(cursor, batch, only_available).await?;
+            if products.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some((products, next.map(Some))))
+        }
+    })
+}
+
 #[async_trait]
 pub trait DeleteProducts {
     async fn delete_articles(&self, articles: &[String]) -> Result<(), anyhow::Error>;
 }
 
+// Рядок додається в `save` лише коли ціна чи наявність змінились, і ніколи не змінюється
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProductHistoryEntry {
+    pub article: String,
+    pub price: Option<usize>,
+    pub source_price: Option<usize>,
+    pub available: Availability,
+    pub quantity: Option<usize>,
+    pub changed_at: OffsetDateTime,
+}
+
+#[async_trait]
+pub trait ProductHistory {
+    async fn history_by_article(
+        &self,
+        article: &str,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+    ) -> Result<Vec<ProductHistoryEntry>, anyhow::Error>;
+    async fn history_between(
+        &self,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+    ) -> Result<Vec<ProductHistoryEntry>, anyhow::Error>;
+    async fn change_counts_since(
+        &self,
+        from: OffsetDateTime,
+    ) -> Result<HashMap<String, usize>, anyhow::Error>;
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct TrackedFields {
+    price: Option<i64>,
+    source_price: Option<i64>,
+    available: u8,
+    quantity: Option<i64>,
+}
+
+impl TrackedFields {
+    fn of(p: &Product) -> Self {
+        Self {
+            price: p.price.map(|v| v as i64),
+            source_price: p.source_price.map(|v| v as i64),
+            available: p.available.clone() as u8,
+            quantity: p.quantity.map(|v| v as i64),
+        }
+    }
+}
+
+fn row_to_history_entry(row: &rusqlite::Row<'_>) -> rusqlite::Result<ProductHistoryEntry> {
+    let price: Option<i64> = row.get(1)?;
+    let source_price: Option<i64> = row.get(2)?;
+    let quantity: Option<i64> = row.get(4)?;
+    Ok(ProductHistoryEntry {
+        article: row.get(0)?,
+        price: price.map(|v| v.max(0) as usize),
+        source_price: source_price.map(|v| v.max(0) as usize),
+        available: row.get::<_, u8>(3)?.into(),
+        quantity: quantity.map(|v| v.max(0) as usize),
+        changed_at: OffsetDateTime::from_unix_timestamp(row.get(5)?)
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+    })
+}
+
 pub struct SqliteProductRepository {
     conn: Connection,
 }
@@ -203,6 +300,37 @@ impl SqliteProductRepository {
             let _ = conn.execute("ALTER TABLE product ADD COLUMN quantity INTEGER", []);
             let _ = conn.execute("ALTER TABLE product ADD COLUMN supplier TEXT", []);
             let _ = conn.execute("ALTER TABLE product ADD COLUMN discount_percent INTEGER", []);
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS product_history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    article TEXT NOT NULL,
+                    price INTEGER,
+                    source_price INTEGER,
+                    available INTEGER NOT NULL,
+                    quantity INTEGER,
+                    changed_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS product_history_article_idx
+                 ON product_history(article, changed_at)",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS product_history_changed_at_idx
+                 ON product_history(changed_at)",
+                [],
+            )?;
+            // Товари, збережені до появи історії, отримують поточний стан як початковий
+            conn.execute(
+                "INSERT INTO product_history (article, price, source_price, available, quantity, changed_at)
+                 SELECT p.article, p.price, p.source_price, COALESCE(p.available, 0), p.quantity,
+                    COALESCE(unixepoch(p.last_visited), unixepoch('now'))
+                 FROM product p
+                 WHERE NOT EXISTS (SELECT 1 FROM product_history h WHERE h.article = p.article)",
+                [],
+            )?;
             conn.commit()?;
             Ok(())
         })
@@ -222,7 +350,37 @@ impl Save<Product> for SqliteProductRepository {
             .call(move |conn| {
                 let img = p.img_as_str();
                 let attrs = Product::attrs_to_db(&p.attributes);
-                conn.execute(
+                let tracked = TrackedFields::of(&p);
+                let tx = conn.transaction()?;
+                let previous = tx
+                    .query_row(
+                        "SELECT price, source_price, available, quantity FROM product WHERE article = ?1",
+                        [&p.article],
+                        |row| {
+                            Ok(TrackedFields {
+                                price: row.get(0)?,
+                                source_price: row.get(1)?,
+                                available: row.get::<_, Option<u8>>(2)?.unwrap_or_default(),
+                                quantity: row.get(3)?,
+                            })
+                        },
+                    )
+                    .optional()?;
+                if previous.as_ref() != Some(&tracked) {
+                    tx.execute(
+                        "INSERT INTO product_history (article, price, source_price, available, quantity, changed_at)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        rusqlite::params![
+                            p.article,
+                            tracked.price,
+                            tracked.source_price,
+                            tracked.available,
+                            tracked.quantity,
+                            OffsetDateTime::now_utc().unix_timestamp(),
+                        ],
+                    )?;
+                }
+                tx.execute(
                     "INSERT OR REPLACE INTO product 
                     (title, description, title_ua, description_ua, price, source_price, article, model, category, attributes, available, quantity, url, supplier, discount_percent, last_visited, brand, images, upsell) 
                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
@@ -248,6 +406,7 @@ impl Save<Product> for SqliteProductRepository {
                         p.upsell,
                     ],
                 )?;
+                tx.commit()?;
                 Ok(())
             })
             .await?;
@@ -385,6 +544,39 @@ impl Select<Product, FromDateAvailableSelector> for SqliteProductRepository {
 
 impl ProductRepository for SqliteProductRepository {}
 
+#[async_trait]
+impl ListPage for SqliteProductRepository {
+    async fn list_page(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+        only_available: bool,
+    ) -> Result<(Vec<Product>, Option<String>), anyhow::Error> {
+        Ok(self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT title, description, title_ua, description_ua, price, source_price, article, model, category, attributes, available, quantity, url, supplier, discount_percent, last_visited, brand, images, upsell
+                    FROM product WHERE (?1 IS NULL OR article > ?1) AND (?2 = 0 OR available > 0)
+                    ORDER BY article LIMIT ?3",
+                )?;
+                let p = stmt
+                    .query_map(
+                        rusqlite::params![cursor, only_available, limit as i64],
+                        row_to_product,
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let next = if p.len() < limit {
+                    None
+                } else {
+                    p.last().map(|p| p.article.clone())
+                };
+                Ok((p, next))
+            })
+            .await?)
+    }
+}
+
 #[async_trait]
 impl DeleteProducts for SqliteProductRepository {
     async fn delete_articles(&self, articles: &[String]) -> Result<(), anyhow::Error> {
@@ -406,6 +598,89 @@ impl DeleteProducts for SqliteProductRepository {
     }
 }
 
+#[async_trait]
+impl ProductHistory for SqliteProductRepository {
+    async fn history_by_article(
+        &self,
+        article: &str,
+        from: Option<OffsetDateTime>,
+        to: Option<OffsetDateTime>,
+    ) -> Result<Vec<ProductHistoryEntry>, anyhow::Error> {
+        let article = article.to_string();
+        let from = from.map(OffsetDateTime::unix_timestamp);
+        let to = to.map(OffsetDateTime::unix_timestamp);
+        Ok(self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT article, price, source_price, available, quantity, changed_at
+                    FROM product_history
+                    WHERE article = ?1
+                      AND (?2 IS NULL OR changed_at >= ?2)
+                      AND (?3 IS NULL OR changed_at < ?3)
+                    ORDER BY changed_at, id",
+                )?;
+                let items = stmt
+                    .query_map(rusqlite::params![article, from, to], row_to_history_entry)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(items)
+            })
+            .await?)
+    }
+
+    async fn history_between(
+        &self,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+    ) -> Result<Vec<ProductHistoryEntry>, anyhow::Error> {
+        Ok(self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT article, price, source_price, available, quantity, changed_at
+                    FROM product_history
+                    WHERE changed_at >= ?1 AND changed_at < ?2
+                    ORDER BY changed_at, id",
+                )?;
+                let items = stmt
+                    .query_map(
+                        rusqlite::params![from.unix_timestamp(), to.unix_timestamp()],
+                        row_to_history_entry,
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(items)
+            })
+            .await?)
+    }
+
+    async fn change_counts_since(
+        &self,
+        from: OffsetDateTime,
+    ) -> Result<HashMap<String, usize>, anyhow::Error> {
+        Ok(self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT h.article, COUNT(*)
+                    FROM product_history h
+                    WHERE h.changed_at >= ?1
+                      AND EXISTS (
+                        SELECT 1 FROM product_history p
+                        WHERE p.article = h.article AND p.id < h.id
+                      )
+                    GROUP BY h.article",
+                )?;
+                let items = stmt
+                    .query_map([from.unix_timestamp()], |row| {
+                        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?.max(0) as usize))
+                    })?
+                    .collect::<Result<HashMap<_, _>, _>>()?;
+                Ok(items)
+            })
+            .await?)
+    }
+}
+
 impl TryInto<rt_types::product::Product> for Product {
     type Error = anyhow::Error;
 
@@ -464,6 +739,7 @@ impl TryInto<rt_types::product::Product> for Product {
                 .price
                 .map(Into::into)
                 .ok_or(anyhow::anyhow!("self must contain price"))?,
+            source_price: self.source_price.map(Into::into),
             in_stock: self.quantity,
             currency: "UAH".to_string(),
             article: self.article,
@@ -543,6 +819,7 @@ impl TryInto<rt_types::product::Product> for MaxtonProduct {
                 .price
                 .map(Into::into)
                 .ok_or(anyhow::anyhow!("self.0 must contain price"))?,
+            source_price: self.0.source_price.map(Into::into),
             in_stock: self.0.quantity,
             currency: "UAH".to_string(),
             article: self.0.article,
@@ -556,3 +833,119 @@ impl TryInto<rt_types::product::Product> for MaxtonProduct {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(price: usize, available: Availability) -> Product {
+        Product {
+            title: "Спліттер".to_string(),
+            description: None,
+            title_ua: None,
+            description_ua: None,
+            price: Some(price),
+            source_price: Some(price / 2),
+            article: "DT-1".to_string(),
+            brand: "BMW".to_string(),
+            model: Model("X5".to_string()),
+            category: None,
+            attributes: None,
+            available,
+            quantity: None,
+            url: Url("/dt-1".to_string()),
+            supplier: None,
+            discount_percent: None,
+            last_visited: OffsetDateTime::now_utc(),
+            images: vec![],
+            upsell: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn history_is_written_only_on_change() {
+        let conn = Connection::open_in_memory().await.unwrap();
+        let repo = SqliteProductRepository::init(conn).await.unwrap();
+        repo.save(product(1000, Availability::Available)).await.unwrap();
+        repo.save(product(1000, Availability::Available)).await.unwrap();
+        repo.save(product(1200, Availability::Available)).await.unwrap();
+        repo.save(product(1200, Availability::NotAvailable)).await.unwrap();
+
+        let history = repo.history_by_article("DT-1", None, None).await.unwrap();
+        let states = history
+            .iter()
+            .map(|h| (h.price, h.available.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            vec![
+                (Some(1000), Availability::Available),
+                (Some(1200), Availability::Available),
+                (Some(1200), Availability::NotAvailable),
+            ]
+        );
+        let future = OffsetDateTime::now_utc() + Duration::days(1);
+        assert!(repo
+            .history_by_article("DT-1", Some(future), None)
+            .await
+            .unwrap()
+            .is_empty());
+        let counts = repo
+            .change_counts_since(OffsetDateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+        assert_eq!(counts.get("DT-1"), Some(&2));
+    }
+
+    #[tokio::test]
+    async fn batches_page_by_article() {
+        use futures::TryStreamExt;
+
+        let conn = Connection::open_in_memory().await.unwrap();
+        let repo = std::sync::Arc::new(SqliteProductRepository::init(conn).await.unwrap());
+        for i in 0..7 {
+            let available = if i % 3 == 0 {
+                Availability::NotAvailable
+            } else {
+                Availability::Available
+            };
+            repo.save(Product {
+                article: format!("DT-{i}"),
+                ..product(1000 + i, available)
+            })
+            .await
+            .unwrap();
+        }
+        let articles =
+            |products: Vec<Product>| products.into_iter().map(|p| p.article).collect::<Vec<_>>();
+
+        let pages: Vec<_> = batches(repo.clone(), false, 3).try_collect().await.unwrap();
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![3, 3, 1]
+        );
+        assert_eq!(
+            articles(pages.concat()),
+            articles(repo.list().await.unwrap())
+        );
+
+        let available: Vec<_> = batches(repo.clone(), true, 2).try_collect().await.unwrap();
+        assert_eq!(
+            articles(available.concat()),
+            articles(repo.select(&AvailableSelector).await.unwrap())
+        );
+
+        let (first, next) = repo.list_page(None, 3, false).await.unwrap();
+        repo.save(Product {
+            article: "DT-0".to_string(),
+            ..product(2000, Availability::Available)
+        })
+        .await
+        .unwrap();
+        let (rest, _) = repo.list_page(next, 10, false).await.unwrap();
+        assert_eq!(
+            articles([first, rest].concat()),
+            (0..7).map(|i| format!("DT-{i}")).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use crate::dt::product::{Product, ProductRepository};
+use std::collections::{HashMap, HashSet};
+use time::{Duration, OffsetDateTime};
+
+pub const CHANGES_WINDOW: Duration = Duration::days(30);
+// Товари зі зміненої сторінки списку не читаємо повторно раніше за цей час
+pub const MIN_REVISIT: Duration = Duration::hours(1);
+
+#[derive(Default, Debug, Clone)]
+pub struct CrawlSchedule {
+    changes: HashMap<String, usize>,
+    exported: HashSet<String>,
+}
+
+impl CrawlSchedule {
+    pub fn new(changes: HashMap<String, usize>, exported: HashSet<String>) -> Self {
+        Self { changes, exported }
+    }
+
+    pub async fn load(repo: &dyn ProductRepository) -> Result<Self, anyhow::Error> {
+        let changes = repo
+            .change_counts_since(OffsetDateTime::now_utc() - CHANGES_WINDOW)
+            .await?;
+        let exported = crate::export_diff::exported_articles().await;
+        Ok(Self::new(changes, exported))
+    }
+
+    pub fn changes(&self, article: &str) -> usize {
+        self.changes.get(article).copied().unwrap_or_default()
+    }
+
+    pub fn is_exported(&self, article: &str) -> bool {
+        self.exported.contains(article)
+    }
+
+    pub fn revisit_interval(&self, article: &str) -> Duration {
+        let base = match self.changes(article) {
+            0 => Duration::hours(48),
+            1..=2 => Duration::hours(24),
+            3..=9 => Duration::hours(12),
+            _ => Duration::hours(6),
+        };
+        if self.is_exported(article) {
+            base
+        } else {
+            base * 2
+        }
+    }
+
+    pub fn next_visit(&self, p: &Product) -> OffsetDateTime {
+        p.last_visited
+            .checked_add(self.revisit_interval(&p.article))
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    }
+
+    pub fn is_due(&self, p: &Product, now: OffsetDateTime) -> bool {
+        self.next_visit(p) <= now
+    }
+
+    pub fn priority(
+        &self,
+        p: Option<&Product>,
+    ) -> (bool, std::cmp::Reverse<usize>, OffsetDateTime) {
+        match p {
+            Some(p) => (
+                !self.is_exported(&p.article),
+                std::cmp::Reverse(self.changes(&p.article)),
+                self.next_visit(p),
+            ),
+            None => (
+                false,
+                std::cmp::Reverse(usize::MAX),
+                OffsetDateTime::UNIX_EPOCH,
+            ),
+        }
+    }
+}
+
+// Лише з карток товарів, щоб банери й лічильники не впливали
+pub fn list_fingerprint<'a, I: IntoIterator<Item = &'a str>>(cards: I) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    for card in cards {
+        hasher.update(card.as_bytes());
+        hasher.update(b"\0");
+    }
+    hasher.digest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Model, Url};
+    use rt_types::Availability;
+
+    fn product(article: &str, last_visited: OffsetDateTime) -> Product {
+        Product {
+            title: String::new(),
+            description: None,
+            title_ua: None,
+            description_ua: None,
+            price: None,
+            source_price: None,
+            article: article.to_string(),
+            brand: String::new(),
+            model: Model(String::new()),
+            category: None,
+            attributes: None,
+            available: Availability::Available,
+            quantity: None,
+            url: Url(String::new()),
+            supplier: None,
+            discount_percent: None,
+            last_visited,
+            images: vec![],
+            upsell: None,
+        }
+    }
+
+    #[test]
+    fn volatile_and_exported_products_are_due_sooner() {
+        let now = OffsetDateTime::now_utc();
+        let schedule = CrawlSchedule::new(
+            HashMap::from([("hot".to_string(), 12), ("warm".to_string(), 1)]),
+            HashSet::from(["hot".to_string(), "warm".to_string()]),
+        );
+        let visited = now - Duration::hours(8);
+        assert!(schedule.is_due(&product("hot", visited), now));
+        assert!(!schedule.is_due(&product("warm", visited), now));
+        assert_eq!(schedule.revisit_interval("cold"), Duration::hours(96));
+
+        let mut items = vec![
+            Some(product("cold", visited)),
+            Some(product("warm", visited)),
+            None,
+            Some(product("hot", visited)),
+        ];
+        items.sort_by_key(|p| schedule.priority(p.as_ref()));
+        let order: Vec<_> = items
+            .iter()
+            .map(|p| p.as_ref().map(|p| p.article.as_str()).unwrap_or("new"))
+            .collect();
+        assert_eq!(order, ["new", "hot", "warm", "cold"]);
+    }
+
+    #[test]
+    fn fingerprint_depends_on_cards_only() {
+        assert_eq!(list_fingerprint(["a", "b"]), list_fingerprint(["a", "b"]));
+        assert_ne!(list_fingerprint(["a", "b"]), list_fingerprint(["a", "c"]));
+        assert_ne!(list_fingerprint(["ab"]), list_fingerprint(["a", "b"]));
+    }
+}
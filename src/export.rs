@@ -1,5 +1,10 @@
 use crate::ddaudio_export;
+use crate::export_diff;
+use crate::export_history::{self, ExportRun, ExportRunRepository};
+use crate::export_spool::SpoolWriter;
 use crate::external_import::{Item, Offer, Vendored};
+use crate::google_merchant::Language;
+use crate::scheduler::{JobClass, JobScheduler, RunCounts, RunOutcome, Schedule};
 use crate::SELF_ADDR;
 use crate::{dt, tt};
 use crate::{parse_vendor_from_link, site_publish, uploader};
@@ -17,19 +22,23 @@ use once_cell::sync::Lazy;
 use reqwest::Client;
 use rt_types::access::UserCredentials;
 use rt_types::category::{self, By};
+use rt_types::pricing::{self, PriceSubject};
 use rt_types::product::{Product, UaTranslation};
 use rt_types::shop::service::ShopService;
 use rt_types::shop::ConfigurationChanged;
 use rt_types::shop::{
-    self, ExportEntry, ExportEntryLink, ExportOptions, FileFormat, ParsingCategoriesAction, Shop,
+    self, ExportEntry, ExportEntryLink, ExportOptions, FeedSettings, FileFormat,
+    ParsingCategoriesAction, PromUploadOptions, Shop,
 };
 use rt_types::subscription::service::UserSubscription;
 use rt_types::watermark::service::WatermarkUpdated;
 use rt_types::Availability;
-use rust_decimal_macros::dec;
-use serde::Serialize;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::iter;
 use std::sync::Arc;
+use time::OffsetDateTime;
 use tokio::sync::Semaphore;
 use tokio::sync::{broadcast, Notify, RwLock};
 use typesafe_repository::IdentityOf;
@@ -1314,6 +1323,14 @@ async fn replace_export_file(src: &str, dest: &str) -> Result<(), std::io::Error
     Ok(())
 }
 
+async fn remove_stale_export_file(dest: &str) {
+    match tokio::fs::remove_file(dest).await {
+        Ok(()) => log::info!("Removed stale feed {dest}"),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => log::error!("Unable to remove stale feed {dest}: {err}"),
+    }
+}
+
 pub struct ExportService {
     client: Client,
     entries: Vec<(IdentityOf<Shop>, ExportEntry)>,
@@ -1324,6 +1341,8 @@ pub struct ExportService {
     category_repo: Arc<dyn category::CategoryRepository>,
     shop_service: Addr<ShopService>,
     currency_service: Addr<CurrencyService>,
+    jobs: Arc<JobScheduler>,
+    runs: Arc<dyn ExportRunRepository>,
     export: HashMap<String, Arc<RwLock<Export>>>,
 }
 
@@ -1338,6 +1357,8 @@ impl ExportService {
         category_repo: Arc<dyn category::CategoryRepository>,
         shop_service: Addr<ShopService>,
         currency_service: Addr<CurrencyService>,
+        jobs: Arc<JobScheduler>,
+        runs: Arc<dyn ExportRunRepository>,
     ) -> Self {
         Self {
             client,
@@ -1349,6 +1370,8 @@ impl ExportService {
             category_repo,
             shop_service,
             currency_service,
+            jobs,
+            runs,
             export: HashMap::new(),
         }
     }
@@ -1375,6 +1398,9 @@ impl ExportService {
         category_repo: Arc<dyn category::CategoryRepository>,
         trans_repo: Arc<dyn tt::product::TranslationRepository>,
         currency_service: Addr<CurrencyService>,
+        shop_service: Addr<ShopService>,
+        jobs: Arc<JobScheduler>,
+        runs: Arc<dyn ExportRunRepository>,
     ) {
         let (mut entry, start_notify, stop_notify, mut shop, mut rx) = {
             let e = export.read().await;
@@ -1387,7 +1413,51 @@ impl ExportService {
             )
         };
         let mut file_name = entry.file_name(FileFormat::Csv);
+        let mut job_id = register_export_job(&jobs, shop, &entry).await;
+        let deps = ExportDeps {
+            client: client.clone(),
+            dt_repo,
+            tt_repo,
+            davi_repo,
+            category_repo,
+            trans_repo,
+            currency_service,
+        };
         let mut retry_count = 0;
+        let mut prom_upload: Option<tokio::task::JoinHandle<()>> = None;
+        let last_run = runs
+            .list_runs(shop, &entry.file_name(None), 1)
+            .await
+            .log_error("Unable to read last export run")
+            .and_then(|runs| runs.into_iter().next());
+        let last_status = match &last_run {
+            Some(ExportRun {
+                outcome: RunOutcome::Failure | RunOutcome::Interrupted | RunOutcome::Cancelled,
+                error,
+                ..
+            }) => ExportStatus::Failure(
+                error
+                    .clone()
+                    .unwrap_or_else(|| "экспорт прерван перезапуском".to_string()),
+            ),
+            _ => ExportStatus::Success,
+        };
+        let prom_import = load_export_status(shop, &entry.file_name(FileFormat::Xlsx), "prom")
+            .await
+            .log_error("Unable to read last Prom import")
+            .flatten();
+        let rozetka_issues =
+            load_export_status(shop, &entry.file_name(FileFormat::RozetkaYml), "issues")
+                .await
+                .log_error("Unable to read Rozetka issues")
+                .flatten()
+                .unwrap_or_default();
+        {
+            let mut export = export.write().await;
+            export.last_run = last_run;
+            export.prom_import = prom_import;
+            export.rozetka_issues = rozetka_issues;
+        }
         match tokio::fs::metadata(format!("./export/{shop}/{file_name}"))
             .await
             .map(|m| m.modified())
@@ -1396,12 +1466,15 @@ impl ExportService {
                 Ok(d) if d < entry.update_rate => {
                     {
                         let mut export = export.write().await;
-                        export.status = ExportStatus::Success;
+                        export.status = last_status;
                     }
                     tokio::select! {
                         _ = tokio::time::sleep(entry.update_rate - d) => (),
                         _ = start_notify.notified() => (),
-                        _ = stop_notify.notified() => return,
+                        _ = stop_notify.notified() => {
+                            jobs.unregister(&job_id).await;
+                            return;
+                        }
                     }
                 }
                 Ok(_) => (),
@@ -1414,6 +1487,16 @@ impl ExportService {
                 log::error!("Unable to read export file metadata: {err}");
             }
         }
+        if jobs.is_paused(&job_id).await {
+            tokio::select! {
+                _ = jobs.wait_due(&job_id) => (),
+                _ = start_notify.notified() => (),
+                _ = stop_notify.notified() => {
+                    jobs.unregister(&job_id).await;
+                    return;
+                }
+            }
+        }
         loop {
             if !export.read().await.armed {
                 {
@@ -1429,9 +1512,14 @@ impl ExportService {
             {
                 let mut state = export.write().await;
                 state.status = ExportStatus::Enqueued;
-                if entry != state.entry {
+                if entry != state.entry || shop != state.shop {
                     entry = state.entry.clone();
                     file_name = entry.file_name(None);
+                    let id = register_export_job(&jobs, state.shop, &entry).await;
+                    if id != job_id {
+                        jobs.unregister(&job_id).await;
+                        job_id = id;
+                    }
                 }
                 shop = state.shop;
                 if let Some(true) = rx.try_recv().log_error("Unable to read suspend rx") {
@@ -1455,33 +1543,77 @@ impl ExportService {
             };
             log::info!("Generating {file_name}");
             let shop_id = shop.to_string();
-            let (res, _) = tokio::join!(
-                do_export(
-                    &entry,
-                    shop,
-                    client.clone(),
-                    &shop_id,
-                    dt_repo.clone(),
-                    tt_repo.clone(),
-                    davi_repo.clone(),
-                    category_repo.clone(),
-                    trans_repo.clone(),
-                    currency_service.clone(),
-                    export.clone(),
-                ),
-                async {
-                    let mut export = export.write().await;
-                    export.status = ExportStatus::InProgress;
+            let feeds = match shop_service.send(shop::service::Get(shop)).await {
+                Ok(Ok(Some(shop))) => shop.feeds,
+                Ok(Ok(None)) => FeedSettings::default(),
+                Ok(Err(err)) => {
+                    log::error!("Unable to read shop {shop}: {err}");
+                    FeedSettings::default()
                 }
-            );
+                Err(err) => {
+                    log::error!("Unable to send message to ShopService: {err}");
+                    FeedSettings::default()
+                }
+            };
+            let res = jobs
+                .run(&job_id, |_| async {
+                    let (res, _) = tokio::join!(
+                        do_export(&entry, shop, &feeds, &shop_id, &deps, export.clone()),
+                        async {
+                            let mut export = export.write().await;
+                            export.status = ExportStatus::InProgress;
+                        }
+                    );
+                    res.map(|summary| {
+                        let counts = summary.counts.clone();
+                        (summary, counts)
+                    })
+                })
+                .await;
             drop(permit);
+            let run = runs
+                .list_runs(shop, &entry.file_name(None), 1)
+                .await
+                .log_error("Unable to read export run")
+                .and_then(|runs| runs.into_iter().next());
+            if let Some(mut run) = run {
+                run.stage = export
+                    .read()
+                    .await
+                    .progress
+                    .as_ref()
+                    .map(|p| p.stage.clone());
+                if let Some(Ok(summary)) = &res {
+                    run.timings = summary.timings.clone();
+                }
+                runs.save_details(run.id, run.stage.clone(), run.timings.clone())
+                    .await
+                    .log_error("Unable to record export run");
+                if let RunOutcome::Success = run.outcome {
+                    if let Err(err) =
+                        export_history::archive(&run, &export_history::output_files(&entry)).await
+                    {
+                        log::error!("Unable to keep files of export run {}: {err}", run.id);
+                    }
+                }
+                export.write().await.last_run = Some(run);
+            }
+            let guard_blocked = matches!(res, Some(Err(ExportError::Guard(_))));
             let status = match res {
-                Ok(_) => {
+                None => {
+                    log::warn!("Generation of {file_name} was cancelled");
+                    retry_count = 0;
+                    ExportStatus::Failure("экспорт отменён".to_string())
+                }
+                Some(Ok(_)) => {
                     log::info!("{file_name} has been generated");
                     retry_count = 0;
                     ExportStatus::Success
                 }
-                Err(ExportError::Download(_, uploader::DownloadFromLinkError::Other(err))) => {
+                Some(Err(ExportError::Download(
+                    _,
+                    uploader::DownloadFromLinkError::Other(err),
+                ))) => {
                     log::error!("Unable to generate {file_name}: {err}");
                     if retry_count < MAX_RETRY_COUNT {
                         retry_count += 1;
@@ -1491,10 +1623,10 @@ impl ExportService {
                         ExportStatus::Failure(err.to_string())
                     }
                 }
-                Err(ExportError::Download(
+                Some(Err(ExportError::Download(
                     l,
                     uploader::DownloadFromLinkError::UnableToParse { err, content },
-                )) => {
+                ))) => {
                     let hash = &xxh64(l.as_bytes(), l.len() as u64);
                     if let Err(err) = std::fs::write(format!("{hash:x}.xml.tmp"), content) {
                         log::error!("Unable to write unparsed content of link {}: {err}", l);
@@ -1503,27 +1635,188 @@ impl ExportService {
                     }
                     ExportStatus::Failure(err.to_string())
                 }
-                Err(err) => {
+                Some(Err(ExportError::Guard(reason))) => {
+                    log::warn!("{file_name} was not published: {reason}");
+                    retry_count = 0;
+                    ExportStatus::Failure(format!(
+                        "файл не опубликован, предыдущая версия сохранена: {reason}"
+                    ))
+                }
+                Some(Err(err)) => {
                     log::error!("Unable to generate {file_name}: {err}");
                     ExportStatus::Failure(err.to_string())
                 }
             };
+            let published = matches!(status, ExportStatus::Success);
             {
                 let mut export = export.write().await;
                 export.status = status;
+                export.guard_blocked = guard_blocked;
                 if let ExportStatus::Success | ExportStatus::Failure(_) = export.status {
                     export.progress = None;
                 }
             }
+            if let (true, Some(opts)) = (published, entry.prom.clone()) {
+                if let Some(upload) = prom_upload.take() {
+                    upload.abort();
+                }
+                prom_upload = Some(tokio::task::spawn_local(upload_to_prom(
+                    client.clone(),
+                    export.clone(),
+                    shop_service.clone(),
+                    shop,
+                    entry.file_name(FileFormat::Xlsx),
+                    opts,
+                )));
+            }
             tokio::select! {
-                _ = tokio::time::sleep(entry.update_rate) => (),
+                _ = jobs.wait_due(&job_id) => (),
                 _ = start_notify.notified() => (),
-                _ = stop_notify.notified() => return,
+                _ = stop_notify.notified() => {
+                    if let Some(upload) = prom_upload.take() {
+                        upload.abort();
+                    }
+                    jobs.unregister(&job_id).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+const PROM_STATUS_POLL: std::time::Duration = std::time::Duration::from_secs(30);
+const PROM_STATUS_MAX_POLLS: usize = 240;
+
+async fn upload_to_prom(
+    client: Client,
+    export: Arc<RwLock<Export>>,
+    shop_service: Addr<ShopService>,
+    shop: IdentityOf<Shop>,
+    file_name: String,
+    opts: PromUploadOptions,
+) {
+    let set = |import: PromImport| {
+        let export = export.clone();
+        let file_name = file_name.clone();
+        async move {
+            if let Err(err) = save_export_status(shop, &file_name, "prom", &import).await {
+                log::error!("Unable to save Prom import of {file_name}: {err}");
             }
+            export.write().await.prom_import = Some(import);
+        }
+    };
+    let mut import = PromImport {
+        id: None,
+        started: OffsetDateTime::now_utc(),
+        status: None,
+        error: None,
+    };
+    let token = match shop_service.send(shop::service::Get(shop)).await {
+        Ok(Ok(Some(shop))) => shop.prom.api_token.filter(|t| !t.trim().is_empty()),
+        Ok(Ok(None)) => None,
+        Ok(Err(err)) => {
+            log::error!("Unable to read shop {shop}: {err}");
+            None
         }
+        Err(err) => {
+            log::error!("Unable to send message to ShopService: {err}");
+            None
+        }
+    };
+    let Some(token) = token else {
+        import.error = Some("не указан токен Prom в настройках магазина".to_string());
+        set(import).await;
+        return;
+    };
+    let path = format!("./export/{shop}/{file_name}");
+    let upload =
+        uploader::upload_products(&uploader::PROM_API, &path, &token, &opts, client.clone());
+    let id = match upload.await {
+        Ok(id) => id,
+        Err(err) => {
+            log::error!("Unable to upload {file_name} to Prom: {err}");
+            import.error = Some(err.to_string());
+            set(import).await;
+            return;
+        }
+    };
+    log::info!("{file_name} uploaded to Prom, import {id}");
+    import.id = Some(id.clone());
+    set(import.clone()).await;
+    for _ in 0..PROM_STATUS_MAX_POLLS {
+        tokio::time::sleep(PROM_STATUS_POLL).await;
+        match uploader::import_status(&uploader::PROM_API, &id, &token, client.clone()).await {
+            Ok(status) => {
+                let finished = status.is_finished();
+                import.status = Some(status);
+                import.error = None;
+                set(import.clone()).await;
+                if finished {
+                    return;
+                }
+            }
+            Err(err) => {
+                log::warn!("Unable to read status of Prom import {id}: {err}");
+                import.error = Some(err.to_string());
+                set(import.clone()).await;
+            }
+        }
+    }
+    import.error = Some("Prom не завершил импорт за отведённое время".to_string());
+    set(import).await;
+}
+
+fn export_status_path(shop: IdentityOf<Shop>, file_name: &str, kind: &str) -> String {
+    format!("./export_status/{shop}/{file_name}.{kind}.json")
+}
+
+async fn save_export_status<T: Serialize>(
+    shop: IdentityOf<Shop>,
+    file_name: &str,
+    kind: &str,
+    value: &T,
+) -> Result<(), anyhow::Error> {
+    tokio::fs::create_dir_all(format!("./export_status/{shop}")).await?;
+    tokio::fs::write(
+        export_status_path(shop, file_name, kind),
+        serde_json::to_vec(value)?,
+    )
+    .await?;
+    Ok(())
+}
+
+// Стан файлу експорту між перезапусками: останнє вивантаження в Prom, пропущені пропозиції Rozetka
+async fn load_export_status<T: serde::de::DeserializeOwned>(
+    shop: IdentityOf<Shop>,
+    file_name: &str,
+    kind: &str,
+) -> Result<Option<T>, anyhow::Error> {
+    match tokio::fs::read(export_status_path(shop, file_name, kind)).await {
+        Ok(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
     }
 }
 
+// Задача за назвою файлу, бо хеш запису змінюється при кожному редагуванні
+async fn register_export_job(
+    jobs: &JobScheduler,
+    shop: IdentityOf<Shop>,
+    entry: &ExportEntry,
+) -> String {
+    let file_name = entry.file_name(None);
+    let id = export_history::job_id(shop, &file_name);
+    jobs.register(
+        id.clone(),
+        format!("Экспорт {file_name}"),
+        Some(shop),
+        JobClass::Task,
+        Schedule::Interval(entry.update_rate),
+    )
+    .await;
+    id
+}
+
 pub struct AddExportPermission(IdentityOf<Shop>);
 
 impl AddExportPermission {
@@ -1590,6 +1883,10 @@ pub struct Remove(pub String);
 #[rtype(result = "()")]
 pub struct Start(pub String);
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PublishAnyway(pub String);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct StartAll;
@@ -1620,6 +1917,11 @@ pub struct Export {
     pub entry: ExportEntry,
     pub progress: Option<ProgressInfo>,
     pub armed: bool,
+    pub skip_guards: bool,
+    pub guard_blocked: bool,
+    pub prom_import: Option<PromImport>,
+    pub rozetka_issues: Vec<crate::rozetka::OfferIssues>,
+    pub last_run: Option<ExportRun>,
     start: Arc<Notify>,
     suspend_tx: broadcast::Sender<bool>,
     stop: Arc<Notify>,
@@ -1634,6 +1936,15 @@ impl Export {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromImport {
+    pub id: Option<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub started: OffsetDateTime,
+    pub status: Option<uploader::ImportStatus>,
+    pub error: Option<String>,
+}
+
 #[derive(Clone, Debug, Display, Serialize)]
 pub enum ExportStatus {
     #[display("В очереди")]
@@ -1668,6 +1979,11 @@ impl Actor for ExportService {
                     suspend_tx,
                     status: ExportStatus::Enqueued,
                     armed: true,
+                    skip_guards: false,
+                    guard_blocked: false,
+                    prom_import: None,
+                    rozetka_issues: vec![],
+                    last_run: None,
                 })),
             );
         }
@@ -1681,6 +1997,9 @@ impl Actor for ExportService {
                 self.category_repo.clone(),
                 self.trans_repo.clone(),
                 self.currency_service.clone(),
+                self.shop_service.clone(),
+                self.jobs.clone(),
+                self.runs.clone(),
             ));
         }
         Context::new().run(self)
@@ -1799,6 +2118,35 @@ impl Handler<Start> for ExportService {
     }
 }
 
+impl Handler<PublishAnyway> for ExportService {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        PublishAnyway(hash): PublishAnyway,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let export = self.export.get(&hash).cloned();
+        let fut = async move {
+            if let Some(n) = export {
+                {
+                    let mut entry = n.write().await;
+                    if !entry.guard_blocked {
+                        return;
+                    }
+                    entry.armed = true;
+                    entry.skip_guards = true;
+                    entry.status = ExportStatus::Enqueued;
+                }
+                n.read().await.start.notify_waiters();
+            } else {
+                log::warn!("Export entry {hash} not found");
+            }
+        };
+        Box::pin(fut.into_actor(self))
+    }
+}
+
 impl Handler<StartAll> for ExportService {
     type Result = ResponseActFuture<Self, ()>;
 
@@ -1894,6 +2242,11 @@ impl Handler<Add> for ExportService {
             suspend_tx,
             status: ExportStatus::Enqueued,
             armed: true,
+            skip_guards: false,
+            guard_blocked: false,
+            prom_import: None,
+            rozetka_issues: vec![],
+            last_run: None,
         }));
         let client = self.client.clone();
         let dt_repo = self.dt_repo.clone();
@@ -1902,15 +2255,18 @@ impl Handler<Add> for ExportService {
         let trans_repo = self.trans_repo.clone();
         let category_repo = self.category_repo.clone();
         let currency_service = self.currency_service.clone();
+        let jobs = self.jobs.clone();
+        let runs = self.runs.clone();
         let new_entry = entry.clone();
+        let shop_service = addr.clone();
         let fut = async move {
-            let mut shop = addr
+            let mut shop = shop_service
                 .send(shop::service::Get(shop))
                 .await?
                 .context("Unable to read shop")?
                 .ok_or(anyhow::anyhow!("Shop not found"))?;
             shop.export_entries.push(new_entry);
-            addr.send(shop::service::Update(shop)).await??;
+            shop_service.send(shop::service::Update(shop)).await??;
             Ok(())
         };
         Box::pin(fut.into_actor(self).map(move |res, act, _| {
@@ -1925,6 +2281,9 @@ impl Handler<Add> for ExportService {
                 category_repo,
                 trans_repo,
                 currency_service,
+                addr,
+                jobs,
+                runs,
             ));
             res
         }))
@@ -2096,6 +2455,8 @@ pub async fn parse_from_links(
 pub enum ExportError {
     #[display("Unable to download items from link: {:?}", _0)]
     Download(String, uploader::DownloadFromLinkError),
+    #[display("Export blocked by guards: {}", _0)]
+    Guard(String),
     #[display("{}", _0)]
     Other(anyhow::Error),
 }
@@ -2122,6 +2483,267 @@ fn export_concurrency() -> usize {
 
 static SEMAPHORE: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(export_concurrency()));
 
+// Водяні знаки, наявність і правила цін джерела до запису в спул, записувачі бачать лише кінцеві ціни
+struct Collect<'a> {
+    shop: IdentityOf<Shop>,
+    shop_id: &'a str,
+    category_names: HashMap<uuid::Uuid, String>,
+    rules: HashMap<ExportOptions, (ExportOptions, Vec<pricing::PricingRule>)>,
+    spool: SpoolWriter,
+    snapshot: export_diff::Snapshot,
+    count: usize,
+}
+
+impl<'a> Collect<'a> {
+    fn new(
+        shop: IdentityOf<Shop>,
+        shop_id: &'a str,
+        spool: SpoolWriter,
+        categories: &HashSet<category::Category, xxhash_rust::xxh3::Xxh3DefaultBuilder>,
+    ) -> Self {
+        let mut collect = Self {
+            shop,
+            shop_id,
+            category_names: HashMap::new(),
+            rules: HashMap::new(),
+            spool,
+            snapshot: export_diff::Snapshot::new(),
+            count: 0,
+        };
+        collect.set_categories(categories);
+        collect
+    }
+
+    fn set_categories(
+        &mut self,
+        categories: &HashSet<category::Category, xxhash_rust::xxh3::Xxh3DefaultBuilder>,
+    ) {
+        self.category_names = categories.iter().map(|c| (c.id, c.name.clone())).collect();
+    }
+
+    fn push(
+        &mut self,
+        source: usize,
+        opts: &ExportOptions,
+        mut p: Product,
+    ) -> Result<(), anyhow::Error> {
+        self.count += 1;
+        let shop = self.shop;
+        rt_types::watermark::apply_to_product(
+            opts,
+            &mut p,
+            self.shop_id,
+            &SELF_ADDR,
+            |image, watermark, opts| crate::watermark::link_query(shop, image, watermark, opts),
+        );
+        if opts.only_available
+            && !matches!(p.available, Availability::Available | Availability::OnOrder)
+        {
+            return Ok(());
+        }
+        if let Some(av) = &opts.set_availability {
+            p.available = av.clone();
+        }
+        if !self.rules.contains_key(opts) {
+            let mut priced = opts.clone();
+            priced.adjust_price = None;
+            self.rules
+                .insert(opts.clone(), (priced, opts.pricing_rules()));
+        }
+        let (priced, rules) = &self.rules[opts];
+        if !rules.is_empty() {
+            let subject = PriceSubject {
+                vendor: &p.vendor,
+                category: p
+                    .category
+                    .as_ref()
+                    .and_then(|c| self.category_names.get(c))
+                    .map(String::as_str),
+                brand: &p.brand,
+                article: &p.article,
+            };
+            let source_price = p.source_price.unwrap_or(p.price);
+            p.price = pricing::apply_rules(rules, &subject, source_price, p.price);
+        }
+        self.snapshot.insert(priced, &p);
+        self.spool.push(source, priced, &p)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub counts: RunCounts,
+    pub timings: RunCounts,
+}
+
+impl ExportSummary {
+    fn count(&mut self, source: &str, products: usize) {
+        *self.counts.entry(source.to_string()).or_default() += products as u64;
+    }
+}
+
+const DT_BATCH_SIZE: usize = 2000;
+
+struct Prepare<'a> {
+    categories: category::CategoryAssigner<'a>,
+    rates: &'a HashMap<String, Decimal>,
+}
+
+impl Prepare<'_> {
+    fn apply(&self, opts: &ExportOptions, mut p: Product) -> Product {
+        if opts.categories {
+            self.categories.assign(&mut p);
+        }
+        if opts.convert_to_uah {
+            if let Some(rate) = self.rates.get(&p.currency) {
+                p.currency = "UAH".to_string();
+                p.price *= rate;
+            }
+        }
+        p
+    }
+}
+
+enum DtSupplier {
+    Maxton,
+    Jgd,
+    Pl,
+    Skm,
+    OpTuning,
+    Dt,
+}
+
+fn dt_supplier(p: &dt::product::Product) -> DtSupplier {
+    let known = supplier_key(p).is_some();
+    let is_maxton = if known {
+        supplier_is(p, "maxton")
+    } else {
+        p.article.ends_with("-M")
+            || p.title.to_lowercase().contains("maxton")
+            || p.description
+                .as_ref()
+                .is_some_and(|d| d.to_lowercase().contains("maxton"))
+    };
+    let article = p.article.to_uppercase();
+    if is_maxton {
+        DtSupplier::Maxton
+    } else if (known && supplier_is(p, "jgd")) || (!known && article.starts_with("JGD")) {
+        DtSupplier::Jgd
+    } else if (known && supplier_is(p, "pl")) || (!known && PL_ARTICLES.contains(article.as_str()))
+    {
+        DtSupplier::Pl
+    } else if (known && supplier_is(p, "skm")) || (!known && article.starts_with("SKM")) {
+        DtSupplier::Skm
+    } else if site_publish::detect_supplier(p).as_deref() == Some("op_tuning") {
+        DtSupplier::OpTuning
+    } else {
+        DtSupplier::Dt
+    }
+}
+
+// Порядок, в якому джерела додавались до експорту до спулу
+mod source {
+    pub const DDAUDIO: usize = 0;
+    pub const LINKS: usize = 1;
+    pub const TT: usize = 2;
+    pub const DT_TT: usize = 3;
+    pub const OP_TUNING: usize = 4;
+    pub const DT: usize = 5;
+    pub const JGD: usize = 6;
+    pub const PL: usize = 7;
+    pub const SKM: usize = 8;
+    pub const MAXTON: usize = 9;
+    pub const DAVI: usize = 10;
+}
+
+struct DtSplit {
+    articles: HashSet<String>,
+    maxton_skipped: usize,
+    counts: RunCounts,
+    op_tuning: Option<ExportOptions>,
+    dt: Option<ExportOptions>,
+    jgd: Option<ExportOptions>,
+    pl: Option<ExportOptions>,
+    skm: Option<ExportOptions>,
+    maxton: Option<ExportOptions>,
+}
+
+impl DtSplit {
+    fn new(entry: &ExportEntry) -> Self {
+        // Товари OP Tuning і DT завжди несуть постачальника в примітках
+        let with_vendor = |opts: &ExportOptions| ExportOptions {
+            add_vendor: true,
+            ..opts.clone()
+        };
+        Self {
+            articles: HashSet::new(),
+            maxton_skipped: 0,
+            counts: RunCounts::default(),
+            op_tuning: entry
+                .op_tuning_parsing
+                .as_ref()
+                .map(|o| with_vendor(&o.options)),
+            dt: entry.dt_parsing.as_ref().map(|o| with_vendor(&o.options)),
+            jgd: entry.jgd_parsing.as_ref().map(|o| o.options.clone()),
+            pl: entry.pl_parsing.as_ref().map(|o| o.options.clone()),
+            skm: entry.skm_parsing.as_ref().map(|o| o.options.clone()),
+            maxton: entry.maxton_parsing.as_ref().map(|o| o.options.clone()),
+        }
+    }
+
+    fn push(
+        &mut self,
+        mut p: dt::product::Product,
+        prepare: &Prepare,
+        collect: &mut Collect,
+    ) -> Result<(), anyhow::Error> {
+        self.articles.insert(p.article.to_uppercase());
+        let supplier = dt_supplier(&p);
+        let (opts, source, name, vendor) = match supplier {
+            DtSupplier::OpTuning => (&self.op_tuning, source::OP_TUNING, "op_tuning", None),
+            DtSupplier::Dt => (&self.dt, source::DT, "dt", None),
+            DtSupplier::Jgd => (&self.jgd, source::JGD, "jgd", Some("JGD")),
+            DtSupplier::Pl => (&self.pl, source::PL, "pl", Some("Скловолокно PL")),
+            DtSupplier::Skm => (&self.skm, source::SKM, "skm", Some("SKM")),
+            DtSupplier::Maxton => (&self.maxton, source::MAXTON, "maxton", None),
+        };
+        let Some(opts) = opts else {
+            return Ok(());
+        };
+        let product = match supplier {
+            DtSupplier::Maxton => {
+                if p.article.ends_with('T') {
+                    self.maxton_skipped += 1;
+                    return Ok(());
+                }
+                rt_types::product::convert(iter::once(dt::product::MaxtonProduct(p)))
+                    .next()
+                    .map(|mut p| {
+                        p.available = Availability::OnOrder;
+                        p
+                    })
+            }
+            DtSupplier::Jgd | DtSupplier::Pl | DtSupplier::Skm => {
+                p.available = Availability::OnOrder;
+                rt_types::product::convert(iter::once(p)).next()
+            }
+            DtSupplier::OpTuning | DtSupplier::Dt => {
+                rt_types::product::convert(iter::once(p)).next()
+            }
+        };
+        let Some(product) = product else {
+            return Ok(());
+        };
+        let mut product = prepare.apply(opts, product);
+        if let Some(vendor) = vendor {
+            product.vendor = vendor.to_string();
+            ensure_bilingual(&mut product);
+        }
+        *self.counts.entry(name.to_string()).or_default() += 1;
+        collect.push(source, opts, product)
+    }
+}
+
 fn ensure_bilingual(p: &mut Product) {
     let ua_title = p
         .ua_translation
@@ -2167,20 +2789,37 @@ fn ensure_bilingual(p: &mut Product) {
     }
 }
 
+pub struct ExportDeps {
+    pub client: Client,
+    pub dt_repo: Arc<dyn dt::product::ProductRepository>,
+    pub tt_repo: Arc<dyn tt::product::ProductRepository>,
+    pub davi_repo: Arc<dyn rt_parsing_davi::ProductRepository>,
+    pub category_repo: Arc<dyn category::CategoryRepository>,
+    pub trans_repo: Arc<dyn tt::product::TranslationRepository>,
+    pub currency_service: Addr<CurrencyService>,
+}
+
 pub async fn do_export(
     entry: &ExportEntry,
     shop: IdentityOf<Shop>,
-    client: Client,
+    feeds: &FeedSettings,
     shop_id: &str,
-    dt_repo: Arc<dyn dt::product::ProductRepository>,
-    tt_repo: Arc<dyn tt::product::ProductRepository>,
-    davi_repo: Arc<dyn rt_parsing_davi::ProductRepository>,
-    category_repo: Arc<dyn category::CategoryRepository>,
-    trans_repo: Arc<dyn tt::product::TranslationRepository>,
-    currency_service: Addr<CurrencyService>,
+    deps: &ExportDeps,
     export_handle: Arc<RwLock<Export>>,
-) -> Result<(), ExportError> {
-    const TOTAL_STEPS: usize = 5;
+) -> Result<ExportSummary, ExportError> {
+    let ExportDeps {
+        client,
+        dt_repo,
+        tt_repo,
+        davi_repo,
+        category_repo,
+        trans_repo,
+        currency_service,
+    } = deps;
+    const TOTAL_STEPS: usize = 6;
+    let mut summary = ExportSummary::default();
+    // Забираємо одразу, щоб невдалий запуск не лишив його наступному
+    let skip_guards = std::mem::take(&mut export_handle.write().await.skip_guards);
     ExportService::set_progress(&export_handle, "Сбор данных", 0, TOTAL_STEPS).await;
     match tokio::fs::create_dir_all(format!("/tmp/export/{shop}")).await {
         Ok(_) => (),
@@ -2196,7 +2835,32 @@ pub async fn do_export(
             log::error!("Unable to create export directory for shop {shop}: {err}");
         }
     }
-    let (dt, tt, davi, res) = tokio::join!(
+    let categories_list = category_repo.select(&By(shop)).await?;
+    let all_categories = categories_list.iter().cloned().fold(
+        HashSet::with_hasher(xxhash_rust::xxh3::Xxh3DefaultBuilder::new()),
+        |mut r, e| {
+            r.insert(e);
+            r
+        },
+    );
+    let currency_settings = crate::shop::currency_settings(&shop);
+    let rates = match currency_service.send(ListRates).await {
+        Ok(rates) => currency_settings.apply(rates),
+        Err(err) => {
+            log::error!("Unable to list rates: {err}");
+            HashMap::new()
+        }
+    };
+    let prepare = Prepare {
+        categories: category::CategoryAssigner::new(&all_categories),
+        rates: &rates,
+    };
+    let spool = SpoolWriter::create(format!(
+        "/tmp/export/{shop}/{}.spool",
+        entry.file_name(None)
+    ))?;
+    let mut collect = Collect::new(shop, shop_id, spool, &all_categories);
+    let (dt, res) = tokio::join!(
         async {
             let need_dt = entry.dt_parsing.is_some()
                 || entry.op_tuning_parsing.is_some()
@@ -2206,7 +2870,7 @@ pub async fn do_export(
                 || entry.skm_parsing.is_some()
                 || entry.dt_tt_parsing.is_some();
             if !need_dt {
-                return Ok::<Option<Vec<dt::product::Product>>, anyhow::Error>(None);
+                return Ok::<Option<DtSplit>, anyhow::Error>(None);
             }
             let wants_all = [
                 entry.dt_parsing.as_ref().map(|x| x.options.only_available),
@@ -2229,39 +2893,20 @@ pub async fn do_export(
             .into_iter()
             .flatten()
             .any(|v| !v);
-            if wants_all {
-                Ok(Some(dt_repo.list().await?))
-            } else {
-                Ok(Some(dt_repo.select(&dt::product::AvailableSelector).await?))
-            }
-        },
-        async {
-            let tt_only_available = entry
-                .tt_parsing
-                .as_ref()
-                .map(|x| x.options.only_available)
-                .or(entry
-                    .dt_tt_parsing
-                    .as_ref()
-                    .map(|x| x.options.only_available));
-            match &tt_only_available {
-                Some(true) => Ok::<_, anyhow::Error>(Some(
-                    tt_repo.select(&tt::product::AvailableSelector).await?,
-                )),
-                Some(false) => Ok(Some(tt_repo.list().await?)),
-                None => Ok(None),
-            }
-        },
-        async {
-            match &entry.davi_parsing.as_ref().map(|x| x.only_available) {
-                Some(true) => Ok::<_, anyhow::Error>(Some(
-                    davi_repo
-                        .select(&rt_types::product::AvailableSelector)
-                        .await?,
-                )),
-                Some(false) => Ok(Some(davi_repo.list().await?)),
-                None => Ok(None),
+            let mut split = DtSplit::new(entry);
+            let mut batches = std::pin::pin!(dt::product::batches(
+                dt_repo.clone(),
+                !wants_all,
+                DT_BATCH_SIZE
+            ));
+            while let Some(batch) = batches.try_next().await? {
+                for product in batch {
+                    if !is_dt_export_blocked(&product) {
+                        split.push(product, &prepare, &mut collect)?;
+                    }
+                }
             }
+            Ok(Some(split))
         },
         async {
             match &entry.links {
@@ -2275,27 +2920,9 @@ pub async fn do_export(
             .map(Option::unzip)
         }
     );
-    let (dt, tt, davi) = (dt?, tt?, davi?);
-    let dt: Option<Vec<dt::product::Product>> = dt.map(|list| {
-        list.into_iter()
-            .filter(|product| !is_dt_export_blocked(product))
-            .collect::<Vec<_>>()
-    });
-    let dt_articles: Option<HashSet<String>> = dt
-        .as_ref()
-        .map(|list| list.iter().map(|p| p.article.to_uppercase()).collect());
+    let dt = dt?;
     let (offers, items) = res?;
 
-    let mut res: HashMap<ExportOptions, Vec<Product>, _> =
-        HashMap::with_hasher(xxhash_rust::xxh3::Xxh3DefaultBuilder::new());
-    let categories_list = category_repo.select(&By(shop)).await?;
-    let categories = categories_list.iter().cloned().fold(
-        HashSet::with_hasher(xxhash_rust::xxh3::Xxh3DefaultBuilder::new()),
-        |mut r, e| {
-            r.insert(e);
-            r
-        },
-    );
     let categories_used = entry
         .dt_parsing
         .as_ref()
@@ -2341,49 +2968,26 @@ pub async fn do_export(
                 .any(|(e, _)| e.options.as_ref().is_some_and(|opts| opts.categories))
         });
     let mut categories = if categories_used {
-        categories
+        all_categories.clone()
     } else {
         HashSet::with_hasher(xxhash_rust::xxh3::Xxh3DefaultBuilder::new())
     };
-    let rates = match currency_service.send(ListRates).await {
-        Ok(rates) => rates
-            .into_iter()
-            .map(|(k, v)| (k, v * dec!(1.07)))
-            .collect(),
-        Err(err) => {
-            log::error!("Unable to list rates: {err}");
-            HashMap::new()
-        }
-    };
     if let Some(ddaudio_opts) = entry.ddaudio_api.as_ref() {
         let products =
-            ddaudio_export::fetch_products(ddaudio_opts, &categories_list, Some(export_handle.clone()))
+            ddaudio_export::fetch_products(
+                ddaudio_opts,
+                &categories_list,
+                &currency_settings,
+                Some(export_handle.clone()),
+            )
                 .await
                 .map_err(ExportError::Other)?;
-        let opts = ddaudio_opts.options.clone();
-        let products: Vec<_> = if opts.categories {
-            category::assign_categories(products, &categories).collect()
-        } else {
-            products
-        };
-        let mut products = match opts.convert_to_uah {
-            true => products
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
-                    }
-                    i
-                })
-                .collect(),
-            false => products,
-        };
-        products.iter_mut().for_each(ensure_bilingual);
-        if let Some(entry) = res.get_mut(&opts) {
-            entry.append(&mut products);
-        } else {
-            res.insert(opts, products);
+        let opts = &ddaudio_opts.options;
+        summary.count("ddaudio", products.len());
+        for p in products {
+            let mut p = prepare.apply(opts, p);
+            ensure_bilingual(&mut p);
+            collect.push(source::DDAUDIO, opts, p)?;
         }
     }
     if let Some(offers) = offers {
@@ -2401,29 +3005,14 @@ pub async fn do_export(
             if i.is_empty() {
                 log::warn!("Empty items list for offers: {opts:#?}");
             }
-            let items =
-                rt_types::product::convert(i.into_iter().map(Vendored::with_vendor(vendor)));
-            let items: Vec<_> = if opts.categories {
-                rt_types::category::assign_categories(items, &categories).collect()
-            } else {
-                items.collect()
-            };
-            let items = match opts.convert_to_uah {
-                true => items
-                    .into_iter()
-                    .map(|mut i| {
-                        if let Some(rate) = rates.get(&i.currency) {
-                            i.currency = "UAH".to_string();
-                            i.price *= rate;
-                        }
-                        i
-                    })
-                    .collect(),
-                false => items,
-            };
-            let mut items: Vec<_> = items.into_iter().collect();
-            items.iter_mut().for_each(ensure_bilingual);
-            res.insert(opts, items);
+            let mut count = 0;
+            for p in rt_types::product::convert(i.into_iter().map(Vendored::with_vendor(vendor))) {
+                let mut p = prepare.apply(&opts, p);
+                ensure_bilingual(&mut p);
+                collect.push(source::LINKS, &opts, p)?;
+                count += 1;
+            }
+            summary.count("links", count);
         }
     }
     if let Some(items) = items {
@@ -2441,417 +3030,138 @@ pub async fn do_export(
             if i.is_empty() {
                 log::warn!("Empty items list for items: {opts:#?}");
             }
-            let offers =
-                rt_types::product::convert(i.into_iter().map(Vendored::with_vendor(vendor)));
-            let offers: Vec<_> = if opts.categories {
-                rt_types::category::assign_categories(offers, &categories).collect()
-            } else {
-                offers.collect()
-            };
-            let mut offers = match opts.convert_to_uah {
-                true => offers
-                    .into_iter()
-                    .map(|mut i| {
-                        if let Some(rate) = rates.get(&i.currency) {
-                            i.currency = "UAH".to_string();
-                            i.price *= rate;
-                        }
-                        i
-                    })
-                    .collect(),
-                false => offers,
-            };
-            offers.iter_mut().for_each(ensure_bilingual);
-            if let Some(entry) = res.get_mut(&opts) {
-                entry.append(&mut offers);
-            } else {
-                res.insert(opts, offers);
+            let mut count = 0;
+            for p in rt_types::product::convert(i.into_iter().map(Vendored::with_vendor(vendor))) {
+                let mut p = prepare.apply(&opts, p);
+                ensure_bilingual(&mut p);
+                collect.push(source::LINKS, &opts, p)?;
+                count += 1;
             }
+            summary.count("links", count);
         }
     }
-    if let Some((options, products)) = entry.tt_parsing.as_ref().zip(tt) {
-        let products = match &options.append_categories {
-            Some(ParsingCategoriesAction::BeforeTitle { separator }) => products
-                .into_iter()
-                .map(|mut p| {
-                    if let Some(category) = &p.category {
-                        p.title = format!("{} {} {}", category.trim(), separator.trim(), p.title);
-                    }
-                    p
-                })
-                .collect(),
-            Some(ParsingCategoriesAction::AfterTitle { separator }) => products
-                .into_iter()
+    if let Some(options) = entry.tt_parsing.as_ref() {
+        let dt_tt = entry
+            .dt_tt_parsing
+            .as_ref()
+            .zip(dt.as_ref().map(|dt| &dt.articles));
+        let (mut tt_count, mut dt_tt_count) = (0, 0);
+        let mut batches = std::pin::pin!(tt::product::batches(
+            tt_repo.clone(),
+            options.options.only_available,
+            DT_BATCH_SIZE
+        ));
+        while let Some(batch) = batches.try_next().await? {
+            let translated = stream::iter(batch)
                 .map(|mut p| {
-                    if let Some(category) = &p.category {
-                        p.title = format!("{} {} {}", p.title, separator.trim(), category.trim());
-                    }
-                    p
-                })
-                .collect(),
-            None => products,
-        };
-        let products: Vec<_> = stream::iter(products)
-            .map(|mut p| async {
-                let trans = trans_repo.get_one(&p.id).await?;
-                if let Some(trans) = trans {
-                    p.title = trans.title;
-                    p.description = trans.description;
-                    Ok::<_, anyhow::Error>(Some(p))
-                } else {
-                    Ok(None)
-                }
-            })
-            .buffered(10)
-            .filter_map(|p| async { p.transpose() })
-            .try_collect()
-            .await?;
-        let products_for_dt_tt = products.clone();
-        let dto = rt_types::product::convert(products.into_iter());
-        let dto: Vec<_> = if options.options.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match options.options.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
-                    }
-                    i
-                })
-                .collect(),
-            false => dto,
-        };
-        dto.iter_mut().for_each(ensure_bilingual);
-        if let Some(entry) = res.get_mut(&options.options) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(options.options.clone(), dto);
-        }
-        if let Some((dt_tt_opts, dt_articles)) =
-            entry.dt_tt_parsing.as_ref().zip(dt_articles.as_ref())
-        {
-            let filtered: Vec<_> = products_for_dt_tt
-                .into_iter()
-                .filter(|p| p.available == Availability::Available)
-                .filter(|p| dt_articles.contains(&p.article.to_uppercase()))
-                .collect();
-            let dto = rt_types::product::convert(filtered.into_iter());
-            let dto: Vec<_> = if dt_tt_opts.options.categories {
-                category::assign_categories(dto, &categories).collect()
-            } else {
-                dto.collect()
-            };
-            let mut dto = match dt_tt_opts.options.convert_to_uah {
-                true => dto
-                    .into_iter()
-                    .map(|mut i| {
-                        if let Some(rate) = rates.get(&i.currency) {
-                            i.currency = "UAH".to_string();
-                            i.price *= rate;
+                    match &options.append_categories {
+                        Some(ParsingCategoriesAction::BeforeTitle { separator }) => {
+                            if let Some(category) = &p.category {
+                                p.title =
+                                    format!("{} {} {}", category.trim(), separator.trim(), p.title);
+                            }
                         }
-                        i
-                    })
-                    .collect(),
-                false => dto,
-            };
-            dto.iter_mut().for_each(ensure_bilingual);
-            if let Some(entry) = res.get_mut(&dt_tt_opts.options) {
-                entry.append(&mut dto);
-            } else {
-                res.insert(dt_tt_opts.options.clone(), dto);
-            }
-        }
-    }
-    let (maxton, jgd, pl, skm, op_tuning, dt): (
-        Option<Vec<_>>,
-        Option<Vec<_>>,
-        Option<Vec<_>>,
-        Option<Vec<_>>,
-        Option<Vec<_>>,
-        Option<Vec<_>>,
-    ) = dt
-        .map(|dt| {
-            let (maxton, rest): (Vec<_>, Vec<_>) = dt.into_iter().partition(|p| {
-                if supplier_key(p).is_some() {
-                    return supplier_is(p, "maxton");
-                }
-                p.article.ends_with("-M")
-                    || p.title.to_lowercase().contains("maxton")
-                    || p.description
-                        .as_ref()
-                        .is_some_and(|d| d.to_lowercase().contains("maxton"))
-            });
-            let (jgd, rest): (Vec<_>, Vec<_>) = rest
-                .into_iter()
-                .partition(|p| {
-                    if supplier_key(p).is_some() {
-                        return supplier_is(p, "jgd");
-                    }
-                    p.article.to_uppercase().starts_with("JGD")
-                });
-            let (pl, rest): (Vec<_>, Vec<_>) = rest
-                .into_iter()
-                .partition(|p| {
-                    if supplier_key(p).is_some() {
-                        return supplier_is(p, "pl");
-                    }
-                    PL_ARTICLES.contains(p.article.to_uppercase().as_str())
-                });
-            let (skm, rest): (Vec<_>, Vec<_>) = rest
-                .into_iter()
-                .partition(|p| {
-                    if supplier_key(p).is_some() {
-                        return supplier_is(p, "skm");
-                    }
-                    p.article.to_uppercase().starts_with("SKM")
-                });
-            let (op_tuning, dt): (Vec<_>, Vec<_>) = rest
-                .into_iter()
-                .partition(|p| site_publish::detect_supplier(p).as_deref() == Some("op_tuning"));
-            (
-                Some(maxton),
-                Some(jgd),
-                Some(pl),
-                Some(skm),
-                Some(op_tuning),
-                Some(dt),
-            )
-        })
-        .unwrap_or((None, None, None, None, None, None));
-    if let Some((options, products)) = entry.op_tuning_parsing.as_ref().zip(op_tuning) {
-        let mut opts = options.options.clone();
-        opts.add_vendor = true;
-        let dto = rt_types::product::convert(products.into_iter());
-        let dto: Vec<_> = if opts.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match opts.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
-                    }
-                    i
-                })
-                .collect(),
-            false => dto,
-        };
-        if let Some(entry) = res.get_mut(&opts) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(opts, dto);
-        }
-    }
-    if let Some((options, products)) = entry.dt_parsing.as_ref().zip(dt) {
-        // DT items should always carry vendor info into notes, even if the flag
-        // was not set explicitly.
-        let mut opts = options.options.clone();
-        opts.add_vendor = true;
-        let dto = rt_types::product::convert(products.into_iter());
-        let dto: Vec<_> = if opts.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match opts.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
+                        Some(ParsingCategoriesAction::AfterTitle { separator }) => {
+                            if let Some(category) = &p.category {
+                                p.title =
+                                    format!("{} {} {}", p.title, separator.trim(), category.trim());
+                            }
+                        }
+                        None => (),
                     }
-                    i
-                })
-                .collect(),
-            false => dto,
-        };
-        if let Some(entry) = res.get_mut(&opts) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(opts, dto);
-        }
-    }
-    if let Some((options, products)) = entry.jgd_parsing.as_ref().zip(jgd) {
-        let products = products.into_iter().map(|mut p| {
-            p.available = Availability::OnOrder;
-            p
-        });
-        let dto = rt_types::product::convert(products);
-        let dto: Vec<_> = if options.options.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match options.options.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
+                    let trans_repo = trans_repo.clone();
+                    async move {
+                        let trans = trans_repo.get_one(&p.id).await?;
+                        if let Some(trans) = trans {
+                            p.title = trans.title;
+                            p.description = trans.description;
+                            Ok::<_, anyhow::Error>(Some(p))
+                        } else {
+                            Ok(None)
+                        }
                     }
-                    i
                 })
-                .collect(),
-            false => dto,
-        };
-        dto.iter_mut().for_each(|p| {
-            p.vendor = "JGD".to_string();
-            ensure_bilingual(p);
-        });
-        if let Some(entry) = res.get_mut(&options.options) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(options.options.clone(), dto);
-        }
-    }
-    if let Some((options, products)) = entry.pl_parsing.as_ref().zip(pl) {
-        let products = products.into_iter().map(|mut p| {
-            p.available = Availability::OnOrder;
-            p
-        });
-        let dto = rt_types::product::convert(products);
-        let dto: Vec<_> = if options.options.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match options.options.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
+                .buffered(10)
+                .try_filter_map(|p| async move { Ok(p) })
+                .try_collect::<Vec<_>>()
+                .await?;
+            for p in translated {
+                if let Some((dt_tt_opts, dt_articles)) = dt_tt {
+                    if p.available == Availability::Available
+                        && dt_articles.contains(&p.article.to_uppercase())
+                    {
+                        if let Some(p) = rt_types::product::convert(iter::once(p.clone())).next() {
+                            let mut p = prepare.apply(&dt_tt_opts.options, p);
+                            ensure_bilingual(&mut p);
+                            collect.push(source::DT_TT, &dt_tt_opts.options, p)?;
+                            dt_tt_count += 1;
+                        }
                     }
-                    i
-                })
-                .collect(),
-            false => dto,
-        };
-        dto.iter_mut().for_each(|p| {
-            p.vendor = "Скловолокно PL".to_string();
-            ensure_bilingual(p);
-        });
-        if let Some(entry) = res.get_mut(&options.options) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(options.options.clone(), dto);
+                }
+                if let Some(p) = rt_types::product::convert(iter::once(p)).next() {
+                    let mut p = prepare.apply(&options.options, p);
+                    ensure_bilingual(&mut p);
+                    collect.push(source::TT, &options.options, p)?;
+                    tt_count += 1;
+                }
+            }
         }
-    }
-    if let Some((options, products)) = entry.skm_parsing.as_ref().zip(skm) {
-        let products = products.into_iter().map(|mut p| {
-            p.available = Availability::OnOrder;
-            p
-        });
-        let dto = rt_types::product::convert(products);
-        let dto: Vec<_> = if options.options.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match options.options.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
-                    }
-                    i
-                })
-                .collect(),
-            false => dto,
-        };
-        dto.iter_mut().for_each(|p| {
-            p.vendor = "SKM".to_string();
-            ensure_bilingual(p);
-        });
-        if let Some(entry) = res.get_mut(&options.options) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(options.options.clone(), dto);
+        summary.count("tt", tt_count);
+        if dt_tt.is_some() {
+            summary.count("dt_tt", dt_tt_count);
         }
     }
-    if let Some((options, products)) = entry.maxton_parsing.as_ref().zip(maxton) {
-        let mut skipped_t_suffix = 0usize;
-        let filtered: Vec<_> = products
-            .into_iter()
-            .filter(|p| {
-                let drop = p.article.ends_with('T');
-                if drop {
-                    skipped_t_suffix += 1;
-                }
-                !drop
-            })
-            .collect();
-        if skipped_t_suffix > 0 {
+    if let Some(dt) = dt {
+        if dt.maxton_skipped > 0 {
             log::warn!(
-                "Maxton: skipped {skipped_t_suffix} items with trailing 'T' suffix (unsupported on supplier site)"
+                "Maxton: skipped {} items with trailing 'T' suffix (unsupported on supplier site)",
+                dt.maxton_skipped
             );
         }
-        let dto = rt_types::product::convert(filtered.into_iter().map(dt::product::MaxtonProduct))
-            .map(|mut p| {
-                p.available = Availability::OnOrder;
-                p
-            });
-        let dto: Vec<_> = if options.options.categories {
-            category::assign_categories(dto, &categories).collect()
-        } else {
-            dto.collect()
-        };
-        let mut dto = match options.options.convert_to_uah {
-            true => dto
-                .into_iter()
-                .map(|mut i| {
-                    if let Some(rate) = rates.get(&i.currency) {
-                        i.currency = "UAH".to_string();
-                        i.price *= rate;
-                    }
-                    i
-                })
-                .collect(),
-            false => dto,
-        };
-        if let Some(entry) = res.get_mut(&options.options) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(options.options.clone(), dto);
+        for (name, opts) in [
+            ("op_tuning", &dt.op_tuning),
+            ("dt", &dt.dt),
+            ("jgd", &dt.jgd),
+            ("pl", &dt.pl),
+            ("skm", &dt.skm),
+            ("maxton", &dt.maxton),
+        ] {
+            if opts.is_some() {
+                summary.count(
+                    name,
+                    dt.counts.get(name).copied().unwrap_or_default() as usize,
+                );
+            }
         }
     }
-    if let Some((options, dto)) = entry.davi_parsing.as_ref().zip(davi) {
-        let new_categories = rt_parsing_davi::get_categories(&dto, categories.clone(), shop);
-        for c in new_categories.iter() {
+    if let Some(options) = entry.davi_parsing.as_ref() {
+        let mut count = 0;
+        let mut batches = std::pin::pin!(rt_parsing_davi::batches(
+            davi_repo.clone(),
+            options.only_available,
+            DT_BATCH_SIZE
+        ));
+        while let Some(batch) = batches.try_next().await? {
+            categories = rt_parsing_davi::get_categories(&batch, categories, shop);
+            collect.set_categories(&categories);
+            for d in batch {
+                let p: Product = d.enrich(categories.iter()).into();
+                collect.push(source::DAVI, options, p)?;
+                count += 1;
+            }
+        }
+        for c in categories.iter() {
             category_repo
                 .save(c.clone())
                 .await
                 .log_error("Unable to save new category for davi product");
         }
-        categories = new_categories;
-        let dto: Vec<_> = dto
-            .into_iter()
-            .map(|d: rt_parsing_davi::Product| d.enrich(categories.iter()))
-            .collect();
-        let mut dto = dto.into_iter().map(Into::into).collect();
-        if let Some(entry) = res.get_mut(&options) {
-            entry.append(&mut dto);
-        } else {
-            res.insert(options.clone(), dto);
-        }
+        summary.count("davi", count);
     }
     let instant = std::time::Instant::now();
 
-    let count: usize = res.values().map(|v| v.len()).sum();
+    let count = collect.count;
+    summary.count("products", count);
 
     let file_path = |file_name| format!("/tmp/export/{shop}/{file_name}");
     let c = categories.clone();
@@ -2868,36 +3178,20 @@ pub async fn do_export(
     )
     .await;
 
-    let res = rt_types::watermark::apply_to_product_map(res, shop_id, &Lazy::force(&SELF_ADDR))?;
-    let res: HashMap<_, _> = res
-        .into_iter()
-        .map(|(o, p)| {
-            let only_available = o.only_available;
-            let p = p.into_iter().filter(|p| {
-                if only_available {
-                    if let Availability::Available | Availability::OnOrder = p.available {
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    true
-                }
-            });
-            if let Some(av) = &o.set_availability {
-                let p = p
-                    .map(|mut p| {
-                        p.available = av.clone();
-                        p
-                    })
-                    .collect();
-                (o, p)
-            } else {
-                (o, p.collect())
-            }
-        })
-        .collect();
-    let r = res.clone();
+    let Collect {
+        spool, snapshot, ..
+    } = collect;
+    // Спільний з потоком XLSX, кожен записувач читає товари з диска
+    let spool = Arc::new(spool.finish()?);
+    let previous_snapshot = export_diff::load_snapshot(shop_id, &entry.file_name(None)).await?;
+    if !skip_guards {
+        let violations =
+            export_diff::check_guards(&entry.guards, previous_snapshot.as_ref(), &snapshot);
+        if !violations.is_empty() {
+            return Err(ExportError::Guard(violations.join("; ")));
+        }
+    }
+    let s = spool.clone();
 
     ExportService::set_progress(
         &export_handle,
@@ -2908,8 +3202,8 @@ pub async fn do_export(
     .await;
 
     tokio::task::spawn_blocking(move || {
-        crate::xlsx::write_xlsx_dto_map(&f, r, c, &id)?;
-        Ok::<_, anyhow::Error>(())
+        crate::xlsx::write_xlsx_dto_map(&f, s.items(), c, &id)?;
+        s.check()
     })
     .await
     .context("Unable to join thread")??;
@@ -2921,7 +3215,14 @@ pub async fn do_export(
 
     ExportService::set_progress(&export_handle, "Записываем XML".to_string(), 3, TOTAL_STEPS).await;
 
-    crate::xml::write_dto_map(&xml_filename, &res, categories, shop_id).await?;
+    crate::xml::write_dto_map(
+        &xml_filename,
+        || spool.stream(),
+        categories.clone(),
+        shop_id,
+    )
+    .await?;
+    spool.check()?;
     let xml = i.elapsed().as_millis();
 
     let horoshop_filename = format!("{}", file_path(entry.file_name(FileFormat::HoroshopCsv)));
@@ -2935,24 +3236,124 @@ pub async fn do_export(
     )
     .await;
 
-    crate::horoshop::export_csv(horoshop_filename.clone(), &res, category_repo.clone()).await?;
+    crate::horoshop::export_csv(
+        horoshop_filename.clone(),
+        spool.stream(),
+        category_repo.clone(),
+    )
+    .await?;
+    spool.check()?;
 
     let horoshop_filename = format!(
         "{}",
         file_path(entry.file_name(FileFormat::HoroshopCategories))
     );
-    crate::horoshop::export_csv_categories(horoshop_filename.clone(), &res, category_repo.clone())
-        .await?;
+    crate::horoshop::export_csv_categories(
+        horoshop_filename.clone(),
+        spool.stream(),
+        category_repo.clone(),
+    )
+    .await?;
+    spool.check()?;
     let horoshop = i.elapsed().as_millis();
+
+    let i = std::time::Instant::now();
+
+    ExportService::set_progress(
+        &export_handle,
+        "Facebook, Google Merchant и Rozetka".to_string(),
+        5,
+        TOTAL_STEPS,
+    )
+    .await;
+
+    let site_base = crate::control::site_api::shop_site_base(&shop);
+    let mut feed_files = Vec::new();
+    let mut skipped_feeds = Vec::new();
+    let facebook_csv_filename = file_path(entry.file_name(FileFormat::FacebookCsv));
+    let facebook_xml_filename = file_path(entry.file_name(FileFormat::FacebookXml));
+    match (feeds.facebook, site_base.as_deref()) {
+        (true, Some(base)) => {
+            let items = || spool.stream();
+            crate::facebook::write_csv(&facebook_csv_filename, items(), &categories, base).await?;
+            crate::facebook::write_xml(&facebook_xml_filename, items(), &categories, base).await?;
+            spool.check()?;
+            feed_files.extend([facebook_csv_filename, facebook_xml_filename]);
+        }
+        (enabled, _) => {
+            if enabled {
+                log::warn!("No site host configured for {shop_id}, Facebook feed skipped");
+            }
+            skipped_feeds.extend([facebook_csv_filename, facebook_xml_filename]);
+        }
+    }
+    let gmc_ua_filename = file_path(entry.file_name(FileFormat::GoogleMerchantUa));
+    let gmc_ru_filename = file_path(entry.file_name(FileFormat::GoogleMerchantRu));
+    match (feeds.google_merchant, site_base.as_deref()) {
+        (true, Some(base)) => {
+            for (path, language) in [
+                (&gmc_ua_filename, Language::Ua),
+                (&gmc_ru_filename, Language::Ru),
+            ] {
+                let items = spool.stream();
+                crate::google_merchant::write_xml(path, items, &categories, language, base).await?;
+            }
+            spool.check()?;
+            feed_files.extend([gmc_ua_filename, gmc_ru_filename]);
+        }
+        (enabled, _) => {
+            if enabled {
+                log::warn!("No site host configured for {shop_id}, Google Merchant feed skipped");
+            }
+            skipped_feeds.extend([gmc_ua_filename, gmc_ru_filename]);
+        }
+    }
+    let rozetka_filename = file_path(entry.file_name(FileFormat::RozetkaYml));
+    let mut rozetka_issues = vec![];
+    match (feeds.rozetka, site_base.as_deref()) {
+        (true, Some(base)) => {
+            let items = || spool.stream();
+            rozetka_issues =
+                crate::rozetka::write_yml(&rozetka_filename, items, &categories, &rates, base)
+                    .await?;
+            spool.check()?;
+            if !rozetka_issues.is_empty() {
+                log::warn!(
+                    "{} offers left out of Rozetka file for {shop_id}",
+                    rozetka_issues.len()
+                );
+            }
+            feed_files.push(rozetka_filename);
+        }
+        (enabled, _) => {
+            if enabled {
+                log::warn!("No site host configured for {shop_id}, Rozetka feed skipped");
+            }
+            skipped_feeds.push(rozetka_filename);
+        }
+    }
+    let feeds = i.elapsed().as_millis();
+
     let i = std::time::Instant::now();
     let csv_filename = format!("{}", file_path(entry.file_name(FileFormat::Csv)));
 
-    ExportService::set_progress(&export_handle, "Генерируем CSV".to_string(), 5, TOTAL_STEPS).await;
+    ExportService::set_progress(&export_handle, "Генерируем CSV".to_string(), 6, TOTAL_STEPS).await;
 
-    crate::csv::write_dto_map(&csv_filename, res, shop_id).await?;
+    crate::csv::write_dto_map(&csv_filename, || spool.stream(), shop_id).await?;
+    spool.check()?;
+    let csv = i.elapsed().as_millis();
     log::info!(
-        "Time:\nxlsx: {xlsx}ms\nxml: {xml}ms\ncsv: {}ms\nhoroshop: {horoshop}ms",
-        i.elapsed().as_millis()
+        "Time:\nxlsx: {xlsx}ms\nxml: {xml}ms\ncsv: {csv}ms\nhoroshop: {horoshop}ms\nfeeds: {feeds}ms"
+    );
+    summary.timings = RunCounts::from(
+        [
+            ("xlsx", xlsx),
+            ("xml", xml),
+            ("csv", csv),
+            ("horoshop", horoshop),
+            ("feeds", feeds),
+        ]
+        .map(|(format, ms)| (format.to_string(), ms as u64)),
     );
     log::info!(
         "Performance: {} items/min",
@@ -2968,22 +3369,49 @@ pub async fn do_export(
         replace_export_file(&xml_filename, &xml_dest),
         replace_export_file(&csv_filename, &csv_dest),
         replace_export_file(&horoshop_filename, &horoshop_dest),
+        futures::future::try_join_all(
+            feed_files
+                .iter()
+                .map(|f| async move { replace_export_file(f, &f.replace("/tmp", ".")).await }),
+        ),
     );
     res.0?;
     res.1?;
     res.2?;
+    res.4?;
+    for f in &skipped_feeds {
+        remove_stale_export_file(&f.replace("/tmp", ".")).await;
+    }
+    let rozetka_file = entry.file_name(FileFormat::RozetkaYml);
+    if let Err(err) = save_export_status(shop, &rozetka_file, "issues", &rozetka_issues).await {
+        log::error!("Unable to save Rozetka issues of {rozetka_file}: {err}");
+    }
+    export_handle.write().await.rozetka_issues = rozetka_issues;
 
-    let (a, b, c, d) = tokio::join!(
+    if let Err(err) =
+        export_diff::record_run(shop_id, &entry.file_name(None), previous_snapshot, snapshot).await
+    {
+        log::error!("Unable to record export diff: {err}");
+    }
+
+    let remove_feeds = futures::future::join_all(
+        feed_files
+            .iter()
+            .map(|f| async move { tokio::fs::remove_file(f).await.with_context(|| f.clone()) }),
+    );
+    let (a, b, c, d, e) = tokio::join!(
         tokio::fs::remove_file(&xlsx_filename),
         tokio::fs::remove_file(&xml_filename),
         tokio::fs::remove_file(&csv_filename),
         tokio::fs::remove_file(&horoshop_filename),
+        remove_feeds,
     );
     let res = a
         .context(xlsx_filename)
         .and(b.context(xml_filename))
         .and(c.context(csv_filename))
-        .and(d.context(horoshop_filename));
+        .and(d.context(horoshop_filename))
+        .and(e.into_iter().collect::<Result<Vec<_>, _>>().map(|_| ()));
     if let Err(err) = res {
         log::error!("Unable to remove tmp file: {err}");
     }
@@ -2994,5 +3422,308 @@ pub async fn do_export(
         TOTAL_STEPS,
     )
     .await;
-    Ok(())
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_zip::tokio::read::fs::ZipFileReader;
+    use rt_types::category::Category;
+    use rt_types::product::UaTranslation;
+    use typesafe_repository::async_ops::Save;
+    use uuid::Uuid;
+
+    fn price_list_fixture() -> HashMap<ExportOptions, Vec<Product>> {
+        let opts = ExportOptions {
+            title_prefix: Some("Тюнинг".to_string()),
+            title_suffix_ua: Some("для авто".to_string()),
+            format_years: true,
+            add_vendor: true,
+            delivery_time: Some(14),
+            ..Default::default()
+        };
+        let product = |id: &str, available: Availability| Product {
+            id: id.to_string(),
+            title: format!("Спойлер BMW E60 2003-2010гг. {id}"),
+            ua_translation: None,
+            description: None,
+            price: Decimal::new(125050, 2),
+            source_price: None,
+            article: format!("ART-{id}"),
+            in_stock: None,
+            currency: "UAH".to_string(),
+            keywords: None,
+            params: HashMap::new(),
+            brand: "BMW".to_string(),
+            model: "E60".to_string(),
+            category: None,
+            available,
+            vendor: "DT".to_string(),
+            images: vec![],
+        };
+        let products = vec![
+            Product {
+                ua_translation: Some(UaTranslation {
+                    title: "Спойлер BMW E60 UA".to_string(),
+                    description: Some("<p>Опис</p>\n<img src=\"a.jpg\">".to_string()),
+                }),
+                description: Some("Качественная реплика спойлера <b>M5</b>".to_string()),
+                keywords: Some("спойлер, bmw".to_string()),
+                params: HashMap::from([("Материал".to_string(), "ABS".to_string())]),
+                category: Some(Uuid::from_u128(0x1234_5678_9abc_def0_1122_3344_5566_7788)),
+                images: vec![
+                    "https://cdn.example.com/1.jpg".to_string(),
+                    "https://cdn.example.com/2.jpg".to_string(),
+                ],
+                ..product("1", Availability::OnOrder)
+            },
+            Product {
+                title: String::new(),
+                ua_translation: Some(UaTranslation {
+                    title: "Решітка радіатора".to_string(),
+                    description: None,
+                }),
+                in_stock: Some(4),
+                ..product("2", Availability::Available)
+            },
+            Product {
+                article: "VERY-LONG-ARTICLE-NUMBER-0123456789".to_string(),
+                currency: "EUR".to_string(),
+                in_stock: Some(0),
+                params: HashMap::from([("Материал".to_string(), "сталь".to_string())]),
+                ..product("3", Availability::NotAvailable)
+            },
+            Product {
+                description: Some("Описание\nв две строки".to_string()),
+                ..product("4", Availability::OnOrder)
+            },
+        ];
+        HashMap::from([(opts, products)])
+    }
+
+    fn price_list_categories() -> HashSet<Category> {
+        HashSet::from([Category {
+            name: "Спойлеры".to_string(),
+            id: Uuid::from_u128(0x1234_5678_9abc_def0_1122_3344_5566_7788),
+            parent_id: None,
+            regex: None,
+            shop_id: Uuid::nil(),
+            seo_title: None,
+            seo_description: None,
+            seo_text: None,
+        }])
+    }
+
+    async fn unzip(path: &str, name: &str) -> String {
+        let reader = ZipFileReader::new(path).await.unwrap();
+        let index = reader
+            .file()
+            .entries()
+            .iter()
+            .position(|e| e.filename().as_str().ok() == Some(name))
+            .unwrap();
+        let mut content = String::new();
+        reader
+            .reader_with_entry(index)
+            .await
+            .unwrap()
+            .read_to_string_checked(&mut content)
+            .await
+            .unwrap();
+        content
+    }
+
+    fn feed_fixture() -> HashMap<ExportOptions, Vec<Product>> {
+        price_list_fixture()
+            .into_iter()
+            .map(|(opts, products)| {
+                let products = products
+                    .into_iter()
+                    .map(|p| Product {
+                        description: p.description.or(Some("Описание".to_string())),
+                        ua_translation: Some(UaTranslation {
+                            title: p
+                                .ua_translation
+                                .map(|t| t.title)
+                                .unwrap_or_else(|| format!("{} UA", p.title)),
+                            description: Some("Опис".to_string()),
+                        }),
+                        params: HashMap::from([("Материал".to_string(), "ABS".to_string())]),
+                        category: Some(Uuid::from_u128(0x1234_5678_9abc_def0_1122_3344_5566_7788)),
+                        images: vec![format!("https://cdn.example.com/{}.jpg", p.id)],
+                        ..p
+                    })
+                    .collect();
+                (opts, products)
+            })
+            .collect()
+    }
+
+    // Фікстури записані записувачами прайсів до переходу на рядки по товару і спул
+    #[tokio::test]
+    async fn writes_price_lists_as_before() {
+        let dir = std::env::temp_dir().join(format!("price_list_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let mut writer = SpoolWriter::create(dir.join("spool")).unwrap();
+        for (opts, products) in price_list_fixture() {
+            for p in products {
+                writer.push(source::DT, &opts, &p).unwrap();
+            }
+        }
+        let spool = writer.finish().unwrap();
+
+        crate::csv::write_dto_map(&path("price_list.csv.zip"), || spool.stream(), "shop")
+            .await
+            .unwrap();
+        crate::xml::write_dto_map(
+            &path("price_list.xml.zip"),
+            || spool.stream(),
+            price_list_categories(),
+            "shop",
+        )
+        .await
+        .unwrap();
+        crate::xlsx::write_xlsx_dto_map(
+            &path("price_list.xlsx"),
+            spool.items(),
+            HashSet::<Category>::new(),
+            "shop",
+        )
+        .unwrap();
+
+        assert_eq!(
+            unzip(&path("price_list.csv.zip"), "price_list.csv").await,
+            include_str!("../tests/fixtures/price_list.csv")
+        );
+        assert_eq!(
+            unzip(&path("price_list.xml.zip"), "price_list.xml").await,
+            include_str!("../tests/fixtures/price_list.xml")
+        );
+        assert_eq!(
+            unzip(&path("price_list.xlsx"), "xl/worksheets/sheet1.xml").await,
+            include_str!("../tests/fixtures/price_list_sheet.xml")
+        );
+        assert_eq!(
+            unzip(&path("price_list.xlsx"), "xl/sharedStrings.xml").await,
+            include_str!("../tests/fixtures/price_list_strings.xml")
+        );
+        spool.check().unwrap();
+        drop(spool);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Фікстури записані фідами до переходу на потік зі спулу, дати замінені на {date}
+    #[tokio::test]
+    async fn writes_feeds_as_before() {
+        let dir = std::env::temp_dir().join(format!("feeds_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let mut writer = SpoolWriter::create(dir.join("spool")).unwrap();
+        for (opts, products) in feed_fixture() {
+            for p in products {
+                writer.push(source::DT, &opts, &p).unwrap();
+            }
+        }
+        let spool = writer.finish().unwrap();
+        let categories = price_list_categories();
+        let category_repo = crate::category::SqliteCategoryRepository::init(
+            tokio_rusqlite::Connection::open_in_memory().await.unwrap(),
+        )
+        .await
+        .unwrap();
+        for c in categories.iter() {
+            category_repo.save(c.clone()).await.unwrap();
+        }
+        let category_repo: Arc<dyn category::CategoryRepository> = Arc::new(category_repo);
+        let base = "https://shop.example.com";
+        let rates = HashMap::from([("EUR".to_string(), Decimal::new(45, 0))]);
+
+        crate::horoshop::export_csv(
+            path("horoshop.csv.zip"),
+            spool.stream(),
+            category_repo.clone(),
+        )
+        .await
+        .unwrap();
+        crate::horoshop::export_csv_categories(
+            path("horoshop_categories.csv.zip"),
+            spool.stream(),
+            category_repo,
+        )
+        .await
+        .unwrap();
+        crate::facebook::write_csv(&path("facebook.csv.zip"), spool.stream(), &categories, base)
+            .await
+            .unwrap();
+        crate::facebook::write_xml(&path("facebook.xml.zip"), spool.stream(), &categories, base)
+            .await
+            .unwrap();
+        crate::google_merchant::write_xml(
+            &path("gmc_ua.xml.zip"),
+            spool.stream(),
+            &categories,
+            Language::Ua,
+            base,
+        )
+        .await
+        .unwrap();
+        let issues = crate::rozetka::write_yml(
+            &path("rozetka.xml.zip"),
+            || spool.stream(),
+            &categories,
+            &rates,
+            base,
+        )
+        .await
+        .unwrap();
+        assert_eq!(issues.len(), 1);
+
+        let dates = &regex::Regex::new(
+            r#"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?Z|date="\d{4}-\d{2}-\d{2} \d{2}:\d{2}""#,
+        )
+        .unwrap();
+        let read = |name: &'static str| {
+            let zip = path(&format!("{name}.zip"));
+            async move {
+                let content = unzip(&zip, name).await;
+                dates
+                    .replace_all(&content, |c: &regex::Captures| {
+                        match c[0].starts_with("date") {
+                            true => r#"date="{date}""#,
+                            false => "{date}",
+                        }
+                    })
+                    .into_owned()
+            }
+        };
+        assert_eq!(
+            read("horoshop.csv").await,
+            include_str!("../tests/fixtures/feed_horoshop.csv")
+        );
+        assert_eq!(
+            read("horoshop_categories.csv").await,
+            include_str!("../tests/fixtures/feed_horoshop_categories.csv")
+        );
+        assert_eq!(
+            read("facebook.csv").await,
+            include_str!("../tests/fixtures/feed_facebook.csv")
+        );
+        assert_eq!(
+            read("facebook.xml").await,
+            include_str!("../tests/fixtures/feed_facebook.xml")
+        );
+        assert_eq!(
+            read("gmc_ua.xml").await,
+            include_str!("../tests/fixtures/feed_gmc_ua.xml")
+        );
+        assert_eq!(
+            read("rozetka.xml").await,
+            include_str!("../tests/fixtures/feed_rozetka.xml")
+        );
+        spool.check().unwrap();
+        drop(spool);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,472 @@
+use rt_types::product::Product;
+use rt_types::shop::{ExportGuards, ExportOptions};
+use rt_types::Availability;
+use rust_decimal::Decimal;
+use rust_xlsxwriter::Workbook;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use xxhash_rust::xxh64::xxh64;
+
+pub const MAX_REPORT_CHANGES: usize = 50_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnapshotEntry {
+    pub article: String,
+    pub title: String,
+    pub description_hash: u64,
+    pub price: Decimal,
+    pub available: Availability,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Snapshot {
+    #[serde(with = "time::serde::timestamp::option")]
+    pub created: Option<OffsetDateTime>,
+    pub products: BTreeMap<String, SnapshotEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Price,
+    Availability,
+    Title,
+    Description,
+}
+
+impl ChangeKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Added => "Добавлен",
+            Self::Removed => "Удалён",
+            Self::Price => "Цена",
+            Self::Availability => "Наличие",
+            Self::Title => "Название",
+            Self::Description => "Описание",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Change {
+    pub id: String,
+    pub article: String,
+    pub kind: ChangeKind,
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DiffReport {
+    #[serde(with = "time::serde::timestamp::option")]
+    pub previous: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub current: Option<OffsetDateTime>,
+    pub total: usize,
+    pub counts: BTreeMap<ChangeKind, usize>,
+    pub changes: Vec<Change>,
+}
+
+impl DiffReport {
+    pub fn count(&self, kind: &ChangeKind) -> usize {
+        self.counts.get(kind).copied().unwrap_or_default()
+    }
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Self {
+            created: Some(OffsetDateTime::now_utc()),
+            products: BTreeMap::new(),
+        }
+    }
+
+    pub fn capture<'a, I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a ExportOptions, &'a Vec<Product>)>,
+    {
+        let mut snapshot = Self::new();
+        for (opts, list) in items {
+            for p in list {
+                snapshot.insert(opts, p);
+            }
+        }
+        snapshot
+    }
+
+    pub fn insert(&mut self, opts: &ExportOptions, p: &Product) {
+        let description = p
+            .description
+            .as_deref()
+            .into_iter()
+            .chain(
+                p.ua_translation
+                    .as_ref()
+                    .and_then(|t| t.description.as_deref()),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.products.insert(
+            p.id.clone(),
+            SnapshotEntry {
+                article: p.article.clone(),
+                title: crate::xlsx::build_title(opts, &p.title, false),
+                description_hash: xxh64(description.as_bytes(), 0),
+                price: p.price.normalize(),
+                available: p.available.clone(),
+            },
+        );
+    }
+
+    pub fn diff(&self, current: &Snapshot) -> DiffReport {
+        let mut changes = Vec::new();
+        for (id, new) in &current.products {
+            let Some(old) = self.products.get(id) else {
+                changes.push(Change {
+                    id: id.clone(),
+                    article: new.article.clone(),
+                    kind: ChangeKind::Added,
+                    old: String::new(),
+                    new: new.title.clone(),
+                });
+                continue;
+            };
+            let mut push = |kind, old: String, new_value: String| {
+                changes.push(Change {
+                    id: id.clone(),
+                    article: new.article.clone(),
+                    kind,
+                    old,
+                    new: new_value,
+                })
+            };
+            if old.price != new.price {
+                push(
+                    ChangeKind::Price,
+                    old.price.to_string(),
+                    new.price.to_string(),
+                );
+            }
+            if old.available != new.available {
+                push(
+                    ChangeKind::Availability,
+                    old.available.to_string(),
+                    new.available.to_string(),
+                );
+            }
+            if old.title != new.title {
+                push(ChangeKind::Title, old.title.clone(), new.title.clone());
+            }
+            if old.description_hash != new.description_hash {
+                push(ChangeKind::Description, String::new(), String::new());
+            }
+        }
+        for (id, old) in &self.products {
+            if !current.products.contains_key(id) {
+                changes.push(Change {
+                    id: id.clone(),
+                    article: old.article.clone(),
+                    kind: ChangeKind::Removed,
+                    old: old.title.clone(),
+                    new: String::new(),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.kind.cmp(&b.kind).then_with(|| a.article.cmp(&b.article)));
+        let mut counts = BTreeMap::new();
+        for c in &changes {
+            *counts.entry(c.kind).or_default() += 1;
+        }
+        let total = changes.len();
+        changes.truncate(MAX_REPORT_CHANGES);
+        DiffReport {
+            previous: self.created,
+            current: current.created,
+            total,
+            counts,
+            changes,
+        }
+    }
+}
+
+fn dir(shop_id: &str) -> PathBuf {
+    PathBuf::from(format!("./export_diff/{shop_id}"))
+}
+
+pub fn snapshot_path(shop_id: &str, file_name: &str) -> PathBuf {
+    dir(shop_id).join(format!("{file_name}.snapshot.json"))
+}
+
+fn report_path(shop_id: &str, file_name: &str) -> PathBuf {
+    dir(shop_id).join(format!("{file_name}.diff.json"))
+}
+
+pub async fn load_snapshot(
+    shop_id: &str,
+    file_name: &str,
+) -> Result<Option<Snapshot>, anyhow::Error> {
+    let path = snapshot_path(shop_id, file_name);
+    match tokio::fs::read(&path).await {
+        Ok(raw) => match serde_json::from_slice(&raw) {
+            Ok(s) => Ok(Some(s)),
+            Err(err) => {
+                log::warn!("Unable to parse export snapshot {}: {err}", path.display());
+                Ok(None)
+            }
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Перший запуск лише зберігає знімок, порівнювати ще нема з чим
+pub async fn record_run(
+    shop_id: &str,
+    file_name: &str,
+    previous: Option<Snapshot>,
+    current: Snapshot,
+) -> Result<Option<DiffReport>, anyhow::Error> {
+    tokio::fs::create_dir_all(dir(shop_id)).await?;
+    let report = previous.map(|p| p.diff(&current));
+    if let Some(report) = &report {
+        tokio::fs::write(report_path(shop_id, file_name), serde_json::to_vec(report)?).await?;
+    }
+    tokio::fs::write(
+        snapshot_path(shop_id, file_name),
+        serde_json::to_vec(&current)?,
+    )
+    .await?;
+    Ok(report)
+}
+
+pub async fn exported_articles() -> HashSet<String> {
+    let mut articles = HashSet::new();
+    let Ok(mut shops) = tokio::fs::read_dir("./export_diff").await else {
+        return articles;
+    };
+    while let Ok(Some(shop)) = shops.next_entry().await {
+        let Ok(mut files) = tokio::fs::read_dir(shop.path()).await else {
+            continue;
+        };
+        while let Ok(Some(file)) = files.next_entry().await {
+            if !file
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".snapshot.json")
+            {
+                continue;
+            }
+            let snapshot = match tokio::fs::read(file.path()).await {
+                Ok(raw) => serde_json::from_slice::<Snapshot>(&raw),
+                Err(err) => {
+                    log::warn!("Unable to read {}: {err}", file.path().display());
+                    continue;
+                }
+            };
+            match snapshot {
+                Ok(s) => articles.extend(s.products.into_values().map(|p| p.article)),
+                Err(err) => log::warn!("Unable to parse {}: {err}", file.path().display()),
+            }
+        }
+    }
+    articles
+}
+
+fn percent(part: usize, total: usize) -> Decimal {
+    if total == 0 {
+        return Decimal::ZERO;
+    }
+    (Decimal::from(part) * Decimal::ONE_HUNDRED / Decimal::from(total)).round_dp(1)
+}
+
+pub fn check_guards(
+    guards: &ExportGuards,
+    previous: Option<&Snapshot>,
+    current: &Snapshot,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    if !guards.is_enabled() {
+        return violations;
+    }
+    let total = current.products.len();
+    if let Some((max, previous)) = guards.max_count_drop_percent.zip(previous) {
+        let before = previous.products.len();
+        let drop = percent(before.saturating_sub(total), before);
+        if drop > max {
+            violations.push(format!(
+                "количество товаров уменьшилось на {drop}% ({before} → {total}), допустимо {max}%"
+            ));
+        }
+    }
+    if let Some(max) = guards.max_zero_price_percent {
+        let zero = current
+            .products
+            .values()
+            .filter(|p| p.price <= Decimal::ZERO)
+            .count();
+        let share = percent(zero, total);
+        if share > max {
+            violations.push(format!(
+                "{share}% товаров без цены ({zero} шт.), допустимо {max}%"
+            ));
+        }
+    }
+    if let Some(max) = guards.max_not_available_percent {
+        let missing = current
+            .products
+            .values()
+            .filter(|p| p.available == Availability::NotAvailable)
+            .count();
+        let share = percent(missing, total);
+        if share > max {
+            violations.push(format!(
+                "{share}% товаров нет в наличии ({missing} шт.), допустимо {max}%"
+            ));
+        }
+    }
+    if let Some((max, previous)) = guards.max_avg_price_change_percent.zip(previous) {
+        let (sum, count) = current
+            .products
+            .iter()
+            .filter_map(|(id, p)| {
+                let old = previous.products.get(id)?.price;
+                (old > Decimal::ZERO).then(|| (p.price - old).abs() / old)
+            })
+            .fold((Decimal::ZERO, 0usize), |(sum, count), change| {
+                (sum + change, count + 1)
+            });
+        if count > 0 {
+            let change = (sum * Decimal::ONE_HUNDRED / Decimal::from(count)).round_dp(1);
+            if change > max {
+                violations.push(format!(
+                    "средняя цена изменилась на {change}%, допустимо {max}%"
+                ));
+            }
+        }
+    }
+    violations
+}
+
+pub async fn read_report(
+    shop_id: &str,
+    file_name: &str,
+) -> Result<Option<DiffReport>, anyhow::Error> {
+    match tokio::fs::read(report_path(shop_id, file_name)).await {
+        Ok(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+const HEADERS: [&str; 5] = ["Изменение", "Артикул", "ID", "Было", "Стало"];
+
+fn row(c: &Change) -> [&str; 5] {
+    [c.kind.label(), &c.article, &c.id, &c.old, &c.new]
+}
+
+pub fn report_to_csv(report: &DiffReport) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(HEADERS)?;
+    for c in &report.changes {
+        writer.write_record(row(c))?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+pub fn report_to_xlsx(report: &DiffReport) -> Result<Vec<u8>, anyhow::Error> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    for (i, name) in HEADERS.iter().enumerate() {
+        sheet.write_string(0, i as u16, *name)?;
+    }
+    for (r, c) in report.changes.iter().enumerate() {
+        for (i, value) in row(c).iter().enumerate() {
+            sheet.write_string(r as u32 + 1, i as u16, *value)?;
+        }
+    }
+    Ok(workbook.save_to_buffer()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(article: &str, price: i64, available: Availability) -> SnapshotEntry {
+        SnapshotEntry {
+            article: article.to_string(),
+            title: format!("Товар {article}"),
+            description_hash: 0,
+            price: Decimal::from(price),
+            available,
+        }
+    }
+
+    #[test]
+    fn guards_block_suspicious_runs() {
+        let previous = Snapshot {
+            created: None,
+            products: (0..10)
+                .map(|i| (i.to_string(), entry("A", 100, Availability::Available)))
+                .collect(),
+        };
+        let current = Snapshot {
+            created: None,
+            products: (0..5)
+                .map(|i| {
+                    let price = if i == 0 { 0 } else { 150 };
+                    (i.to_string(), entry("A", price, Availability::NotAvailable))
+                })
+                .collect(),
+        };
+        let guards = ExportGuards {
+            max_count_drop_percent: Some(Decimal::from(30)),
+            max_zero_price_percent: Some(Decimal::from(10)),
+            max_not_available_percent: Some(Decimal::from(50)),
+            max_avg_price_change_percent: Some(Decimal::from(40)),
+        };
+        assert_eq!(check_guards(&guards, Some(&previous), &current).len(), 4);
+        assert_eq!(check_guards(&guards, None, &current).len(), 2);
+        assert!(check_guards(&ExportGuards::default(), Some(&previous), &current).is_empty());
+        assert!(check_guards(&guards, Some(&previous), &previous).is_empty());
+    }
+
+    #[test]
+    fn diff_reports_every_kind_of_change() {
+        let old = Snapshot {
+            created: None,
+            products: BTreeMap::from([
+                ("a".to_string(), entry("A", 100, Availability::Available)),
+                ("b".to_string(), entry("B", 200, Availability::Available)),
+                ("c".to_string(), entry("C", 300, Availability::Available)),
+            ]),
+        };
+        let mut changed = entry("B", 250, Availability::NotAvailable);
+        changed.description_hash = 1;
+        let new = Snapshot {
+            created: None,
+            products: BTreeMap::from([
+                ("a".to_string(), entry("A", 100, Availability::Available)),
+                ("b".to_string(), changed),
+                ("d".to_string(), entry("D", 400, Availability::OnOrder)),
+            ]),
+        };
+        let report = old.diff(&new);
+        assert_eq!(report.total, 5);
+        assert_eq!(report.count(&ChangeKind::Added), 1);
+        assert_eq!(report.count(&ChangeKind::Removed), 1);
+        assert_eq!(report.count(&ChangeKind::Price), 1);
+        assert_eq!(report.count(&ChangeKind::Availability), 1);
+        assert_eq!(report.count(&ChangeKind::Description), 1);
+        assert_eq!(report.count(&ChangeKind::Title), 0);
+        let price = report
+            .changes
+            .iter()
+            .find(|c| c.kind == ChangeKind::Price)
+            .unwrap();
+        assert_eq!((price.old.as_str(), price.new.as_str()), ("200", "250"));
+    }
+}
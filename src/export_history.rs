@@ -0,0 +1,355 @@
+use crate::export_diff;
+use crate::scheduler::{RunCounts, RunOutcome};
+use crate::SqlWrapper;
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rt_types::shop::{ExportEntry, FileFormat, Shop};
+use rusqlite::{params, OptionalExtension};
+use std::path::PathBuf;
+use time::OffsetDateTime;
+use tokio_rusqlite::Connection;
+use typesafe_repository::IdentityOf;
+use uuid::Uuid;
+
+// Скільки попередніх успішних запусків зберігають файли, крім останнього
+pub static HISTORY_DEPTH: Lazy<usize> = Lazy::new(|| {
+    std::env::var("EXPORT_HISTORY_DEPTH")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3)
+});
+
+// За назвою файлу, щоб редагування запису не губило історію
+pub fn job_id(shop: IdentityOf<Shop>, file_name: &str) -> String {
+    format!("export:{shop}:{file_name}")
+}
+
+fn parse_job_id(id: &str) -> Option<(IdentityOf<Shop>, String)> {
+    let (shop, file_name) = id.strip_prefix("export:")?.split_once(':')?;
+    Some((Uuid::parse_str(shop).ok()?, file_name.to_string()))
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportRun {
+    pub id: i64,
+    pub shop: IdentityOf<Shop>,
+    pub file_name: String,
+    pub started: OffsetDateTime,
+    pub finished: Option<OffsetDateTime>,
+    pub outcome: RunOutcome,
+    pub stage: Option<String>,
+    pub error: Option<String>,
+    pub counts: RunCounts,
+    pub timings: RunCounts,
+}
+
+#[async_trait]
+pub trait ExportRunRepository: Send + Sync {
+    async fn save_details(
+        &self,
+        run: i64,
+        stage: Option<String>,
+        timings: RunCounts,
+    ) -> anyhow::Result<()>;
+    async fn get_run(&self, id: i64) -> anyhow::Result<Option<ExportRun>>;
+    async fn list_runs(
+        &self,
+        shop: IdentityOf<Shop>,
+        file_name: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ExportRun>>;
+}
+
+// Читає `job_run` з `SqliteJobRepository`, тож відкривається на тій же базі
+pub struct SqliteExportRunRepository {
+    conn: Connection,
+}
+
+impl SqliteExportRunRepository {
+    pub async fn init(conn: Connection) -> Result<Self, tokio_rusqlite::Error> {
+        conn.call(|conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS export_run_detail (
+                    run_id INTEGER PRIMARY KEY,
+                    stage TEXT,
+                    timings TEXT
+                )",
+                [],
+            )?;
+            Ok(())
+        })
+        .await?;
+        Ok(Self { conn })
+    }
+}
+
+fn from_timestamp(t: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(t).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+const RUN_QUERY: &str = "SELECT r.id, r.job_id, r.started, r.finished, r.outcome, d.stage, r.error,
+        r.counts, d.timings
+     FROM job_run r LEFT JOIN export_run_detail d ON d.run_id = r.id";
+
+fn run_from_row(row: &rusqlite::Row) -> rusqlite::Result<Option<ExportRun>> {
+    let counts = |i| {
+        row.get::<_, Option<String>>(i).map(|c| {
+            c.and_then(|c| serde_json::from_str(&c).ok())
+                .unwrap_or_default()
+        })
+    };
+    let Some((shop, file_name)) = parse_job_id(&row.get::<_, String>(1)?) else {
+        return Ok(None);
+    };
+    Ok(Some(ExportRun {
+        id: row.get(0)?,
+        shop,
+        file_name,
+        started: from_timestamp(row.get(2)?),
+        finished: row.get::<_, Option<i64>>(3)?.map(from_timestamp),
+        outcome: RunOutcome::from_db(row.get::<_, String>(4)?.as_str()),
+        stage: row.get(5)?,
+        error: row.get(6)?,
+        counts: counts(7)?,
+        timings: counts(8)?,
+    }))
+}
+
+#[async_trait]
+impl ExportRunRepository for SqliteExportRunRepository {
+    async fn save_details(
+        &self,
+        run: i64,
+        stage: Option<String>,
+        timings: RunCounts,
+    ) -> anyhow::Result<()> {
+        let timings = serde_json::to_string(&timings)?;
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO export_run_detail (run_id, stage, timings) VALUES (?1, ?2, ?3)
+                     ON CONFLICT(run_id) DO UPDATE SET
+                        stage = excluded.stage,
+                        timings = excluded.timings",
+                    params![run, stage, timings],
+                )?;
+                // Старі запуски прибирає планувальник
+                conn.execute(
+                    "DELETE FROM export_run_detail WHERE run_id NOT IN (SELECT id FROM job_run)",
+                    [],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get_run(&self, id: i64) -> anyhow::Result<Option<ExportRun>> {
+        let SqlWrapper(run) = self
+            .conn
+            .call(move |conn| {
+                let run = conn
+                    .query_row(
+                        &format!("{RUN_QUERY} WHERE r.id = ?1"),
+                        params![id],
+                        run_from_row,
+                    )
+                    .optional()?
+                    .flatten();
+                Ok(SqlWrapper(run))
+            })
+            .await?;
+        Ok(run)
+    }
+
+    async fn list_runs(
+        &self,
+        shop: IdentityOf<Shop>,
+        file_name: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ExportRun>> {
+        let job = job_id(shop, file_name);
+        let SqlWrapper(runs) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "{RUN_QUERY} WHERE r.job_id = ?1 ORDER BY r.started DESC, r.id DESC LIMIT ?2"
+                ))?;
+                let runs = stmt
+                    .query_map(params![job, limit as i64], run_from_row)?
+                    .filter_map(Result::transpose)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(runs))
+            })
+            .await?;
+        Ok(runs)
+    }
+}
+
+pub fn output_files(entry: &ExportEntry) -> Vec<String> {
+    FileFormat::ALL
+        .iter()
+        .map(|f| entry.file_name(f.clone()))
+        .collect()
+}
+
+fn history_dir(shop: IdentityOf<Shop>, file_name: &str) -> PathBuf {
+    PathBuf::from(format!("./export/{shop}/history/{file_name}"))
+}
+
+pub fn artifacts_dir(run: &ExportRun) -> PathBuf {
+    history_dir(run.shop, &run.file_name).join(run.id.to_string())
+}
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+async fn copy_if_exists(src: &std::path::Path, dest: &std::path::Path) -> std::io::Result<bool> {
+    match tokio::fs::copy(src, dest).await {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn archive(run: &ExportRun, files: &[String]) -> anyhow::Result<()> {
+    let dir = artifacts_dir(run);
+    tokio::fs::create_dir_all(&dir).await?;
+    for file in files {
+        let src = format!("./export/{}/{file}", run.shop);
+        copy_if_exists(src.as_ref(), &dir.join(file)).await?;
+    }
+    let snapshot = export_diff::snapshot_path(&run.shop.to_string(), &run.file_name);
+    copy_if_exists(&snapshot, &dir.join(SNAPSHOT_FILE)).await?;
+
+    let mut archived = vec![];
+    let mut entries = tokio::fs::read_dir(history_dir(run.shop, &run.file_name)).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<i64>().ok())
+        {
+            archived.push((id, entry.path()));
+        }
+    }
+    archived.sort_unstable_by_key(|(id, _)| std::cmp::Reverse(*id));
+    for (id, path) in archived.into_iter().skip(*HISTORY_DEPTH + 1) {
+        match tokio::fs::remove_dir_all(path).await {
+            Ok(_) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => log::error!("Unable to remove files of export run {id}: {err}"),
+        }
+    }
+    Ok(())
+}
+
+pub async fn artifacts(run: &ExportRun, files: &[String]) -> Vec<(String, String)> {
+    let dir = artifacts_dir(run);
+    if tokio::fs::metadata(&dir).await.is_err() {
+        return vec![];
+    }
+    let mut res = vec![];
+    for file in files {
+        if tokio::fs::metadata(dir.join(file)).await.is_ok() {
+            res.push((
+                file.clone(),
+                format!(
+                    "/export/{}/history/{}/{}/{file}",
+                    run.shop, run.file_name, run.id
+                ),
+            ));
+        }
+    }
+    res
+}
+
+// Знімок запуску стає базою наступного порівняння, щоб наступний запуск порівнювався з опублікованим
+pub async fn rollback(run: &ExportRun, files: &[String]) -> anyhow::Result<usize> {
+    let dir = artifacts_dir(run);
+    let mut restored = 0;
+    for file in files {
+        let src = dir.join(file);
+        if tokio::fs::metadata(&src).await.is_err() {
+            continue;
+        }
+        let dest = format!("./export/{}/{file}", run.shop);
+        let tmp_dest = format!("{dest}.part");
+        tokio::fs::copy(&src, &tmp_dest).await?;
+        tokio::fs::rename(&tmp_dest, &dest).await?;
+        restored += 1;
+    }
+    if restored > 0 {
+        let snapshot = export_diff::snapshot_path(&run.shop.to_string(), &run.file_name);
+        let tmp_snapshot = snapshot.with_extension("part");
+        if copy_if_exists(&dir.join(SNAPSHOT_FILE), &tmp_snapshot).await? {
+            tokio::fs::rename(&tmp_snapshot, &snapshot).await?;
+        } else {
+            match tokio::fs::remove_file(&snapshot).await {
+                Ok(_) => (),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::{JobRepository, JobRun, SqliteJobRepository};
+
+    #[tokio::test]
+    async fn reads_export_runs_from_job_runs() {
+        let path = std::env::temp_dir().join(format!("export_runs_{}.db", Uuid::new_v4()));
+        let shop = Uuid::new_v4();
+        let conn = Connection::open(&path).await.unwrap();
+        let jobs = SqliteJobRepository::init(conn.clone()).await.unwrap();
+        let repo = SqliteExportRunRepository::init(conn).await.unwrap();
+        let started = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let id = jobs.start_run(&job_id(shop, "dt"), started).await.unwrap();
+        jobs.finish_run(JobRun {
+            id,
+            job: job_id(shop, "dt"),
+            started,
+            finished: Some(started + time::Duration::minutes(3)),
+            outcome: RunOutcome::Failure,
+            error: Some("disk full".to_string()),
+            counts: RunCounts::from([("dt".to_string(), 120)]),
+        })
+        .await
+        .unwrap();
+        repo.save_details(
+            id,
+            Some("Записываем XML".to_string()),
+            RunCounts::from([("xml".to_string(), 900)]),
+        )
+        .await
+        .unwrap();
+        let running = jobs.start_run(&job_id(shop, "dt"), started).await.unwrap();
+        let other = jobs.start_run("site_import", started).await.unwrap();
+        drop((jobs, repo));
+
+        let conn = Connection::open(&path).await.unwrap();
+        let jobs = SqliteJobRepository::init(conn.clone()).await.unwrap();
+        let repo = SqliteExportRunRepository::init(conn).await.unwrap();
+        assert_eq!(jobs.interrupt_running().await.unwrap(), 2);
+        let runs = repo.list_runs(shop, "dt", 10).await.unwrap();
+        assert_eq!(
+            runs.iter().map(|r| (r.id, r.outcome)).collect::<Vec<_>>(),
+            vec![
+                (running, RunOutcome::Interrupted),
+                (id, RunOutcome::Failure)
+            ]
+        );
+        let failed = repo.get_run(id).await.unwrap().unwrap();
+        assert_eq!((failed.shop, failed.file_name.as_str()), (shop, "dt"));
+        assert_eq!(failed.error.as_deref(), Some("disk full"));
+        assert_eq!(failed.stage.as_deref(), Some("Записываем XML"));
+        assert_eq!(failed.counts.get("dt"), Some(&120));
+        assert_eq!(failed.timings.get("xml"), Some(&900));
+        assert!(repo.get_run(other).await.unwrap().is_none());
+        assert!(repo.list_runs(shop, "tt", 10).await.unwrap().is_empty());
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,322 @@
+use anyhow::Context;
+use futures::stream::{self, Stream};
+use rt_types::product::Product;
+use rt_types::shop::ExportOptions;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Товари експорту на диску, рядок JSON на товар і файл на групу опцій та джерело
+pub struct SpoolWriter {
+    dir: PathBuf,
+    files: Vec<(ExportOptions, usize, BufWriter<File>)>,
+    index: HashMap<(ExportOptions, usize), usize>,
+}
+
+impl SpoolWriter {
+    pub fn create(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).with_context(|| format!("{dir:?}"))?;
+        }
+        std::fs::create_dir_all(&dir).with_context(|| format!("{dir:?}"))?;
+        Ok(Self {
+            dir,
+            files: vec![],
+            index: HashMap::new(),
+        })
+    }
+
+    // Товари одного джерела йдуть у порядку додавання, джерела — за `source`
+    pub fn push(
+        &mut self,
+        source: usize,
+        opts: &ExportOptions,
+        p: &Product,
+    ) -> Result<(), anyhow::Error> {
+        let i = match self.index.get(&(opts.clone(), source)) {
+            Some(i) => *i,
+            None => {
+                let i = self.files.len();
+                let file = File::create(file_path(&self.dir, i))?;
+                self.files
+                    .push((opts.clone(), source, BufWriter::new(file)));
+                self.index.insert((opts.clone(), source), i);
+                i
+            }
+        };
+        let writer = &mut self.files[i].2;
+        serde_json::to_writer(&mut *writer, p)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Spool, anyhow::Error> {
+        let mut groups: Vec<(ExportOptions, Vec<(usize, PathBuf)>)> = vec![];
+        let mut by_opts: HashMap<ExportOptions, usize> = HashMap::new();
+        for (i, (opts, source, mut writer)) in
+            std::mem::take(&mut self.files).into_iter().enumerate()
+        {
+            writer.flush()?;
+            let path = file_path(&self.dir, i);
+            match by_opts.get(&opts) {
+                Some(g) => groups[*g].1.push((source, path)),
+                None => {
+                    by_opts.insert(opts.clone(), groups.len());
+                    groups.push((opts, vec![(source, path)]));
+                }
+            }
+        }
+        let order = groups
+            .iter()
+            .enumerate()
+            .fold(
+                HashMap::with_hasher(xxhash_rust::xxh3::Xxh3DefaultBuilder::new()),
+                |mut r, (i, (opts, _))| {
+                    r.insert(opts, i);
+                    r
+                },
+            )
+            .into_values()
+            .collect::<Vec<_>>();
+        let mut groups = groups.into_iter().map(Some).collect::<Vec<_>>();
+        let groups: Vec<(ExportOptions, Vec<PathBuf>)> = order
+            .into_iter()
+            .filter_map(|i| groups[i].take())
+            .map(|(opts, mut files)| {
+                files.sort_by_key(|(source, _)| *source);
+                (opts, files.into_iter().map(|(_, path)| path).collect())
+            })
+            .collect();
+        let (options, files) = groups.into_iter().unzip();
+        Ok(Spool {
+            dir: std::mem::take(&mut self.dir),
+            options,
+            files: Arc::new(files),
+            error: Mutex::new(None),
+        })
+    }
+}
+
+impl Drop for SpoolWriter {
+    fn drop(&mut self) {
+        if !self.dir.as_os_str().is_empty() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+fn file_path(dir: &Path, i: usize) -> PathBuf {
+    dir.join(format!("{i}.jsonl"))
+}
+
+pub struct Spool {
+    dir: PathBuf,
+    options: Vec<ExportOptions>,
+    files: Arc<Vec<Vec<PathBuf>>>,
+    error: Mutex<Option<anyhow::Error>>,
+}
+
+const CHUNK: usize = 256;
+
+#[derive(Clone, Copy, Default)]
+struct Cursor {
+    group: usize,
+    file: usize,
+    offset: u64,
+}
+
+fn read_chunk(
+    files: &[Vec<PathBuf>],
+    mut cursor: Cursor,
+) -> Result<(Vec<(usize, Product)>, Cursor), anyhow::Error> {
+    let mut items = vec![];
+    let mut line = String::new();
+    while items.len() < CHUNK {
+        let Some(group) = files.get(cursor.group) else {
+            break;
+        };
+        let Some(path) = group.get(cursor.file) else {
+            cursor = Cursor {
+                group: cursor.group + 1,
+                ..Default::default()
+            };
+            continue;
+        };
+        let mut file = File::open(path).with_context(|| format!("{path:?}"))?;
+        file.seek(SeekFrom::Start(cursor.offset))?;
+        let mut reader = BufReader::new(file);
+        while items.len() < CHUNK {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                cursor.file += 1;
+                cursor.offset = 0;
+                break;
+            }
+            cursor.offset += read as u64;
+            items.push((cursor.group, serde_json::from_str(&line)?));
+        }
+    }
+    Ok((items, cursor))
+}
+
+impl Spool {
+    pub fn items(&self) -> SpoolItems<'_> {
+        SpoolItems {
+            spool: self,
+            cursor: Cursor::default(),
+            buffer: VecDeque::new(),
+        }
+    }
+
+    // Файли читаються в блокуючому пулі, щоб не тримати runtime
+    pub fn stream(&self) -> impl Stream<Item = (&ExportOptions, Product)> + '_ {
+        stream::unfold(
+            (Cursor::default(), VecDeque::new()),
+            move |(mut cursor, mut buffer): (Cursor, VecDeque<_>)| async move {
+                if buffer.is_empty() {
+                    let files = self.files.clone();
+                    let read = tokio::task::spawn_blocking(move || read_chunk(&files, cursor))
+                        .await
+                        .map_err(anyhow::Error::from)
+                        .and_then(|r| r);
+                    match read {
+                        Ok((items, next)) => {
+                            buffer = VecDeque::from(items);
+                            cursor = next;
+                        }
+                        Err(err) => {
+                            self.fail(err);
+                            return None;
+                        }
+                    }
+                }
+                let (group, p) = buffer.pop_front()?;
+                Some(((&self.options[group], p), (cursor, buffer)))
+            },
+        )
+    }
+
+    // Обірваний прохід не дає опублікувати неповний файл
+    pub fn check(&self) -> Result<(), anyhow::Error> {
+        let error = self
+            .error
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Spool lock poisoned"))?
+            .take();
+        match error {
+            Some(err) => Err(err.context("Unable to read export products")),
+            None => Ok(()),
+        }
+    }
+
+    fn fail(&self, err: anyhow::Error) {
+        log::error!("Unable to read export products: {err}");
+        if let Ok(mut error) = self.error.lock() {
+            error.get_or_insert(err);
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.dir) {
+            log::error!("Unable to remove {:?}: {err}", self.dir);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SpoolItems<'a> {
+    spool: &'a Spool,
+    cursor: Cursor,
+    buffer: VecDeque<(usize, Product)>,
+}
+
+impl<'a> Iterator for SpoolItems<'a> {
+    type Item = (&'a ExportOptions, Product);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            match read_chunk(&self.spool.files, self.cursor) {
+                Ok((items, cursor)) => {
+                    self.buffer = VecDeque::from(items);
+                    self.cursor = cursor;
+                }
+                Err(err) => {
+                    self.spool.fail(err);
+                    self.cursor.group = self.spool.files.len();
+                    return None;
+                }
+            }
+        }
+        let (group, p) = self.buffer.pop_front()?;
+        Some((&self.spool.options[group], p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rt_types::Availability;
+
+    fn product(id: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            title: format!("Товар {id}"),
+            ua_translation: None,
+            description: Some("Опис\nу два рядки".to_string()),
+            price: rust_decimal::Decimal::new(125050, 2),
+            source_price: None,
+            article: format!("ART-{id}"),
+            in_stock: None,
+            currency: "UAH".to_string(),
+            keywords: None,
+            params: HashMap::new(),
+            brand: String::new(),
+            model: String::new(),
+            category: None,
+            available: Availability::Available,
+            vendor: String::new(),
+            images: vec![],
+        }
+    }
+
+    #[test]
+    fn items_follow_sources_and_clone_mid_pass() {
+        let dir = std::env::temp_dir().join(format!("spool_{}", uuid::Uuid::new_v4()));
+        let a = ExportOptions::default();
+        let b = ExportOptions {
+            add_vendor: true,
+            ..Default::default()
+        };
+        let mut writer = SpoolWriter::create(&dir).unwrap();
+        for (source, opts, id) in [(1, &a, "1"), (0, &b, "2"), (0, &a, "3"), (1, &a, "4")] {
+            writer.push(source, opts, &product(id)).unwrap();
+        }
+        let spool = writer.finish().unwrap();
+        let ids = |items: SpoolItems| items.map(|(o, p)| (o.add_vendor, p.id)).collect::<Vec<_>>();
+        let all = ids(spool.items());
+        assert_eq!(all.len(), 4);
+        for vendor in [false, true] {
+            let group = all
+                .iter()
+                .filter(|(v, _)| *v == vendor)
+                .map(|(_, id)| id.as_str());
+            let expected: &[&str] = if vendor { &["2"] } else { &["3", "1", "4"] };
+            assert_eq!(group.collect::<Vec<_>>(), expected);
+        }
+
+        let mut items = spool.items();
+        items.next();
+        assert_eq!(ids(items.clone()), all[1..]);
+        assert_eq!(ids(items), all[1..]);
+        assert_eq!(spool.items().next().unwrap().1.price.to_string(), "1250.50");
+        spool.check().unwrap();
+        drop(spool);
+        assert!(!dir.exists());
+    }
+}
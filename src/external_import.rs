@@ -212,6 +212,7 @@ impl TryInto<Product> for Vendored<Offer> {
                 .price
                 .map(|p| p.parse().context("Unable to parse offer price"))
                 .ok_or(anyhow!("Offer must contain price"))??,
+            source_price: None,
             currency: offer.currency.unwrap_or("UAH".to_string()),
             article,
             brand: offer.vendor.unwrap_or_default(),
@@ -258,6 +259,7 @@ impl TryInto<Product> for Vendored<Item> {
                 .priceuah
                 .map(Into::into)
                 .ok_or(anyhow!("Item must contain price"))?,
+            source_price: None,
             currency: item.currency.unwrap_or("UAH".to_string()),
             article,
             brand: item.vendor.unwrap_or_default(),
@@ -1,7 +1,50 @@
+use crate::control::site_api;
+use crate::horoshop::CategoryChain;
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, DeflateOption, ZipEntryBuilder};
+use futures::{Stream, StreamExt};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::writer::Writer;
+use rt_types::category::Category;
 use rt_types::product::Product;
+use rt_types::shop::{Discount, ExportOptions};
 use rt_types::Availability;
 use rust_decimal::Decimal;
 use serde::{Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+
+pub const DEFAULT_GOOGLE_CATEGORY: &str = "Vehicles & Parts > Vehicle Parts & Accessories";
+
+// Таксономія Google за словами в назвах наших категорій, перший збіг перемагає
+const GOOGLE_CATEGORIES: &[(&[&str], &str)] = &[
+    (
+        &["фар", "фонар", "ліхтар", "свет", "світл", "led", "ходов"],
+        "Vehicles & Parts > Vehicle Parts & Accessories > Motor Vehicle Parts > Motor Vehicle Lighting",
+    ),
+    (
+        &["диск", "колес", "колі"],
+        "Vehicles & Parts > Vehicle Parts & Accessories > Motor Vehicle Parts > Motor Vehicle Wheel Systems",
+    ),
+    (
+        &["выхлоп", "вихлоп", "глушит"],
+        "Vehicles & Parts > Vehicle Parts & Accessories > Motor Vehicle Parts > Motor Vehicle Exhaust",
+    ),
+    (
+        &[
+            "бампер", "обвес", "обвіс", "спойлер", "решет", "решіт", "порог", "диффузор",
+            "дифузор", "капот", "крыл", "крил", "губа", "накладк", "молдинг",
+        ],
+        "Vehicles & Parts > Vehicle Parts & Accessories > Motor Vehicle Parts > Motor Vehicle Frame & Body Parts",
+    ),
+    (
+        &["багажн", "бокс", "рейлинг", "рейлінг", "поперечин"],
+        "Vehicles & Parts > Vehicle Parts & Accessories > Vehicle Storage & Cargo",
+    ),
+];
 
 #[derive(Serialize)]
 pub struct Entry {
@@ -11,9 +54,15 @@ pub struct Entry {
     pub availability: String,
     pub condition: Condition,
     pub price: Price,
+    pub sale_price: Option<Price>,
+    pub sale_price_effective_date: Option<String>,
     pub link: String,
     pub image_link: String,
+    #[serde(serialize_with = "serialize_joined")]
+    pub additional_image_link: Vec<String>,
     pub brand: String,
+    pub google_product_category: String,
+    pub product_type: String,
 }
 
 #[derive(Serialize)]
@@ -24,37 +73,352 @@ pub enum Condition {
     Used,
 }
 
+impl Condition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::New => "new",
+            Self::Refurbished => "refurbished",
+            Self::Used => "used",
+        }
+    }
+}
+
 pub struct Price {
     pub amount: Decimal,
     pub currency: String,
 }
 
+impl std::fmt::Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount.round_dp(2), self.currency)
+    }
+}
+
 impl Serialize for Price {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        serializer.serialize_str(&format!("{} {}", self.amount, self.currency))
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+fn serialize_joined<S: Serializer>(v: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&v.join(","))
+}
+
+pub fn google_product_category(chain: Option<&CategoryChain>) -> &'static str {
+    chain
+        .into_iter()
+        .flat_map(|c| c.inner().iter().rev())
+        .find_map(|c| {
+            let name = c.name.to_lowercase();
+            GOOGLE_CATEGORIES
+                .iter()
+                .find(|(words, _)| words.iter().any(|w| name.contains(w)))
+                .map(|(_, category)| *category)
+        })
+        .unwrap_or(DEFAULT_GOOGLE_CATEGORY)
+}
+
+pub fn product_link(base: &str, p: &Product) -> String {
+    let path = site_api::product_path(&p.title, &p.brand, &p.model, &p.article);
+    format!("{base}{path}")
+}
+
+pub fn entry(
+    p: &Product,
+    opts: &ExportOptions,
+    chain: Option<&CategoryChain>,
+    base: &str,
+    now: OffsetDateTime,
+) -> Entry {
+    let availability = match p.available {
+        Availability::Available => "in stock",
+        Availability::OnOrder => "available for order",
+        Availability::NotAvailable => "out of stock",
     }
+    .to_string();
+    let base_title = if p.title.is_empty() {
+        p.ua_translation
+            .as_ref()
+            .map(|t| t.title.as_str())
+            .unwrap_or_default()
+    } else {
+        &p.title
+    };
+    let title = crate::xlsx::build_title(opts, base_title, false);
+    let description = p
+        .description
+        .clone()
+        .or_else(|| {
+            p.ua_translation
+                .as_ref()
+                .and_then(|t| t.description.clone())
+        })
+        .map(|d| crate::xlsx::format_replica(&crate::xlsx::trim_images(&d)))
+        .filter(|d| !d.trim().is_empty())
+        .unwrap_or_else(|| title.clone());
+    let price = p.price;
+    let (sale_price, sale_price_effective_date) = match &opts.discount {
+        Some(Discount { percent, duration })
+            if !matches!(p.available, Availability::NotAvailable) =>
+        {
+            let amount =
+                price * (Decimal::ONE_HUNDRED - Decimal::from(*percent)) / Decimal::ONE_HUNDRED;
+            let dates = now
+                .format(&Rfc3339)
+                .and_then(|from| Ok(format!("{from}/{}", (now + *duration).format(&Rfc3339)?)))
+                .map_err(|err| log::error!("Unable to format discount dates: {err}"))
+                .ok();
+            (
+                Some(Price {
+                    amount,
+                    currency: p.currency.clone(),
+                }),
+                dates,
+            )
+        }
+        _ => (None, None),
+    };
+    let mut images = p.images.iter().cloned();
+    let brand = if p.brand.trim().is_empty() {
+        p.vendor.clone()
+    } else {
+        p.brand.clone()
+    };
+    Entry {
+        id: p.id.clone(),
+        link: product_link(base, p),
+        title,
+        description,
+        availability,
+        condition: Condition::New,
+        price: Price {
+            amount: price,
+            currency: p.currency.clone(),
+        },
+        sale_price,
+        sale_price_effective_date,
+        image_link: images.next().unwrap_or_default(),
+        additional_image_link: images.take(20).collect(),
+        brand,
+        google_product_category: google_product_category(chain).to_string(),
+        product_type: chain
+            .map(|c| {
+                itertools::intersperse(c.inner().iter().map(|c| c.name.as_str()), " > ").collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+pub(crate) fn with_categories<'a, 'c>(
+    items: impl Stream<Item = (&'a ExportOptions, Product)> + 'c,
+    categories: &'c HashSet<Category, impl BuildHasher>,
+) -> impl Stream<Item = (&'a ExportOptions, Product, Option<CategoryChain>)> + 'c
+where
+    'a: 'c,
+{
+    let by_id: HashMap<_, _> = categories.iter().map(|c| (c.id, c)).collect();
+    items.map(move |(o, p)| {
+        let chain = p.category.and_then(|id| {
+            let root = (*by_id.get(&id)?).clone();
+            CategoryChain::new(root, |id| by_id.get(&id).map(|c| (*c).clone()))
+        });
+        (o, p, chain)
+    })
+}
+
+pub(crate) fn zip_entry(path: &str) -> Result<ZipEntryBuilder, anyhow::Error> {
+    Ok(ZipEntryBuilder::new(
+        std::path::Path::new(path)
+            .file_name()
+            .and_then(|f| f.to_str())
+            .map(|f| f.replace(".zip", ""))
+            .ok_or_else(|| anyhow::anyhow!("No filename for path {path:?}"))?
+            .into(),
+        Compression::Deflate,
+    )
+    .deflate_option(DeflateOption::Fast)
+    .unix_permissions(0o777))
+}
+
+pub async fn write_csv<'a>(
+    path: &str,
+    items: impl Stream<Item = (&'a ExportOptions, Product)>,
+    categories: &HashSet<Category, impl BuildHasher>,
+    base: &str,
+) -> Result<(), anyhow::Error> {
+    let mut res_file = tokio::fs::File::create(&path).await?;
+    let mut w = ZipFileWriter::with_tokio(&mut res_file);
+    let mut zip_writer = w.write_entry_stream(zip_entry(path)?).await?.compat_write();
+    let mut ser = csv_async::AsyncWriterBuilder::new().create_serializer(&mut zip_writer);
+    let now = OffsetDateTime::now_utc();
+    let mut entries = std::pin::pin!(with_categories(items, categories));
+    while let Some((o, p, chain)) = entries.next().await {
+        ser.serialize(entry(&p, o, chain.as_ref(), base, now))
+            .await?;
+    }
+    ser.flush().await?;
+    drop(ser);
+    zip_writer.into_inner().close().await?;
+    w.close().await?;
+    Ok(())
 }
 
-impl From<Product> for Entry {
-    fn from(p: Product) -> Entry {
-        let availability = match p.available {
-            Availability::Available => "in stock",
-            Availability::OnOrder | Availability::NotAvailable => "out of stock",
+pub async fn write_xml<'a>(
+    path: &str,
+    items: impl Stream<Item = (&'a ExportOptions, Product)>,
+    categories: &HashSet<Category, impl BuildHasher>,
+    base: &str,
+) -> Result<(), anyhow::Error> {
+    let mut res_file = tokio::fs::File::create(&path).await?;
+    let mut w = ZipFileWriter::with_tokio(&mut res_file);
+    let mut zip_writer = w.write_entry_stream(zip_entry(path)?).await?.compat_write();
+    let mut writer = Writer::new(&mut zip_writer);
+    writer
+        .write_event_async(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("rss").with_attributes([
+            ("version", "2.0"),
+            ("xmlns:g", "http://base.google.com/ns/1.0"),
+        ])))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("channel")))
+        .await?;
+    let now = OffsetDateTime::now_utc();
+    let mut entries = std::pin::pin!(with_categories(items, categories));
+    while let Some((o, p, chain)) = entries.next().await {
+        let e = entry(&p, o, chain.as_ref(), base, now);
+        let mut fields = vec![
+            ("g:id", e.id),
+            ("g:title", e.title),
+            ("g:description", e.description),
+            ("g:availability", e.availability),
+            ("g:condition", e.condition.as_str().to_string()),
+            ("g:price", e.price.to_string()),
+            ("g:link", e.link),
+            ("g:image_link", e.image_link),
+            ("g:brand", e.brand),
+            ("g:google_product_category", e.google_product_category),
+        ];
+        if let Some(sale_price) = e.sale_price {
+            fields.push(("g:sale_price", sale_price.to_string()));
         }
-        .to_string();
-        Entry {
-            id: p.id,
-            title: p.title,
-            description: p.description.unwrap_or_default(),
-            availability,
-            condition: Condition::New,
-            price: Price {
-                amount: p.price,
-                currency: p.currency,
-            },
-            link: "".to_string(),
-            image_link: p.images.first().cloned().unwrap_or_default(),
-            brand: "rt-auto".to_string(),
+        if let Some(dates) = e.sale_price_effective_date {
+            fields.push(("g:sale_price_effective_date", dates));
         }
+        if !e.product_type.is_empty() {
+            fields.push(("g:product_type", e.product_type));
+        }
+        fields.extend(
+            e.additional_image_link
+                .into_iter()
+                .map(|l| ("g:additional_image_link", l)),
+        );
+        writer
+            .create_element("item")
+            .write_inner_content_async::<_, _, quick_xml::Error>(|writer| async move {
+                for (name, value) in &fields {
+                    writer
+                        .create_element(*name)
+                        .write_text_content_async(BytesText::new(value))
+                        .await?;
+                }
+                Ok(writer)
+            })
+            .await?;
+    }
+    writer
+        .write_event_async(Event::End(BytesEnd::new("channel")))
+        .await?;
+    writer
+        .write_event_async(Event::End(BytesEnd::new("rss")))
+        .await?;
+    zip_writer.into_inner().close().await?;
+    w.close().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use time::macros::datetime;
+    use uuid::Uuid;
+
+    fn category(name: &str, parent_id: Option<Uuid>) -> Category {
+        Category {
+            name: name.to_string(),
+            id: Uuid::new_v4(),
+            parent_id,
+            regex: None,
+            shop_id: Uuid::nil(),
+            seo_title: None,
+            seo_description: None,
+            seo_text: None,
+        }
+    }
+
+    #[test]
+    fn builds_entry_with_sale_price_images_and_category() {
+        let root = category("BMW", None);
+        let leaf = category("Задние фонари", Some(root.id));
+        let chain = CategoryChain::new(leaf, |_| Some(root.clone())).unwrap();
+        let product = Product {
+            id: "dt-1".to_string(),
+            title: "Фонари BMW X5".to_string(),
+            ua_translation: None,
+            description: None,
+            price: Decimal::new(1000, 0),
+            source_price: None,
+            article: "DT 01".to_string(),
+            in_stock: Some(3),
+            currency: "UAH".to_string(),
+            keywords: None,
+            params: HashMap::new(),
+            brand: "".to_string(),
+            model: "X5".to_string(),
+            category: None,
+            available: Availability::Available,
+            vendor: "DT".to_string(),
+            images: vec![
+                "a.jpg".to_string(),
+                "b.jpg".to_string(),
+                "c.jpg".to_string(),
+            ],
+        };
+        let opts = ExportOptions {
+            discount: Some(Discount {
+                percent: 10,
+                duration: Duration::from_secs(60 * 60 * 24),
+            }),
+            ..Default::default()
+        };
+        let e = entry(
+            &product,
+            &opts,
+            Some(&chain),
+            "https://shop.example",
+            datetime!(2026-10-16 0:00 UTC),
+        );
+
+        assert_eq!(e.price.to_string(), "1000 UAH");
+        assert_eq!(
+            e.sale_price.map(|p| p.to_string()).as_deref(),
+            Some("900 UAH")
+        );
+        assert_eq!(
+            e.sale_price_effective_date.as_deref(),
+            Some("2026-10-16T00:00:00Z/2026-10-17T00:00:00Z")
+        );
+        assert_eq!(e.image_link, "a.jpg");
+        assert_eq!(e.additional_image_link, ["b.jpg", "c.jpg"]);
+        assert_eq!(e.brand, "DT");
+        assert_eq!(e.link, "https://shop.example/item/fonary-bmw-x5-x5-DT+01");
+        assert_eq!(e.product_type, "BMW > Задние фонари");
+        assert!(e
+            .google_product_category
+            .ends_with("Motor Vehicle Lighting"));
+        assert_eq!(google_product_category(None), DEFAULT_GOOGLE_CATEGORY);
     }
 }
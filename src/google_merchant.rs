@@ -0,0 +1,234 @@
+use crate::facebook;
+use async_zip::tokio::write::ZipFileWriter;
+use futures::{Stream, StreamExt};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::writer::Writer;
+use rt_types::category::Category;
+use rt_types::product::Product;
+use rt_types::shop::ExportOptions;
+use rt_types::Availability;
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+
+// Merchant Center вимагає дату для `backorder`, товар під замовлення чекаємо стільки
+pub const ON_ORDER_DELIVERY: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Ua,
+    Ru,
+}
+
+impl Language {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Ua => "uk",
+            Self::Ru => "ru",
+        }
+    }
+}
+
+fn availability(available: &Availability) -> &'static str {
+    match available {
+        Availability::Available => "in_stock",
+        Availability::OnOrder => "backorder",
+        Availability::NotAvailable => "out_of_stock",
+    }
+}
+
+pub fn item(
+    p: &Product,
+    opts: &ExportOptions,
+    chain: Option<&crate::horoshop::CategoryChain>,
+    language: Language,
+    base: &str,
+    now: OffsetDateTime,
+) -> Vec<(&'static str, String)> {
+    let e = facebook::entry(p, opts, chain, base, now);
+    let (title, description) = match language {
+        Language::Ru => (e.title, e.description),
+        Language::Ua => {
+            let ua = p.ua_translation.as_ref();
+            let base_title = ua
+                .map(|t| t.title.as_str())
+                .filter(|t| !t.is_empty())
+                .unwrap_or(&p.title);
+            let title = crate::xlsx::build_title(opts, base_title, true);
+            let description = ua
+                .and_then(|t| t.description.as_ref())
+                .map(|d| crate::xlsx::format_replica(&crate::xlsx::trim_images(d)))
+                .filter(|d| !d.trim().is_empty())
+                .unwrap_or(e.description);
+            (title, description)
+        }
+    };
+    let mut fields = vec![
+        ("g:id", e.id),
+        ("g:title", title),
+        ("g:description", description),
+        ("g:link", e.link),
+        ("g:image_link", e.image_link),
+    ];
+    fields.extend(
+        e.additional_image_link
+            .into_iter()
+            .take(10)
+            .map(|l| ("g:additional_image_link", l)),
+    );
+    fields.push(("g:availability", availability(&p.available).to_string()));
+    if let Availability::OnOrder = p.available {
+        match (now + ON_ORDER_DELIVERY).format(&Rfc3339) {
+            Ok(date) => fields.push(("g:availability_date", date)),
+            Err(err) => log::error!("Unable to format availability date: {err}"),
+        }
+    }
+    fields.push(("g:price", e.price.to_string()));
+    if let Some(sale_price) = e.sale_price {
+        fields.push(("g:sale_price", sale_price.to_string()));
+    }
+    if let Some(dates) = e.sale_price_effective_date {
+        fields.push(("g:sale_price_effective_date", dates));
+    }
+    fields.push(("g:brand", e.brand));
+    if !p.article.trim().is_empty() {
+        fields.push(("g:mpn", p.article.trim().to_string()));
+    }
+    fields.push(("g:condition", e.condition.as_str().to_string()));
+    fields.push(("g:google_product_category", e.google_product_category));
+    if !e.product_type.is_empty() {
+        fields.push(("g:product_type", e.product_type));
+    }
+    fields
+}
+
+pub async fn write_xml<'a>(
+    path: &str,
+    items: impl Stream<Item = (&'a ExportOptions, Product)>,
+    categories: &HashSet<Category, impl BuildHasher>,
+    language: Language,
+    base: &str,
+) -> Result<(), anyhow::Error> {
+    let mut res_file = tokio::fs::File::create(&path).await?;
+    let mut w = ZipFileWriter::with_tokio(&mut res_file);
+    let mut zip_writer = w
+        .write_entry_stream(facebook::zip_entry(path)?)
+        .await?
+        .compat_write();
+    let mut writer = Writer::new(&mut zip_writer);
+    writer
+        .write_event_async(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("rss").with_attributes([
+            ("version", "2.0"),
+            ("xmlns:g", "http://base.google.com/ns/1.0"),
+        ])))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("channel")))
+        .await?;
+    let channel_link = format!("{base}/");
+    for (name, value) in [
+        ("title", path.rsplit('/').next().unwrap_or(path)),
+        ("link", channel_link.as_str()),
+        ("language", language.code()),
+    ] {
+        writer
+            .create_element(name)
+            .write_text_content_async(BytesText::new(value))
+            .await?;
+    }
+    let now = OffsetDateTime::now_utc();
+    let mut entries = std::pin::pin!(facebook::with_categories(items, categories));
+    while let Some((o, p, chain)) = entries.next().await {
+        let fields = item(&p, o, chain.as_ref(), language, base, now);
+        writer
+            .create_element("item")
+            .write_inner_content_async::<_, _, quick_xml::Error>(|writer| async move {
+                for (name, value) in &fields {
+                    writer
+                        .create_element(*name)
+                        .write_text_content_async(BytesText::new(value))
+                        .await?;
+                }
+                Ok(writer)
+            })
+            .await?;
+    }
+    writer
+        .write_event_async(Event::End(BytesEnd::new("channel")))
+        .await?;
+    writer
+        .write_event_async(Event::End(BytesEnd::new("rss")))
+        .await?;
+    zip_writer.into_inner().close().await?;
+    w.close().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rt_types::product::UaTranslation;
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+    use time::macros::datetime;
+
+    #[test]
+    fn writes_language_variants_and_mpn() {
+        let product = Product {
+            id: "tt-7".to_string(),
+            title: "Спойлер".to_string(),
+            ua_translation: Some(UaTranslation {
+                title: "Спойлер UA".to_string(),
+                description: Some("Опис".to_string()),
+            }),
+            description: Some("Описание".to_string()),
+            price: Decimal::new(1250, 0),
+            source_price: None,
+            article: "TT-77".to_string(),
+            in_stock: None,
+            currency: "UAH".to_string(),
+            keywords: None,
+            params: HashMap::new(),
+            brand: "Maxton".to_string(),
+            model: "".to_string(),
+            category: None,
+            available: Availability::OnOrder,
+            vendor: "TT".to_string(),
+            images: vec![],
+        };
+        let opts = ExportOptions::default();
+        let now = datetime!(2026-10-16 0:00 UTC);
+        let field = |fields: &[(&str, String)], name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.clone())
+        };
+
+        let base = "https://shop.example";
+
+        let ua = item(&product, &opts, None, Language::Ua, base, now);
+        assert_eq!(field(&ua, "g:title").as_deref(), Some("Спойлер UA"));
+        assert_eq!(field(&ua, "g:description").as_deref(), Some("Опис"));
+        assert_eq!(field(&ua, "g:availability").as_deref(), Some("backorder"));
+        assert_eq!(
+            field(&ua, "g:availability_date").as_deref(),
+            Some("2026-10-30T00:00:00Z")
+        );
+        assert!(field(&ua, "g:link").is_some_and(|l| l.starts_with("https://shop.example/item/")));
+        assert_eq!(field(&ua, "g:mpn").as_deref(), Some("TT-77"));
+        assert_eq!(field(&ua, "g:brand").as_deref(), Some("Maxton"));
+        assert_eq!(field(&ua, "g:price").as_deref(), Some("1250 UAH"));
+        assert_eq!(field(&ua, "g:sale_price"), None);
+
+        let ru = item(&product, &opts, None, Language::Ru, base, now);
+        assert_eq!(field(&ru, "g:title").as_deref(), Some("Спойлер"));
+        assert_eq!(field(&ru, "g:description").as_deref(), Some("Описание"));
+    }
+}
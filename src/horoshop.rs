@@ -1,6 +1,6 @@
 use async_zip::tokio::write::ZipFileWriter;
 use async_zip::{Compression, DeflateOption, ZipEntryBuilder};
-use futures::stream::{self, StreamExt, TryStreamExt};
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use itertools::Itertools;
 use log_error::LogError;
 use rt_types::category::Category;
@@ -10,9 +10,7 @@ use rt_types::shop::ExportOptions;
 use rt_types::Availability;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::future::Future;
-use std::hash::BuildHasher;
 use std::sync::Arc;
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
 use typesafe_repository::IdentityOf;
@@ -45,9 +43,6 @@ impl CsvEntry {
     pub fn apply_opts(self, opts: &ExportOptions) -> Self {
         Self {
             title: crate::xlsx::build_title(opts, &self.title, false),
-            price: self
-                .price
-                .map(|p| p * opts.adjust_price.unwrap_or(Decimal::ONE)),
             ..self
         }
     }
@@ -211,9 +206,9 @@ impl ToString for CategoryChain {
     }
 }
 
-pub async fn export_csv(
+pub async fn export_csv<'a>(
     path: String,
-    products: &HashMap<ExportOptions, Vec<Product>, impl BuildHasher>,
+    products: impl Stream<Item = (&'a ExportOptions, Product)>,
     category_repo: Arc<dyn CategoryRepository>,
 ) -> Result<(), anyhow::Error> {
     let mut res_file = tokio::fs::File::create(&path).await?;
@@ -233,7 +228,8 @@ pub async fn export_csv(
     let mut ser = csv_async::AsyncWriterBuilder::new()
         .quote_style(csv_async::QuoteStyle::NonNumeric)
         .create_serializer(&mut zip_writer);
-    for product in products.values().flatten() {
+    let mut products = std::pin::pin!(products);
+    while let Some((_, product)) = products.next().await {
         let entry = match product.category {
             Some(category) => {
                 let category = category_repo
@@ -251,11 +247,11 @@ pub async fn export_csv(
                 .await?
                 .log_error("Category not found");
                 match category_chain {
-                    Some(category_chain) => CsvEntryRef::from((product, category_chain)),
+                    Some(category_chain) => CsvEntryRef::from((&product, category_chain)),
                     None => continue,
                 }
             }
-            None => CsvEntryRef::from((product, "Главная".to_string())),
+            None => CsvEntryRef::from((&product, "Главная".to_string())),
         };
         ser.serialize(entry).await?;
     }
@@ -266,9 +262,9 @@ pub async fn export_csv(
     Ok(())
 }
 
-pub async fn export_csv_categories(
+pub async fn export_csv_categories<'a>(
     path: String,
-    products: &HashMap<ExportOptions, Vec<Product>, impl BuildHasher>,
+    products: impl Stream<Item = (&'a ExportOptions, Product)>,
     category_repo: Arc<dyn CategoryRepository>,
 ) -> Result<(), anyhow::Error> {
     let mut res_file = tokio::fs::File::create(&path).await?;
@@ -288,31 +284,27 @@ pub async fn export_csv_categories(
     let mut ser = csv_async::AsyncWriterBuilder::new()
         .quote_style(csv_async::QuoteStyle::NonNumeric)
         .create_serializer(&mut zip_writer);
-    let categories = stream::iter(
-        products
-            .values()
-            .flatten()
-            .filter_map(|product| product.category),
-    )
-    .map(|category| {
-        let category_repo = category_repo.clone();
-        async move {
-            let category = category_repo
-                .get_one(&category)
+    let categories = products
+        .filter_map(|(_, product)| futures::future::ready(product.category))
+        .map(|category| {
+            let category_repo = category_repo.clone();
+            async move {
+                let category = category_repo
+                    .get_one(&category)
+                    .await?
+                    .ok_or(anyhow::anyhow!("Category not found"))?;
+                let category_chain = CategoryChain::new_async(category, |id| {
+                    let category_repo = category_repo.clone();
+                    async move { category_repo.get_one(&id).await }
+                })
                 .await?
-                .ok_or(anyhow::anyhow!("Category not found"))?;
-            let category_chain = CategoryChain::new_async(category, |id| {
-                let category_repo = category_repo.clone();
-                async move { category_repo.get_one(&id).await }
-            })
-            .await?
-            .ok_or(anyhow::anyhow!("Category not found"));
-            category_chain
-        }
-    })
-    .buffered(64)
-    .try_collect::<Vec<_>>()
-    .await?;
+                .ok_or(anyhow::anyhow!("Category not found"));
+                category_chain
+            }
+        })
+        .buffered(64)
+        .try_collect::<Vec<_>>()
+        .await?;
     let max = categories
         .iter()
         .map(|c| c.inner().len())
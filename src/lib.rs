@@ -25,19 +25,27 @@ pub mod category;
 pub mod category_auto;
 pub mod control;
 pub mod csv;
+pub mod customer;
 pub mod dt;
 pub mod ddaudio;
 pub mod ddaudio_export;
 pub mod ddaudio_import;
 pub mod export;
+pub mod export_diff;
+pub mod export_history;
+pub mod export_spool;
 pub mod external_import;
 pub mod facebook;
+pub mod google_merchant;
 pub mod horoshop;
 pub mod import_throttle;
 pub mod invoice;
+pub mod nova_poshta;
 pub mod product_category;
 pub mod product_category_auto;
 pub mod restal;
+pub mod rozetka;
+pub mod scheduler;
 pub mod review;
 pub mod quick_order;
 pub mod order;
@@ -46,10 +54,13 @@ pub mod shop_product;
 pub mod seo_page;
 pub mod site_import;
 pub mod site_publish;
+pub mod site_search;
 pub mod subscription;
 pub mod tt;
 pub mod uploader;
 pub mod watermark;
+pub mod watermark_cache;
+pub mod watermark_guard;
 pub mod xlsx;
 pub mod xml;
 
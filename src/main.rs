@@ -17,11 +17,12 @@ use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use rt_parsing::{
     access,
     category::SqliteCategoryRepository,
-    control,
+    control, customer,
     dt::{self, parser::ParsingOptions},
-    export,
+    export, export_history,
     export::ExportService,
-    invoice, order, product_category, quick_order, review, seo_page, shop, shop_product, subscription, tt,
+    invoice, nova_poshta, order, product_category, quick_order, review, scheduler, seo_page, shop, shop_product,
+    subscription, tt,
     site_import, site_publish, ddaudio_import, watermark,
     watermark::FilesystemWatermarkGroupRepository,
     RateLimiter,
@@ -154,6 +155,23 @@ async fn main() -> Result<(), anyhow::Error> {
     let conn = Connection::open("storage/shop_orders.db").await?;
     let order_repository: Arc<dyn order::OrderRepository> =
         Arc::new(order::SqliteOrderRepository::init(conn).await?);
+    let conn = Connection::open("storage/customers.db").await?;
+    let customer_repository: Arc<dyn customer::CustomerRepository> =
+        Arc::new(customer::SqliteCustomerRepository::init(conn).await?);
+    // Без SMS-провайдера коди йдуть у CUSTOMER_OTP_FILE або в лог
+    let otp_sender: Arc<dyn customer::OtpSender> = match env::var("CUSTOMER_OTP_FILE") {
+        Ok(path) if !path.trim().is_empty() => Arc::new(customer::FileOtpSender::new(path)),
+        _ => Arc::new(customer::LogOtpSender),
+    };
+    let conn = Connection::open("storage/nova_poshta.db").await?;
+    let nova_poshta_repository: Arc<dyn nova_poshta::NovaPoshtaRepository> =
+        Arc::new(nova_poshta::SqliteNovaPoshtaRepository::init(conn).await?);
+    let conn = Connection::open("storage/jobs.db").await?;
+    let job_repository: Arc<dyn scheduler::JobRepository> =
+        Arc::new(scheduler::SqliteJobRepository::init(conn.clone()).await?);
+    let jobs = Arc::new(scheduler::JobScheduler::from_env(job_repository).await?);
+    let export_runs: Arc<dyn export_history::ExportRunRepository> =
+        Arc::new(export_history::SqliteExportRunRepository::init(conn).await?);
 
     let shop_repository = Arc::new(shop::FileSystemShopRepository::new());
     let shop_service = rt_types::shop::service::ShopService::new(shop_repository).start();
@@ -198,6 +216,23 @@ async fn main() -> Result<(), anyhow::Error> {
     }
 
     let currency_service = currency_service::CurrencyService::new().start();
+    jobs.spawn(
+        "currency_rates",
+        "Обновление курсов валют",
+        scheduler::JobClass::Task,
+        "0 */4 * * *".parse()?,
+        {
+            let currency_service = currency_service.clone();
+            move |_| {
+                let currency_service = currency_service.clone();
+                async move {
+                    currency_service.send(currency_service::Refresh).await??;
+                    Ok::<_, anyhow::Error>(scheduler::RunCounts::new())
+                }
+            }
+        },
+    )
+    .await;
 
     // DB config with sensible defaults for local/dev runs
     let postgres_password: String = std::env::var("POSTGRES_PASSWORD")
@@ -266,6 +301,8 @@ async fn main() -> Result<(), anyhow::Error> {
     let watermark_service =
         rt_types::watermark::service::WatermarkService::new(watermark_group_repository.clone())
             .start();
+    let watermark_cache = Arc::new(rt_parsing::watermark_cache::WatermarkCache::from_env());
+    rt_parsing::watermark_cache::WatermarkCacheService::new(watermark_cache.clone()).start();
 
     let payment_repository: Arc<dyn subscription::payment::PaymentRepository> =
         Arc::new(subscription::payment::PostgresPaymentRepository::new(client.clone()).await?);
@@ -290,11 +327,76 @@ async fn main() -> Result<(), anyhow::Error> {
         .with(reqwest_ratelimit::all(RateLimiter::new(240)))
         .build();
 
-    let davi_service = rt_parsing_davi::ParserService::new(rt_parsing_davi::ParsingOptions {
+    let davi_options = rt_parsing_davi::ParsingOptions {
         client: davi_client,
         repo: davi_repo.clone(),
-    })
-    .start();
+    };
+    jobs.spawn(
+        "davi_parser",
+        "Парсинг Davi",
+        scheduler::JobClass::Parser,
+        scheduler::Schedule::Interval(Duration::ZERO),
+        move |_| {
+            let opts = davi_options.clone();
+            async move {
+                rt_parsing_davi::work_cycle(opts).await?;
+                Ok::<_, anyhow::Error>(scheduler::RunCounts::new())
+            }
+        },
+    )
+    .await;
+
+    // NOVA_POSHTA_API_KEY лише для довідника, ТТН створюються й відстежуються ключем магазину
+    let nova_poshta_client = nova_poshta::NovaPoshtaClient::new(
+        nova_poshta::NOVA_POSHTA_API.as_str(),
+        envmnt::get_or("NOVA_POSHTA_API_KEY", ""),
+        client.clone(),
+    );
+    jobs.spawn(
+        "nova_poshta_directory",
+        "Справочник Новой Почты",
+        scheduler::JobClass::Task,
+        scheduler::Schedule::Interval(Duration::from_secs(24 * 60 * 60)),
+        {
+            let client = nova_poshta_client.clone();
+            let repo = nova_poshta_repository.clone();
+            move |_| {
+                let client = client.clone();
+                let repo = repo.clone();
+                async move { nova_poshta::refresh_directory(&client, repo.as_ref()).await }
+            }
+        },
+    )
+    .await;
+    jobs.spawn(
+        "nova_poshta_tracking",
+        "Отслеживание ТТН Новой Почты",
+        scheduler::JobClass::Task,
+        "*/30 * * * *".parse()?,
+        {
+            let client = nova_poshta_client.clone();
+            let order_repository = order_repository.clone();
+            let shop_service = shop_service.clone();
+            move |_| {
+                let client = client.clone();
+                let order_repository = order_repository.clone();
+                let shop_service = shop_service.clone();
+                async move {
+                    let api_keys = shop_service
+                        .send(rt_types::shop::service::List)
+                        .await??
+                        .into_iter()
+                        .filter_map(|shop| {
+                            let key = shop.nova_poshta.api_key?;
+                            (!key.trim().is_empty()).then_some((shop.id, key))
+                        })
+                        .collect();
+                    nova_poshta::track_orders(&client, order_repository.as_ref(), &api_keys).await
+                }
+            }
+        },
+    )
+    .await;
 
     let wayforpay_secret_key: Option<String> =
         envmnt::get_parse("WAYFORPAY_SECRET_KEY").ok();
@@ -322,6 +424,8 @@ async fn main() -> Result<(), anyhow::Error> {
         category_repository.clone(),
         shop_service.clone(),
         currency_service.clone(),
+        jobs.clone(),
+        export_runs.clone(),
     )
     .start();
 
@@ -334,6 +438,7 @@ async fn main() -> Result<(), anyhow::Error> {
         product_category_repository.clone(),
         shop_service.clone(),
         currency_service.clone(),
+        jobs.clone(),
     )
     .start();
 
@@ -344,6 +449,7 @@ async fn main() -> Result<(), anyhow::Error> {
             shop_product_repository.clone(),
             category_repository.clone(),
             product_category_repository.clone(),
+            jobs.clone(),
         )
         .await;
     }
@@ -406,7 +512,7 @@ async fn main() -> Result<(), anyhow::Error> {
             tt_trans_repo.clone(),
             None,
         );
-        Some(tt::parser::ParserService::new(opts).start())
+        Some(tt::parser::ParserService::new(opts, jobs.clone()).start())
     } else {
         None
     };
@@ -415,6 +521,7 @@ async fn main() -> Result<(), anyhow::Error> {
         pb_style.clone(),
         token.clone(),
         !dt_auto_start(),
+        jobs.clone(),
     )
     .start();
     let secret_key = Key::from(secret_key.as_bytes());
@@ -463,17 +570,24 @@ async fn main() -> Result<(), anyhow::Error> {
             .app_data(Data::new(review_repository.clone()))
             .app_data(Data::new(quick_order_repository.clone()))
             .app_data(Data::new(order_repository.clone()))
+            .app_data(Data::new(customer_repository.clone()))
+            .app_data(Data::new(otp_sender.clone()))
+            .app_data(Data::new(nova_poshta_repository.clone()))
+            .app_data(Data::new(nova_poshta_client.clone()))
             .app_data(Data::new(Arc::new(dt_service.clone())))
             .app_data(Data::new(Arc::new(export_service.clone())))
             .app_data(Data::new(export_service.clone()))
             .app_data(Data::new(Arc::new(site_import_service.clone())))
             .app_data(Data::new(site_import_service.clone()))
-            .app_data(Data::new(davi_service.clone()))
+            .app_data(Data::new(jobs.clone()))
+            .app_data(Data::new(export_runs.clone()))
             .app_data(Data::new(shop_service.clone()))
+            .app_data(Data::new(currency_service.clone()))
             .app_data(Data::new(user_credentials_service.clone()))
             .app_data(Data::new(subscription_service.clone()))
             .app_data(Data::new(watermark_group_repository.clone()))
             .app_data(Data::new(watermark_service.clone()))
+            .app_data(Data::new(watermark_cache.clone()))
             .app_data(Data::new(payment_service.clone()))
             .app_data(Data::new(invoice_service.clone()))
             .service(actix_files::Files::new("/static", "static"))
@@ -511,6 +625,12 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::control_panel_files)
             .service(control::control_panel_files_delete)
             .service(control::control_panel_settings)
+            .service(control::control_panel_jobs)
+            .service(control::control_panel_job_run)
+            .service(control::control_panel_job_cancel)
+            .service(control::control_panel_job_pause)
+            .service(control::control_panel_job_resume)
+            .service(control::control_panel_job_schedule)
             .service(shop::controllers::remove_shop_page)
             .service(shop::controllers::remove_shop)
             .service(shop::controllers::add_shop_page)
@@ -518,6 +638,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(shop::controllers::shop_suspend_toggle)
             .service(control::parsing)
             .service(control::control_panel_dt_products)
+            .service(control::control_panel_dt_product_history)
             .service(control::dt_parse)
             .service(control::dt_parse_page)
             .service(control::dt_product_info)
@@ -525,8 +646,11 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::resume_dt)
             .service(control::start_export)
             .service(control::start_export_all)
+            .service(control::publish_export_anyway)
             .service(control::add_export)
             .service(control::remove_export)
+            .service(control::export_diff_download)
+            .service(control::export_run_rollback)
             .service(control::export_info)
             .service(control::update_export)
             .service(control::update_export_dt)
@@ -539,6 +663,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::update_export_tt)
             .service(control::update_export_davi)
             .service(control::update_export_ddaudio_api)
+            .service(control::update_export_prom)
             .service(control::add_export_link)
             .service(control::add_export_dt)
             .service(control::add_export_op_tuning)
@@ -550,6 +675,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::add_export_tt)
             .service(control::add_export_davi)
             .service(control::add_export_ddaudio_api)
+            .service(control::add_export_prom)
             .service(control::remove_export_link)
             .service(control::remove_export_dt)
             .service(control::remove_export_op_tuning)
@@ -561,6 +687,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::remove_export_tt)
             .service(control::remove_export_davi)
             .service(control::remove_export_ddaudio_api)
+            .service(control::remove_export_prom)
             .service(control::update_export_link)
             .service(control::upload_description_file)
             .service(control::remove_description_file)
@@ -570,6 +697,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::site_publish_save)
             .service(control::site_publish_ddaudio_save)
             .service(control::site_publish_allowed)
+            .service(control::site_publish_api_save)
             .service(control::site_publish_purge_supplier)
             .service(control::ddaudio_import_start)
             .service(control::ddaudio_import_status)
@@ -610,6 +738,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(watermark::remove_watermark_group_entry)
             .service(watermark::watermark_settings)
             .service(watermark::upload_watermark)
+            .service(watermark::update_watermark_hosts)
             .service(watermark::delete_watermark)
             .service(watermark::update_watermark)
             .service(watermark::generate_watermark_link_page)
@@ -623,12 +752,26 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(invoice::controllers::successful_payment)
             .service(shop::controllers::settings_page)
             .service(shop::controllers::update_settings)
+            .service(shop::controllers::update_currency_settings)
+            .service(shop::controllers::update_prom_settings)
+            .service(shop::controllers::update_review_settings)
+            .service(shop::controllers::update_feed_settings)
+            .service(shop::controllers::update_nova_poshta_settings)
             .service(control::shop_crm_page)
             .service(control::shop_quick_orders_page)
             .service(control::shop_quick_order_delete)
             .service(control::shop_orders_page)
+            .service(control::shop_order_page)
+            .service(control::shop_order_status)
+            .service(control::shop_order_items)
+            .service(control::shop_order_waybill)
+            .service(control::shop_order_waybill_refresh)
             .service(control::shop_order_delete)
             .service(control::shop_users_page)
+            .service(control::shop_reviews_page)
+            .service(control::shop_reviews_bulk)
+            .service(control::shop_review_page)
+            .service(control::shop_review_update)
             .service(control::shop_products)
             .service(control::shop_products_bulk)
             // Important: register `/products/new` before `/products/{article}` so that
@@ -648,6 +791,7 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::shop_files)
             .service(control::shop_files_delete)
             .service(control::export_status_json)
+            .service(control::pricing_preview)
             .service(control::index)
             .service(control::landing::index)
             .service(control::site_api::list_products)
@@ -659,9 +803,26 @@ async fn main() -> Result<(), anyhow::Error> {
             .service(control::site_api::get_seo_page)
             .service(control::site_api::list_seo_pages)
             .service(control::site_api::list_reviews)
+            .service(control::site_api::review_ratings)
             .service(control::site_api::create_review)
             .service(control::site_api::get_product)
             .service(control::site_api::sitemap)
+            .service(control::customer_api::request_otp)
+            .service(control::customer_api::verify_otp)
+            .service(control::customer_api::register)
+            .service(control::customer_api::login)
+            .service(control::customer_api::logout)
+            .service(control::customer_api::me)
+            .service(control::customer_api::update_me)
+            .service(control::customer_api::orders)
+            .service(control::customer_api::addresses)
+            .service(control::customer_api::add_address)
+            .service(control::customer_api::remove_address)
+            .service(control::customer_api::favorites)
+            .service(control::customer_api::add_favorite)
+            .service(control::customer_api::remove_favorite)
+            .service(control::nova_poshta_api::cities)
+            .service(control::nova_poshta_api::warehouses)
             .service(control::catalog::search)
             .service(control::product::view)
             .default_service(
@@ -0,0 +1,1098 @@
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use rt_types::shop::{NovaPoshtaPayer, NovaPoshtaSettings};
+use rusqlite::{params, OptionalExtension};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::deserialize_string_from_number;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use time_tz::timezones::db::europe::KYIV;
+use time_tz::OffsetDateTimeExt;
+use tokio_rusqlite::Connection;
+use uuid::Uuid;
+
+use crate::order::{Order, OrderRepository, OrderStatus, OrderWaybill, StatusChange};
+use crate::scheduler::RunCounts;
+use crate::SqlWrapper;
+
+pub static NOVA_POSHTA_API: Lazy<String> = Lazy::new(|| {
+    envmnt::get_or(
+        "NOVA_POSHTA_API_URL",
+        "https://api.novaposhta.ua/v2.0/json/",
+    )
+});
+
+pub const TRACKING_AUTHOR: &str = "nova_poshta";
+
+const CITIES_PAGE: usize = 500;
+const WAREHOUSES_PAGE: usize = 1000;
+const TRACKING_BATCH: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct City {
+    #[serde(rename = "ref")]
+    pub city_ref: String,
+    pub name: String,
+    pub area: Option<String>,
+    pub settlement_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warehouse {
+    #[serde(rename = "ref")]
+    pub warehouse_ref: String,
+    pub city_ref: String,
+    pub number: String,
+    pub name: String,
+    pub short_address: Option<String>,
+    pub category: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiRequest<'a> {
+    api_key: &'a str,
+    model_name: &'a str,
+    called_method: &'a str,
+    method_properties: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiCity {
+    #[serde(rename = "Ref")]
+    city_ref: String,
+    description: String,
+    #[serde(default)]
+    area_description: Option<String>,
+    #[serde(default)]
+    settlement_type_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiWarehouse {
+    #[serde(rename = "Ref")]
+    warehouse_ref: String,
+    city_ref: String,
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    number: String,
+    description: String,
+    #[serde(default)]
+    short_address: Option<String>,
+    #[serde(default)]
+    category_of_warehouse: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiRef {
+    #[serde(rename = "Ref")]
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiContactPerson {
+    #[serde(rename = "Ref")]
+    contact_ref: String,
+    #[serde(default)]
+    phones: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiCounterparty {
+    #[serde(rename = "Ref")]
+    counterparty_ref: String,
+    contact_person: ApiCounterpartyContacts,
+}
+
+#[derive(Deserialize)]
+struct ApiCounterpartyContacts {
+    data: Vec<ApiRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiDocument {
+    #[serde(rename = "Ref")]
+    document_ref: String,
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    int_doc_number: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DocumentStatus {
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub number: String,
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub status_code: String,
+    pub status: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sender {
+    pub counterparty_ref: String,
+    pub contact_ref: String,
+    pub phone: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub counterparty_ref: String,
+    pub contact_ref: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParcelOptions {
+    pub weight: Decimal,
+    pub seats: usize,
+    pub declared_cost: i64,
+    pub description: String,
+    pub payer: NovaPoshtaPayer,
+}
+
+#[derive(Clone)]
+pub struct NovaPoshtaClient {
+    api: String,
+    api_key: String,
+    client: Client,
+}
+
+impl NovaPoshtaClient {
+    pub fn new(api: impl Into<String>, api_key: impl Into<String>, client: Client) -> Self {
+        Self {
+            api: api.into(),
+            api_key: api_key.into(),
+            client,
+        }
+    }
+
+    pub fn with_key(&self, api_key: impl Into<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            ..self.clone()
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        model: &str,
+        method: &str,
+        properties: serde_json::Value,
+    ) -> anyhow::Result<Vec<T>> {
+        let request = ApiRequest {
+            api_key: &self.api_key,
+            model_name: model,
+            called_method: method,
+            method_properties: properties,
+        };
+        let resp = self
+            .client
+            .post(&self.api)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?;
+        let resp: ApiResponse<T> = resp.json().await?;
+        if !resp.success {
+            return Err(anyhow::anyhow!(
+                "Nova Poshta {model}/{method}: {}",
+                resp.errors.join("; ")
+            ));
+        }
+        Ok(resp.data)
+    }
+
+    pub async fn cities(&self, page: usize, limit: usize) -> anyhow::Result<Vec<City>> {
+        let cities: Vec<ApiCity> = self
+            .call(
+                "Address",
+                "getCities",
+                serde_json::json!({ "Page": page.to_string(), "Limit": limit.to_string() }),
+            )
+            .await?;
+        Ok(cities
+            .into_iter()
+            .map(|c| City {
+                city_ref: c.city_ref,
+                name: c.description,
+                area: c.area_description.filter(|a| !a.is_empty()),
+                settlement_type: c.settlement_type_description.filter(|t| !t.is_empty()),
+            })
+            .collect())
+    }
+
+    pub async fn warehouses(&self, page: usize, limit: usize) -> anyhow::Result<Vec<Warehouse>> {
+        let warehouses: Vec<ApiWarehouse> = self
+            .call(
+                "Address",
+                "getWarehouses",
+                serde_json::json!({ "Page": page.to_string(), "Limit": limit.to_string() }),
+            )
+            .await?;
+        Ok(warehouses
+            .into_iter()
+            .map(|w| Warehouse {
+                warehouse_ref: w.warehouse_ref,
+                city_ref: w.city_ref,
+                number: w.number,
+                name: w.description,
+                short_address: w.short_address.filter(|a| !a.is_empty()),
+                category: w.category_of_warehouse.filter(|c| !c.is_empty()),
+            })
+            .collect())
+    }
+
+    pub async fn sender(&self) -> anyhow::Result<Sender> {
+        let counterparty = self
+            .call::<ApiRef>(
+                "Counterparty",
+                "getCounterparties",
+                serde_json::json!({ "CounterpartyProperty": "Sender", "Page": "1" }),
+            )
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Nova Poshta account has no sender"))?;
+        let contact = self
+            .call::<ApiContactPerson>(
+                "Counterparty",
+                "getCounterpartyContactPersons",
+                serde_json::json!({ "Ref": counterparty.value, "Page": "1" }),
+            )
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Nova Poshta sender has no contact person"))?;
+        Ok(Sender {
+            counterparty_ref: counterparty.value,
+            contact_ref: contact.contact_ref,
+            phone: contact.phones.filter(|p| !p.is_empty()),
+        })
+    }
+
+    // Для відомого телефону Нова пошта повертає наявного
+    pub async fn create_recipient(
+        &self,
+        last_name: &str,
+        first_name: &str,
+        middle_name: Option<&str>,
+        phone: &str,
+    ) -> anyhow::Result<Recipient> {
+        let counterparty = self
+            .call::<ApiCounterparty>(
+                "Counterparty",
+                "save",
+                serde_json::json!({
+                    "CounterpartyProperty": "Recipient",
+                    "CounterpartyType": "PrivatePerson",
+                    "LastName": last_name,
+                    "FirstName": first_name,
+                    "MiddleName": middle_name.unwrap_or_default(),
+                    "Phone": phone,
+                }),
+            )
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Nova Poshta returned no recipient"))?;
+        let contact = counterparty
+            .contact_person
+            .data
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Nova Poshta returned no recipient contact"))?;
+        Ok(Recipient {
+            counterparty_ref: counterparty.counterparty_ref,
+            contact_ref: contact.value,
+        })
+    }
+
+    pub async fn delete_waybill(&self, document_ref: &str) -> anyhow::Result<()> {
+        self.call::<serde_json::Value>(
+            "InternetDocument",
+            "delete",
+            serde_json::json!({ "DocumentRefs": document_ref }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn track(
+        &self,
+        documents: &[(String, String)],
+    ) -> anyhow::Result<Vec<DocumentStatus>> {
+        let documents = documents
+            .iter()
+            .map(|(number, phone)| serde_json::json!({ "DocumentNumber": number, "Phone": phone }))
+            .collect::<Vec<_>>();
+        self.call(
+            "TrackingDocument",
+            "getStatusDocuments",
+            serde_json::json!({ "Documents": documents }),
+        )
+        .await
+    }
+}
+
+pub fn api_phone(raw: &str) -> Option<String> {
+    crate::customer::normalize_phone(raw).map(|p| p.trim_start_matches('+').to_string())
+}
+
+pub fn status_for_code(code: &str) -> Option<OrderStatus> {
+    match code.trim() {
+        "4" | "5" | "6" | "7" | "8" | "41" | "101" | "104" | "112" => Some(OrderStatus::Shipped),
+        "9" | "10" | "11" | "106" => Some(OrderStatus::Delivered),
+        "102" | "103" | "105" | "108" | "111" => Some(OrderStatus::Returned),
+        _ => None,
+    }
+}
+
+// Посилка вже в дорозі — спершу підтверджуємо й відправляємо, щоб історія йшла по порядку
+pub fn status_path(current: OrderStatus, target: OrderStatus) -> Vec<OrderStatus> {
+    let mut path = Vec::new();
+    let mut at = current;
+    for step in [OrderStatus::Confirmed, OrderStatus::Shipped] {
+        if at.can_transition_to(target) {
+            break;
+        }
+        if at.can_transition_to(step) {
+            path.push(step);
+            at = step;
+        }
+    }
+    if at.can_transition_to(target) {
+        path.push(target);
+        path
+    } else {
+        Vec::new()
+    }
+}
+
+pub async fn create_waybill(
+    client: &NovaPoshtaClient,
+    settings: &NovaPoshtaSettings,
+    order: &Order,
+    parcel: &ParcelOptions,
+) -> anyhow::Result<OrderWaybill> {
+    let (Some(city_sender), Some(sender_address)) = (
+        settings.sender_city_ref.as_deref(),
+        settings.sender_warehouse_ref.as_deref(),
+    ) else {
+        return Err(anyhow::anyhow!("Nova Poshta sender branch is not set"));
+    };
+    let (Some(city_recipient), Some(recipient_address)) =
+        (order.city_ref.as_deref(), order.branch_ref.as_deref())
+    else {
+        return Err(anyhow::anyhow!("Order has no Nova Poshta branch"));
+    };
+    let recipient_phone = api_phone(&order.phone)
+        .ok_or_else(|| anyhow::anyhow!("Invalid recipient phone {}", order.phone))?;
+    let mut name = order.customer_name.split_whitespace();
+    let last_name = name.next().unwrap_or_default();
+    let first_name = name.next().unwrap_or(last_name);
+    let middle_name = name.next();
+
+    let sender = client.sender().await?;
+    let sender_phone = settings
+        .sender_phone
+        .as_deref()
+        .or(sender.phone.as_deref())
+        .and_then(api_phone)
+        .ok_or_else(|| anyhow::anyhow!("Nova Poshta sender has no phone"))?;
+    let recipient = client
+        .create_recipient(last_name, first_name, middle_name, &recipient_phone)
+        .await?;
+
+    let mut properties = serde_json::json!({
+        "PayerType": parcel.payer.api_value(),
+        "PaymentMethod": "Cash",
+        "DateTime": OffsetDateTime::now_utc()
+            .to_timezone(KYIV)
+            .format(time::macros::format_description!("[day].[month].[year]"))?,
+        "CargoType": "Parcel",
+        "Weight": parcel.weight.to_string(),
+        "ServiceType": "WarehouseWarehouse",
+        "SeatsAmount": parcel.seats.max(1).to_string(),
+        "Description": parcel.description,
+        "Cost": parcel.declared_cost.max(1).to_string(),
+        "CitySender": city_sender,
+        "Sender": sender.counterparty_ref,
+        "SenderAddress": sender_address,
+        "ContactSender": sender.contact_ref,
+        "SendersPhone": sender_phone,
+        "CityRecipient": city_recipient,
+        "Recipient": recipient.counterparty_ref,
+        "RecipientAddress": recipient_address,
+        "ContactRecipient": recipient.contact_ref,
+        "RecipientsPhone": recipient_phone,
+    });
+    if order.payment == "cod" {
+        properties["BackwardDeliveryData"] = serde_json::json!([{
+            "PayerType": "Recipient",
+            "CargoType": "Money",
+            "RedeliveryString": order.total.to_string(),
+        }]);
+    }
+    let document = client
+        .call::<ApiDocument>("InternetDocument", "save", properties)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Nova Poshta returned no waybill"))?;
+    Ok(OrderWaybill {
+        number: document.int_doc_number,
+        document_ref: document.document_ref,
+        status: None,
+        status_code: None,
+        updated_at: OffsetDateTime::now_utc().unix_timestamp(),
+    })
+}
+
+pub async fn apply_status(
+    order_repo: &dyn OrderRepository,
+    order: &Order,
+    status: &DocumentStatus,
+) -> anyhow::Result<bool> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let unchanged = order
+        .waybill
+        .as_ref()
+        .is_some_and(|w| w.status_code.as_deref() == Some(status.status_code.as_str()));
+    if !unchanged {
+        order_repo
+            .update_waybill_status(
+                order.shop_id,
+                order.id,
+                status.status.clone(),
+                status.status_code.clone(),
+                now,
+            )
+            .await?;
+    }
+    let path = status_for_code(&status.status_code)
+        .map(|target| status_path(order.status, target))
+        .unwrap_or_default();
+    for next in &path {
+        order_repo
+            .change_status(
+                order.shop_id,
+                order.id,
+                StatusChange {
+                    status: *next,
+                    author: TRACKING_AUTHOR.to_string(),
+                    comment: Some(format!("ТТН {}: {}", status.number, status.status)),
+                    created_at: now,
+                },
+            )
+            .await?;
+    }
+    Ok(!path.is_empty())
+}
+
+pub async fn track_orders(
+    client: &NovaPoshtaClient,
+    order_repo: &dyn OrderRepository,
+    api_keys: &HashMap<Uuid, String>,
+) -> anyhow::Result<RunCounts> {
+    let mut by_shop = HashMap::<Uuid, Vec<Order>>::new();
+    for order in order_repo.tracked().await? {
+        by_shop.entry(order.shop_id).or_default().push(order);
+    }
+    let (mut tracked, mut changed) = (0, 0);
+    for (shop_id, orders) in by_shop {
+        let Some(key) = api_keys.get(&shop_id) else {
+            continue;
+        };
+        let client = client.with_key(key);
+        for batch in orders.chunks(TRACKING_BATCH) {
+            let documents = batch
+                .iter()
+                .filter_map(|o| {
+                    let waybill = o.waybill.as_ref()?;
+                    Some((
+                        waybill.number.clone(),
+                        api_phone(&o.phone).unwrap_or_default(),
+                    ))
+                })
+                .collect::<Vec<_>>();
+            // Відкликаний ключ одного магазину не зупиняє відстеження інших
+            let statuses = match client.track(&documents).await {
+                Ok(statuses) => statuses,
+                Err(err) => {
+                    log::warn!("Unable to track waybills of shop {shop_id}: {err}");
+                    continue;
+                }
+            };
+            for status in statuses {
+                let order = batch.iter().find(|o| {
+                    o.waybill
+                        .as_ref()
+                        .is_some_and(|w| w.number == status.number)
+                });
+                let Some(order) = order else {
+                    continue;
+                };
+                tracked += 1;
+                match apply_status(order_repo, order, &status).await {
+                    Ok(true) => changed += 1,
+                    Ok(false) => (),
+                    Err(err) => {
+                        log::error!(
+                            "Unable to apply tracking status of order {}: {err}",
+                            order.id
+                        )
+                    }
+                }
+            }
+        }
+    }
+    Ok(RunCounts::from([
+        ("tracked".to_string(), tracked),
+        ("changed".to_string(), changed),
+    ]))
+}
+
+pub async fn refresh_directory(
+    client: &NovaPoshtaClient,
+    repo: &dyn NovaPoshtaRepository,
+) -> anyhow::Result<RunCounts> {
+    let mut cities = Vec::new();
+    for page in 1.. {
+        let items = client.cities(page, CITIES_PAGE).await?;
+        let last = items.len() < CITIES_PAGE;
+        cities.extend(items);
+        if last {
+            break;
+        }
+    }
+    let mut warehouses = Vec::new();
+    for page in 1.. {
+        let items = client.warehouses(page, WAREHOUSES_PAGE).await?;
+        let last = items.len() < WAREHOUSES_PAGE;
+        warehouses.extend(items);
+        if last {
+            break;
+        }
+    }
+    // Порожня відповідь — збій API, старий довідник лишається
+    if cities.is_empty() || warehouses.is_empty() {
+        return Err(anyhow::anyhow!("Nova Poshta returned an empty directory"));
+    }
+    let counts = RunCounts::from([
+        ("cities".to_string(), cities.len() as u64),
+        ("warehouses".to_string(), warehouses.len() as u64),
+    ]);
+    repo.replace(cities, warehouses).await?;
+    Ok(counts)
+}
+
+#[async_trait]
+pub trait NovaPoshtaRepository: Send + Sync {
+    async fn replace(&self, cities: Vec<City>, warehouses: Vec<Warehouse>) -> anyhow::Result<()>;
+    async fn search_cities(&self, query: &str, limit: usize) -> anyhow::Result<Vec<City>>;
+    async fn city(&self, city_ref: &str) -> anyhow::Result<Option<City>>;
+    async fn search_warehouses(
+        &self,
+        city_ref: &str,
+        query: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Warehouse>>;
+    async fn warehouse(&self, warehouse_ref: &str) -> anyhow::Result<Option<Warehouse>>;
+}
+
+pub struct SqliteNovaPoshtaRepository {
+    conn: Connection,
+}
+
+impl SqliteNovaPoshtaRepository {
+    pub async fn init(conn: Connection) -> Result<Self, tokio_rusqlite::Error> {
+        conn.call(|conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS np_city (
+                    ref TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    area TEXT,
+                    settlement_type TEXT,
+                    search TEXT NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS np_warehouse (
+                    ref TEXT PRIMARY KEY,
+                    city_ref TEXT NOT NULL,
+                    number TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    short_address TEXT,
+                    category TEXT,
+                    search TEXT NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS np_warehouse_city_idx ON np_warehouse(city_ref)",
+                [],
+            )?;
+            Ok(())
+        })
+        .await?;
+        Ok(Self { conn })
+    }
+}
+
+// SQLite знижує регістр лише ASCII, тож колонки пошуку знижуємо тут
+fn search_key(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+fn city_from_row(row: &rusqlite::Row) -> rusqlite::Result<City> {
+    Ok(City {
+        city_ref: row.get(0)?,
+        name: row.get(1)?,
+        area: row.get(2)?,
+        settlement_type: row.get(3)?,
+    })
+}
+
+fn warehouse_from_row(row: &rusqlite::Row) -> rusqlite::Result<Warehouse> {
+    Ok(Warehouse {
+        warehouse_ref: row.get(0)?,
+        city_ref: row.get(1)?,
+        number: row.get(2)?,
+        name: row.get(3)?,
+        short_address: row.get(4)?,
+        category: row.get(5)?,
+    })
+}
+
+#[async_trait]
+impl NovaPoshtaRepository for SqliteNovaPoshtaRepository {
+    async fn replace(&self, cities: Vec<City>, warehouses: Vec<Warehouse>) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                tx.execute("DELETE FROM np_city", [])?;
+                tx.execute("DELETE FROM np_warehouse", [])?;
+                {
+                    let mut stmt = tx.prepare(
+                        "INSERT OR REPLACE INTO np_city (ref, name, area, settlement_type, search)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                    )?;
+                    for c in &cities {
+                        stmt.execute(params![
+                            c.city_ref,
+                            c.name,
+                            c.area,
+                            c.settlement_type,
+                            search_key(&c.name)
+                        ])?;
+                    }
+                    let mut stmt = tx.prepare(
+                        "INSERT OR REPLACE INTO np_warehouse (
+                            ref, city_ref, number, name, short_address, category, search
+                        )
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    )?;
+                    for w in &warehouses {
+                        stmt.execute(params![
+                            w.warehouse_ref,
+                            w.city_ref,
+                            w.number,
+                            w.name,
+                            w.short_address,
+                            w.category,
+                            search_key(&w.name)
+                        ])?;
+                    }
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn search_cities(&self, query: &str, limit: usize) -> anyhow::Result<Vec<City>> {
+        let query = search_key(query);
+        let SqlWrapper(items) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT ref, name, area, settlement_type FROM np_city
+                     WHERE search LIKE ?1
+                     ORDER BY search LIKE ?2 DESC, length(name), name
+                     LIMIT ?3",
+                )?;
+                let items = stmt
+                    .query_map(
+                        params![format!("%{query}%"), format!("{query}%"), limit as i64],
+                        city_from_row,
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(items)
+    }
+
+    async fn city(&self, city_ref: &str) -> anyhow::Result<Option<City>> {
+        let city_ref = city_ref.to_string();
+        let SqlWrapper(item) = self
+            .conn
+            .call(move |conn| {
+                let item = conn
+                    .query_row(
+                        "SELECT ref, name, area, settlement_type FROM np_city WHERE ref = ?1",
+                        params![city_ref],
+                        city_from_row,
+                    )
+                    .optional()?;
+                Ok(SqlWrapper(item))
+            })
+            .await?;
+        Ok(item)
+    }
+
+    async fn search_warehouses(
+        &self,
+        city_ref: &str,
+        query: &str,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Warehouse>> {
+        let city_ref = city_ref.to_string();
+        let query = search_key(query);
+        let SqlWrapper(items) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT ref, city_ref, number, name, short_address, category FROM np_warehouse
+                     WHERE city_ref = ?1 AND (?2 = '' OR number = ?2 OR search LIKE ?3)
+                     ORDER BY number = ?2 DESC, CAST(number AS INTEGER), name
+                     LIMIT ?4",
+                )?;
+                let items = stmt
+                    .query_map(
+                        params![city_ref, query, format!("%{query}%"), limit as i64],
+                        warehouse_from_row,
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(items)
+    }
+
+    async fn warehouse(&self, warehouse_ref: &str) -> anyhow::Result<Option<Warehouse>> {
+        let warehouse_ref = warehouse_ref.to_string();
+        let SqlWrapper(item) = self
+            .conn
+            .call(move |conn| {
+                let item = conn
+                    .query_row(
+                        "SELECT ref, city_ref, number, name, short_address, category
+                         FROM np_warehouse WHERE ref = ?1",
+                        params![warehouse_ref],
+                        warehouse_from_row,
+                    )
+                    .optional()?;
+                Ok(SqlWrapper(item))
+            })
+            .await?;
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{NewOrder, OrderStatus, SqliteOrderRepository};
+    use rust_decimal_macros::dec;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            let Some(header_end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let body_len = text[..header_end]
+                .to_lowercase()
+                .lines()
+                .find_map(|l| {
+                    l.strip_prefix("content-length:")
+                        .map(|l| l.trim().to_string())
+                })
+                .and_then(|l| l.parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= header_end + 4 + body_len || n == 0 {
+                return text[header_end + 4..].to_string();
+            }
+        }
+    }
+
+    async fn serve(bodies: Vec<&'static str>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for body in bodies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                requests.push(read_request(&mut socket).await);
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(resp.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (format!("http://{addr}/v2.0/json/"), handle)
+    }
+
+    fn called(request: &str) -> String {
+        let request: serde_json::Value = serde_json::from_str(request).unwrap();
+        format!(
+            "{}/{}",
+            request["modelName"].as_str().unwrap(),
+            request["calledMethod"].as_str().unwrap()
+        )
+    }
+
+    #[tokio::test]
+    async fn refreshes_directory_and_searches_it() {
+        let (api, requests) = serve(vec![
+            r#"{"success": true, "data": [
+                {"Ref": "city-kyiv", "Description": "Київ", "AreaDescription": "Київська", "SettlementTypeDescription": "місто"},
+                {"Ref": "city-kyivets", "Description": "Кам'янка-Київська", "AreaDescription": "", "SettlementTypeDescription": "село"},
+                {"Ref": "city-lviv", "Description": "Львів"}
+            ], "errors": []}"#,
+            r#"{"success": true, "data": [
+                {"Ref": "wh-2", "CityRef": "city-kyiv", "Number": "2", "Description": "Відділення №2: вул. Богатирська, 11", "CategoryOfWarehouse": "Branch"},
+                {"Ref": "wh-12", "CityRef": "city-kyiv", "Number": 12, "Description": "Відділення №12: вул. Хрещатик, 1", "CategoryOfWarehouse": "Branch"},
+                {"Ref": "wh-1", "CityRef": "city-lviv", "Number": "1", "Description": "Відділення №1: вул. Городоцька, 355"}
+            ], "errors": []}"#,
+        ])
+        .await;
+        let client = NovaPoshtaClient::new(api, "", Client::new());
+        let repo = SqliteNovaPoshtaRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+
+        let counts = refresh_directory(&client, &repo).await.unwrap();
+        assert_eq!(counts["cities"], 3);
+        assert_eq!(counts["warehouses"], 3);
+        let requests = requests.await.unwrap();
+        assert_eq!(called(&requests[0]), "Address/getCities");
+        assert_eq!(called(&requests[1]), "Address/getWarehouses");
+
+        let cities = repo.search_cities("КИЇВ", 10).await.unwrap();
+        let refs = cities
+            .iter()
+            .map(|c| c.city_ref.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(refs, ["city-kyiv", "city-kyivets"]);
+        assert_eq!(cities[1].area, None);
+
+        let by_number = repo.search_warehouses("city-kyiv", "12", 10).await.unwrap();
+        assert_eq!(by_number[0].warehouse_ref, "wh-12");
+        let by_street = repo
+            .search_warehouses("city-kyiv", "богатирська", 10)
+            .await
+            .unwrap();
+        assert_eq!(by_street.len(), 1);
+        assert_eq!(by_street[0].number, "2");
+        let all = repo.search_warehouses("city-kyiv", "", 10).await.unwrap();
+        assert_eq!(
+            all.iter().map(|w| w.number.as_str()).collect::<Vec<_>>(),
+            ["2", "12"]
+        );
+        assert_eq!(
+            repo.warehouse("wh-1").await.unwrap().unwrap().city_ref,
+            "city-lviv"
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_directory_on_api_error() {
+        let (api, _) = serve(vec![
+            r#"{"success": false, "data": [], "errors": ["API key expired"]}"#,
+        ])
+        .await;
+        let client = NovaPoshtaClient::new(api, "", Client::new());
+        let repo = SqliteNovaPoshtaRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let err = refresh_directory(&client, &repo).await.unwrap_err();
+        assert!(err.to_string().contains("API key expired"));
+    }
+
+    #[tokio::test]
+    async fn creates_waybill_and_tracks_it() {
+        let (api, requests) = serve(vec![
+            r#"{"success": true, "data": [{"Ref": "sender-1"}]}"#,
+            r#"{"success": true, "data": [{"Ref": "contact-1", "Phones": "380501112233"}]}"#,
+            r#"{"success": true, "data": [{"Ref": "recipient-1", "ContactPerson": {"success": true, "data": [{"Ref": "recipient-contact-1"}]}}]}"#,
+            r#"{"success": true, "data": [{"Ref": "doc-1", "IntDocNumber": "20450000000001", "CostOnSite": 70}]}"#,
+            r#"{"success": true, "data": [{"Number": "20450000000001", "StatusCode": "9", "Status": "Відправлення отримано"}]}"#,
+        ])
+        .await;
+        let orders = SqliteOrderRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let shop_id = Uuid::new_v4();
+        let order = orders
+            .add(NewOrder {
+                shop_id,
+                customer_name: "Шевченко Тарас Григорович".to_string(),
+                phone: "050 123 45 67".to_string(),
+                email: None,
+                delivery: "nova-poshta-branch".to_string(),
+                city_name: Some("Київ".to_string()),
+                branch_name: Some("Відділення №2".to_string()),
+                payment: "cod".to_string(),
+                total: 1500,
+                items_count: 1,
+                items_json: "[]".to_string(),
+                comment: None,
+                created_at: 1,
+                customer_id: None,
+                city_ref: Some("city-kyiv".to_string()),
+                branch_ref: Some("wh-2".to_string()),
+            })
+            .await
+            .unwrap();
+        let settings = NovaPoshtaSettings {
+            api_key: Some("shop-key".to_string()),
+            sender_warehouse_ref: Some("wh-1".to_string()),
+            sender_city_ref: Some("city-lviv".to_string()),
+            ..Default::default()
+        };
+        let client = NovaPoshtaClient::new(api, "", Client::new()).with_key("shop-key");
+        let parcel = ParcelOptions {
+            weight: dec!(0.5),
+            seats: 1,
+            declared_cost: order.total,
+            description: "Спойлер".to_string(),
+            payer: NovaPoshtaPayer::Recipient,
+        };
+
+        let waybill = create_waybill(&client, &settings, &order, &parcel)
+            .await
+            .unwrap();
+        assert_eq!(waybill.number, "20450000000001");
+        assert_eq!(waybill.document_ref, "doc-1");
+        orders
+            .set_waybill(shop_id, order.id, waybill, "manager".to_string())
+            .await
+            .unwrap();
+        let events = orders.events(shop_id, order.id).await.unwrap();
+        assert_eq!(events[0].comment, "Створено ТТН 20450000000001");
+
+        let api_keys = HashMap::from([(shop_id, "shop-key".to_string())]);
+        let counts = track_orders(&client, &orders, &api_keys).await.unwrap();
+        assert_eq!(counts["tracked"], 1);
+        assert_eq!(counts["changed"], 1);
+
+        let order = orders.get(shop_id, order.id).await.unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Delivered);
+        let waybill = order.waybill.unwrap();
+        assert_eq!(waybill.status_code.as_deref(), Some("9"));
+        let history = orders.history(shop_id, order.id).await.unwrap();
+        let last = history
+            .iter()
+            .rev()
+            .take(3)
+            .map(|h| (h.to_status, h.author.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            last,
+            [
+                (OrderStatus::Delivered, TRACKING_AUTHOR),
+                (OrderStatus::Shipped, TRACKING_AUTHOR),
+                (OrderStatus::Confirmed, TRACKING_AUTHOR)
+            ]
+        );
+        assert!(orders.tracked().await.unwrap().is_empty());
+
+        let requests = requests.await.unwrap();
+        let methods = requests.iter().map(|r| called(r)).collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            [
+                "Counterparty/getCounterparties",
+                "Counterparty/getCounterpartyContactPersons",
+                "Counterparty/save",
+                "InternetDocument/save",
+                "TrackingDocument/getStatusDocuments"
+            ]
+        );
+        let recipient: serde_json::Value = serde_json::from_str(&requests[2]).unwrap();
+        assert_eq!(recipient["apiKey"], "shop-key");
+        assert_eq!(recipient["methodProperties"]["LastName"], "Шевченко");
+        assert_eq!(recipient["methodProperties"]["Phone"], "380501234567");
+        let document: serde_json::Value = serde_json::from_str(&requests[3]).unwrap();
+        let properties = &document["methodProperties"];
+        assert_eq!(properties["Sender"], "sender-1");
+        assert_eq!(properties["SendersPhone"], "380501112233");
+        assert_eq!(properties["RecipientAddress"], "wh-2");
+        assert_eq!(properties["Weight"], "0.5");
+        assert_eq!(
+            properties["BackwardDeliveryData"][0]["RedeliveryString"],
+            "1500"
+        );
+        let tracking: serde_json::Value = serde_json::from_str(&requests[4]).unwrap();
+        assert_eq!(
+            tracking["methodProperties"]["Documents"][0]["DocumentNumber"],
+            "20450000000001"
+        );
+    }
+
+    #[test]
+    fn maps_tracking_codes_to_order_statuses() {
+        assert_eq!(status_for_code("1"), None);
+        assert_eq!(status_for_code("5"), Some(OrderStatus::Shipped));
+        assert_eq!(status_for_code(" 10 "), Some(OrderStatus::Delivered));
+        assert_eq!(status_for_code("103"), Some(OrderStatus::Returned));
+        assert_eq!(
+            status_path(OrderStatus::Confirmed, OrderStatus::Returned),
+            [OrderStatus::Shipped, OrderStatus::Returned]
+        );
+        assert_eq!(
+            status_path(OrderStatus::Shipped, OrderStatus::Delivered),
+            [OrderStatus::Delivered]
+        );
+        assert_eq!(
+            status_path(OrderStatus::New, OrderStatus::Delivered),
+            [
+                OrderStatus::Confirmed,
+                OrderStatus::Shipped,
+                OrderStatus::Delivered
+            ]
+        );
+        assert_eq!(
+            status_path(OrderStatus::New, OrderStatus::Cancelled),
+            [OrderStatus::Cancelled]
+        );
+        assert!(status_path(OrderStatus::Shipped, OrderStatus::Shipped).is_empty());
+    }
+}
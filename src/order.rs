@@ -1,6 +1,8 @@
 use async_trait::async_trait;
+use rt_types::Availability;
 use rusqlite::params;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tokio_rusqlite::Connection;
 use uuid::Uuid;
 
@@ -14,6 +16,212 @@ pub struct OrderItem {
     pub quantity: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct CatalogItem {
+    pub title: String,
+    pub price: Option<usize>,
+    pub available: Availability,
+    pub visible: bool,
+}
+
+pub const MAX_QUANTITY: usize = 999;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderLineError {
+    UnknownArticle,
+    Hidden,
+    NotAvailable,
+    TooMany,
+    TotalTooLarge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrderLineIssue {
+    pub line: usize,
+    pub article: String,
+    pub error: OrderLineError,
+}
+
+// Назви й ціни беремо з каталогу, а не з запиту вітрини
+pub fn price_order_lines<F>(
+    lines: &[(String, usize)],
+    lookup: F,
+) -> Result<(Vec<OrderItem>, i64), Vec<OrderLineIssue>>
+where
+    F: Fn(&str) -> Option<CatalogItem>,
+{
+    let mut items = Vec::with_capacity(lines.len());
+    let mut issues = Vec::new();
+    for (line, (article, quantity)) in lines.iter().enumerate() {
+        let article = article.trim();
+        let error = match lookup(article).filter(|_| !article.is_empty()) {
+            None => Some(OrderLineError::UnknownArticle),
+            Some(c) if !c.visible => Some(OrderLineError::Hidden),
+            Some(c) if c.available == Availability::NotAvailable => {
+                Some(OrderLineError::NotAvailable)
+            }
+            Some(_) if *quantity > MAX_QUANTITY => Some(OrderLineError::TooMany),
+            Some(c) => {
+                items.push(OrderItem {
+                    article: article.to_string(),
+                    title: c.title,
+                    price: c.price,
+                    quantity: (*quantity).max(1),
+                });
+                None
+            }
+        };
+        if let Some(error) = error {
+            issues.push(OrderLineIssue {
+                line,
+                article: article.to_string(),
+                error,
+            });
+        }
+    }
+    if !issues.is_empty() {
+        return Err(issues);
+    }
+    let mut total = 0i64;
+    for (line, item) in items.iter().enumerate() {
+        total = line_total(item)
+            .and_then(|t| total.checked_add(t))
+            .ok_or_else(|| {
+                vec![OrderLineIssue {
+                    line,
+                    article: item.article.clone(),
+                    error: OrderLineError::TotalTooLarge,
+                }]
+            })?;
+    }
+    Ok((items, total))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    New,
+    Confirmed,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Returned,
+}
+
+impl OrderStatus {
+    pub const ALL: [OrderStatus; 6] = [
+        OrderStatus::New,
+        OrderStatus::Confirmed,
+        OrderStatus::Shipped,
+        OrderStatus::Delivered,
+        OrderStatus::Cancelled,
+        OrderStatus::Returned,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "new",
+            OrderStatus::Confirmed => "confirmed",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Returned => "returned",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "Нове",
+            OrderStatus::Confirmed => "Підтверджене",
+            OrderStatus::Shipped => "Відправлене",
+            OrderStatus::Delivered => "Доставлене",
+            OrderStatus::Cancelled => "Скасоване",
+            OrderStatus::Returned => "Повернення",
+        }
+    }
+
+    pub fn next(&self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::New => &[OrderStatus::Confirmed, OrderStatus::Cancelled],
+            OrderStatus::Confirmed => &[OrderStatus::Shipped, OrderStatus::Cancelled],
+            OrderStatus::Shipped => &[OrderStatus::Delivered, OrderStatus::Returned],
+            OrderStatus::Delivered => &[OrderStatus::Returned],
+            OrderStatus::Cancelled | OrderStatus::Returned => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, to: OrderStatus) -> bool {
+        self.next().contains(&to)
+    }
+
+    pub fn items_editable(&self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::Confirmed)
+    }
+
+    pub fn shippable(&self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::Confirmed)
+    }
+}
+
+impl std::str::FromStr for OrderStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "new" => Ok(OrderStatus::New),
+            "confirmed" => Ok(OrderStatus::Confirmed),
+            "shipped" => Ok(OrderStatus::Shipped),
+            "delivered" => Ok(OrderStatus::Delivered),
+            "cancelled" => Ok(OrderStatus::Cancelled),
+            "returned" => Ok(OrderStatus::Returned),
+            _ => Err(anyhow::anyhow!("Unknown order status {value}")),
+        }
+    }
+}
+
+impl std::fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum OrderError {
+    NotFound(i64),
+    InvalidTransition { from: OrderStatus, to: OrderStatus },
+    NotEditable(OrderStatus),
+    NotShippable(OrderStatus),
+    WaybillExists(i64),
+}
+
+impl std::fmt::Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderError::NotFound(id) => write!(f, "Order {id} not found"),
+            OrderError::InvalidTransition { from, to } => {
+                write!(f, "Unable to change order status from {from} to {to}")
+            }
+            OrderError::NotEditable(status) => {
+                write!(f, "Items of order in status {status} cannot be edited")
+            }
+            OrderError::NotShippable(status) => {
+                write!(f, "Order in status {status} cannot be shipped")
+            }
+            OrderError::WaybillExists(id) => write!(f, "Order {id} already has a waybill"),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+pub fn order_error(err: &anyhow::Error) -> Option<&OrderError> {
+    match err.downcast_ref::<tokio_rusqlite::Error>()? {
+        tokio_rusqlite::Error::Other(err) => err.downcast_ref(),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Order {
     pub id: i64,
@@ -29,14 +237,140 @@ pub struct Order {
     pub items_count: usize,
     pub items_json: String,
     pub comment: Option<String>,
+    pub status: OrderStatus,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub customer_id: Option<i64>,
+    pub city_ref: Option<String>,
+    pub branch_ref: Option<String>,
+    pub waybill: Option<OrderWaybill>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderWaybill {
+    pub number: String,
+    pub document_ref: String,
+    pub status: Option<String>,
+    pub status_code: Option<String>,
+    pub updated_at: i64,
+}
+
+impl Order {
+    pub fn items(&self) -> anyhow::Result<Vec<OrderItem>> {
+        Ok(serde_json::from_str(&self.items_json)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderHistoryEntry {
+    pub id: i64,
+    pub order_id: i64,
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub author: String,
+    pub comment: Option<String>,
     pub created_at: i64,
 }
 
+#[derive(Debug, Clone)]
+pub struct OrderEvent {
+    pub id: i64,
+    pub order_id: i64,
+    pub author: String,
+    pub comment: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusChange {
+    pub status: OrderStatus,
+    pub author: String,
+    pub comment: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderFilter {
+    pub status: Option<OrderStatus>,
+    pub query: Option<String>,
+    pub created_from: Option<i64>,
+    pub created_to: Option<i64>,
+    pub customer_id: Option<i64>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl Default for OrderFilter {
+    fn default() -> Self {
+        Self {
+            status: None,
+            query: None,
+            created_from: None,
+            created_to: None,
+            customer_id: None,
+            limit: 50,
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderPage {
+    pub items: Vec<Order>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CustomerOrderStats {
+    pub orders: usize,
+    pub total: i64,
+    pub last_order_at: Option<i64>,
+}
+
 #[async_trait]
 pub trait OrderRepository: Send + Sync {
     async fn add(&self, item: NewOrder) -> anyhow::Result<Order>;
-    async fn list_by_shop(&self, shop_id: Uuid) -> anyhow::Result<Vec<Order>>;
+    async fn get(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Option<Order>>;
+    async fn list_by_shop(&self, shop_id: Uuid, filter: OrderFilter) -> anyhow::Result<OrderPage>;
+    async fn change_status(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        change: StatusChange,
+    ) -> anyhow::Result<Order>;
+    async fn update_items(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        items: Vec<OrderItem>,
+        author: String,
+        updated_at: i64,
+    ) -> anyhow::Result<Order>;
+    async fn history(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Vec<OrderHistoryEntry>>;
+    async fn events(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Vec<OrderEvent>>;
     async fn remove(&self, shop_id: Uuid, id: i64) -> anyhow::Result<()>;
+    // Без скасованих і повернених замовлень
+    async fn sold_quantities(&self, shop_id: Uuid) -> anyhow::Result<HashMap<String, usize>>;
+    async fn customer_stats(
+        &self,
+        shop_id: Uuid,
+    ) -> anyhow::Result<HashMap<i64, CustomerOrderStats>>;
+    async fn set_waybill(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        waybill: OrderWaybill,
+        author: String,
+    ) -> anyhow::Result<Order>;
+    async fn update_waybill_status(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        status: String,
+        status_code: String,
+        updated_at: i64,
+    ) -> anyhow::Result<()>;
+    async fn tracked(&self) -> anyhow::Result<Vec<Order>>;
 }
 
 #[derive(Debug, Clone)]
@@ -54,12 +388,51 @@ pub struct NewOrder {
     pub items_json: String,
     pub comment: Option<String>,
     pub created_at: i64,
+    pub customer_id: Option<i64>,
+    pub city_ref: Option<String>,
+    pub branch_ref: Option<String>,
+}
+
+fn line_total(item: &OrderItem) -> Option<i64> {
+    i64::try_from(item.price.unwrap_or(0))
+        .ok()?
+        .checked_mul(i64::try_from(item.quantity).ok()?)
+}
+
+pub fn items_total(items: &[OrderItem]) -> Option<i64> {
+    items
+        .iter()
+        .try_fold(0i64, |total, item| total.checked_add(line_total(item)?))
 }
 
 pub struct SqliteOrderRepository {
     conn: Connection,
 }
 
+fn ensure_columns(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    // Колонки життєвого циклу для старих баз. Помилки про дублювання ігноруємо.
+    let alters = [
+        "ALTER TABLE shop_order ADD COLUMN status TEXT NOT NULL DEFAULT 'new'",
+        "ALTER TABLE shop_order ADD COLUMN updated_at INTEGER",
+        "ALTER TABLE shop_order ADD COLUMN customer_id INTEGER",
+        "ALTER TABLE shop_order ADD COLUMN city_ref TEXT",
+        "ALTER TABLE shop_order ADD COLUMN branch_ref TEXT",
+        "ALTER TABLE shop_order ADD COLUMN waybill_number TEXT",
+        "ALTER TABLE shop_order ADD COLUMN waybill_ref TEXT",
+        "ALTER TABLE shop_order ADD COLUMN waybill_status TEXT",
+        "ALTER TABLE shop_order ADD COLUMN waybill_status_code TEXT",
+        "ALTER TABLE shop_order ADD COLUMN waybill_updated_at INTEGER",
+    ];
+    for sql in alters {
+        let _ = conn.execute(sql, []);
+    }
+    conn.execute(
+        "UPDATE shop_order SET updated_at = created_at WHERE updated_at IS NULL",
+        [],
+    )?;
+    Ok(())
+}
+
 impl SqliteOrderRepository {
     pub async fn init(conn: Connection) -> Result<Self, tokio_rusqlite::Error> {
         conn.call(|conn| {
@@ -78,10 +451,57 @@ impl SqliteOrderRepository {
                     items_count INTEGER NOT NULL,
                     items_json TEXT NOT NULL,
                     comment TEXT,
+                    status TEXT NOT NULL DEFAULT 'new',
+                    created_at INTEGER NOT NULL,
+                    updated_at INTEGER
+                )",
+                [],
+            )?;
+            ensure_columns(conn)?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS shop_order_shop_status_idx
+                 ON shop_order(shop_id, status, created_at)",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS shop_order_customer_idx
+                 ON shop_order(shop_id, customer_id)",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS shop_order_status_history (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    order_id INTEGER NOT NULL,
+                    shop_id TEXT NOT NULL,
+                    from_status TEXT,
+                    to_status TEXT NOT NULL,
+                    author TEXT NOT NULL,
+                    comment TEXT,
                     created_at INTEGER NOT NULL
                 )",
                 [],
             )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS shop_order_status_history_order_idx
+                 ON shop_order_status_history(order_id)",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS shop_order_event (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    order_id INTEGER NOT NULL,
+                    shop_id TEXT NOT NULL,
+                    author TEXT NOT NULL,
+                    comment TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS shop_order_event_order_idx
+                 ON shop_order_event(order_id)",
+                [],
+            )?;
             Ok(())
         })
         .await?;
@@ -89,21 +509,133 @@ impl SqliteOrderRepository {
     }
 }
 
+const ORDER_COLUMNS: &str = "id, shop_id, customer_name, phone, email, delivery,
+    city_name, branch_name, payment, total, items_count,
+    items_json, comment, status, created_at, updated_at, customer_id,
+    city_ref, branch_ref, waybill_number, waybill_ref, waybill_status,
+    waybill_status_code, waybill_updated_at";
+
+fn order_from_row(row: &rusqlite::Row) -> rusqlite::Result<Order> {
+    let shop_id: String = row.get(1)?;
+    let items_count: i64 = row.get(10)?;
+    let status: String = row.get(13)?;
+    let created_at: i64 = row.get(14)?;
+    let updated_at: Option<i64> = row.get(15)?;
+    let waybill_number: Option<String> = row.get(19)?;
+    let waybill_ref: Option<String> = row.get(20)?;
+    let waybill = match (waybill_number, waybill_ref) {
+        (Some(number), Some(document_ref)) => Some(OrderWaybill {
+            number,
+            document_ref,
+            status: row.get(21)?,
+            status_code: row.get(22)?,
+            updated_at: row.get::<_, Option<i64>>(23)?.unwrap_or(created_at),
+        }),
+        _ => None,
+    };
+    Ok(Order {
+        id: row.get(0)?,
+        shop_id: Uuid::parse_str(&shop_id).unwrap_or(Uuid::nil()),
+        customer_name: row.get(2)?,
+        phone: row.get(3)?,
+        email: row.get(4)?,
+        delivery: row.get(5)?,
+        city_name: row.get(6)?,
+        branch_name: row.get(7)?,
+        payment: row.get(8)?,
+        total: row.get(9)?,
+        items_count: items_count.max(0) as usize,
+        items_json: row.get(11)?,
+        comment: row.get(12)?,
+        status: status.parse().unwrap_or(OrderStatus::New),
+        created_at,
+        updated_at: updated_at.unwrap_or(created_at),
+        customer_id: row.get(16)?,
+        city_ref: row.get(17)?,
+        branch_ref: row.get(18)?,
+        waybill,
+    })
+}
+
+fn select_order(
+    conn: &rusqlite::Connection,
+    shop_id: &str,
+    id: i64,
+) -> rusqlite::Result<Option<Order>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ORDER_COLUMNS} FROM shop_order WHERE shop_id = ?1 AND id = ?2"
+    ))?;
+    let mut rows = stmt.query_map(params![shop_id, id], order_from_row)?;
+    rows.next().transpose()
+}
+
+fn insert_history(
+    conn: &rusqlite::Connection,
+    shop_id: &str,
+    order_id: i64,
+    from: Option<OrderStatus>,
+    change: &StatusChange,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO shop_order_status_history (
+            order_id, shop_id, from_status, to_status, author, comment, created_at
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            order_id,
+            shop_id,
+            from.map(|s| s.as_str()),
+            change.status.as_str(),
+            change.author,
+            change.comment,
+            change.created_at
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_event(
+    conn: &rusqlite::Connection,
+    shop_id: &str,
+    order_id: i64,
+    author: &str,
+    comment: &str,
+    created_at: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO shop_order_event (order_id, shop_id, author, comment, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![order_id, shop_id, author, comment, created_at],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn like_contains(query: &str) -> String {
+    let escaped = query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
 #[async_trait]
 impl OrderRepository for SqliteOrderRepository {
     async fn add(&self, item: NewOrder) -> anyhow::Result<Order> {
         let SqlWrapper(out) = self
             .conn
             .call(move |conn| {
-                conn.execute(
+                let tx = conn.transaction()?;
+                let shop_id = item.shop_id.to_string();
+                tx.execute(
                     "INSERT INTO shop_order (
                         shop_id, customer_name, phone, email, delivery,
                         city_name, branch_name, payment, total, items_count,
-                        items_json, comment, created_at
+                        items_json, comment, status, created_at, updated_at, customer_id,
+                        city_ref, branch_ref
                     )
-                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?14, ?15, ?16, ?17)",
                     params![
-                        item.shop_id.to_string(),
+                        shop_id,
                         item.customer_name,
                         item.phone,
                         item.email,
@@ -115,10 +647,27 @@ impl OrderRepository for SqliteOrderRepository {
                         item.items_count as i64,
                         item.items_json,
                         item.comment,
-                        item.created_at
+                        OrderStatus::New.as_str(),
+                        item.created_at,
+                        item.customer_id,
+                        item.city_ref,
+                        item.branch_ref
                     ],
                 )?;
-                let id = conn.last_insert_rowid();
+                let id = tx.last_insert_rowid();
+                insert_history(
+                    &tx,
+                    &shop_id,
+                    id,
+                    None,
+                    &StatusChange {
+                        status: OrderStatus::New,
+                        author: "site".to_string(),
+                        comment: None,
+                        created_at: item.created_at,
+                    },
+                )?;
+                tx.commit()?;
                 Ok(SqlWrapper(Order {
                     id,
                     shop_id: item.shop_id,
@@ -133,55 +682,220 @@ impl OrderRepository for SqliteOrderRepository {
                     items_count: item.items_count,
                     items_json: item.items_json,
                     comment: item.comment,
+                    status: OrderStatus::New,
                     created_at: item.created_at,
+                    updated_at: item.created_at,
+                    customer_id: item.customer_id,
+                    city_ref: item.city_ref,
+                    branch_ref: item.branch_ref,
+                    waybill: None,
                 }))
             })
             .await?;
         Ok(out)
     }
 
-    async fn list_by_shop(&self, shop_id: Uuid) -> anyhow::Result<Vec<Order>> {
+    async fn get(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Option<Order>> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| Ok(SqlWrapper(select_order(conn, &shop_id.to_string(), id)?)))
+            .await?;
+        Ok(out)
+    }
+
+    async fn list_by_shop(&self, shop_id: Uuid, filter: OrderFilter) -> anyhow::Result<OrderPage> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let mut clause = String::from("WHERE shop_id = ?");
+                let mut args: Vec<rusqlite::types::Value> = vec![shop_id.to_string().into()];
+                if let Some(status) = filter.status {
+                    clause.push_str(" AND status = ?");
+                    args.push(status.as_str().to_string().into());
+                }
+                if let Some(from) = filter.created_from {
+                    clause.push_str(" AND created_at >= ?");
+                    args.push(from.into());
+                }
+                if let Some(to) = filter.created_to {
+                    clause.push_str(" AND created_at < ?");
+                    args.push(to.into());
+                }
+                if let Some(customer_id) = filter.customer_id {
+                    clause.push_str(" AND customer_id = ?");
+                    args.push(customer_id.into());
+                }
+                if let Some(query) = filter.query.as_ref().map(|q| q.trim()).filter(|q| !q.is_empty()) {
+                    let pattern = like_contains(&query.to_lowercase());
+                    clause.push_str(
+                        " AND (lower(customer_name) LIKE ? ESCAPE '\\' OR phone LIKE ? ESCAPE '\\'
+                         OR lower(email) LIKE ? ESCAPE '\\' OR CAST(id AS TEXT) = ?)",
+                    );
+                    args.push(pattern.clone().into());
+                    args.push(pattern.clone().into());
+                    args.push(pattern.into());
+                    args.push(query.to_string().into());
+                }
+                let total: i64 = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM shop_order {clause}"),
+                    rusqlite::params_from_iter(args.iter()),
+                    |row| row.get(0),
+                )?;
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {ORDER_COLUMNS} FROM shop_order {clause}
+                     ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?"
+                ))?;
+                args.push((filter.limit as i64).into());
+                args.push((filter.offset as i64).into());
+                let items = stmt
+                    .query_map(rusqlite::params_from_iter(args.iter()), order_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(OrderPage {
+                    items,
+                    total: total.max(0) as usize,
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn change_status(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        change: StatusChange,
+    ) -> anyhow::Result<Order> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let shop_id = shop_id.to_string();
+                let current = select_order(&tx, &shop_id, id)?
+                    .ok_or_else(|| tokio_rusqlite::Error::Other(Box::new(OrderError::NotFound(id))))?;
+                if !current.status.can_transition_to(change.status) {
+                    return Err(tokio_rusqlite::Error::Other(Box::new(
+                        OrderError::InvalidTransition {
+                            from: current.status,
+                            to: change.status,
+                        },
+                    )));
+                }
+                tx.execute(
+                    "UPDATE shop_order SET status = ?1, updated_at = ?2 WHERE shop_id = ?3 AND id = ?4",
+                    params![change.status.as_str(), change.created_at, shop_id, id],
+                )?;
+                insert_history(&tx, &shop_id, id, Some(current.status), &change)?;
+                tx.commit()?;
+                Ok(SqlWrapper(Order {
+                    status: change.status,
+                    updated_at: change.created_at,
+                    ..current
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn update_items(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        items: Vec<OrderItem>,
+        author: String,
+        updated_at: i64,
+    ) -> anyhow::Result<Order> {
+        let items_json = serde_json::to_string(&items)?;
+        let total =
+            items_total(&items).ok_or_else(|| anyhow::anyhow!("Order total is too large"))?;
+        let items_count = items.len();
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let shop_id = shop_id.to_string();
+                let current = select_order(&tx, &shop_id, id)?
+                    .ok_or_else(|| tokio_rusqlite::Error::Other(Box::new(OrderError::NotFound(id))))?;
+                if !current.status.items_editable() {
+                    return Err(tokio_rusqlite::Error::Other(Box::new(
+                        OrderError::NotEditable(current.status),
+                    )));
+                }
+                tx.execute(
+                    "UPDATE shop_order
+                     SET items_json = ?1, items_count = ?2, total = ?3, updated_at = ?4
+                     WHERE shop_id = ?5 AND id = ?6",
+                    params![items_json, items_count as i64, total, updated_at, shop_id, id],
+                )?;
+                insert_event(
+                    &tx,
+                    &shop_id,
+                    id,
+                    &author,
+                    &format!("Позиції змінено: {} → {} грн", current.total, total),
+                    updated_at,
+                )?;
+                tx.commit()?;
+                Ok(SqlWrapper(Order {
+                    total,
+                    items_count,
+                    items_json,
+                    updated_at,
+                    ..current
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn history(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Vec<OrderHistoryEntry>> {
         let SqlWrapper(items) = self
             .conn
             .call(move |conn| {
                 let mut stmt = conn.prepare(
-                    "SELECT id, shop_id, customer_name, phone, email, delivery,
-                        city_name, branch_name, payment, total, items_count,
-                        items_json, comment, created_at
-                     FROM shop_order WHERE shop_id = ?1 ORDER BY created_at DESC",
+                    "SELECT id, order_id, from_status, to_status, author, comment, created_at
+                     FROM shop_order_status_history
+                     WHERE shop_id = ?1 AND order_id = ?2
+                     ORDER BY created_at ASC, id ASC",
                 )?;
                 let items = stmt
-                    .query_map(params![shop_id.to_string()], |row| {
-                        let id: i64 = row.get(0)?;
-                        let shop_id: String = row.get(1)?;
-                        let customer_name: String = row.get(2)?;
-                        let phone: String = row.get(3)?;
-                        let email: Option<String> = row.get(4)?;
-                        let delivery: String = row.get(5)?;
-                        let city_name: Option<String> = row.get(6)?;
-                        let branch_name: Option<String> = row.get(7)?;
-                        let payment: String = row.get(8)?;
-                        let total: i64 = row.get(9)?;
-                        let items_count: i64 = row.get(10)?;
-                        let items_json: String = row.get(11)?;
-                        let comment: Option<String> = row.get(12)?;
-                        let created_at: i64 = row.get(13)?;
-                        let shop_id = Uuid::parse_str(&shop_id).unwrap_or(Uuid::nil());
-                        Ok(Order {
-                            id,
-                            shop_id,
-                            customer_name,
-                            phone,
-                            email,
-                            delivery,
-                            city_name,
-                            branch_name,
-                            payment,
-                            total,
-                            items_count: items_count.max(0) as usize,
-                            items_json,
-                            comment,
-                            created_at,
+                    .query_map(params![shop_id.to_string(), id], |row| {
+                        let from_status: Option<String> = row.get(2)?;
+                        let to_status: String = row.get(3)?;
+                        Ok(OrderHistoryEntry {
+                            id: row.get(0)?,
+                            order_id: row.get(1)?,
+                            from_status: from_status.and_then(|s| s.parse().ok()),
+                            to_status: to_status.parse().unwrap_or(OrderStatus::New),
+                            author: row.get(4)?,
+                            comment: row.get(5)?,
+                            created_at: row.get(6)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(items)
+    }
+
+    async fn events(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Vec<OrderEvent>> {
+        let SqlWrapper(items) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, order_id, author, comment, created_at
+                     FROM shop_order_event
+                     WHERE shop_id = ?1 AND order_id = ?2
+                     ORDER BY created_at ASC, id ASC",
+                )?;
+                let items = stmt
+                    .query_map(params![shop_id.to_string(), id], |row| {
+                        Ok(OrderEvent {
+                            id: row.get(0)?,
+                            order_id: row.get(1)?,
+                            author: row.get(2)?,
+                            comment: row.get(3)?,
+                            created_at: row.get(4)?,
                         })
                     })?
                     .collect::<Result<Vec<_>, _>>()?;
@@ -194,13 +908,392 @@ impl OrderRepository for SqliteOrderRepository {
     async fn remove(&self, shop_id: Uuid, id: i64) -> anyhow::Result<()> {
         self.conn
             .call(move |conn| {
-                conn.execute(
+                let tx = conn.transaction()?;
+                tx.execute(
                     "DELETE FROM shop_order WHERE shop_id = ?1 AND id = ?2",
                     params![shop_id.to_string(), id],
                 )?;
+                tx.execute(
+                    "DELETE FROM shop_order_status_history WHERE shop_id = ?1 AND order_id = ?2",
+                    params![shop_id.to_string(), id],
+                )?;
+                tx.execute(
+                    "DELETE FROM shop_order_event WHERE shop_id = ?1 AND order_id = ?2",
+                    params![shop_id.to_string(), id],
+                )?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn sold_quantities(&self, shop_id: Uuid) -> anyhow::Result<HashMap<String, usize>> {
+        let SqlWrapper(rows) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT items_json FROM shop_order
+                     WHERE shop_id = ?1 AND status NOT IN (?2, ?3)",
+                )?;
+                let rows = stmt
+                    .query_map(
+                        params![
+                            shop_id.to_string(),
+                            OrderStatus::Cancelled.as_str(),
+                            OrderStatus::Returned.as_str()
+                        ],
+                        |row| row.get::<_, String>(0),
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(rows))
+            })
+            .await?;
+        let mut sold = HashMap::new();
+        for items_json in rows {
+            let items = serde_json::from_str::<Vec<OrderItem>>(&items_json).unwrap_or_default();
+            for item in items {
+                let article = item.article.trim().to_lowercase();
+                if !article.is_empty() {
+                    *sold.entry(article).or_default() += item.quantity;
+                }
+            }
+        }
+        Ok(sold)
+    }
+
+    async fn customer_stats(
+        &self,
+        shop_id: Uuid,
+    ) -> anyhow::Result<HashMap<i64, CustomerOrderStats>> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT customer_id, COUNT(*), SUM(total), MAX(created_at)
+                     FROM shop_order
+                     WHERE shop_id = ?1 AND customer_id IS NOT NULL
+                     GROUP BY customer_id",
+                )?;
+                let stats = stmt
+                    .query_map(params![shop_id.to_string()], |row| {
+                        let orders: i64 = row.get(1)?;
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            CustomerOrderStats {
+                                orders: orders.max(0) as usize,
+                                total: row.get::<_, Option<i64>>(2)?.unwrap_or(0),
+                                last_order_at: row.get(3)?,
+                            },
+                        ))
+                    })?
+                    .collect::<Result<HashMap<_, _>, _>>()?;
+                Ok(SqlWrapper(stats))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn set_waybill(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        waybill: OrderWaybill,
+        author: String,
+    ) -> anyhow::Result<Order> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let shop_id = shop_id.to_string();
+                let current = select_order(&tx, &shop_id, id)?
+                    .ok_or_else(|| tokio_rusqlite::Error::Other(Box::new(OrderError::NotFound(id))))?;
+                if !current.status.shippable() {
+                    return Err(tokio_rusqlite::Error::Other(Box::new(
+                        OrderError::NotShippable(current.status),
+                    )));
+                }
+                let changed = tx.execute(
+                    "UPDATE shop_order
+                     SET waybill_number = ?1, waybill_ref = ?2, waybill_status = ?3,
+                         waybill_status_code = ?4, waybill_updated_at = ?5, updated_at = ?5
+                     WHERE shop_id = ?6 AND id = ?7 AND waybill_number IS NULL",
+                    params![
+                        waybill.number,
+                        waybill.document_ref,
+                        waybill.status,
+                        waybill.status_code,
+                        waybill.updated_at,
+                        shop_id,
+                        id
+                    ],
+                )?;
+                if changed == 0 {
+                    return Err(tokio_rusqlite::Error::Other(Box::new(
+                        OrderError::WaybillExists(id),
+                    )));
+                }
+                insert_event(
+                    &tx,
+                    &shop_id,
+                    id,
+                    &author,
+                    &format!("Створено ТТН {}", waybill.number),
+                    waybill.updated_at,
+                )?;
+                tx.commit()?;
+                Ok(SqlWrapper(Order {
+                    updated_at: waybill.updated_at,
+                    waybill: Some(waybill),
+                    ..current
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn update_waybill_status(
+        &self,
+        shop_id: Uuid,
+        id: i64,
+        status: String,
+        status_code: String,
+        updated_at: i64,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE shop_order
+                     SET waybill_status = ?1, waybill_status_code = ?2, waybill_updated_at = ?3
+                     WHERE shop_id = ?4 AND id = ?5",
+                    params![status, status_code, updated_at, shop_id.to_string(), id],
+                )?;
                 Ok(())
             })
             .await?;
         Ok(())
     }
+
+    async fn tracked(&self) -> anyhow::Result<Vec<Order>> {
+        let SqlWrapper(items) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {ORDER_COLUMNS} FROM shop_order
+                     WHERE waybill_number IS NOT NULL AND status IN (?1, ?2, ?3)
+                     ORDER BY id"
+                ))?;
+                let items = stmt
+                    .query_map(
+                        params![
+                            OrderStatus::New.as_str(),
+                            OrderStatus::Confirmed.as_str(),
+                            OrderStatus::Shipped.as_str()
+                        ],
+                        order_from_row,
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        order_error, price_order_lines, CatalogItem, NewOrder, OrderError, OrderFilter, OrderItem,
+        OrderLineError, OrderRepository, OrderStatus, OrderWaybill, SqliteOrderRepository,
+        StatusChange,
+    };
+    use rt_types::Availability;
+    use tokio_rusqlite::Connection;
+    use uuid::Uuid;
+
+    fn catalog(article: &str) -> Option<CatalogItem> {
+        let (price, available, visible) = match article {
+            "A1" => (Some(1200), Availability::Available, true),
+            "A2" => (None, Availability::OnOrder, true),
+            "HIDDEN" => (Some(10), Availability::Available, false),
+            "GONE" => (Some(10), Availability::NotAvailable, true),
+            _ => return None,
+        };
+        Some(CatalogItem {
+            title: format!("Product {article}"),
+            price,
+            available,
+            visible,
+        })
+    }
+
+    #[test]
+    fn prices_lines_from_catalog() {
+        let lines = vec![("A1".to_string(), 2), (" A2 ".to_string(), 0)];
+        let (items, total) = price_order_lines(&lines, catalog).expect("valid order");
+        assert_eq!(total, 2400);
+        assert_eq!(items[0].title, "Product A1");
+        assert_eq!(items[1].article, "A2");
+        assert_eq!(items[1].quantity, 1);
+        assert_eq!(items[1].price, None);
+    }
+
+    #[test]
+    fn rejects_huge_quantities_and_totals() {
+        let lines = vec![("A1".to_string(), super::MAX_QUANTITY + 1)];
+        let issues = price_order_lines(&lines, catalog).expect_err("too many");
+        assert_eq!(issues[0].error, OrderLineError::TooMany);
+
+        let expensive = |_: &str| {
+            Some(CatalogItem {
+                title: "Product".to_string(),
+                price: Some(usize::MAX / 2),
+                available: Availability::Available,
+                visible: true,
+            })
+        };
+        let lines = vec![("A1".to_string(), 1), ("A2".to_string(), 3)];
+        let issues = price_order_lines(&lines, expensive).expect_err("overflow");
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 1);
+        assert_eq!(issues[0].error, OrderLineError::TotalTooLarge);
+        let item = |quantity| OrderItem {
+            article: "A1".to_string(),
+            title: "Product".to_string(),
+            price: Some(i64::MAX as usize),
+            quantity,
+        };
+        assert_eq!(super::items_total(&[item(1)]), Some(i64::MAX));
+        assert_eq!(super::items_total(&[item(1), item(1)]), None);
+    }
+
+    #[test]
+    fn reports_every_failed_line() {
+        let lines = vec![
+            ("A1".to_string(), 1),
+            ("NOPE".to_string(), 1),
+            ("HIDDEN".to_string(), 1),
+            ("GONE".to_string(), 1),
+            ("".to_string(), 1),
+        ];
+        let issues = price_order_lines(&lines, catalog).expect_err("invalid order");
+        let errors = issues.iter().map(|i| (i.line, i.error)).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (1, OrderLineError::UnknownArticle),
+                (2, OrderLineError::Hidden),
+                (3, OrderLineError::NotAvailable),
+                (4, OrderLineError::UnknownArticle),
+            ]
+        );
+    }
+
+    #[test]
+    fn status_transitions_follow_lifecycle() {
+        assert!(OrderStatus::New.can_transition_to(OrderStatus::Confirmed));
+        assert!(OrderStatus::Confirmed.can_transition_to(OrderStatus::Shipped));
+        assert!(OrderStatus::Shipped.can_transition_to(OrderStatus::Delivered));
+        assert!(OrderStatus::Delivered.can_transition_to(OrderStatus::Returned));
+        assert!(!OrderStatus::New.can_transition_to(OrderStatus::Delivered));
+        assert!(!OrderStatus::Cancelled.can_transition_to(OrderStatus::New));
+        assert!(!OrderStatus::Shipped.items_editable());
+        for status in OrderStatus::ALL {
+            assert_eq!(status.as_str().parse::<OrderStatus>().ok(), Some(status));
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_item_edits_out_of_status_history() {
+        let repo = SqliteOrderRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let shop_id = Uuid::new_v4();
+        let mut ids = Vec::new();
+        for name in ["100% Ivan", "Petro"] {
+            let order = repo
+                .add(NewOrder {
+                    shop_id,
+                    customer_name: name.to_string(),
+                    phone: "380501234567".to_string(),
+                    email: None,
+                    delivery: "pickup".to_string(),
+                    city_name: None,
+                    branch_name: None,
+                    payment: "cod".to_string(),
+                    total: 100,
+                    items_count: 1,
+                    items_json: "[]".to_string(),
+                    comment: None,
+                    created_at: 1,
+                    customer_id: None,
+                    city_ref: None,
+                    branch_ref: None,
+                })
+                .await
+                .unwrap();
+            ids.push(order.id);
+        }
+        let items = vec![OrderItem {
+            article: "A1".to_string(),
+            title: "Product".to_string(),
+            price: Some(250),
+            quantity: 2,
+        }];
+        let order = repo
+            .update_items(shop_id, ids[0], items, "manager".to_string(), 2)
+            .await
+            .unwrap();
+        assert_eq!(order.total, 500);
+        assert_eq!(repo.history(shop_id, ids[0]).await.unwrap().len(), 1);
+        let events = repo.events(shop_id, ids[0]).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].author, "manager");
+
+        let search = |query: &str| OrderFilter {
+            query: Some(query.to_string()),
+            ..OrderFilter::default()
+        };
+        let found = repo.list_by_shop(shop_id, search("%")).await.unwrap();
+        assert_eq!(found.items.iter().map(|o| o.id).collect::<Vec<_>>(), [ids[0]]);
+        assert_eq!(repo.list_by_shop(shop_id, search("_")).await.unwrap().total, 0);
+
+        let waybill = |number: &str| OrderWaybill {
+            number: number.to_string(),
+            document_ref: format!("ref-{number}"),
+            status: None,
+            status_code: None,
+            updated_at: 3,
+        };
+        repo.set_waybill(shop_id, ids[0], waybill("1"), "manager".to_string())
+            .await
+            .unwrap();
+        let err = repo
+            .set_waybill(shop_id, ids[0], waybill("2"), "manager".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(order_error(&err), Some(OrderError::WaybillExists(_))));
+        let order = repo.get(shop_id, ids[0]).await.unwrap().unwrap();
+        assert_eq!(order.waybill.map(|w| w.number), Some("1".to_string()));
+        repo.change_status(
+            shop_id,
+            ids[1],
+            StatusChange {
+                status: OrderStatus::Cancelled,
+                author: "manager".to_string(),
+                comment: None,
+                created_at: 3,
+            },
+        )
+        .await
+        .unwrap();
+        let err = repo
+            .set_waybill(shop_id, ids[1], waybill("3"), "manager".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            order_error(&err),
+            Some(OrderError::NotShippable(OrderStatus::Cancelled))
+        ));
+    }
 }
@@ -1,30 +1,48 @@
 use async_trait::async_trait;
+use rt_types::shop::{ReviewModeration, ReviewSettings};
 use rusqlite::params;
 use tokio_rusqlite::Connection;
 use uuid::Uuid;
 
 use crate::SqlWrapper;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReviewStatus {
     Published,
     Pending,
+    Rejected,
 }
 
 impl ReviewStatus {
+    pub const ALL: [ReviewStatus; 3] = [
+        ReviewStatus::Pending,
+        ReviewStatus::Published,
+        ReviewStatus::Rejected,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             ReviewStatus::Published => "published",
             ReviewStatus::Pending => "pending",
+            ReviewStatus::Rejected => "rejected",
         }
     }
 
     pub fn from_str(value: &str) -> Self {
         match value.trim().to_lowercase().as_str() {
             "pending" => ReviewStatus::Pending,
+            "rejected" => ReviewStatus::Rejected,
             _ => ReviewStatus::Published,
         }
     }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ReviewStatus::Published => "Опубліковано",
+            ReviewStatus::Pending => "На модерації",
+            ReviewStatus::Rejected => "Відхилено",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +56,8 @@ pub struct Review {
     pub photos: Vec<String>,
     pub status: ReviewStatus,
     pub created_at: i64,
+    pub reply: Option<String>,
+    pub replied_at: Option<i64>,
 }
 
 #[derive(Debug, Clone)]
@@ -52,6 +72,47 @@ pub struct NewReview {
     pub created_at: i64,
 }
 
+#[derive(Debug, Clone)]
+pub struct ReviewFilter {
+    pub status: Option<ReviewStatus>,
+    pub query: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl Default for ReviewFilter {
+    fn default() -> Self {
+        Self {
+            status: None,
+            query: None,
+            limit: 50,
+            offset: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReviewPage {
+    pub items: Vec<Review>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReviewEdit {
+    pub name: String,
+    pub text: String,
+    pub rating: i64,
+    pub reply: Option<String>,
+    pub replied_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewRating {
+    pub product_key: String,
+    pub average: f64,
+    pub count: usize,
+}
+
 #[async_trait]
 pub trait ReviewRepository: Send + Sync {
     async fn add(&self, item: NewReview) -> anyhow::Result<Review>;
@@ -63,6 +124,22 @@ pub trait ReviewRepository: Send + Sync {
         offset: usize,
         status: ReviewStatus,
     ) -> anyhow::Result<Vec<Review>>;
+    async fn get(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Option<Review>>;
+    async fn list_by_shop(&self, shop_id: Uuid, filter: ReviewFilter)
+        -> anyhow::Result<ReviewPage>;
+    async fn update(&self, shop_id: Uuid, id: i64, edit: ReviewEdit) -> anyhow::Result<()>;
+    async fn set_status(
+        &self,
+        shop_id: Uuid,
+        ids: Vec<i64>,
+        status: ReviewStatus,
+    ) -> anyhow::Result<usize>;
+    async fn remove(&self, shop_id: Uuid, ids: Vec<i64>) -> anyhow::Result<usize>;
+    async fn ratings(
+        &self,
+        shop_id: Uuid,
+        product_key: Option<String>,
+    ) -> anyhow::Result<Vec<ReviewRating>>;
 }
 
 pub struct SqliteReviewRepository {
@@ -94,6 +171,8 @@ impl SqliteReviewRepository {
                 "CREATE INDEX IF NOT EXISTS review_shop_product_idx ON review(shop_id, product_key)",
                 [],
             )?;
+            let _ = conn.execute("ALTER TABLE review ADD COLUMN reply TEXT", []);
+            let _ = conn.execute("ALTER TABLE review ADD COLUMN replied_at INTEGER", []);
             Ok(())
         })
         .await?;
@@ -114,6 +193,30 @@ fn photos_from_db(data: Option<String>) -> Vec<String> {
         .unwrap_or_default()
 }
 
+const REVIEW_COLUMNS: &str =
+    "id, shop_id, product_key, author, text, rating, photos, status, created_at, reply, replied_at";
+
+fn review_from_row(row: &rusqlite::Row) -> rusqlite::Result<Review> {
+    let shop_id: String = row.get(1)?;
+    Ok(Review {
+        id: row.get(0)?,
+        shop_id: Uuid::parse_str(&shop_id).unwrap_or(Uuid::nil()),
+        product_key: row.get(2)?,
+        name: row.get(3)?,
+        text: row.get(4)?,
+        rating: row.get(5)?,
+        photos: photos_from_db(row.get(6)?),
+        status: ReviewStatus::from_str(row.get::<_, String>(7)?.as_str()),
+        created_at: row.get(8)?,
+        reply: row.get(9)?,
+        replied_at: row.get(10)?,
+    })
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
 #[async_trait]
 impl ReviewRepository for SqliteReviewRepository {
     async fn add(&self, item: NewReview) -> anyhow::Result<Review> {
@@ -146,6 +249,8 @@ impl ReviewRepository for SqliteReviewRepository {
                     photos: item.photos,
                     status: item.status,
                     created_at: item.created_at,
+                    reply: None,
+                    replied_at: None,
                 }))
             })
             .await?;
@@ -165,68 +270,220 @@ impl ReviewRepository for SqliteReviewRepository {
         let SqlWrapper(items) = self
             .conn
             .call(move |conn| {
-                let mut items = Vec::new();
-                if let Some(product_key) = product_key {
-                    let mut stmt = conn.prepare(
-                        "SELECT id, shop_id, product_key, author, text, rating, photos, status, created_at
+                let items = if let Some(product_key) = product_key {
+                    let mut stmt = conn.prepare(&format!(
+                        "SELECT {REVIEW_COLUMNS}
                          FROM review
                          WHERE shop_id = ?1 AND product_key = ?2 AND status = ?3
                          ORDER BY created_at DESC
-                         LIMIT ?4 OFFSET ?5",
-                    )?;
+                         LIMIT ?4 OFFSET ?5"
+                    ))?;
                     let rows = stmt.query_map(
-                        params![shop_id, product_key, status_raw, limit as i64, offset as i64],
-                        |row| {
-                            let created_at: i64 = row.get(8)?;
-                            let shop_id: String = row.get(1)?;
-                            let shop_id = Uuid::parse_str(&shop_id).unwrap_or(Uuid::nil());
-                            Ok(Review {
-                                id: row.get(0)?,
-                                shop_id,
-                                product_key: row.get(2)?,
-                                name: row.get(3)?,
-                                text: row.get(4)?,
-                                rating: row.get(5)?,
-                                photos: photos_from_db(row.get(6)?),
-                                status: ReviewStatus::from_str(row.get::<_, String>(7)?.as_str()),
-                                created_at,
-                            })
-                        },
+                        params![
+                            shop_id,
+                            product_key,
+                            status_raw,
+                            limit as i64,
+                            offset as i64
+                        ],
+                        review_from_row,
                     )?;
-                    for row in rows {
-                        items.push(row?);
-                    }
+                    rows.collect::<Result<Vec<_>, _>>()?
                 } else {
-                    let mut stmt = conn.prepare(
-                        "SELECT id, shop_id, product_key, author, text, rating, photos, status, created_at
+                    let mut stmt = conn.prepare(&format!(
+                        "SELECT {REVIEW_COLUMNS}
                          FROM review
                          WHERE shop_id = ?1 AND status = ?2
                          ORDER BY created_at DESC
-                         LIMIT ?3 OFFSET ?4",
-                    )?;
+                         LIMIT ?3 OFFSET ?4"
+                    ))?;
                     let rows = stmt.query_map(
                         params![shop_id, status_raw, limit as i64, offset as i64],
+                        review_from_row,
+                    )?;
+                    rows.collect::<Result<Vec<_>, _>>()?
+                };
+                Ok(SqlWrapper(items))
+            })
+            .await?;
+        Ok(items)
+    }
+
+    async fn get(&self, shop_id: Uuid, id: i64) -> anyhow::Result<Option<Review>> {
+        let SqlWrapper(item) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {REVIEW_COLUMNS} FROM review WHERE shop_id = ?1 AND id = ?2"
+                ))?;
+                let item = stmt
+                    .query_map(params![shop_id.to_string(), id], review_from_row)?
+                    .next()
+                    .transpose()?;
+                Ok(SqlWrapper(item))
+            })
+            .await?;
+        Ok(item)
+    }
+
+    async fn list_by_shop(
+        &self,
+        shop_id: Uuid,
+        filter: ReviewFilter,
+    ) -> anyhow::Result<ReviewPage> {
+        let SqlWrapper(out) = self
+            .conn
+            .call(move |conn| {
+                let mut clause = String::from("WHERE shop_id = ?");
+                let mut args: Vec<rusqlite::types::Value> = vec![shop_id.to_string().into()];
+                if let Some(status) = filter.status {
+                    clause.push_str(" AND status = ?");
+                    args.push(status.as_str().to_string().into());
+                }
+                if let Some(query) = filter
+                    .query
+                    .as_ref()
+                    .map(|q| q.trim())
+                    .filter(|q| !q.is_empty())
+                {
+                    let pattern = crate::order::like_contains(&query.to_lowercase());
+                    clause.push_str(
+                        " AND (lower(author) LIKE ? ESCAPE '\\' OR lower(text) LIKE ? ESCAPE '\\'
+                         OR product_key LIKE ? ESCAPE '\\')",
+                    );
+                    args.push(pattern.clone().into());
+                    args.push(pattern.clone().into());
+                    args.push(pattern.into());
+                }
+                let total: i64 = conn.query_row(
+                    &format!("SELECT COUNT(*) FROM review {clause}"),
+                    rusqlite::params_from_iter(args.iter()),
+                    |row| row.get(0),
+                )?;
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {REVIEW_COLUMNS} FROM review {clause}
+                     ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?"
+                ))?;
+                args.push((filter.limit as i64).into());
+                args.push((filter.offset as i64).into());
+                let items = stmt
+                    .query_map(rusqlite::params_from_iter(args.iter()), review_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(ReviewPage {
+                    items,
+                    total: total.max(0) as usize,
+                }))
+            })
+            .await?;
+        Ok(out)
+    }
+
+    async fn update(&self, shop_id: Uuid, id: i64, edit: ReviewEdit) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE review SET author = ?3, text = ?4, rating = ?5, reply = ?6, replied_at = ?7
+                     WHERE shop_id = ?1 AND id = ?2",
+                    params![
+                        shop_id.to_string(),
+                        id,
+                        edit.name,
+                        edit.text,
+                        edit.rating,
+                        edit.reply,
+                        edit.replied_at
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn set_status(
+        &self,
+        shop_id: Uuid,
+        ids: Vec<i64>,
+        status: ReviewStatus,
+    ) -> anyhow::Result<usize> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let SqlWrapper(count) = self
+            .conn
+            .call(move |conn| {
+                let mut args: Vec<rusqlite::types::Value> = vec![
+                    status.as_str().to_string().into(),
+                    shop_id.to_string().into(),
+                ];
+                args.extend(ids.iter().map(|id| (*id).into()));
+                let count = conn.execute(
+                    &format!(
+                        "UPDATE review SET status = ? WHERE shop_id = ? AND id IN ({})",
+                        placeholders(ids.len())
+                    ),
+                    rusqlite::params_from_iter(args.iter()),
+                )?;
+                Ok(SqlWrapper(count))
+            })
+            .await?;
+        Ok(count)
+    }
+
+    async fn remove(&self, shop_id: Uuid, ids: Vec<i64>) -> anyhow::Result<usize> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let SqlWrapper(count) = self
+            .conn
+            .call(move |conn| {
+                let mut args: Vec<rusqlite::types::Value> = vec![shop_id.to_string().into()];
+                args.extend(ids.iter().map(|id| (*id).into()));
+                let count = conn.execute(
+                    &format!(
+                        "DELETE FROM review WHERE shop_id = ? AND id IN ({})",
+                        placeholders(ids.len())
+                    ),
+                    rusqlite::params_from_iter(args.iter()),
+                )?;
+                Ok(SqlWrapper(count))
+            })
+            .await?;
+        Ok(count)
+    }
+
+    async fn ratings(
+        &self,
+        shop_id: Uuid,
+        product_key: Option<String>,
+    ) -> anyhow::Result<Vec<ReviewRating>> {
+        let SqlWrapper(items) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT product_key, AVG(rating), COUNT(*)
+                     FROM review
+                     WHERE shop_id = ?1 AND status = ?2 AND product_key IS NOT NULL
+                        AND (?3 IS NULL OR product_key = ?3)
+                     GROUP BY product_key
+                     ORDER BY product_key",
+                )?;
+                let items = stmt
+                    .query_map(
+                        params![
+                            shop_id.to_string(),
+                            ReviewStatus::Published.as_str(),
+                            product_key
+                        ],
                         |row| {
-                            let created_at: i64 = row.get(8)?;
-                            let shop_id: String = row.get(1)?;
-                            let shop_id = Uuid::parse_str(&shop_id).unwrap_or(Uuid::nil());
-                            Ok(Review {
-                                id: row.get(0)?,
-                                shop_id,
-                                product_key: row.get(2)?,
-                                name: row.get(3)?,
-                                text: row.get(4)?,
-                                rating: row.get(5)?,
-                                photos: photos_from_db(row.get(6)?),
-                                status: ReviewStatus::from_str(row.get::<_, String>(7)?.as_str()),
-                                created_at,
+                            Ok(ReviewRating {
+                                product_key: row.get(0)?,
+                                average: row.get(1)?,
+                                count: row.get::<_, i64>(2)?.max(0) as usize,
                             })
                         },
-                    )?;
-                    for row in rows {
-                        items.push(row?);
-                    }
-                }
+                    )?
+                    .collect::<Result<Vec<_>, _>>()?;
                 Ok(SqlWrapper(items))
             })
             .await?;
@@ -275,9 +532,41 @@ pub(crate) fn clamp_rating(value: i64) -> i64 {
     value.clamp(1, 5)
 }
 
+fn has_link(text: &str) -> bool {
+    let lowered = text.to_lowercase();
+    lowered.contains("http://")
+        || lowered.contains("https://")
+        || lowered.contains("www.")
+        || lowered.contains("t.me/")
+}
+
+pub(crate) fn initial_status(settings: &ReviewSettings, name: &str, text: &str) -> ReviewStatus {
+    let suspicious = || {
+        let content = format!("{name} {text}").to_lowercase();
+        has_link(&content)
+            || settings
+                .stop_words
+                .iter()
+                .map(|w| w.trim().to_lowercase())
+                .any(|w| !w.is_empty() && content.contains(&w))
+    };
+    match settings.moderation {
+        ReviewModeration::AutoPublish => ReviewStatus::Published,
+        ReviewModeration::Always => ReviewStatus::Pending,
+        ReviewModeration::Suspicious if suspicious() => ReviewStatus::Pending,
+        ReviewModeration::Suspicious => ReviewStatus::Published,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{clamp_rating, normalize_product_key, normalize_text};
+    use super::{
+        clamp_rating, initial_status, normalize_product_key, normalize_text, NewReview,
+        ReviewFilter, ReviewRepository, ReviewStatus, SqliteReviewRepository,
+    };
+    use rt_types::shop::{ReviewModeration, ReviewSettings};
+    use tokio_rusqlite::Connection;
+    use uuid::Uuid;
 
     #[test]
     fn normalize_product_key_trims_and_lowercases() {
@@ -301,4 +590,77 @@ mod tests {
         assert_eq!(clamp_rating(3), 3);
         assert_eq!(clamp_rating(6), 5);
     }
+
+    #[test]
+    fn moderates_links_and_stop_words() {
+        let settings = ReviewSettings {
+            moderation: ReviewModeration::Suspicious,
+            stop_words: vec![" Казино ".to_string()],
+        };
+        assert_eq!(
+            initial_status(&settings, "Ivan", "Все подошло"),
+            ReviewStatus::Published
+        );
+        assert_eq!(
+            initial_status(&settings, "Ivan", "Дешевле на WWW.example.com"),
+            ReviewStatus::Pending
+        );
+        assert_eq!(
+            initial_status(&settings, "казино онлайн", "Отлично"),
+            ReviewStatus::Pending
+        );
+        let always = ReviewSettings {
+            moderation: ReviewModeration::Always,
+            ..Default::default()
+        };
+        assert_eq!(initial_status(&always, "Ivan", "Ok"), ReviewStatus::Pending);
+        assert_eq!(
+            initial_status(&ReviewSettings::default(), "Ivan", "https://x.com"),
+            ReviewStatus::Published
+        );
+    }
+
+    #[tokio::test]
+    async fn search_matches_wildcards_literally() {
+        let repo = SqliteReviewRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let shop_id = Uuid::new_v4();
+        for (i, text) in ["Знижка 50% спрацювала", "Знижка 500 грн", "snake_case"]
+            .into_iter()
+            .enumerate()
+        {
+            repo.add(NewReview {
+                shop_id,
+                product_key: None,
+                name: "Ivan".to_string(),
+                text: text.to_string(),
+                rating: 5,
+                photos: vec![],
+                status: ReviewStatus::Published,
+                created_at: i as i64,
+            })
+            .await
+            .unwrap();
+        }
+        let search = |query: &str| {
+            let repo = &repo;
+            let filter = ReviewFilter {
+                query: Some(query.to_string()),
+                ..Default::default()
+            };
+            async move {
+                repo.list_by_shop(shop_id, filter)
+                    .await
+                    .unwrap()
+                    .items
+                    .into_iter()
+                    .map(|r| r.text)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(search("50%").await, vec!["Знижка 50% спрацювала"]);
+        assert_eq!(search("e_c").await, vec!["snake_case"]);
+        assert!(search("e%c").await.is_empty());
+    }
 }
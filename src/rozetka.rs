@@ -0,0 +1,422 @@
+use crate::facebook;
+use async_zip::tokio::write::ZipFileWriter;
+use futures::{future, Stream, StreamExt};
+use quick_xml::events::{BytesCData, BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::writer::Writer;
+use rt_types::category::Category;
+use rt_types::product::Product;
+use rt_types::shop::ExportOptions;
+use rt_types::Availability;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+
+pub const CURRENCY: &str = "UAH";
+// Rozetka відхиляє такі ціни як помилкові, перевіряємо після перерахунку в гривні
+pub const MAX_PRICE: Decimal = Decimal::from_parts(10_000_000, 0, 0, false, 0);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfferIssues {
+    pub id: String,
+    pub article: String,
+    pub errors: Vec<String>,
+}
+
+struct Offer {
+    name: String,
+    name_ua: String,
+    description: String,
+    description_ua: String,
+    price: Decimal,
+    price_old: Option<Decimal>,
+}
+
+fn description(d: Option<&String>) -> Option<String> {
+    d.map(|d| crate::xlsx::format_replica(&crate::xlsx::trim_images(d)))
+        .filter(|d| !d.trim().is_empty())
+}
+
+fn offer(
+    p: &Product,
+    opts: &ExportOptions,
+    rates: &HashMap<String, Decimal>,
+) -> Result<Offer, Vec<String>> {
+    let mut errors = vec![];
+    let ua = p.ua_translation.as_ref();
+    let name_ua = ua.map(|t| t.title.trim()).unwrap_or_default();
+    if p.title.trim().is_empty() {
+        errors.push("нет названия".to_string());
+    }
+    if name_ua.is_empty() {
+        errors.push("нет названия на украинском (name_ua)".to_string());
+    }
+    let description_ru = description(p.description.as_ref());
+    let description_ua = description(ua.and_then(|t| t.description.as_ref()));
+    if description_ru.is_none() {
+        errors.push("нет описания".to_string());
+    }
+    if description_ua.is_none() {
+        errors.push("нет описания на украинском (description_ua)".to_string());
+    }
+    if p.brand.trim().is_empty() {
+        errors.push("не указан производитель (vendor)".to_string());
+    }
+    if p.params
+        .iter()
+        .all(|(k, v)| k.trim().is_empty() || v.trim().is_empty())
+    {
+        errors.push("нет характеристик (param)".to_string());
+    }
+    if p.images.is_empty() {
+        errors.push("нет изображений".to_string());
+    }
+    if p.category.is_none() {
+        errors.push("нет категории".to_string());
+    }
+    if matches!(p.available, Availability::Available) && p.in_stock.is_none() {
+        errors.push("не указан остаток (stock_quantity)".to_string());
+    }
+    let currency = p.currency.trim().to_uppercase();
+    let rate = if currency == CURRENCY {
+        Some(Decimal::ONE)
+    } else {
+        rates.get(&currency).copied()
+    };
+    if rate.is_none() {
+        errors.push(format!("нет курса для валюты {}", p.currency));
+    }
+    let base_price = p.price * rate.unwrap_or(Decimal::ONE);
+    let (price, price_old) = match &opts.discount {
+        Some(d) if d.percent > 0 => (
+            base_price * (Decimal::ONE_HUNDRED - Decimal::from(d.percent)) / Decimal::ONE_HUNDRED,
+            Some(base_price),
+        ),
+        _ => (base_price, None),
+    };
+    if price <= Decimal::ZERO {
+        errors.push("цена должна быть больше 0".to_string());
+    } else if price > MAX_PRICE {
+        errors.push(format!("цена {} больше {MAX_PRICE}", price.round_dp(2)));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(Offer {
+        name: crate::xlsx::build_title(opts, &p.title, false),
+        name_ua: crate::xlsx::build_title(opts, name_ua, true),
+        description: description_ru.unwrap_or_default(),
+        description_ua: description_ua.unwrap_or_default(),
+        price: price.round_dp(2),
+        price_old: price_old.map(|p| p.round_dp(2)),
+    })
+}
+
+fn offers<'a, 'c>(
+    items: impl Stream<Item = (&'a ExportOptions, Product)> + 'c,
+    rates: &'c HashMap<String, Decimal>,
+) -> impl Stream<Item = (&'a ExportOptions, Product, Offer)> + 'c
+where
+    'a: 'c,
+{
+    items.filter_map(|(o, p)| {
+        future::ready(match p.available {
+            Availability::OnOrder => None,
+            _ => offer(&p, o, rates).ok().map(|offer| (o, p, offer)),
+        })
+    })
+}
+
+async fn validate<'a>(
+    items: impl Stream<Item = (&'a ExportOptions, Product)>,
+    rates: &HashMap<String, Decimal>,
+) -> (HashSet<uuid::Uuid>, Vec<OfferIssues>) {
+    let mut categories = HashSet::new();
+    let mut issues = vec![];
+    let mut items = std::pin::pin!(items);
+    while let Some((o, p)) = items.next().await {
+        if let Availability::OnOrder = p.available {
+            continue;
+        }
+        match offer(&p, o, rates) {
+            Ok(_) => categories.extend(p.category),
+            Err(errors) => issues.push(OfferIssues {
+                id: p.id,
+                article: p.article,
+                errors,
+            }),
+        }
+    }
+    (categories, issues)
+}
+
+async fn write_text<W: tokio::io::AsyncWrite + Unpin>(
+    writer: &mut Writer<W>,
+    name: &str,
+    value: &str,
+) -> Result<(), quick_xml::Error> {
+    writer
+        .create_element(name)
+        .write_text_content_async(BytesText::new(value))
+        .await?;
+    Ok(())
+}
+
+pub async fn write_yml<'a, S>(
+    path: &str,
+    items: impl Fn() -> S,
+    categories: &HashSet<Category, impl BuildHasher>,
+    rates: &HashMap<String, Decimal>,
+    base: &str,
+) -> Result<Vec<OfferIssues>, anyhow::Error>
+where
+    S: Stream<Item = (&'a ExportOptions, Product)>,
+{
+    let (used_categories, issues) = validate(items(), rates).await;
+    let by_id: HashMap<_, _> = categories.iter().map(|c| (c.id, c)).collect();
+    let mut category_ids = used_categories.into_iter().collect::<Vec<_>>();
+    let mut i = 0;
+    while let Some(id) = category_ids.get(i) {
+        if let Some(parent) = by_id.get(id).and_then(|c| c.parent_id) {
+            if !category_ids.contains(&parent) {
+                category_ids.push(parent);
+            }
+        }
+        i += 1;
+    }
+
+    let mut res_file = tokio::fs::File::create(&path).await?;
+    let mut w = ZipFileWriter::with_tokio(&mut res_file);
+    let mut zip_writer = w
+        .write_entry_stream(facebook::zip_entry(path)?)
+        .await?
+        .compat_write();
+    let mut writer = Writer::new(&mut zip_writer);
+    let date = OffsetDateTime::now_utc()
+        .format(format_description!("[year]-[month]-[day] [hour]:[minute]"))?;
+    writer
+        .write_event_async(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))
+        .await?;
+    writer
+        .write_event_async(Event::Start(
+            BytesStart::new("yml_catalog").with_attributes([("date", date.as_str())]),
+        ))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("shop")))
+        .await?;
+    // Хост сайту слугує і назвою магазину, і компанії
+    let url = format!("{base}/");
+    let host = url::Url::parse(&url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default();
+    write_text(&mut writer, "name", &host).await?;
+    write_text(&mut writer, "company", &host).await?;
+    write_text(&mut writer, "url", &url).await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("currencies")))
+        .await?;
+    writer
+        .create_element("currency")
+        .with_attributes([("id", CURRENCY), ("rate", "1")])
+        .write_empty_async()
+        .await?;
+    writer
+        .write_event_async(Event::End(BytesEnd::new("currencies")))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("categories")))
+        .await?;
+    for c in category_ids.iter().filter_map(|id| by_id.get(id)) {
+        let id = c.id.as_u64_pair().0.to_string();
+        let parent_id = c.parent_id.map(|p| p.as_u64_pair().0.to_string());
+        let mut attrs = vec![("id", id.as_str())];
+        if let Some(parent_id) = &parent_id {
+            attrs.push(("parentId", parent_id.as_str()));
+        }
+        writer
+            .create_element("category")
+            .with_attributes(attrs)
+            .write_text_content_async(BytesText::new(&c.name))
+            .await?;
+    }
+    writer
+        .write_event_async(Event::End(BytesEnd::new("categories")))
+        .await?;
+    writer
+        .write_event_async(Event::Start(BytesStart::new("offers")))
+        .await?;
+    let mut offers = std::pin::pin!(offers(items(), rates));
+    while let Some((_, p, offer)) = offers.next().await {
+        let p = &p;
+        let offer = &offer;
+        let available = matches!(p.available, Availability::Available);
+        let stock = if available {
+            p.in_stock.unwrap_or_default()
+        } else {
+            0
+        };
+        writer
+            .create_element("offer")
+            .with_attributes([
+                ("id", p.id.as_str()),
+                ("available", if available { "true" } else { "false" }),
+            ])
+            .write_inner_content_async::<_, _, quick_xml::Error>(|writer| async move {
+                write_text(writer, "price", &offer.price.to_string()).await?;
+                if let Some(price_old) = offer.price_old {
+                    write_text(writer, "price_old", &price_old.to_string()).await?;
+                }
+                write_text(writer, "currencyId", CURRENCY).await?;
+                if let Some(c) = p.category {
+                    write_text(writer, "categoryId", &c.as_u64_pair().0.to_string()).await?;
+                }
+                for image in p.images.iter().take(15) {
+                    write_text(writer, "picture", image).await?;
+                }
+                write_text(writer, "vendor", &p.brand).await?;
+                write_text(writer, "article", &p.article).await?;
+                write_text(writer, "stock_quantity", &stock.to_string()).await?;
+                write_text(writer, "name", &offer.name).await?;
+                write_text(writer, "name_ua", &offer.name_ua).await?;
+                writer
+                    .create_element("description")
+                    .write_cdata_content_async(BytesCData::new(&offer.description))
+                    .await?;
+                writer
+                    .create_element("description_ua")
+                    .write_cdata_content_async(BytesCData::new(&offer.description_ua))
+                    .await?;
+                let mut params = p.params.iter().collect::<Vec<_>>();
+                params.sort();
+                for (name, value) in params {
+                    if name.trim().is_empty() || value.trim().is_empty() {
+                        continue;
+                    }
+                    writer
+                        .create_element("param")
+                        .with_attribute(("name", name.as_str()))
+                        .write_text_content_async(BytesText::new(value))
+                        .await?;
+                }
+                Ok(writer)
+            })
+            .await?;
+    }
+    writer
+        .write_event_async(Event::End(BytesEnd::new("offers")))
+        .await?;
+    writer
+        .write_event_async(Event::End(BytesEnd::new("shop")))
+        .await?;
+    writer
+        .write_event_async(Event::End(BytesEnd::new("yml_catalog")))
+        .await?;
+    zip_writer.into_inner().close().await?;
+    w.close().await?;
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rt_types::product::UaTranslation;
+
+    fn product(id: &str) -> Product {
+        Product {
+            id: id.to_string(),
+            title: "Спойлер".to_string(),
+            ua_translation: Some(UaTranslation {
+                title: "Спойлер UA".to_string(),
+                description: Some("Опис".to_string()),
+            }),
+            description: Some("Описание".to_string()),
+            price: Decimal::new(1250, 0),
+            source_price: None,
+            article: "TT-77".to_string(),
+            in_stock: Some(3),
+            currency: "UAH".to_string(),
+            keywords: None,
+            params: HashMap::from([("Материал".to_string(), "ABS".to_string())]),
+            brand: "Maxton".to_string(),
+            model: "".to_string(),
+            category: Some(uuid::Uuid::nil()),
+            available: Availability::Available,
+            vendor: "TT".to_string(),
+            images: vec!["https://cdn.example.com/1.jpg".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn validates_offers_and_skips_goods_on_order() {
+        let valid = product("tt-1");
+        let on_order = Product {
+            available: Availability::OnOrder,
+            params: HashMap::new(),
+            ..product("tt-2")
+        };
+        let invalid = Product {
+            ua_translation: None,
+            params: HashMap::new(),
+            in_stock: None,
+            brand: "".to_string(),
+            price: Decimal::ZERO,
+            ..product("tt-3")
+        };
+        let in_euro = Product {
+            currency: "eur".to_string(),
+            price: Decimal::new(10, 0),
+            ..product("tt-4")
+        };
+        let in_pln = Product {
+            currency: "PLN".to_string(),
+            ..product("tt-5")
+        };
+        // В євро в межах, після перерахунку — понад межу
+        let too_expensive = Product {
+            currency: "EUR".to_string(),
+            price: Decimal::new(250_000, 0),
+            ..product("tt-6")
+        };
+        let opts = ExportOptions::default();
+        let products = [valid, on_order, invalid, in_euro, in_pln, too_expensive];
+        let rates = HashMap::from([("EUR".to_string(), Decimal::new(45, 0))]);
+
+        let items = || futures::stream::iter(products.iter().map(|p| (&opts, p.clone())));
+        let offers = offers(items(), &rates).collect::<Vec<_>>().await;
+        assert_eq!(
+            offers
+                .iter()
+                .map(|(_, p, o)| (p.id.as_str(), o.price))
+                .collect::<Vec<_>>(),
+            vec![
+                ("tt-1", Decimal::new(1250, 0)),
+                ("tt-4", Decimal::new(450, 0))
+            ]
+        );
+        assert_eq!(offers[0].2.name_ua, "Спойлер UA");
+        let (categories, issues) = validate(items(), &rates).await;
+        assert_eq!(categories, HashSet::from([uuid::Uuid::nil()]));
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[2].id, "tt-6");
+        assert_eq!(issues[2].errors, vec!["цена 11250000 больше 10000000"]);
+        assert_eq!(issues[1].id, "tt-5");
+        assert_eq!(issues[1].errors, vec!["нет курса для валюты PLN"]);
+        assert_eq!(issues[0].id, "tt-3");
+        assert_eq!(
+            issues[0].errors,
+            vec![
+                "нет названия на украинском (name_ua)",
+                "нет описания на украинском (description_ua)",
+                "не указан производитель (vendor)",
+                "нет характеристик (param)",
+                "не указан остаток (stock_quantity)",
+                "цена должна быть больше 0",
+            ]
+        );
+    }
+}
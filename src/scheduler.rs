@@ -0,0 +1,1037 @@
+use anyhow::{anyhow, Context as AnyhowContext};
+use async_trait::async_trait;
+use derive_more::Display;
+use log_error::LogError;
+use rusqlite::{params, OptionalExtension};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+use time_tz::timezones::db::europe::KYIV;
+use time_tz::{OffsetDateTimeExt, OffsetResult, PrimitiveDateTimeExt};
+use tokio::sync::{Mutex, Notify, RwLock, Semaphore};
+use tokio_rusqlite::Connection;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::SqlWrapper;
+
+const RUNS_PER_JOB: usize = 200;
+const CANCEL_GRACE: Duration = Duration::from_secs(30);
+const MAX_INTERVAL: Duration = Duration::from_secs(366 * 86400);
+// Подвоюється з кожною невдачею поспіль
+const FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(3600);
+
+fn failure_backoff(failures: u32) -> Duration {
+    match failures {
+        0 => Duration::ZERO,
+        n => FAILURE_BACKOFF
+            .saturating_mul(1 << (n - 1).min(16))
+            .min(MAX_FAILURE_BACKOFF),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobClass {
+    Task,
+    // Парсери йдуть один за одним і не враховуються в загальному ліміті
+    Parser,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    // Інтервальні задачі без запусків — одразу, пропущені cron-запуски не наздоганяються
+    pub fn next_run(
+        &self,
+        last_finished: Option<OffsetDateTime>,
+        now: OffsetDateTime,
+    ) -> Option<OffsetDateTime> {
+        match self {
+            Schedule::Interval(d) => match last_finished {
+                Some(l) => l.checked_add(time::Duration::try_from(*d).ok()?),
+                None => Some(now),
+            },
+            Schedule::Cron(c) => c.next_after(last_finished.map_or(now, |l| l.max(now))),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Interval(d) => {
+                let secs = d.as_secs();
+                match secs {
+                    0 => write!(f, "0s"),
+                    s if s % 86400 == 0 => write!(f, "{}d", s / 86400),
+                    s if s % 3600 == 0 => write!(f, "{}h", s / 3600),
+                    s if s % 60 == 0 => write!(f, "{}m", s / 60),
+                    s => write!(f, "{s}s"),
+                }
+            }
+            Schedule::Cron(c) => write!(f, "{}", c.source),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.split_whitespace().count() > 1 {
+            return s.parse().map(Schedule::Cron);
+        }
+        let unit_at = s
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("Interval {s} has no unit"))?;
+        let (value, unit) = s.split_at(unit_at);
+        let value: u64 = value
+            .parse()
+            .with_context(|| format!("Invalid interval {s}"))?;
+        let secs = match unit {
+            "s" => Some(value),
+            "m" => value.checked_mul(60),
+            "h" => value.checked_mul(3600),
+            "d" => value.checked_mul(86400),
+            _ => return Err(anyhow!("Unknown interval unit {unit}")),
+        }
+        .filter(|secs| *secs <= MAX_INTERVAL.as_secs())
+        .ok_or_else(|| anyhow!("Interval {s} is too long"))?;
+        if secs == 0 {
+            return Err(anyhow!("Interval must be longer than zero"));
+        }
+        Ok(Schedule::Interval(Duration::from_secs(secs)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, min: u8, max: u8) -> Result<u64, anyhow::Error> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u8>().ok().filter(|s| *s > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| anyhow!("Invalid step in {part}"))?;
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (from.parse()?, to.parse()?),
+            None if part.contains('/') => (range.parse()?, max),
+            None => {
+                let v = range.parse()?;
+                (v, v)
+            }
+        };
+        if from < min || to > max || from > to {
+            return Err(anyhow!("{part} is out of range {min}-{max}"));
+        }
+        for v in (from..=to).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!("Cron expression must have 5 fields: {s}"));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7).context("Invalid day of week")?;
+        // 0 і 7 — неділя
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            source: fields.join(" "),
+            minutes: parse_field(minute, 0, 59).context("Invalid minute")?,
+            hours: parse_field(hour, 0, 23).context("Invalid hour")?,
+            days: parse_field(day, 1, 31).context("Invalid day of month")?,
+            months: parse_field(month, 1, 12).context("Invalid month")?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+impl Cron {
+    fn day_matches(&self, date: Date) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().number_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            // Як у cron: обмежені день місяця і день тижня діють через «або»
+            (false, false) => day || weekday,
+        }
+    }
+
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let local = after.to_timezone(KYIV);
+        let mut t = PrimitiveDateTime::new(
+            local.date(),
+            Time::from_hms(local.hour(), local.minute(), 0).ok()?,
+        ) + time::Duration::MINUTE;
+        let limit = t + time::Duration::days(366 * 5);
+        while t < limit {
+            if self.months & (1 << t.month() as u8) == 0 {
+                let (year, month) = match t.month() {
+                    time::Month::December => (t.year() + 1, time::Month::January),
+                    m => (t.year(), m.next()),
+                };
+                t = Date::from_calendar_date(year, month, 1).ok()?.midnight();
+                continue;
+            }
+            if !self.day_matches(t.date()) {
+                t = t.date().next_day()?.midnight();
+                continue;
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.replace_minute(0).ok()? + time::Duration::HOUR;
+                continue;
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += time::Duration::MINUTE;
+                continue;
+            }
+            match t.assume_timezone(KYIV) {
+                OffsetResult::Some(res) if res > after => return Some(res),
+                OffsetResult::Ambiguous(a, _) if a > after => return Some(a),
+                OffsetResult::Ambiguous(_, b) if b > after => return Some(b),
+                // Пропущено переходом на літній час або вже минуло
+                _ => t += time::Duration::MINUTE,
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobDefinition {
+    pub id: String,
+    pub title: String,
+    pub shop: Option<Uuid>,
+    pub schedule: Option<Schedule>,
+    pub paused: bool,
+}
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    #[display("Выполняется")]
+    Running,
+    #[display("Успешно")]
+    Success,
+    #[display("Ошибка")]
+    Failure,
+    #[display("Отменено")]
+    Cancelled,
+    #[display("Прервано")]
+    Interrupted,
+}
+
+impl RunOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Running => "running",
+            RunOutcome::Success => "success",
+            RunOutcome::Failure => "failure",
+            RunOutcome::Cancelled => "cancelled",
+            RunOutcome::Interrupted => "interrupted",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "running" => RunOutcome::Running,
+            "success" => RunOutcome::Success,
+            "failure" => RunOutcome::Failure,
+            "cancelled" => RunOutcome::Cancelled,
+            _ => RunOutcome::Interrupted,
+        }
+    }
+}
+
+pub type RunCounts = BTreeMap<String, u64>;
+
+#[derive(Debug, Clone)]
+pub struct JobRun {
+    pub id: i64,
+    pub job: String,
+    pub started: OffsetDateTime,
+    pub finished: Option<OffsetDateTime>,
+    pub outcome: RunOutcome,
+    pub error: Option<String>,
+    pub counts: RunCounts,
+}
+
+#[async_trait]
+pub trait JobRepository: Send + Sync {
+    async fn get(&self, id: &str) -> anyhow::Result<Option<JobDefinition>>;
+    async fn save(&self, job: JobDefinition) -> anyhow::Result<()>;
+    async fn remove(&self, id: &str) -> anyhow::Result<()>;
+    async fn start_run(&self, job: &str, started: OffsetDateTime) -> anyhow::Result<i64>;
+    async fn finish_run(&self, run: JobRun) -> anyhow::Result<()>;
+    async fn list_runs(&self, job: Option<String>, limit: usize) -> anyhow::Result<Vec<JobRun>>;
+    async fn last_finished(&self, job: &str) -> anyhow::Result<Option<OffsetDateTime>>;
+    async fn interrupt_running(&self) -> anyhow::Result<usize>;
+}
+
+pub struct SqliteJobRepository {
+    conn: Connection,
+}
+
+impl SqliteJobRepository {
+    pub async fn init(conn: Connection) -> Result<Self, tokio_rusqlite::Error> {
+        conn.call(|conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS job (
+                    id TEXT PRIMARY KEY,
+                    title TEXT NOT NULL,
+                    shop_id TEXT,
+                    schedule TEXT,
+                    paused INTEGER NOT NULL DEFAULT 0
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS job_run (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    job_id TEXT NOT NULL,
+                    started INTEGER NOT NULL,
+                    finished INTEGER,
+                    outcome TEXT NOT NULL,
+                    error TEXT,
+                    counts TEXT
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS job_run_job_idx ON job_run(job_id, started)",
+                [],
+            )?;
+            Ok(())
+        })
+        .await?;
+        Ok(Self { conn })
+    }
+}
+
+fn timestamp(t: OffsetDateTime) -> i64 {
+    t.unix_timestamp()
+}
+
+fn from_timestamp(t: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(t).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+fn run_from_row(row: &rusqlite::Row) -> rusqlite::Result<JobRun> {
+    let counts: Option<String> = row.get(6)?;
+    Ok(JobRun {
+        id: row.get(0)?,
+        job: row.get(1)?,
+        started: from_timestamp(row.get(2)?),
+        finished: row.get::<_, Option<i64>>(3)?.map(from_timestamp),
+        outcome: RunOutcome::from_db(row.get::<_, String>(4)?.as_str()),
+        error: row.get(5)?,
+        counts: counts
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default(),
+    })
+}
+
+#[async_trait]
+impl JobRepository for SqliteJobRepository {
+    async fn get(&self, id: &str) -> anyhow::Result<Option<JobDefinition>> {
+        let id = id.to_string();
+        let SqlWrapper(row) = self
+            .conn
+            .call(move |conn| {
+                let row = conn
+                    .query_row(
+                        "SELECT id, title, shop_id, schedule, paused FROM job WHERE id = ?1",
+                        params![id],
+                        |row| {
+                            Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, String>(1)?,
+                                row.get::<_, Option<String>>(2)?,
+                                row.get::<_, Option<String>>(3)?,
+                                row.get::<_, bool>(4)?,
+                            ))
+                        },
+                    )
+                    .optional()?;
+                Ok(SqlWrapper(row))
+            })
+            .await?;
+        let Some((id, title, shop, schedule, paused)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(JobDefinition {
+            id,
+            title,
+            shop: shop.and_then(|s| Uuid::parse_str(&s).ok()),
+            schedule: schedule
+                .map(|s| s.parse())
+                .transpose()
+                .log_error("Unable to parse persisted job schedule")
+                .flatten(),
+            paused,
+        }))
+    }
+
+    async fn save(&self, job: JobDefinition) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO job (id, title, shop_id, schedule, paused)
+                     VALUES (?1, ?2, ?3, ?4, ?5)
+                     ON CONFLICT(id) DO UPDATE SET
+                        title = excluded.title,
+                        shop_id = excluded.shop_id,
+                        schedule = excluded.schedule,
+                        paused = excluded.paused",
+                    params![
+                        job.id,
+                        job.title,
+                        job.shop.map(|s| s.to_string()),
+                        job.schedule.map(|s| s.to_string()),
+                        job.paused
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.conn
+            .call(move |conn| {
+                conn.execute("DELETE FROM job WHERE id = ?1", params![id])?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn start_run(&self, job: &str, started: OffsetDateTime) -> anyhow::Result<i64> {
+        let job = job.to_string();
+        let SqlWrapper(id) = self
+            .conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO job_run (job_id, started, outcome) VALUES (?1, ?2, ?3)",
+                    params![job, timestamp(started), RunOutcome::Running.as_str()],
+                )?;
+                Ok(SqlWrapper(conn.last_insert_rowid()))
+            })
+            .await?;
+        Ok(id)
+    }
+
+    async fn finish_run(&self, run: JobRun) -> anyhow::Result<()> {
+        let counts = serde_json::to_string(&run.counts)?;
+        self.conn
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE job_run SET finished = ?2, outcome = ?3, error = ?4, counts = ?5
+                     WHERE id = ?1",
+                    params![
+                        run.id,
+                        run.finished.map(timestamp),
+                        run.outcome.as_str(),
+                        run.error,
+                        counts
+                    ],
+                )?;
+                conn.execute(
+                    "DELETE FROM job_run WHERE job_id = ?1 AND id NOT IN (
+                        SELECT id FROM job_run WHERE job_id = ?1 ORDER BY started DESC LIMIT ?2
+                    )",
+                    params![run.job, RUNS_PER_JOB as i64],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn list_runs(&self, job: Option<String>, limit: usize) -> anyhow::Result<Vec<JobRun>> {
+        let SqlWrapper(runs) = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, job_id, started, finished, outcome, error, counts
+                     FROM job_run
+                     WHERE ?1 IS NULL OR job_id = ?1
+                     ORDER BY started DESC, id DESC
+                     LIMIT ?2",
+                )?;
+                let runs = stmt
+                    .query_map(params![job, limit as i64], run_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlWrapper(runs))
+            })
+            .await?;
+        Ok(runs)
+    }
+
+    async fn last_finished(&self, job: &str) -> anyhow::Result<Option<OffsetDateTime>> {
+        let job = job.to_string();
+        let SqlWrapper(finished) = self
+            .conn
+            .call(move |conn| {
+                let finished = conn.query_row(
+                    "SELECT MAX(finished) FROM job_run WHERE job_id = ?1",
+                    params![job],
+                    |row| row.get::<_, Option<i64>>(0),
+                )?;
+                Ok(SqlWrapper(finished))
+            })
+            .await?;
+        Ok(finished.map(from_timestamp))
+    }
+
+    async fn interrupt_running(&self) -> anyhow::Result<usize> {
+        let SqlWrapper(count) = self
+            .conn
+            .call(move |conn| {
+                let count = conn.execute(
+                    "UPDATE job_run SET outcome = ?1 WHERE outcome = ?2",
+                    params![
+                        RunOutcome::Interrupted.as_str(),
+                        RunOutcome::Running.as_str()
+                    ],
+                )?;
+                Ok(SqlWrapper(count))
+            })
+            .await?;
+        Ok(count)
+    }
+}
+
+struct JobHandle {
+    definition: JobDefinition,
+    class: JobClass,
+    default_schedule: Schedule,
+    trigger: Arc<Notify>,
+    changed: Arc<Notify>,
+    running: Option<(OffsetDateTime, CancellationToken)>,
+    last_finished: Option<OffsetDateTime>,
+    failures: u32,
+}
+
+impl JobHandle {
+    fn schedule(&self) -> &Schedule {
+        self.definition
+            .schedule
+            .as_ref()
+            .unwrap_or(&self.default_schedule)
+    }
+
+    fn next_run(&self, now: OffsetDateTime) -> Option<OffsetDateTime> {
+        let next = self.schedule().next_run(self.last_finished, now)?;
+        match (self.failures, self.last_finished) {
+            (0, _) | (_, None) => Some(next),
+            (n, Some(l)) => Some(next.max(l + failure_backoff(n))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub definition: JobDefinition,
+    pub schedule: Schedule,
+    pub default_schedule: Schedule,
+    pub running_since: Option<OffsetDateTime>,
+    pub next_run: Option<OffsetDateTime>,
+}
+
+pub struct JobScheduler {
+    repo: Arc<dyn JobRepository>,
+    jobs: RwLock<HashMap<String, JobHandle>>,
+    global: Semaphore,
+    per_shop: usize,
+    shops: Mutex<HashMap<Uuid, Arc<Semaphore>>>,
+}
+
+impl JobScheduler {
+    pub async fn new(
+        repo: Arc<dyn JobRepository>,
+        global: usize,
+        per_shop: usize,
+    ) -> Result<Self, anyhow::Error> {
+        let interrupted = repo.interrupt_running().await?;
+        if interrupted > 0 {
+            log::warn!("{interrupted} job runs were interrupted by restart");
+        }
+        Ok(Self {
+            repo,
+            jobs: RwLock::new(HashMap::new()),
+            global: Semaphore::new(global),
+            per_shop,
+            shops: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn from_env(repo: Arc<dyn JobRepository>) -> Result<Self, anyhow::Error> {
+        let limit = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+        Self::new(
+            repo,
+            limit("JOB_CONCURRENCY", 8),
+            limit("JOB_SHOP_CONCURRENCY", 2),
+        )
+        .await
+    }
+
+    // Пауза і розклад з панелі керування зберігаються між перезапусками
+    pub async fn register(
+        &self,
+        id: impl Into<String>,
+        title: impl Into<String>,
+        shop: Option<Uuid>,
+        class: JobClass,
+        schedule: Schedule,
+    ) {
+        let id = id.into();
+        let title = title.into();
+        let mut jobs = self.jobs.write().await;
+        if let Some(job) = jobs.get_mut(&id) {
+            let changed = job.default_schedule != schedule || job.definition.title != title;
+            job.default_schedule = schedule;
+            job.definition.title = title;
+            job.class = class;
+            if changed {
+                job.changed.notify_one();
+                self.repo
+                    .save(job.definition.clone())
+                    .await
+                    .log_error("Unable to save job");
+            }
+            return;
+        }
+        let stored = self
+            .repo
+            .get(&id)
+            .await
+            .log_error("Unable to read job")
+            .flatten();
+        let definition = JobDefinition {
+            id: id.clone(),
+            title,
+            shop,
+            schedule: stored.as_ref().and_then(|j| j.schedule.clone()),
+            paused: stored.is_some_and(|j| j.paused),
+        };
+        self.repo
+            .save(definition.clone())
+            .await
+            .log_error("Unable to save job");
+        let last_finished = self
+            .repo
+            .last_finished(&id)
+            .await
+            .log_error("Unable to read last job run")
+            .flatten();
+        jobs.insert(
+            id,
+            JobHandle {
+                definition,
+                class,
+                default_schedule: schedule,
+                trigger: Arc::new(Notify::new()),
+                changed: Arc::new(Notify::new()),
+                running: None,
+                last_finished,
+                failures: 0,
+            },
+        );
+    }
+
+    pub async fn unregister(&self, id: &str) {
+        if let Some(job) = self.jobs.write().await.remove(id) {
+            if let Some((_, token)) = job.running {
+                token.cancel();
+            }
+        }
+        self.repo.remove(id).await.log_error("Unable to remove job");
+    }
+
+    pub async fn wait_due(&self, id: &str) {
+        loop {
+            let (trigger, changed, waiting, next_run) = {
+                let jobs = self.jobs.read().await;
+                let Some(job) = jobs.get(id) else {
+                    return;
+                };
+                (
+                    job.trigger.clone(),
+                    job.changed.clone(),
+                    job.definition.paused || job.running.is_some(),
+                    job.next_run(OffsetDateTime::now_utc()),
+                )
+            };
+            if waiting {
+                tokio::select! {
+                    _ = trigger.notified() => return,
+                    _ = changed.notified() => continue,
+                }
+            }
+            let Some(next_run) = next_run else {
+                log::warn!("Job {id} has no upcoming runs");
+                tokio::select! {
+                    _ = trigger.notified() => return,
+                    _ = changed.notified() => continue,
+                }
+            };
+            let wait = (next_run - OffsetDateTime::now_utc())
+                .try_into()
+                .unwrap_or(Duration::ZERO);
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => return,
+                _ = trigger.notified() => return,
+                _ = changed.notified() => continue,
+            }
+        }
+    }
+
+    async fn shop_semaphore(&self, shop: Uuid) -> Arc<Semaphore> {
+        self.shops
+            .lock()
+            .await
+            .entry(shop)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_shop)))
+            .clone()
+    }
+
+    // Задача має зупинитись за токеном, інакше через CANCEL_GRACE її відкидають
+    pub async fn run<T, E, Fut>(
+        &self,
+        id: &str,
+        task: impl FnOnce(CancellationToken) -> Fut,
+    ) -> Option<Result<T, E>>
+    where
+        E: std::fmt::Display,
+        Fut: Future<Output = Result<(T, RunCounts), E>>,
+    {
+        let (shop, class) = self
+            .jobs
+            .read()
+            .await
+            .get(id)
+            .map(|j| (j.definition.shop, j.class))
+            .unwrap_or((None, JobClass::Task));
+        let _global = match class {
+            JobClass::Task => self
+                .global
+                .acquire()
+                .await
+                .log_error("Unable to acquire job permit"),
+            JobClass::Parser => None,
+        };
+        let _shop = match shop {
+            Some(shop) => self
+                .shop_semaphore(shop)
+                .await
+                .acquire_owned()
+                .await
+                .log_error("Unable to acquire shop job permit"),
+            None => None,
+        };
+        let token = CancellationToken::new();
+        let started = OffsetDateTime::now_utc();
+        if let Some(job) = self.jobs.write().await.get_mut(id) {
+            job.running = Some((started, token.clone()));
+        }
+        let run_id = self
+            .repo
+            .start_run(id, started)
+            .await
+            .log_error("Unable to record job run");
+
+        let task = task(token.clone());
+        tokio::pin!(task);
+        let res = tokio::select! {
+            res = &mut task => Some(res),
+            _ = token.cancelled() => None,
+        };
+        let res = match res {
+            Some(_) if token.is_cancelled() => None,
+            Some(res) => Some(res),
+            None => {
+                if tokio::time::timeout(CANCEL_GRACE, &mut task).await.is_err() {
+                    log::warn!("Job {id} didn't stop within {CANCEL_GRACE:?} after cancel");
+                }
+                None
+            }
+        };
+
+        let finished = OffsetDateTime::now_utc();
+        if let Some(job) = self.jobs.write().await.get_mut(id) {
+            job.running = None;
+            job.last_finished = Some(finished);
+            job.failures = match res {
+                Some(Err(_)) => job.failures.saturating_add(1),
+                _ => 0,
+            };
+            job.changed.notify_one();
+        }
+        let (outcome, error, counts, res) = match res {
+            Some(Ok((res, counts))) => (RunOutcome::Success, None, counts, Some(Ok(res))),
+            Some(Err(err)) => (
+                RunOutcome::Failure,
+                Some(err.to_string()),
+                RunCounts::new(),
+                Some(Err(err)),
+            ),
+            None => (RunOutcome::Cancelled, None, RunCounts::new(), None),
+        };
+        if let Some(run_id) = run_id {
+            self.repo
+                .finish_run(JobRun {
+                    id: run_id,
+                    job: id.to_string(),
+                    started,
+                    finished: Some(finished),
+                    outcome,
+                    error,
+                    counts,
+                })
+                .await
+                .log_error("Unable to record job run");
+        }
+        res
+    }
+
+    pub async fn spawn<F, Fut, E>(
+        self: &Arc<Self>,
+        id: &'static str,
+        title: &str,
+        class: JobClass,
+        schedule: Schedule,
+        task: F,
+    ) where
+        F: Fn(CancellationToken) -> Fut + 'static,
+        Fut: Future<Output = Result<RunCounts, E>>,
+        E: std::fmt::Display,
+    {
+        self.register(id, title, None, class, schedule).await;
+        let jobs = self.clone();
+        tokio::task::spawn_local(async move {
+            loop {
+                jobs.wait_due(id).await;
+                let res = jobs
+                    .run(id, |cancel| {
+                        let task = task(cancel);
+                        async move { task.await.map(|c| ((), c)) }
+                    })
+                    .await;
+                if let Some(Err(err)) = res {
+                    log::error!("Job {id} failed: {err}");
+                }
+            }
+        });
+    }
+
+    pub async fn trigger(&self, id: &str) -> bool {
+        let jobs = self.jobs.read().await;
+        let Some(job) = jobs.get(id) else {
+            return false;
+        };
+        job.trigger.notify_one();
+        true
+    }
+
+    pub async fn cancel(&self, id: &str) -> bool {
+        let jobs = self.jobs.read().await;
+        match jobs.get(id).and_then(|j| j.running.as_ref()) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn is_paused(&self, id: &str) -> bool {
+        self.jobs
+            .read()
+            .await
+            .get(id)
+            .is_some_and(|j| j.definition.paused)
+    }
+
+    pub async fn set_paused(&self, id: &str, paused: bool) -> Result<(), anyhow::Error> {
+        self.update(id, |d| d.paused = paused).await
+    }
+
+    pub async fn set_schedule(
+        &self,
+        id: &str,
+        schedule: Option<Schedule>,
+    ) -> Result<(), anyhow::Error> {
+        self.update(id, |d| d.schedule = schedule).await
+    }
+
+    async fn update(
+        &self,
+        id: &str,
+        f: impl FnOnce(&mut JobDefinition),
+    ) -> Result<(), anyhow::Error> {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("Job {id} not found"))?;
+        f(&mut job.definition);
+        self.repo.save(job.definition.clone()).await?;
+        job.changed.notify_one();
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Vec<JobInfo> {
+        let now = OffsetDateTime::now_utc();
+        let mut jobs = self
+            .jobs
+            .read()
+            .await
+            .values()
+            .map(|j| JobInfo {
+                definition: j.definition.clone(),
+                schedule: j.schedule().clone(),
+                default_schedule: j.default_schedule.clone(),
+                running_since: j.running.as_ref().map(|(s, _)| *s),
+                next_run: match (&j.running, j.definition.paused) {
+                    (None, false) => j.next_run(now),
+                    _ => None,
+                },
+            })
+            .collect::<Vec<_>>();
+        jobs.sort_by(|a, b| a.definition.title.cmp(&b.definition.title));
+        jobs
+    }
+
+    pub async fn runs(&self, id: Option<String>, limit: usize) -> anyhow::Result<Vec<JobRun>> {
+        self.repo.list_runs(id, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn parses_schedules_and_finds_next_run() {
+        assert_eq!(
+            "6h".parse::<Schedule>().unwrap(),
+            Schedule::Interval(Duration::from_secs(6 * 3600))
+        );
+        assert_eq!("90m".parse::<Schedule>().unwrap().to_string(), "90m");
+        assert_eq!("120m".parse::<Schedule>().unwrap().to_string(), "2h");
+        assert!("6".parse::<Schedule>().is_err());
+        assert!("* * *".parse::<Schedule>().is_err());
+        assert!("61 * * * *".parse::<Schedule>().is_err());
+        assert!("0m".parse::<Schedule>().is_err());
+        assert!("18446744073709551615d".parse::<Schedule>().is_err());
+        assert!("400d".parse::<Schedule>().is_err());
+
+        let last = datetime!(2024-03-10 10:00 UTC);
+        let interval = Schedule::Interval(Duration::from_secs(3600));
+        assert_eq!(
+            interval.next_run(Some(last), last),
+            Some(datetime!(2024-03-10 11:00 UTC))
+        );
+        assert_eq!(interval.next_run(None, last), Some(last));
+
+        // Кожні 4 години за Києвом, взимку UTC+2
+        let cron: Cron = "0 */4 * * *".parse().unwrap();
+        assert_eq!(
+            cron.next_after(datetime!(2024-01-15 10:30 UTC)),
+            Some(datetime!(2024-01-15 14:00 UTC))
+        );
+        let cron: Cron = "30 3 1 * 1".parse().unwrap();
+        assert_eq!(
+            cron.next_after(datetime!(2024-01-15 02:00 UTC)),
+            Some(datetime!(2024-01-22 01:30 UTC))
+        );
+        assert_eq!(
+            cron.next_after(datetime!(2024-01-29 02:00 UTC)),
+            Some(datetime!(2024-02-01 01:30 UTC))
+        );
+        // 03:30 немає в ніч переходу на літній час
+        let cron: Cron = "30 3 * * 7".parse().unwrap();
+        assert_eq!(
+            cron.next_after(datetime!(2024-03-30 12:00 UTC)),
+            Some(datetime!(2024-04-07 00:30 UTC))
+        );
+        assert_eq!("0 0 30 2 *".parse::<Cron>().unwrap().next_after(last), None);
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_failed_runs() {
+        assert_eq!(failure_backoff(0), Duration::ZERO);
+        assert_eq!(failure_backoff(3), Duration::from_secs(240));
+        assert_eq!(failure_backoff(100), MAX_FAILURE_BACKOFF);
+
+        let repo = SqliteJobRepository::init(Connection::open_in_memory().await.unwrap())
+            .await
+            .unwrap();
+        let jobs = JobScheduler::new(Arc::new(repo), 1, 1).await.unwrap();
+        jobs.register(
+            "parser",
+            "Parser",
+            None,
+            JobClass::Parser,
+            Schedule::Interval(Duration::ZERO),
+        )
+        .await;
+        for failures in 1..=2 {
+            let res = jobs
+                .run("parser", |_| async {
+                    Err::<((), RunCounts), _>("unreachable")
+                })
+                .await;
+            assert!(matches!(res, Some(Err(_))));
+            let wait = jobs.list().await[0].next_run.unwrap() - OffsetDateTime::now_utc();
+            assert!(wait > time::Duration::seconds(50 * failures));
+        }
+        jobs.run("parser", |_| async {
+            Ok::<_, String>(((), RunCounts::new()))
+        })
+        .await;
+        assert!(jobs.list().await[0].next_run.unwrap() <= OffsetDateTime::now_utc());
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Context as AnyhowContext;
 use async_trait::async_trait;
-use rt_types::shop::{Shop, ShopRepository};
+use rt_types::shop::{CurrencySettings, Shop, ShopRepository};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::os::unix::fs::PermissionsExt;
 use typesafe_repository::{
     async_ops::{Get, List, Remove, Save},
@@ -100,6 +103,31 @@ impl ShopRepository for FileSystemShopRepository {}
 
 const CONFIG_DIR: &str = "cfg.d";
 
+pub fn currency_settings(id: &IdentityOf<Shop>) -> CurrencySettings {
+    match read_shop(id) {
+        Ok(shop) => shop.currency,
+        Err(err) => {
+            log::warn!("Unable to read currency settings of shop {id}: {err}");
+            CurrencySettings::default()
+        }
+    }
+}
+
+pub fn apply_currency_settings_f64(
+    settings: &CurrencySettings,
+    rates: HashMap<String, f64>,
+) -> HashMap<String, f64> {
+    let rates = rates
+        .into_iter()
+        .filter_map(|(code, rate)| Some((code, Decimal::try_from(rate).ok()?)))
+        .collect();
+    settings
+        .apply_to_supplier_rates(rates)
+        .into_iter()
+        .filter_map(|(code, rate)| Some((code, rate.to_f64()?)))
+        .collect()
+}
+
 pub fn read_shop(id: &IdentityOf<Shop>) -> Result<Shop, anyhow::Error> {
     let config = std::fs::read_to_string(format!("{CONFIG_DIR}/{id}.yml"))?;
     let value = serde_json::from_str(&config)?;
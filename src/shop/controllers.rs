@@ -1,12 +1,14 @@
 use crate::control::{
-    render_template, see_other, ControlPanelAccess, Record, Response, ShopAccess,
+    render_template, see_other, ControlPanelAccess, ControllerError, Record, Response, ShopAccess,
 };
 use crate::export::{self, ExportService};
+use crate::nova_poshta::NovaPoshtaRepository;
 use actix::prelude::*;
-use actix_web::web::{Data, Form, Path};
+use actix_web::web::{Bytes, Data, Form, Path};
 use actix_web::{get, post};
 use anyhow::Context as AnyhowContext;
 use askama::Template;
+use currency_service::{CurrencyService, ListRates};
 use rt_types::access::UserCredentials;
 use rt_types::shop::{
     service::{CreateShopPermission, ShopService},
@@ -14,8 +16,14 @@ use rt_types::shop::{
 };
 use rt_types::subscription::service::SubscriptionService;
 use rt_types::{shop, subscription};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::Arc;
 use typesafe_repository::IdentityOf;
+use url::form_urlencoded;
 use uuid::Uuid;
 
 #[derive(Template)]
@@ -58,6 +66,12 @@ async fn add_shop(
         limits,
         default_custom_options: None,
         image_proxy: false,
+        currency: Default::default(),
+        watermark_hosts: vec![],
+        prom: Default::default(),
+        reviews: Default::default(),
+        nova_poshta: Default::default(),
+        feeds: Default::default(),
     };
     let shops = shop_service
         .send(shop::service::ListBy(user.login.clone()))
@@ -94,11 +108,47 @@ async fn remove_shop(
 pub struct SettingsPage {
     shop: Shop,
     user: UserCredentials,
+    currency_rates: Vec<CurrencyRateView>,
+    review_moderations: [shop::ReviewModeration; 3],
+    nova_poshta_payers: [shop::NovaPoshtaPayer; 2],
+}
+
+pub struct CurrencyRateView {
+    code: String,
+    rule: shop::CurrencyRule,
+    base: Option<Decimal>,
+    effective: Option<Decimal>,
 }
 
 #[get("/shop/{shop_id}/settings")]
-async fn settings_page(ShopAccess { shop, user }: ShopAccess) -> Response {
-    render_template(SettingsPage { shop, user })
+async fn settings_page(
+    currency_service: Data<Addr<CurrencyService>>,
+    ShopAccess { shop, user }: ShopAccess,
+) -> Response {
+    let base_rates = currency_service.send(ListRates).await.unwrap_or_default();
+    let mut codes = base_rates.keys().cloned().collect::<Vec<_>>();
+    codes.extend(shop.currency.currencies.keys().cloned());
+    codes.sort();
+    codes.dedup();
+    let currency_rates = codes
+        .into_iter()
+        .map(|code| {
+            let base = base_rates.get(&code).copied();
+            CurrencyRateView {
+                effective: shop.currency.effective_rate(&code, base),
+                rule: shop.currency.rule(&code).cloned().unwrap_or_default(),
+                base,
+                code,
+            }
+        })
+        .collect();
+    render_template(SettingsPage {
+        shop,
+        user,
+        currency_rates,
+        review_moderations: shop::ReviewModeration::ALL,
+        nova_poshta_payers: shop::NovaPoshtaPayer::ALL,
+    })
 }
 
 #[derive(Deserialize, Debug)]
@@ -132,6 +182,290 @@ async fn update_settings(
     Ok(see_other(&format!("/shop/{shop_id}/settings")))
 }
 
+fn parse_decimal(field: &str, raw: Option<&String>) -> Result<Option<Decimal>, ControllerError> {
+    let Some(raw) = raw
+        .map(|v| v.trim().replace(',', "."))
+        .filter(|v| !v.is_empty())
+    else {
+        return Ok(None);
+    };
+    Decimal::from_str(&raw)
+        .map(Some)
+        .map_err(|_| ControllerError::InvalidInput {
+            field: field.to_string(),
+            msg: format!("Некорректное число: {raw}"),
+        })
+}
+
+const MIN_MARKUP_PERCENT: Decimal = dec!(-100);
+
+fn parse_currency_settings(body: &[u8]) -> Result<shop::CurrencySettings, ControllerError> {
+    let mut params = HashMap::<String, Vec<String>>::new();
+    for (key, value) in form_urlencoded::parse(body) {
+        params
+            .entry(key.into_owned())
+            .or_default()
+            .push(value.into_owned());
+    }
+    let column = |name: &str| params.get(name).cloned().unwrap_or_default();
+    let (markups, manual, min, max) = (
+        column("markup"),
+        column("manual_rate"),
+        column("min_rate"),
+        column("max_rate"),
+    );
+    let invalid = |field: &str, msg: String| ControllerError::InvalidInput {
+        field: field.to_string(),
+        msg,
+    };
+    let mut currencies = BTreeMap::new();
+    for (i, code) in column("code").into_iter().enumerate() {
+        let code = code.trim().to_uppercase();
+        if code.is_empty() {
+            continue;
+        }
+        let rule = shop::CurrencyRule {
+            markup_percent: parse_decimal("markup", markups.get(i))?,
+            manual_rate: parse_decimal("manual_rate", manual.get(i))?,
+            min_rate: parse_decimal("min_rate", min.get(i))?,
+            max_rate: parse_decimal("max_rate", max.get(i))?,
+        };
+        if rule.markup_percent.is_some_and(|m| m <= MIN_MARKUP_PERCENT) {
+            return Err(invalid(
+                "markup",
+                format!("Наценка {code} должна быть больше {MIN_MARKUP_PERCENT}%"),
+            ));
+        }
+        for (field, rate) in [
+            ("manual_rate", rule.manual_rate),
+            ("min_rate", rule.min_rate),
+            ("max_rate", rule.max_rate),
+        ] {
+            if rate.is_some_and(|r| r <= Decimal::ZERO) {
+                return Err(invalid(
+                    field,
+                    format!("Курс {code} ({field}) должен быть больше нуля"),
+                ));
+            }
+        }
+        if let (Some(min), Some(max)) = (rule.min_rate, rule.max_rate) {
+            if min > max {
+                return Err(invalid(
+                    "min_rate",
+                    format!("Min {code} ({min}) больше max ({max})"),
+                ));
+            }
+        }
+        if rule != shop::CurrencyRule::default() {
+            currencies.insert(code, rule);
+        }
+    }
+    let default_markup_percent = parse_decimal(
+        "default_markup",
+        params.get("default_markup").and_then(|v| v.first()),
+    )?;
+    if default_markup_percent.is_some_and(|m| m <= MIN_MARKUP_PERCENT) {
+        return Err(invalid(
+            "default_markup",
+            format!("Наценка должна быть больше {MIN_MARKUP_PERCENT}%"),
+        ));
+    }
+    Ok(shop::CurrencySettings {
+        default_markup_percent,
+        currencies,
+    })
+}
+
+#[post("/shop/{shop_id}/settings/currency")]
+async fn update_currency_settings(
+    body: Bytes,
+    shop_service: Data<Addr<ShopService>>,
+    ShopAccess { mut shop, .. }: ShopAccess,
+) -> Response {
+    shop.currency = parse_currency_settings(&body)?;
+    let shop_id = shop.id;
+    shop_service
+        .send(shop::service::Update(shop))
+        .await?
+        .context("Unable to update shop")?;
+    Ok(see_other(&format!("/shop/{shop_id}/settings")))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PromSettingsDto {
+    pub api_token: Option<String>,
+    pub remove_token: Option<String>,
+}
+
+#[post("/shop/{shop_id}/settings/prom")]
+async fn update_prom_settings(
+    dto: Form<PromSettingsDto>,
+    shop_service: Data<Addr<ShopService>>,
+    ShopAccess { mut shop, .. }: ShopAccess,
+) -> Response {
+    let dto = dto.into_inner();
+    // Збережений токен на сторінку не віддаємо, порожнє поле його зберігає
+    let token = dto
+        .api_token
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    if dto.remove_token.is_some() {
+        shop.prom.api_token = None;
+    } else if token.is_some() {
+        shop.prom.api_token = token;
+    }
+    let shop_id = shop.id;
+    shop_service
+        .send(shop::service::Update(shop))
+        .await?
+        .context("Unable to update shop")?;
+    Ok(see_other(&format!("/shop/{shop_id}/settings")))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReviewSettingsDto {
+    pub moderation: String,
+    pub stop_words: Option<String>,
+}
+
+#[post("/shop/{shop_id}/settings/reviews")]
+async fn update_review_settings(
+    dto: Form<ReviewSettingsDto>,
+    shop_service: Data<Addr<ShopService>>,
+    ShopAccess { mut shop, .. }: ShopAccess,
+) -> Response {
+    let dto = dto.into_inner();
+    shop.reviews = shop::ReviewSettings {
+        moderation: dto.moderation.parse()?,
+        stop_words: dto
+            .stop_words
+            .unwrap_or_default()
+            .split([',', '\n'])
+            .map(|w| w.trim().to_string())
+            .filter(|w| !w.is_empty())
+            .collect(),
+    };
+    let shop_id = shop.id;
+    shop_service
+        .send(shop::service::Update(shop))
+        .await?
+        .context("Unable to update shop")?;
+    Ok(see_other(&format!("/shop/{shop_id}/settings")))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FeedSettingsDto {
+    pub facebook: Option<String>,
+    pub google_merchant: Option<String>,
+    pub rozetka: Option<String>,
+}
+
+#[post("/shop/{shop_id}/settings/feeds")]
+async fn update_feed_settings(
+    dto: Form<FeedSettingsDto>,
+    shop_service: Data<Addr<ShopService>>,
+    ShopAccess { mut shop, .. }: ShopAccess,
+) -> Response {
+    let dto = dto.into_inner();
+    shop.feeds = shop::FeedSettings {
+        facebook: dto.facebook.is_some(),
+        google_merchant: dto.google_merchant.is_some(),
+        rozetka: dto.rozetka.is_some(),
+    };
+    let shop_id = shop.id;
+    shop_service
+        .send(shop::service::Update(shop))
+        .await?
+        .context("Unable to update shop")?;
+    Ok(see_other(&format!("/shop/{shop_id}/settings")))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NovaPoshtaSettingsDto {
+    pub api_key: Option<String>,
+    pub remove_key: Option<String>,
+    // Порожні обидва — лишається поточне відділення
+    pub sender_city: Option<String>,
+    pub sender_warehouse: Option<String>,
+    pub sender_phone: Option<String>,
+    pub default_weight: Option<String>,
+    pub payer: String,
+}
+
+#[post("/shop/{shop_id}/settings/nova_poshta")]
+async fn update_nova_poshta_settings(
+    dto: Form<NovaPoshtaSettingsDto>,
+    shop_service: Data<Addr<ShopService>>,
+    np_repo: Data<Arc<dyn NovaPoshtaRepository>>,
+    ShopAccess { mut shop, .. }: ShopAccess,
+) -> Response {
+    let dto = dto.into_inner();
+    let non_empty = |v: Option<String>| v.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let invalid = |field: &str, msg: String| ControllerError::InvalidInput {
+        field: field.to_string(),
+        msg,
+    };
+    let settings = &mut shop.nova_poshta;
+    // Збережений ключ на сторінку не віддаємо, порожнє поле його зберігає
+    let api_key = non_empty(dto.api_key);
+    if dto.remove_key.is_some() {
+        settings.api_key = None;
+    } else if api_key.is_some() {
+        settings.api_key = api_key;
+    }
+    settings.sender_phone = non_empty(dto.sender_phone);
+    settings.default_weight = parse_decimal("default_weight", dto.default_weight.as_ref())?;
+    settings.payer = dto.payer.parse()?;
+    match (non_empty(dto.sender_city), non_empty(dto.sender_warehouse)) {
+        (None, None) => (),
+        (Some(city), Some(number)) => {
+            let cities = np_repo.search_cities(&city, 20).await?;
+            let city = cities
+                .iter()
+                .find(|c| c.name.to_lowercase() == city.to_lowercase())
+                .ok_or_else(|| {
+                    let similar = cities
+                        .iter()
+                        .take(5)
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>();
+                    let msg = if similar.is_empty() {
+                        format!("Город {city} не найден")
+                    } else {
+                        format!("Город {city} не найден, похожие: {}", similar.join(", "))
+                    };
+                    invalid("sender_city", msg)
+                })?;
+            let warehouse = np_repo
+                .search_warehouses(&city.city_ref, &number, 1)
+                .await?
+                .into_iter()
+                .find(|w| w.number == number)
+                .ok_or_else(|| {
+                    invalid(
+                        "sender_warehouse",
+                        format!("Отделение №{number} в городе {} не найдено", city.name),
+                    )
+                })?;
+            settings.sender_city_ref = Some(city.city_ref.clone());
+            settings.sender_warehouse_name = Some(format!("{}, {}", city.name, warehouse.name));
+            settings.sender_warehouse_ref = Some(warehouse.warehouse_ref);
+        }
+        _ => {
+            return Err(invalid(
+                "sender_warehouse",
+                "Укажите и город, и номер отделения".to_string(),
+            ))
+        }
+    }
+    let shop_id = shop.id;
+    shop_service
+        .send(shop::service::Update(shop))
+        .await?
+        .context("Unable to update shop")?;
+    Ok(see_other(&format!("/shop/{shop_id}/settings")))
+}
+
 #[post("/control_panel/shops/{shop_id}/suspend_toggle")]
 async fn shop_suspend_toggle(
     ControlPanelAccess { .. }: ControlPanelAccess,
@@ -228,6 +228,7 @@ fn ensure_columns(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
 pub trait ShopProductRepository: Send + Sync {
     async fn list_by_shop(&self, shop_id: Uuid) -> anyhow::Result<Vec<ShopProduct>>;
     async fn get(&self, shop_id: Uuid, article: &str) -> anyhow::Result<Option<ShopProduct>>;
+    async fn has_image(&self, shop_id: Uuid, image: &str) -> anyhow::Result<bool>;
     async fn upsert(&self, product: ShopProduct) -> anyhow::Result<()>;
     async fn ensure_exists(&self, shop_id: Uuid, article: &str) -> anyhow::Result<()>;
     async fn set_site_category(
@@ -398,6 +399,21 @@ impl ShopProductRepository for SqliteShopProductRepository {
             .context("Unable to list shop products")
     }
 
+    async fn has_image(&self, shop_id: Uuid, image: &str) -> anyhow::Result<bool> {
+        let image = image.trim().to_string();
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT 1 FROM shop_product
+                     WHERE shop_id = ?1 AND instr(',' || images || ',', ',' || ?2 || ',') > 0
+                     LIMIT 1",
+                )?;
+                Ok(stmt.exists([shop_id.to_string(), image])?)
+            })
+            .await
+            .context("Unable to check shop product images")
+    }
+
     async fn get(&self, shop_id: Uuid, article: &str) -> anyhow::Result<Option<ShopProduct>> {
         let article = article.to_string();
         self.conn
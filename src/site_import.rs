@@ -5,13 +5,11 @@ use log_error::LogError;
 use reqwest::Client;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
 use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use time::OffsetDateTime;
 use tokio::sync::{broadcast, Notify, RwLock};
-use tokio::time::sleep;
 use typesafe_repository::IdentityOf;
 
 use crate::dt;
@@ -20,6 +18,7 @@ use crate::external_import::Vendored;
 use crate::product_category;
 use crate::product_category_auto;
 use crate::restal;
+use crate::scheduler::{JobClass, JobScheduler, RunCounts, Schedule};
 use crate::shop_product;
 use crate::site_publish;
 use crate::import_throttle;
@@ -32,6 +31,7 @@ use rt_types::shop::{
     MissingProductPolicy, SiteImportEntry, SiteImportOptions, SiteImportSource,
     SiteImportUpdateFields,
 };
+use rt_types::pricing::{self, PriceSubject};
 use rt_types::{Availability, DescriptionOptions};
 
 const MAX_RETRY_COUNT: usize = 3;
@@ -91,6 +91,7 @@ pub struct SiteImportService {
     product_category_repo: Arc<dyn product_category::ProductCategoryRepository>,
     shop_service: Addr<ShopService>,
     currency_service: Addr<CurrencyService>,
+    jobs: Arc<JobScheduler>,
     import: HashMap<String, Arc<RwLock<SiteImport>>>,
 }
 
@@ -104,6 +105,7 @@ impl SiteImportService {
         product_category_repo: Arc<dyn product_category::ProductCategoryRepository>,
         shop_service: Addr<ShopService>,
         currency_service: Addr<CurrencyService>,
+        jobs: Arc<JobScheduler>,
     ) -> Self {
         Self {
             client,
@@ -114,6 +116,7 @@ impl SiteImportService {
             product_category_repo,
             shop_service,
             currency_service,
+            jobs,
             import: HashMap::new(),
         }
     }
@@ -140,6 +143,7 @@ impl SiteImportService {
         category_repo: Arc<dyn CategoryRepository>,
         product_category_repo: Arc<dyn product_category::ProductCategoryRepository>,
         currency_service: Addr<CurrencyService>,
+        jobs: Arc<JobScheduler>,
     ) {
         let (mut entry, start_notify, stop_notify, shop, mut rx) = {
             let e = import.read().await;
@@ -151,13 +155,26 @@ impl SiteImportService {
                 e.suspend_tx.subscribe(),
             )
         };
+        let job_id = format!("site_import:{shop}:{}", entry.created_time.unix_timestamp());
+        register_import_job(&jobs, &job_id, shop, &entry).await;
         let mut retry_count = 0;
+        if jobs.is_paused(&job_id).await {
+            tokio::select! {
+                _ = jobs.wait_due(&job_id) => (),
+                _ = start_notify.notified() => (),
+                _ = stop_notify.notified() => {
+                    jobs.unregister(&job_id).await;
+                    return;
+                }
+            }
+        }
         loop {
             {
                 let mut state = import.write().await;
                 state.status = SiteImportStatus::Enqueued;
                 if entry != state.entry {
                     entry = state.entry.clone();
+                    register_import_job(&jobs, &job_id, shop, &entry).await;
                 }
                 if let Some(true) = rx.try_recv().log_error("Unable to read suspend rx") {
                     state.status = SiteImportStatus::Suspended;
@@ -178,37 +195,54 @@ impl SiteImportService {
                             let mut state = import.write().await;
                             state.armed = true;
                         }
-                        _ = stop_notify.notified() => return,
+                        _ = stop_notify.notified() => {
+                            jobs.unregister(&job_id).await;
+                            return;
+                        }
                     }
                     continue;
                 }
             }
 
             let _permit = import_throttle::acquire_import_permit().await;
-            let (res, _) = tokio::join!(
-                do_import(
-                    &entry,
-                    shop,
-                    client.clone(),
-                    dt_repo.clone(),
-                    shop_product_repo.clone(),
-                    category_repo.clone(),
-                    product_category_repo.clone(),
-                    currency_service.clone(),
-                    import.clone(),
-                ),
-                async {
-                    let mut import = import.write().await;
-                    import.status = SiteImportStatus::InProgress;
-                }
-            );
+            let res = jobs
+                .run(&job_id, |_| async {
+                    let (res, _) = tokio::join!(
+                        do_import(
+                            &entry,
+                            shop,
+                            client.clone(),
+                            dt_repo.clone(),
+                            shop_product_repo.clone(),
+                            category_repo.clone(),
+                            product_category_repo.clone(),
+                            currency_service.clone(),
+                            import.clone(),
+                        ),
+                        async {
+                            let mut import = import.write().await;
+                            import.status = SiteImportStatus::InProgress;
+                        }
+                    );
+                    res.map(|count| {
+                        (
+                            (),
+                            RunCounts::from([("products".to_string(), count as u64)]),
+                        )
+                    })
+                })
+                .await;
 
             let status = match res {
-                Ok(_) => {
+                None => {
+                    retry_count = 0;
+                    SiteImportStatus::Failure("імпорт скасовано".to_string())
+                }
+                Some(Ok(_)) => {
                     retry_count = 0;
                     SiteImportStatus::Success
                 }
-                Err(err) => {
+                Some(Err(err)) => {
                     log::error!("Site import failed: {err}");
                     if retry_count < MAX_RETRY_COUNT {
                         retry_count += 1;
@@ -226,14 +260,40 @@ impl SiteImportService {
             }
 
             tokio::select! {
-                _ = sleep(entry.update_rate) => (),
+                _ = jobs.wait_due(&job_id) => (),
                 _ = start_notify.notified() => (),
-                _ = stop_notify.notified() => return,
+                _ = stop_notify.notified() => {
+                    jobs.unregister(&job_id).await;
+                    return;
+                }
             }
         }
     }
 }
 
+// Задача за часом створення запису, бо хеш змінюється при кожному редагуванні
+async fn register_import_job(
+    jobs: &JobScheduler,
+    id: &str,
+    shop: IdentityOf<rt_types::shop::Shop>,
+    entry: &SiteImportEntry,
+) {
+    let title = match (&entry.name, &entry.source) {
+        (Some(name), _) => name.clone(),
+        (None, SiteImportSource::Parsing { supplier }) => supplier.clone(),
+        (None, SiteImportSource::Xml { link, .. }) => link.clone(),
+        (None, SiteImportSource::RestalApi) => "Restal API".to_string(),
+    };
+    jobs.register(
+        id,
+        format!("Импорт {title}"),
+        Some(shop),
+        JobClass::Task,
+        Schedule::Interval(entry.update_rate),
+    )
+    .await;
+}
+
 impl Actor for SiteImportService {
     type Context = Context<Self>;
 
@@ -266,6 +326,7 @@ impl Actor for SiteImportService {
                 self.category_repo.clone(),
                 self.product_category_repo.clone(),
                 self.currency_service.clone(),
+                self.jobs.clone(),
             ));
         }
         Context::new().run(self)
@@ -352,6 +413,7 @@ impl Handler<Add> for SiteImportService {
         let product_category_repo = self.product_category_repo.clone();
         let client = self.client.clone();
         let currency_service = self.currency_service.clone();
+        let jobs = self.jobs.clone();
 
         let fut = async move {
             let mut shop = shop_service
@@ -401,6 +463,7 @@ impl Handler<Add> for SiteImportService {
                 category_repo,
                 product_category_repo,
                 currency_service,
+                jobs,
             ));
             Ok(hash)
         }))
@@ -548,13 +611,10 @@ async fn do_import(
     product_category_repo: Arc<dyn product_category::ProductCategoryRepository>,
     currency_service: Addr<CurrencyService>,
     import_handle: Arc<RwLock<SiteImport>>,
-) -> Result<(), anyhow::Error> {
+) -> Result<usize, anyhow::Error> {
     let supplier_key = entry.supplier_key();
     let rates = match currency_service.send(ListRates).await {
-        Ok(rates) => rates
-            .into_iter()
-            .map(|(k, v)| (k, v * dec!(1.07)))
-            .collect::<HashMap<_, _>>(),
+        Ok(rates) => crate::shop::currency_settings(&shop_id).apply(rates),
         Err(err) => {
             log::error!("Unable to list rates: {err}");
             HashMap::new()
@@ -750,7 +810,7 @@ async fn do_import(
         }
     }
 
-    Ok(())
+    Ok(total)
 }
 
 fn matches_supplier(p: &dt::product::Product, supplier: Option<&str>) -> bool {
@@ -999,8 +1059,14 @@ fn apply_transform_dt_product(
     shop_id: IdentityOf<rt_types::shop::Shop>,
 ) -> dt::product::Product {
     let base_price = p.price.map(|v| Decimal::from(v as i64));
+    let subject = PriceSubject {
+        vendor: p.supplier.as_deref().unwrap_or_default(),
+        category: p.category.as_deref(),
+        brand: &p.brand,
+        article: &p.article,
+    };
     let (final_price, source_price, discount_percent) =
-        compute_prices(base_price, "UAH", &opts, rates);
+        compute_prices(base_price, "UAH", &subject, &opts, rates);
     p.source_price = source_price;
     if opts.update_fields.price {
         p.price = final_price;
@@ -1099,8 +1165,14 @@ fn build_import_product(
         &shop_id,
         true,
     );
+    let subject = PriceSubject {
+        vendor: &p.vendor,
+        category: category.as_deref(),
+        brand: &brand,
+        article: &p.article,
+    };
     let (final_price, source_price, discount_percent) =
-        compute_prices(Some(p.price), &p.currency, opts, rates);
+        compute_prices(Some(p.price), &p.currency, &subject, opts, rates);
     let mut attributes = attrs.clone();
     if opts.transform.add_vendor {
         let supplier_label = supplier_key
@@ -1189,6 +1261,7 @@ fn resolve_description(
 fn compute_prices(
     base: Option<Decimal>,
     currency: &str,
+    subject: &PriceSubject,
     opts: &SiteImportOptions,
     rates: &HashMap<String, Decimal>,
 ) -> (Option<usize>, Option<usize>, Option<usize>) {
@@ -1204,37 +1277,21 @@ fn compute_prices(
         }
     }
     let base_uah = price.round().to_i64().unwrap_or(0).max(0) as usize;
-    let mut final_price = Decimal::from(base_uah as i64);
-    if let Some(mult) = opts.transform.adjust_price {
-        final_price *= mult;
-    }
-    let mut discount_percent = None;
-    if opts.update_fields.discounts {
-        if let Some(discount) = &opts.transform.discount {
-            let percent = discount.percent.min(100);
-            discount_percent = Some(percent);
-            let factor = Decimal::from(100u64.saturating_sub(percent as u64)) / Decimal::from(100u64);
-            final_price *= factor;
-        }
-    }
-    let mut final_price = final_price.round().to_i64().unwrap_or(0).max(0) as usize;
-    if opts.round_to_9 {
-        final_price = round_price_to_9(final_price);
-    }
+    let source_price = Decimal::from(base_uah as i64);
+    let discount_percent = opts
+        .transform
+        .discount
+        .as_ref()
+        .filter(|_| opts.update_fields.discounts)
+        .map(|d| d.percent.min(100));
+    let mut rules =
+        pricing::with_adjust_price(opts.transform.adjust_price, &opts.transform.pricing);
+    rules.extend(pricing::legacy_rules(None, discount_percent, opts.round_to_9));
+    let final_price = pricing::apply_rules(&rules, subject, source_price, source_price);
+    let final_price = final_price.round().to_i64().unwrap_or(0).max(0) as usize;
     (Some(final_price), Some(base_uah), discount_percent)
 }
 
-fn round_price_to_9(value: usize) -> usize {
-    if value == 0 {
-        return 0;
-    }
-    if value < 10 {
-        return 9;
-    }
-    let base = value - (value % 10);
-    base + 9
-}
-
 async fn apply_missing_policy(
     policy: &MissingProductPolicy,
     shop_id: IdentityOf<rt_types::shop::Shop>,
@@ -1312,8 +1369,6 @@ fn map_restal_product(
         .price
         .as_ref()
         .and_then(|s| s.parse::<Decimal>().ok());
-    let (final_price, source_price, discount_percent) =
-        compute_prices(base_price, "UAH", opts, rates);
     let available = match src.quantity.as_ref().and_then(|s| s.parse::<i64>().ok()) {
         Some(q) if q > 0 => Availability::Available,
         Some(_) => Availability::NotAvailable,
@@ -1334,6 +1389,14 @@ fn map_restal_product(
                 (fallback_brand, fallback_model, None)
             },
         );
+    let subject = PriceSubject {
+        vendor: "restal",
+        category: category.as_deref(),
+        brand: &brand,
+        article: &article,
+    };
+    let (final_price, source_price, discount_percent) =
+        compute_prices(base_price, "UAH", &subject, opts, rates);
     let model = Model(model);
     let url = Url(format!("/restal/{}.html", article));
     let mut images = src.images.clone();
@@ -309,6 +309,44 @@ pub fn load_restal_key(shop_id: &IdentityOf<rt_types::shop::Shop>) -> Option<Str
         .map(|s| s.trim().to_string())
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct SiteApiConfig {
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub slug: Option<String>,
+}
+
+fn site_api_cfg_path(shop_id: &IdentityOf<rt_types::shop::Shop>) -> PathBuf {
+    PathBuf::from("cfg.d").join(format!("site_api_{shop_id}.json"))
+}
+
+pub fn load_site_api_config(shop_id: &IdentityOf<rt_types::shop::Shop>) -> SiteApiConfig {
+    let data = match fs::read_to_string(site_api_cfg_path(shop_id)) {
+        Ok(v) => v,
+        Err(_) => return SiteApiConfig::default(),
+    };
+    serde_json::from_str(&data)
+        .or_else(|_| serde_yaml::from_str(&data))
+        .unwrap_or_default()
+}
+
+pub fn save_site_api_config(
+    shop_id: &IdentityOf<rt_types::shop::Shop>,
+    config: &SiteApiConfig,
+) -> anyhow::Result<()> {
+    let path = site_api_cfg_path(shop_id);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let payload = serde_json::to_string_pretty(config)?;
+    fs::write(&path, payload)
+        .with_context(|| format!("Unable to write site api config to {path:?}"))?;
+    Ok(())
+}
+
 pub fn load_site_publish_suppliers(shop_id: &IdentityOf<rt_types::shop::Shop>) -> Vec<String> {
     let path = cfg_path(shop_id);
     let data = match fs::read_to_string(&path) {
@@ -0,0 +1,537 @@
+use crate::seo_page::slugify_latin;
+use rt_types::Availability;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+// Закінчення українських і російських слів, найдовші першими
+const ENDINGS: &[&str] = &[
+    "иями", "ями", "ами", "ого", "его", "ому", "ему", "ыми", "ими", "ах", "ях", "ов", "ев", "ів",
+    "їв", "ом", "ем", "ам", "ям", "ою", "ею", "ая", "яя", "ое", "ее", "ые", "ие", "ый", "ий", "ій",
+    "ой", "ей", "ої", "ую", "юю", "ию", "ия", "ья", "а", "я", "о", "е", "ы", "и", "і", "ї", "у",
+    "ю", "ь", "й", "є",
+];
+
+const MIN_STEM_CHARS: usize = 3;
+const MAX_PREFIX_TERMS: usize = 200;
+
+// Порядок полів — порядок ранжування
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Field {
+    Description,
+    BrandModel,
+    Title,
+    Article,
+}
+
+impl Field {
+    fn weight(self) -> f32 {
+        match self {
+            Field::Article => 12.0,
+            Field::Title => 10.0,
+            Field::BrandModel => 6.0,
+            Field::Description => 1.0,
+        }
+    }
+}
+
+const ARTICLE_EXACT: f32 = 1000.0;
+const ARTICLE_PARTIAL: f32 = 50.0;
+const PREFIX_MATCH: f32 = 0.6;
+const TYPO_MATCH: f32 = 0.4;
+
+pub struct SearchDoc<'a> {
+    pub article: &'a str,
+    pub title: &'a str,
+    pub brand: &'a str,
+    pub model: &'a str,
+    pub category: Option<&'a str>,
+    pub description: &'a str,
+}
+
+// Словоформи і транслітерація дають один термін
+fn term(word: &str) -> String {
+    let word = word.to_lowercase().replace('ё', "е");
+    let cyrillic = word
+        .chars()
+        .any(|c| matches!(c, 'а'..='я' | 'є' | 'і' | 'ї' | 'ґ'));
+    let stem = if cyrillic && !word.chars().any(|c| c.is_ascii_digit()) {
+        stem(&word)
+    } else {
+        &word
+    };
+    slugify_latin(stem)
+}
+
+fn stem(word: &str) -> &str {
+    let chars = word.chars().count();
+    ENDINGS
+        .iter()
+        .find(|e| word.ends_with(*e) && chars - e.chars().count() >= MIN_STEM_CHARS)
+        .map(|e| &word[..word.len() - e.len()])
+        .unwrap_or(word)
+}
+
+fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() > 1 || w.chars().all(|c| c.is_ascii_digit()))
+        .map(term)
+        .filter(|t| !t.is_empty())
+}
+
+// `AB-12 3` і `ab123` — один артикул
+fn compact(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut row = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            row[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1);
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
+
+// BK-дерево: діти за відстанню до вузла, пошук обходить лише піддерева в межах помилок
+#[derive(Default)]
+struct TypoTree {
+    nodes: Vec<TypoNode>,
+}
+
+struct TypoNode {
+    term: usize,
+    chars: Vec<char>,
+    children: Vec<(usize, usize)>,
+}
+
+impl TypoTree {
+    fn build(vocabulary: &[String]) -> Self {
+        let mut tree = Self::default();
+        for (term, word) in vocabulary.iter().enumerate() {
+            tree.insert(term, word.chars().collect());
+        }
+        tree
+    }
+
+    fn insert(&mut self, term: usize, chars: Vec<char>) {
+        let new = self.nodes.len();
+        let leaf = |chars| TypoNode {
+            term,
+            chars,
+            children: vec![],
+        };
+        if new == 0 {
+            self.nodes.push(leaf(chars));
+            return;
+        }
+        let mut node = 0;
+        loop {
+            let d = distance(&chars, &self.nodes[node].chars);
+            match self.nodes[node].children.iter().find(|(cd, _)| *cd == d) {
+                Some((_, child)) => node = *child,
+                None => {
+                    self.nodes[node].children.push((d, new));
+                    self.nodes.push(leaf(chars));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn find(&self, query: &[char], max: usize) -> Vec<usize> {
+        if self.nodes.is_empty() {
+            return vec![];
+        }
+        let mut res = vec![];
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let d = distance(query, &node.chars);
+            if d <= max {
+                res.push(node.term);
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(cd, _)| cd.abs_diff(d) <= max)
+                    .map(|(_, child)| *child),
+            );
+        }
+        res
+    }
+}
+
+fn allowed_typos(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, Vec<(usize, Field)>>,
+    vocabulary: Vec<String>,
+    typos: TypoTree,
+    articles: Vec<String>,
+}
+
+impl SearchIndex {
+    pub fn build<'a>(docs: impl IntoIterator<Item = SearchDoc<'a>>) -> Self {
+        let mut postings = HashMap::<String, HashMap<usize, Field>>::new();
+        let mut articles = vec![];
+        for (doc, d) in docs.into_iter().enumerate() {
+            articles.push(compact(d.article));
+            let fields = [
+                (Field::Article, d.article),
+                (Field::Title, d.title),
+                (Field::BrandModel, d.brand),
+                (Field::BrandModel, d.model),
+                (Field::BrandModel, d.category.unwrap_or_default()),
+                (Field::Description, d.description),
+            ];
+            for (field, text) in fields {
+                for term in terms(text) {
+                    let best = postings
+                        .entry(term)
+                        .or_default()
+                        .entry(doc)
+                        .or_insert(field);
+                    *best = (*best).max(field);
+                }
+            }
+        }
+        let postings = postings
+            .into_iter()
+            .map(|(term, docs)| {
+                let mut docs = docs.into_iter().collect::<Vec<_>>();
+                docs.sort_unstable();
+                (term, docs)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut vocabulary = postings.keys().cloned().collect::<Vec<_>>();
+        vocabulary.sort_unstable();
+        let typos = TypoTree::build(&vocabulary);
+        Self {
+            postings,
+            vocabulary,
+            typos,
+            articles,
+        }
+    }
+
+    fn expand(&self, query: &str) -> Vec<(&str, f32)> {
+        let mut res = vec![];
+        if let Some((term, _)) = self.postings.get_key_value(query) {
+            res.push((term.as_str(), 1.0));
+        }
+        if query.chars().count() >= 2 {
+            let start = self.vocabulary.partition_point(|t| t.as_str() < query);
+            res.extend(
+                self.vocabulary[start..]
+                    .iter()
+                    .take_while(|t| t.starts_with(query))
+                    .filter(|t| t.as_str() != query)
+                    .take(MAX_PREFIX_TERMS)
+                    .map(|t| (t.as_str(), PREFIX_MATCH)),
+            );
+        }
+        let chars = query.chars().collect::<Vec<_>>();
+        let max = allowed_typos(chars.len());
+        if max > 0 {
+            res.extend(
+                self.typos
+                    .find(&chars, max)
+                    .into_iter()
+                    .map(|i| self.vocabulary[i].as_str())
+                    .filter(|t| *t != query)
+                    .map(|t| (t, TYPO_MATCH)),
+            );
+        }
+        res
+    }
+
+    // Найкращі першими, при рівності — порядок кешу
+    pub fn search(&self, query: &str) -> Vec<(usize, f32)> {
+        let mut scores = HashMap::<usize, f32>::new();
+        let article = compact(query);
+        let mut by_article = HashMap::<usize, f32>::new();
+        if !article.is_empty() {
+            for (doc, a) in self.articles.iter().enumerate() {
+                if *a == article {
+                    by_article.insert(doc, ARTICLE_EXACT);
+                } else if article.chars().count() >= 3 && a.contains(&article) {
+                    by_article.insert(doc, ARTICLE_PARTIAL);
+                }
+            }
+        }
+        let query_terms = terms(query).collect::<Vec<_>>();
+        for (i, query_term) in query_terms.iter().enumerate() {
+            let mut matched = HashMap::<usize, f32>::new();
+            for (term, quality) in self.expand(query_term) {
+                for (doc, field) in self.postings.get(term).into_iter().flatten() {
+                    let score = field.weight() * quality;
+                    let best = matched.entry(*doc).or_default();
+                    *best = best.max(score);
+                }
+            }
+            if i == 0 {
+                scores = matched;
+            } else {
+                scores.retain(|doc, score| match matched.get(doc) {
+                    Some(s) => {
+                        *score += s;
+                        true
+                    }
+                    None => false,
+                });
+            }
+            if scores.is_empty() {
+                break;
+            }
+        }
+        for (doc, bonus) in by_article {
+            *scores.entry(doc).or_default() += bonus;
+        }
+        let mut res = scores.into_iter().collect::<Vec<_>>();
+        res.sort_unstable_by(|(a_doc, a), (b_doc, b)| b.total_cmp(a).then(a_doc.cmp(b_doc)));
+        res
+    }
+}
+
+pub struct FacetDoc<'a> {
+    pub brand: (&'a str, &'a str),
+    pub model: (&'a str, &'a str),
+    pub category: (&'a str, Option<&'a str>),
+    pub price: Option<usize>,
+    pub available: &'a Availability,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FacetValue {
+    pub value: String,
+    pub label: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PriceBucket {
+    pub from: usize,
+    pub to: Option<usize>,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PriceFacet {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+    pub buckets: Vec<PriceBucket>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Facets {
+    pub brand: Vec<FacetValue>,
+    pub model: Vec<FacetValue>,
+    pub category: Vec<FacetValue>,
+    pub price: PriceFacet,
+    pub availability: Vec<FacetValue>,
+}
+
+const PRICE_BUCKETS: &[usize] = &[0, 1000, 3000, 5000, 10000, 20000];
+
+fn availability_value(a: &Availability) -> &'static str {
+    match a {
+        Availability::Available => "Available",
+        Availability::OnOrder => "OnOrder",
+        Availability::NotAvailable => "NotAvailable",
+    }
+}
+
+pub fn facets<'a>(docs: impl IntoIterator<Item = FacetDoc<'a>>) -> Facets {
+    type Counter<'a> = HashMap<&'a str, (&'a str, usize)>;
+    fn count<'a>(counter: &mut Counter<'a>, value: &'a str, label: &'a str) {
+        if !value.is_empty() {
+            counter.entry(value).or_insert((label, 0)).1 += 1;
+        }
+    }
+    fn values(counter: Counter) -> Vec<FacetValue> {
+        let mut res = counter
+            .into_iter()
+            .map(|(value, (label, count))| FacetValue {
+                value: value.to_string(),
+                label: label.to_string(),
+                count,
+            })
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.label.cmp(&b.label)));
+        res
+    }
+    let (mut brand, mut model, mut category) = (Counter::new(), Counter::new(), Counter::new());
+    let mut availability = BTreeMap::<&Availability, usize>::new();
+    let mut buckets = vec![0; PRICE_BUCKETS.len()];
+    let mut price = PriceFacet::default();
+    for d in docs {
+        count(&mut brand, d.brand.0, d.brand.1);
+        count(&mut model, d.model.0, d.model.1);
+        if let (slug, Some(label)) = d.category {
+            count(&mut category, slug, label);
+        }
+        *availability.entry(d.available).or_default() += 1;
+        if let Some(p) = d.price {
+            price.min = Some(price.min.map_or(p, |m| m.min(p)));
+            price.max = Some(price.max.map_or(p, |m| m.max(p)));
+            buckets[PRICE_BUCKETS.partition_point(|from| *from <= p) - 1] += 1;
+        }
+    }
+    price.buckets = PRICE_BUCKETS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| buckets[*i] > 0)
+        .map(|(i, from)| PriceBucket {
+            from: *from,
+            to: PRICE_BUCKETS.get(i + 1).copied(),
+            count: buckets[i],
+        })
+        .collect();
+    Facets {
+        brand: values(brand),
+        model: values(model),
+        category: values(category),
+        price,
+        availability: availability
+            .into_iter()
+            .map(|(a, count)| FacetValue {
+                value: availability_value(a).to_string(),
+                label: a.to_string(),
+                count,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SearchIndex {
+        let docs = [
+            (
+                "SP-100",
+                "Спойлер багажника BMW G30",
+                "BMW",
+                "5 Series",
+                "Обтекатель",
+            ),
+            (
+                "DF-200",
+                "Дифузор заднього бампера",
+                "Audi",
+                "A6",
+                "Під спойлер не підходить",
+            ),
+            ("SP100X", "Спліттер переднього бампера", "BMW", "G30", ""),
+        ];
+        SearchIndex::build(
+            docs.iter()
+                .map(|(article, title, brand, model, description)| SearchDoc {
+                    article,
+                    title,
+                    brand,
+                    model,
+                    category: None,
+                    description,
+                }),
+        )
+    }
+
+    fn docs(index: &SearchIndex, query: &str) -> Vec<usize> {
+        index
+            .search(query)
+            .into_iter()
+            .map(|(doc, _)| doc)
+            .collect()
+    }
+
+    #[test]
+    fn ranks_article_then_title_then_description() {
+        let index = index();
+        assert_eq!(docs(&index, "sp-100"), vec![0, 2]);
+        assert_eq!(docs(&index, "спойлеры"), vec![0, 1]);
+    }
+
+    #[test]
+    fn matches_word_forms_transliteration_and_typos() {
+        let index = index();
+        assert_eq!(docs(&index, "бампер"), vec![1, 2]);
+        assert_eq!(docs(&index, "spoiler"), vec![0, 1]);
+        assert_eq!(docs(&index, "дифузори"), vec![1]);
+        assert_eq!(docs(&index, "сплітер"), vec![2]);
+        assert_eq!(docs(&index, "bmw спліт"), vec![2]);
+        assert!(docs(&index, "bmw дифузор").is_empty());
+    }
+
+    #[test]
+    fn finds_typos_like_a_full_scan() {
+        let index = index();
+        for query in ["splitter", "spojler", "bamper", "bmv", "difuzor", "zadnogo"] {
+            let chars = query.chars().collect::<Vec<_>>();
+            let max = allowed_typos(chars.len()).max(1);
+            let mut found = index.typos.find(&chars, max);
+            found.sort_unstable();
+            let scan = (0..index.vocabulary.len())
+                .filter(|i| {
+                    let term = index.vocabulary[*i].chars().collect::<Vec<_>>();
+                    distance(&chars, &term) <= max
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(found, scan, "{query}");
+        }
+    }
+
+    #[test]
+    fn counts_facets() {
+        let available = Availability::Available;
+        let on_order = Availability::OnOrder;
+        let facets = facets([
+            FacetDoc {
+                brand: ("bmw", "BMW"),
+                model: ("g30", "G30"),
+                category: ("spoilers", Some("Спойлери")),
+                price: Some(2500),
+                available: &available,
+            },
+            FacetDoc {
+                brand: ("bmw", "BMW"),
+                model: ("", ""),
+                category: ("", None),
+                price: Some(12000),
+                available: &on_order,
+            },
+        ]);
+        assert_eq!(facets.brand[0].count, 2);
+        assert_eq!(facets.model.len(), 1);
+        assert_eq!(facets.category[0].label, "Спойлери");
+        assert_eq!(
+            (facets.price.min, facets.price.max),
+            (Some(2500), Some(12000))
+        );
+        assert_eq!(
+            facets
+                .price
+                .buckets
+                .iter()
+                .map(|b| (b.from, b.to, b.count))
+                .collect::<Vec<_>>(),
+            vec![(1000, Some(3000), 1), (10000, Some(20000), 1)]
+        );
+        assert_eq!(facets.availability[0].value, "Available");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::scheduler::{JobClass, JobScheduler, RunCounts, Schedule};
 use crate::tt::{
     product::{Product, ProductRepository, Translation, TranslationRepository},
     selectors,
@@ -18,6 +19,7 @@ use rust_decimal::Decimal;
 use scraper::Html;
 use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
+use std::time::Duration;
 use time::OffsetDateTime;
 use tokio::sync::{Notify, RwLock};
 use typesafe_repository::{GetIdentity, IdentityOf};
@@ -49,8 +51,11 @@ pub struct GetTranslatedCount;
 pub struct ParserService {
     opts: Arc<RwLock<ParsingOptions>>,
     completed: Arc<Notify>,
+    jobs: Arc<JobScheduler>,
 }
 
+const JOB_ID: &str = "tt_parser";
+
 pub struct ParsingProgress {
     pub ready: u64,
     pub total: u64,
@@ -74,10 +79,11 @@ pub enum ParsingStage {
 }
 
 impl ParserService {
-    pub fn new(opts: ParsingOptions) -> Self {
+    pub fn new(opts: ParsingOptions, jobs: Arc<JobScheduler>) -> Self {
         Self {
             opts: Arc::new(RwLock::new(opts)),
             completed: Arc::new(Notify::new()),
+            jobs,
         }
     }
 }
@@ -89,10 +95,26 @@ impl Actor for ParserService {
         log::info!("TT parser started");
         let opts = self.opts.clone();
         let completed = self.completed.clone();
+        let jobs = self.jobs.clone();
         tokio::task::spawn_local(async move {
+            jobs.register(
+                JOB_ID,
+                "Парсинг TT",
+                None,
+                JobClass::Parser,
+                Schedule::Interval(Duration::ZERO),
+            )
+            .await;
             loop {
-                let res = work_cycle(opts.clone()).await;
-                if let Err(err) = res {
+                jobs.wait_due(JOB_ID).await;
+                let res = jobs
+                    .run(JOB_ID, |_| async {
+                        work_cycle(opts.clone())
+                            .await
+                            .map(|_| ((), RunCounts::new()))
+                    })
+                    .await;
+                if let Some(Err(err)) = res {
                     log::error!("Unable to parse tt products: {err}");
                 }
                 completed.notify_waiters();
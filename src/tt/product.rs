@@ -188,6 +188,32 @@ pub trait ProductRepository:
         model: String,
     ) -> Result<(), Self::Error>;
     async fn count(&self) -> Result<usize, Self::Error>;
+    async fn list_page(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+        only_available: bool,
+    ) -> Result<(Vec<Product>, Option<String>), Self::Error>;
+}
+
+pub fn batches(
+    repo: std::sync::Arc<dyn ProductRepository>,
+    only_available: bool,
+    batch: usize,
+) -> impl futures::Stream<Item = Result<Vec<Product>, anyhow::Error>> {
+    futures::stream::try_unfold(Some(None), move |cursor| {
+        let repo = repo.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+            let (products, next) = repo.list_page(cursor, batch, only_available).await?;
+            if products.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some((products, next.map(Some))))
+        }
+    })
 }
 
 pub struct SqliteProductRepository {
@@ -452,6 +478,54 @@ impl ProductRepository for SqliteProductRepository {
             .await?;
         Ok(res)
     }
+
+    async fn list_page(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+        only_available: bool,
+    ) -> Result<(Vec<Product>, Option<String>), Self::Error> {
+        Ok(self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, title, description, price, article, model, category, available, url, last_visited, img, brand
+                    FROM product WHERE (?1 IS NULL OR article > ?1) AND (?2 = 0 OR available > 0)
+                    ORDER BY article LIMIT ?3",
+                )?;
+                let p = stmt
+                    .query_map(params![cursor, only_available, limit as i64], |row| {
+                        Ok(Product {
+                            id: row.get(0)?,
+                            title: row.get(1)?,
+                            description: row.get(2)?,
+                            price: Decimal::from_str_exact(&row.get::<_, String>(3)?).map_err(|err| {
+                                rusqlite::Error::FromSqlConversionFailure(
+                                    3,
+                                    rusqlite::types::Type::Text,
+                                    Box::new(err)
+                                )
+                            })?,
+                            article: row.get(4)?,
+                            model: Model(row.get(5)?),
+                            category: row.get(6)?,
+                            available: row.get::<_, u8>(7)?.into(),
+                            url: Url(row.get(8)?),
+                            last_visited: row.get(9)?,
+                            images: Product::img_from_str(row.get::<_, String>(10)?),
+                            brand: row.get(11)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let next = if p.len() < limit {
+                    None
+                } else {
+                    p.last().map(|p| p.article.clone())
+                };
+                Ok((p, next))
+            })
+            .await?)
+    }
 }
 
 #[derive(Id, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -585,6 +659,7 @@ impl TryInto<rt_types::product::Product> for Product {
             ua_translation: None,
             description: self.format_description(),
             price: self.price,
+            source_price: None,
             in_stock: None,
             currency: "PLN".to_string(),
             article: self.article,
@@ -1,9 +1,14 @@
 use crate::external_import;
 use async_zip::base::read::mem::ZipFileReader;
 use external_import::{Item, Offer};
+use once_cell::sync::Lazy;
 use reqwest::multipart::Part;
 use reqwest::Client;
-use serde::Deserialize;
+use rt_types::shop::PromUploadOptions;
+use serde::{Deserialize, Serialize};
+
+pub static PROM_API: Lazy<String> =
+    Lazy::new(|| envmnt::get_or("PROM_API_URL", "https://my.prom.ua/api/v1"));
 
 #[derive(Deserialize, Debug)]
 pub struct ImportResponse {
@@ -12,7 +17,8 @@ pub struct ImportResponse {
     pub message: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct ImportStatus {
     pub status: String,
     pub not_changed: usize,
@@ -27,39 +33,49 @@ pub struct ImportStatus {
     pub with_errors_count: usize,
 }
 
+impl ImportStatus {
+    pub fn is_finished(&self) -> bool {
+        ["SUCCESS", "PARTIAL", "FATAL"]
+            .iter()
+            .any(|s| self.status.eq_ignore_ascii_case(s))
+    }
+}
+
+fn import_settings(opts: &PromUploadOptions) -> serde_json::Value {
+    serde_json::json!({
+        "force_update": false,
+        "only_available": false,
+        "mark_missing_product_as": opts.mark_missing_product_as.as_str(),
+        "updated_fields": opts.updated_fields,
+    })
+}
+
+fn parse_import_response(text: &str) -> Result<String, anyhow::Error> {
+    let resp: ImportResponse = serde_yaml::from_str(text)?;
+    match (resp.id.as_ref(), resp.message.as_ref()) {
+        (Some(id), _) => Ok(id.to_string()),
+        (None, None) => Err(anyhow::anyhow!("No id: {resp:?}")),
+        (None, Some(msg)) => Err(anyhow::anyhow!("{}: {}", resp.status, msg)),
+    }
+}
+
 pub async fn upload_products(
+    api: &str,
     path: &str,
     token: &str,
+    opts: &PromUploadOptions,
     client: Client,
 ) -> Result<String, anyhow::Error> {
-    const URL: &str = "https://my.prom.ua/api/v1/products/import_file";
     let file = tokio::fs::read(path).await?;
+    let file_name = std::path::Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
     let form = reqwest::multipart::Form::new()
-        .part("file", Part::bytes(file).file_name(path.to_string()))
-        .text(
-            "data",
-            "
-{
-  \"force_update\": false,
-  \"only_available\": false,
-  \"mark_missing_product_as\": \"not_available\",
-  \"updated_fields\": [
-    \"name\",
-    \"sku\",
-    \"price\",
-    \"image_urls\",
-    \"presence\",
-    \"quantity_in_stock\",
-    \"description\",
-    \"discount\",
-    \"attributes\",
-    \"translations\"
-  ]
-}
-",
-        );
+        .part("file", Part::bytes(file).file_name(file_name))
+        .text("data", import_settings(opts).to_string());
     let resp = client
-        .post(URL)
+        .post(format!("{api}/products/import_file"))
         .multipart(form)
         .bearer_auth(token)
         .send()
@@ -67,12 +83,7 @@ pub async fn upload_products(
     log::info!("{resp:?}");
     let text = resp.text().await?;
     log::info!("{text:?}");
-    let resp: ImportResponse = serde_yaml::from_str(&text)?;
-    match (resp.id.as_ref(), resp.message.as_ref()) {
-        (Some(id), _) => Ok(id.to_string()),
-        (None, None) => Err(anyhow::anyhow!("No id: {resp:?}")),
-        (None, Some(msg)) => Err(anyhow::anyhow!("{}: {}", resp.status, msg)),
-    }
+    parse_import_response(&text)
 }
 
 pub enum DownloadResult {
@@ -167,57 +178,152 @@ async fn unzip_first_text(bytes: Vec<u8>) -> Result<String, DownloadFromLinkErro
 }
 
 pub async fn upload_by_link(
+    api: &str,
     url: &str,
     token: &str,
+    opts: &PromUploadOptions,
     client: Client,
 ) -> Result<String, anyhow::Error> {
-    const URL: &str = "https://my.prom.ua/api/v1/products/import_url";
+    let mut body = import_settings(opts);
+    body["url"] = url.into();
     let resp = client
-        .post(URL)
-        .header("Content-Type", "application/json")
-        .body(format!(
-            "{{
-  \"url\": \"{url}\",
-  \"force_update\": false,
-  \"only_available\": false,
-  \"mark_missing_product_as\": \"none\",
-  \"updated_fields\": [
-    \"name\",
-    \"sku\",
-    \"price\",
-    \"image_urls\",
-    \"presence\",
-    \"quantity_in_stock\",
-    \"description\",
-    \"keywords\",
-    \"attributes\",
-    \"translations\"
-  ]
-}}
-"
-        ))
+        .post(format!("{api}/products/import_url"))
+        .json(&body)
         .bearer_auth(token)
         .send()
         .await?;
     log::info!("{resp:?}");
     let text = resp.text().await?;
-    log::info!("{:?}", text);
-    let resp: ImportResponse = serde_yaml::from_str(&text)?;
-    match (resp.id.as_ref(), resp.message.as_ref()) {
-        (Some(id), _) => Ok(id.to_string()),
-        (None, None) => Err(anyhow::anyhow!("No id: {resp:?}")),
-        (None, Some(msg)) => Err(anyhow::anyhow!("{}: {}", resp.status, msg)),
-    }
+    log::info!("{text:?}");
+    parse_import_response(&text)
 }
 
 pub async fn import_status(
+    api: &str,
     id: &str,
     token: &str,
     client: Client,
 ) -> Result<ImportStatus, anyhow::Error> {
-    const URL: &str = "https://my.prom.ua/api/v1/products/import/status";
-    let url = format!("{URL}/{id}");
+    let url = format!("{api}/products/import/status/{id}");
     let resp = client.get(url).bearer_auth(token).send().await?;
     log::info!("{resp:?}");
-    Ok(resp.json().await?)
+    Ok(resp.error_for_status()?.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rt_types::shop::MissingProductAction;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            let Some(header_end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let headers = text[..header_end].to_lowercase();
+            let body_len = headers
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .and_then(|l| l.trim().parse::<usize>().ok());
+            let complete = match body_len {
+                Some(len) => request.len() >= header_end + 4 + len,
+                None if headers.contains("transfer-encoding: chunked") => {
+                    text.ends_with("0\r\n\r\n")
+                }
+                None => true,
+            };
+            if complete || n == 0 {
+                return text;
+            }
+        }
+    }
+
+    async fn serve(bodies: Vec<&'static str>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for body in bodies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                requests.push(read_request(&mut socket).await);
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(resp.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (format!("http://{addr}/api/v1"), handle)
+    }
+
+    #[tokio::test]
+    async fn uploads_file_and_reads_import_status() {
+        let (api, requests) = serve(vec![
+            r#"{"id": "imp-1", "status": "success"}"#,
+            r#"{"status": "PROCESSING"}"#,
+            r#"{"status": "SUCCESS", "updated": 10, "created": 2, "not_in_file": 3, "total": 15}"#,
+        ])
+        .await;
+        let path = std::env::temp_dir().join(format!("prom-{}.xlsx", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, b"xlsx").await.unwrap();
+        let opts = PromUploadOptions {
+            updated_fields: vec!["price".to_string(), "presence".to_string()],
+            mark_missing_product_as: MissingProductAction::NotOnDisplay,
+        };
+        let client = Client::new();
+
+        let id = upload_products(&api, path.to_str().unwrap(), "token", &opts, client.clone())
+            .await
+            .unwrap();
+        assert_eq!(id, "imp-1");
+        let status = import_status(&api, &id, "token", client.clone())
+            .await
+            .unwrap();
+        assert!(!status.is_finished());
+        let status = import_status(&api, &id, "token", client).await.unwrap();
+        assert!(status.is_finished());
+        assert_eq!(
+            (status.updated, status.created, status.not_in_file),
+            (10, 2, 3)
+        );
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with("POST /api/v1/products/import_file"));
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("authorization: bearer token"));
+        assert!(requests[0].contains(r#""mark_missing_product_as":"not_on_display""#));
+        assert!(requests[0].contains(r#""updated_fields":["price","presence"]"#));
+        assert!(requests[1].starts_with("GET /api/v1/products/import/status/imp-1"));
+    }
+
+    #[tokio::test]
+    async fn uploads_by_link_with_escaped_url() {
+        let (api, requests) = serve(vec![r#"{"id": "imp-2", "status": "success"}"#]).await;
+        let url = r#"https://example.com/feed.xml?a=1&b="2""#;
+        let opts = PromUploadOptions {
+            updated_fields: vec!["price".to_string()],
+            mark_missing_product_as: MissingProductAction::NotOnDisplay,
+        };
+
+        let id = upload_by_link(&api, url, "token", &opts, Client::new())
+            .await
+            .unwrap();
+        assert_eq!(id, "imp-2");
+
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with("POST /api/v1/products/import_url"));
+        assert!(requests[0].contains(r#""url":"https://example.com/feed.xml?a=1&b=\"2\"""#));
+        assert!(requests[0].contains(r#""mark_missing_product_as":"not_on_display""#));
+        assert!(requests[0].contains(r#""updated_fields":["price"]"#));
+    }
 }
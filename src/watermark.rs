@@ -1,17 +1,24 @@
 use crate::control::{
-    deserialize_decimal_form, render_template, see_other, ControllerError, FileInfo, Record,
+    deserialize_decimal_form, deserialize_parse_form, render_template, see_other, ControllerError, FileInfo, Record,
     RecordGuard, RecordResponse, Response, ShopAccess, ShopControllerError,
 };
 use crate::export;
 use crate::export::ExportService;
+use crate::shop_product::ShopProductRepository;
+use crate::watermark_cache::{cache_key, CacheEntry, WatermarkCache};
+use crate::watermark_guard::{self, WATERMARK_SIGNING_KEY};
 use actix::Addr;
+use actix_broker::{Broker, SystemBroker};
 use actix_files::NamedFile;
 use actix_multipart::form::{tempfile::TempFile, MultipartForm};
 use actix_web::dev::Payload;
 use actix_web::web::Data;
 use actix_web::{
     get,
-    http::header::ContentType,
+    http::header::{
+        ContentType, CONTENT_TYPE, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch,
+        LastModified,
+    },
     post,
     web::{Form, Path, Query},
     FromRequest, HttpRequest, HttpResponse,
@@ -27,10 +34,11 @@ use once_cell::sync::Lazy;
 use photon_rs::PhotonImage;
 use reqwest::StatusCode;
 use rt_types::access::UserCredentials;
+use rt_types::shop::service::ShopService;
 use rt_types::shop::Shop;
 use rt_types::watermark::{
-    apply, WatermarkGroup, WatermarkGroupRepository, WatermarkOptions, WatermarkPosition,
-    WatermarkSize,
+    apply, ImageFormat, WatermarkGroup, WatermarkGroupRepository, WatermarkOptions,
+    WatermarkOutput, WatermarkPosition, WatermarkRepeat, WatermarkSize,
 };
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
@@ -46,9 +54,10 @@ use typesafe_repository::{
     async_ops::{Add, Get, ListBy, Remove},
     GetIdentity, IdentityOf, Repository,
 };
+use url::Url;
 use uuid::Uuid;
 
-use rt_types::watermark::service::WatermarkService;
+use rt_types::watermark::service::{WatermarkService, WatermarkUpdated};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash, Default)]
 pub struct WatermarkOptionsDto {
@@ -62,6 +71,79 @@ pub struct WatermarkOptionsDto {
     pub horizontal_position: Option<WatermarkPosition>,
     #[serde(default)]
     pub vertical_position: Option<WatermarkPosition>,
+    #[serde(deserialize_with = "deserialize_decimal_form")]
+    #[serde(default)]
+    pub opacity: Option<Decimal>,
+    #[serde(default)]
+    pub repeat: Option<WatermarkRepeat>,
+    #[serde(deserialize_with = "deserialize_parse_form::<_, ImageFormat>")]
+    #[serde(default)]
+    pub format: Option<ImageFormat>,
+    #[serde(deserialize_with = "deserialize_parse_form::<_, u8>")]
+    #[serde(default)]
+    pub quality: Option<u8>,
+    #[serde(deserialize_with = "deserialize_parse_form::<_, u32>")]
+    #[serde(default)]
+    pub max_width: Option<u32>,
+    #[serde(deserialize_with = "deserialize_parse_form::<_, u32>")]
+    #[serde(default)]
+    pub max_height: Option<u32>,
+}
+
+impl WatermarkOptionsDto {
+    pub fn to_query(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        let params = [
+            ("width_percent", self.width_percent.map(|v| v.to_string())),
+            ("height_percent", self.height_percent.map(|v| v.to_string())),
+            (
+                "horizontal_position",
+                self.horizontal_position.as_ref().map(ToString::to_string),
+            ),
+            (
+                "vertical_position",
+                self.vertical_position.as_ref().map(ToString::to_string),
+            ),
+            ("opacity", self.opacity.map(|v| v.to_string())),
+            ("repeat", self.repeat.map(|v| v.to_string())),
+            ("format", self.format.map(|v| v.to_string())),
+            ("quality", self.quality.map(|v| v.to_string())),
+            ("max_width", self.max_width.map(|v| v.to_string())),
+            ("max_height", self.max_height.map(|v| v.to_string())),
+        ];
+        for (name, value) in params {
+            if let Some(value) = value {
+                query.append_pair(name, &value);
+            }
+        }
+        query.finish()
+    }
+}
+
+impl From<WatermarkOptions> for WatermarkOptionsDto {
+    fn from(opts: WatermarkOptions) -> Self {
+        let default = WatermarkOptions::default();
+        let (width_percent, height_percent) = match opts.size {
+            size if size == default.size => (None, None),
+            WatermarkSize::Width(w) => (Some(w), None),
+            WatermarkSize::Height(h) => (None, Some(h)),
+            WatermarkSize::BoundingBox { width, height } => (Some(width), Some(height)),
+        };
+        Self {
+            width_percent,
+            height_percent,
+            horizontal_position: Some(opts.horizontal_position)
+                .filter(|p| *p != default.horizontal_position),
+            vertical_position: Some(opts.vertical_position)
+                .filter(|p| *p != default.vertical_position),
+            opacity: opts.opacity,
+            repeat: Some(opts.repeat).filter(|r| *r != default.repeat),
+            format: opts.output.format,
+            quality: opts.output.quality,
+            max_width: opts.output.max_width,
+            max_height: opts.output.max_height,
+        }
+    }
 }
 
 impl Into<WatermarkOptions> for WatermarkOptionsDto {
@@ -78,15 +160,51 @@ impl Into<WatermarkOptions> for WatermarkOptionsDto {
                 .horizontal_position
                 .unwrap_or(default.horizontal_position),
             vertical_position: self.vertical_position.unwrap_or(default.vertical_position),
+            opacity: self.opacity,
+            repeat: self.repeat.unwrap_or_default(),
+            output: WatermarkOutput {
+                format: self.format,
+                quality: self.quality,
+                max_width: self.max_width,
+                max_height: self.max_height,
+            },
         }
     }
 }
 
+pub fn link_query(
+    shop_id: IdentityOf<Shop>,
+    image: &str,
+    watermark: &str,
+    opts: Option<&WatermarkOptions>,
+) -> String {
+    let mut query = opts
+        .map(|o| WatermarkOptionsDto::from(o.clone()).to_query())
+        .unwrap_or_default();
+    if let Some(sig) = watermark_guard::sign_link(shop_id, image, watermark) {
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(&format!("sig={sig}"));
+    }
+    query
+}
+
+#[derive(Deserialize)]
+pub struct WatermarkSignatureQuery {
+    #[serde(default)]
+    pub sig: Option<String>,
+}
+
 #[get("/shop/{shop_id}/watermark/{link:.+}/{watermark}")]
 pub async fn apply_watermark(
     q: Query<WatermarkOptionsDto>,
+    sig: Query<WatermarkSignatureQuery>,
     path: Path<(IdentityOf<Shop>, String, String)>,
     watermark_group_repository: Data<Arc<dyn WatermarkGroupRepository>>,
+    cache: Data<Arc<WatermarkCache>>,
+    shop_service: Data<Addr<ShopService>>,
+    shop_product_repository: Data<Arc<dyn ShopProductRepository>>,
     user_credentials: Option<Record<UserCredentials>>,
     req: HttpRequest,
 ) -> Response {
@@ -97,11 +215,56 @@ pub async fn apply_watermark(
             req.uri()
         );
     }
-    let q = q.into_inner();
+    let opts: WatermarkOptions = q.into_inner().into();
     let (shop_id, link, watermark) = path.into_inner();
-    let image = match download_image(link).await {
-        Ok(image) => image,
+    let key = cache_key(shop_id, &link, &watermark, &opts);
+    let url = match authorize_image(
+        shop_id,
+        &link,
+        &watermark,
+        sig.into_inner().sig.as_deref(),
+        cache.get(key).is_some(),
+        &shop_service,
+        shop_product_repository.get_ref().as_ref(),
+    )
+    .await
+    {
+        Ok(url) => url,
         Err(err) => {
+            log::warn!("Watermark request for {link} rejected: {err}");
+            return Ok(HttpResponse::Forbidden()
+                .content_type(ContentType::html())
+                .body(err.to_string()));
+        }
+    };
+    let now = OffsetDateTime::now_utc();
+    let cached = match cache.get(key) {
+        Some(entry) => cache.read(key).await.map(|image| (entry, image)),
+        None => None,
+    };
+    if let Some((entry, image)) = &cached {
+        if !cache.needs_revalidation(entry, now) {
+            return Ok(cached_response(&req, key, entry, image.clone()));
+        }
+    }
+    let source = download_image(url, cached.as_ref().map(|(e, _)| e)).await;
+    let (image, source_etag, source_last_modified) = match (source, cached) {
+        (Ok(SourceImage::NotModified), Some((entry, image))) => {
+            cache.touch(key, now).await;
+            return Ok(cached_response(&req, key, &entry, image));
+        }
+        (Ok(SourceImage::Fetched(image, etag, last_modified)), _) => (image, etag, last_modified),
+        // Застаріла копія краща за помилку, поки джерело недоступне
+        (Err(err), Some((entry, image))) => {
+            log::warn!("Unable to revalidate {link}: {err}");
+            return Ok(cached_response(&req, key, &entry, image));
+        }
+        (Ok(SourceImage::NotModified), None) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type(ContentType::html())
+                .body("Unexpected 304 Not Modified"))
+        }
+        (Err(err), None) => {
             return Ok(HttpResponse::BadRequest()
                 .content_type(ContentType::html())
                 .body(err.to_string()))
@@ -111,23 +274,135 @@ pub async fn apply_watermark(
     let res = apply_watermark_or_group(
         &watermark,
         image.deref(),
-        q.into(),
+        opts,
         shop_id,
         watermark_group_repository.get_ref().clone(),
     )
     .await;
     log::info!("{}ms", ins.elapsed().as_millis());
     match res {
-        Ok(image) => Ok(HttpResponse::Ok().body(image.get_bytes())),
+        Ok((image, format, layers)) => {
+            let entry = CacheEntry {
+                shop_id,
+                url: link,
+                watermark,
+                layers,
+                format,
+                size: 0,
+                source_etag,
+                source_last_modified,
+                created: now,
+                checked: now,
+                last_access: now,
+            };
+            let entry = cache.put(key, entry, &image).await;
+            Ok(cached_response(&req, key, &entry, image))
+        }
         Err(err) => Ok(HttpResponse::BadRequest()
             .content_type(ContentType::html())
             .body(err.to_string())),
     }
 }
 
-#[cached(result, size = 128)]
-async fn download_image(url: String) -> Result<bytes::Bytes, anyhow::Error> {
-    let res = reqwest::get(url).await.context("Unable to get image")?;
+fn cached_response(
+    req: &HttpRequest,
+    key: u64,
+    entry: &CacheEntry,
+    image: Vec<u8>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(entry.etag(key));
+    let last_modified = HttpDate::from(std::time::SystemTime::from(entry.created));
+    let not_modified = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) if !tags.is_empty() => tags.iter().any(|t| t.weak_eq(&etag)),
+        _ => IfModifiedSince::parse(req).is_ok_and(|IfModifiedSince(since)| {
+            OffsetDateTime::from(std::time::SystemTime::from(since)).unix_timestamp()
+                >= entry.created.unix_timestamp()
+        }),
+    };
+    let mut res = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    res.insert_header(ETag(etag))
+        .insert_header(LastModified(last_modified))
+        .insert_header((CONTENT_TYPE, entry.format.mime()));
+    if not_modified {
+        res.finish()
+    } else {
+        res.body(image)
+    }
+}
+
+// Підпис, кеш, дозволений хост або зображення товарів магазину
+async fn authorize_image(
+    shop_id: IdentityOf<Shop>,
+    link: &str,
+    watermark: &str,
+    sig: Option<&str>,
+    cached: bool,
+    shop_service: &Addr<ShopService>,
+    shop_product_repository: &dyn ShopProductRepository,
+) -> Result<Url, anyhow::Error> {
+    let url = Url::parse(link).context("Invalid image link")?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("Unsupported scheme {}", url.scheme()));
+    }
+    if let Some(sig) = sig {
+        let Some(key) = WATERMARK_SIGNING_KEY.as_ref() else {
+            return Err(anyhow!("Signed links are not accepted"));
+        };
+        if watermark_guard::verify(key, shop_id, link, watermark, sig) {
+            return Ok(url);
+        }
+        return Err(anyhow!("Invalid signature"));
+    }
+    if cached {
+        return Ok(url);
+    }
+    let shop = shop_service
+        .send(rt_types::shop::service::Get(shop_id))
+        .await??
+        .ok_or(anyhow!("Shop not found"))?;
+    if watermark_guard::shop_image_allowed(&shop, &url) {
+        return Ok(url);
+    }
+    if shop_product_repository.has_image(shop_id, link).await? {
+        return Ok(url);
+    }
+    Err(anyhow!("Image does not belong to the shop products"))
+}
+
+const MAX_SOURCE_IMAGE_SIZE: u64 = 20 * 1024 * 1024;
+
+enum SourceImage {
+    NotModified,
+    Fetched(bytes::Bytes, Option<String>, Option<String>),
+}
+
+async fn download_image(
+    url: Url,
+    cached: Option<&CacheEntry>,
+) -> Result<SourceImage, anyhow::Error> {
+    let (host, addrs) = watermark_guard::resolve_public(&url).await?;
+    // Редиректи не виконуємо, їх ціль обійшла б перевірку адреси
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(&host, &addrs)
+        .build()
+        .context("Unable to build client")?;
+    let mut req = client.get(url);
+    if let Some(etag) = cached.and_then(|c| c.source_etag.as_ref()) {
+        req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = cached.and_then(|c| c.source_last_modified.as_ref()) {
+        req = req.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let mut res = req.send().await.context("Unable to get image")?;
+    if res.status() == StatusCode::NOT_MODIFIED && cached.is_some() {
+        return Ok(SourceImage::NotModified);
+    }
     if res.status() != StatusCode::OK {
         return Err(anyhow!("{}", res.status()).into());
     }
@@ -140,54 +415,78 @@ async fn download_image(url: String) -> Result<bytes::Bytes, anyhow::Error> {
         Some(Ok(_)) => return Err(anyhow::anyhow!("Mime type not supported")),
         Some(Err(err)) => return Err(err.into()),
     }
-    Ok(res.bytes().await.context("Unable to get response bytes")?)
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(ToString::to_string)
+    };
+    let etag = header(reqwest::header::ETAG);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+    if res.content_length().is_some_and(|len| len > MAX_SOURCE_IMAGE_SIZE) {
+        return Err(anyhow!("Image is larger than {MAX_SOURCE_IMAGE_SIZE} bytes"));
+    }
+    let mut bytes = bytes::BytesMut::new();
+    while let Some(chunk) = res.chunk().await.context("Unable to get response bytes")? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_SOURCE_IMAGE_SIZE {
+            return Err(anyhow!("Image is larger than {MAX_SOURCE_IMAGE_SIZE} bytes"));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(SourceImage::Fetched(bytes.freeze(), etag, last_modified))
 }
 
-#[cached(result, size = 32)]
 async fn open_image(s: String) -> Result<Vec<u8>, std::io::Error> {
     tokio::fs::read(s).await
 }
 
-#[cached(
-    result,
-    key = "(String, Vec<u8>, WatermarkOptions, IdentityOf<Shop>)",
-    convert = r#"{ (watermark.to_string(), image.into(), opts.clone(), shop_id) }"#,
-    size = 128
-)]
+// Для груп опції виводу із запиту переважають опції елементів
 async fn apply_watermark_or_group(
     watermark: &str,
     image: &[u8],
     opts: WatermarkOptions,
     shop_id: IdentityOf<Shop>,
     watermark_group_repository: Arc<dyn WatermarkGroupRepository>,
-) -> Result<PhotonImage, anyhow::Error> {
+) -> Result<(Vec<u8>, ImageFormat, Vec<String>), anyhow::Error> {
     let image = photon_rs::native::open_image_from_bytes(image).context("Unable to open image")?;
-    let res = match watermark.parse() {
+    let (image, output, layers) = match watermark.parse() {
         Ok(id) => {
             let watermarks = watermark_group_repository
                 .get_one(&(id, shop_id))
                 .await?
                 .ok_or(anyhow::anyhow!("Watermark group not found"))?;
-            let mut image = image;
-            for (name, opts) in watermarks.elements {
+            let elements = watermarks.elements.into_iter().sorted().collect::<Vec<_>>();
+            let output = elements
+                .iter()
+                .fold(opts.output, |output, (_, o)| output.or(&o.output));
+            let fit = output.clone();
+            let mut image = tokio::task::spawn_blocking(move || fit.fit(image)).await?;
+            let mut layers = vec![];
+            for (name, opts) in elements {
                 let watermark = open_image(format!("./watermark/{shop_id}/{name}")).await?;
                 let watermark = photon_rs::native::open_image_from_bytes(&watermark)
                     .context("Unable to open watermark image")?;
                 image =
                     tokio::task::spawn_blocking(move || apply(image, &watermark, opts)).await??;
+                layers.push(name);
             }
-            Ok(image)
+            (image, output, layers)
         }
         Err(_) => {
-            let watermark = open_image(format!("./watermark/{shop_id}/{watermark}")).await?;
-            let watermark = photon_rs::native::open_image_from_bytes(&watermark)
+            let layer = open_image(format!("./watermark/{shop_id}/{watermark}")).await?;
+            let layer = photon_rs::native::open_image_from_bytes(&layer)
                 .context("Unable to open watermark image")?;
-            let image =
-                tokio::task::spawn_blocking(move || apply(image, &watermark, opts)).await??;
-            Ok(image)
+            let output = opts.output.clone();
+            let image = tokio::task::spawn_blocking(move || {
+                apply(opts.output.fit(image), &layer, opts)
+            })
+            .await??;
+            (image, output, vec![watermark.to_string()])
         }
     };
-    res
+    let format = output.format();
+    let image = tokio::task::spawn_blocking(move || output.encode(&image)).await?;
+    Ok((image, format, layers))
 }
 
 #[derive(Template)]
@@ -258,6 +557,33 @@ pub async fn watermark_settings(
     })
 }
 
+#[derive(Deserialize)]
+pub struct WatermarkHostsDto {
+    #[serde(default)]
+    pub hosts: String,
+}
+
+#[post("/shop/{shop_id}/watermark_hosts")]
+pub async fn update_watermark_hosts(
+    ShopAccess { mut shop, .. }: ShopAccess,
+    dto: Form<WatermarkHostsDto>,
+    shop_service: Data<Addr<ShopService>>,
+) -> Response {
+    shop.watermark_hosts = dto
+        .hosts
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|h| h.trim().trim_end_matches('.').to_lowercase())
+        .filter(|h| !h.is_empty())
+        .unique()
+        .collect();
+    let shop_id = shop.id;
+    shop_service
+        .send(rt_types::shop::service::Update(shop))
+        .await?
+        .context("Unable to update shop")?;
+    Ok(see_other(&format!("/shop/{shop_id}/watermark")))
+}
+
 #[derive(MultipartForm, Debug)]
 pub struct WatermarkQuery {
     file: TempFile,
@@ -277,6 +603,11 @@ pub async fn upload_watermark(
     tokio::fs::copy(q.file.file.path(), format!("./watermark/{shop_id}/{name}"))
         .await
         .context("Unable to save watermark image")?;
+    Broker::<SystemBroker>::issue_async(WatermarkUpdated {
+        shop_id,
+        from: name.clone(),
+        to: name,
+    });
     Ok(see_other(&format!("/shop/{shop_id}/watermark")))
 }
 
@@ -303,6 +634,11 @@ pub async fn delete_watermark(
     tokio::fs::remove_file(format!("./watermark/{shop_id}/{name}"))
         .await
         .context("Unable to delete watermark")?;
+    Broker::<SystemBroker>::issue_async(WatermarkUpdated {
+        shop_id,
+        from: name.clone(),
+        to: name,
+    });
     Ok(see_other(&format!("/shop/{shop_id}/watermark")))
 }
 
@@ -378,8 +714,11 @@ pub async fn generate_watermark_link(
     let q = q.into_inner();
     let link = q.link;
     let (shop_id, name) = path.into_inner();
+    let sig = watermark_guard::sign_link(shop_id, &link, &name)
+        .map(|sig| format!("?sig={sig}"))
+        .unwrap_or_default();
     Ok(see_other(&format!(
-        "/shop/{shop_id}/watermark/{link}/{name}"
+        "/shop/{shop_id}/watermark/{link}/{name}{sig}"
     )))
 }
 
@@ -615,13 +954,14 @@ pub async fn push_watermark_to_group(
     ShopAccess { .. }: ShopAccess,
     group: Record<WatermarkGroup>,
 ) -> Response {
-    let (shop_id, _) = path.into_inner();
+    let (shop_id, group_id) = path.into_inner();
     let dto = dto.into_inner();
     group
         .map(|g| {
             g.elements.insert(dto.watermark, dto.options.into());
         })
         .await?;
+    group_updated(shop_id, group_id);
     Ok(see_other(&format!("/shop/{shop_id}/watermark")))
 }
 
@@ -632,7 +972,7 @@ pub async fn edit_watermark_group_entry(
     ShopAccess { .. }: ShopAccess,
     group: Record<WatermarkGroup>,
 ) -> Response {
-    let (shop_id, _, name) = path.into_inner();
+    let (shop_id, group_id, name) = path.into_inner();
     let dto = dto.into_inner();
     group
         .try_map(|g| {
@@ -644,6 +984,7 @@ pub async fn edit_watermark_group_entry(
             Ok::<_, anyhow::Error>(())
         })
         .await??;
+    group_updated(shop_id, group_id);
     Ok(see_other(&format!("/shop/{shop_id}/watermark")))
 }
 
@@ -670,6 +1011,7 @@ pub async fn remove_watermark_group(
     watermark_group_repository
         .remove(&(group_id, shop_id))
         .await?;
+    group_updated(shop_id, group_id);
     Ok(see_other(&format!("/shop/{shop_id}/watermark")))
 }
 
@@ -679,15 +1021,25 @@ pub async fn remove_watermark_group_entry(
     ShopAccess { .. }: ShopAccess,
     group: Record<WatermarkGroup>,
 ) -> Response {
-    let (shop_id, _, name) = path.into_inner();
+    let (shop_id, group_id, name) = path.into_inner();
     group
         .map(|g| {
             g.elements.remove(&name);
         })
         .await?;
+    group_updated(shop_id, group_id);
     Ok(see_other(&format!("/shop/{shop_id}/watermark")))
 }
 
+// Кеш групи зібраний зі старих шарів, скидаємо його як при зміні водяного знака
+fn group_updated(shop_id: IdentityOf<Shop>, group_id: u64) {
+    Broker::<SystemBroker>::issue_async(WatermarkUpdated {
+        shop_id,
+        from: group_id.to_string(),
+        to: group_id.to_string(),
+    });
+}
+
 impl RecordResponse for RecordGuard<WatermarkGroup> {
     type Response = Result<(), anyhow::Error>;
 }
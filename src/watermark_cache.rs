@@ -0,0 +1,328 @@
+use actix::prelude::*;
+use actix_broker::BrokerSubscribe;
+use anyhow::Context as AnyhowContext;
+use log_error::LogError;
+use rt_types::shop::Shop;
+use rt_types::watermark::service::WatermarkUpdated;
+use rt_types::watermark::{ImageFormat, WatermarkOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use time::{Duration, OffsetDateTime};
+use typesafe_repository::IdentityOf;
+
+pub const CACHE_DIR: &str = "./watermark.cache.d";
+pub const DEFAULT_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+pub const DEFAULT_REVALIDATE_AFTER: Duration = Duration::hours(24);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CacheEntry {
+    pub shop_id: IdentityOf<Shop>,
+    pub url: String,
+    pub watermark: String,
+    pub layers: Vec<String>,
+    #[serde(default)]
+    pub format: ImageFormat,
+    pub size: u64,
+    #[serde(default)]
+    pub source_etag: Option<String>,
+    #[serde(default)]
+    pub source_last_modified: Option<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub checked: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub last_access: OffsetDateTime,
+}
+
+impl CacheEntry {
+    pub fn etag(&self, key: u64) -> String {
+        format!("{key:016x}-{}", self.created.unix_timestamp())
+    }
+
+    pub fn uses(&self, shop_id: IdentityOf<Shop>, watermark: &str) -> bool {
+        self.shop_id == shop_id
+            && (self.watermark == watermark || self.layers.iter().any(|l| l == watermark))
+    }
+}
+
+pub fn cache_key(
+    shop_id: IdentityOf<Shop>,
+    url: &str,
+    watermark: &str,
+    opts: &WatermarkOptions,
+) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(shop_id.as_bytes());
+    for part in [url, watermark] {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\0");
+    }
+    hasher.update(serde_json::to_string(opts).unwrap_or_default().as_bytes());
+    hasher.digest()
+}
+
+pub fn eviction_candidates(entries: &HashMap<u64, CacheEntry>, max_bytes: u64) -> Vec<u64> {
+    let mut total: u64 = entries.values().map(|e| e.size).sum();
+    let mut by_access: Vec<_> = entries.iter().collect();
+    by_access.sort_by_key(|(_, e)| e.last_access);
+    let mut res = vec![];
+    for (key, entry) in by_access {
+        if total <= max_bytes {
+            break;
+        }
+        total = total.saturating_sub(entry.size);
+        res.push(*key);
+    }
+    res
+}
+
+// Індекс у пам'яті, при старті відновлюється з файлів метаданих
+pub struct WatermarkCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    revalidate_after: Duration,
+    entries: Mutex<HashMap<u64, CacheEntry>>,
+}
+
+impl WatermarkCache {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64, revalidate_after: Duration) -> Self {
+        let dir = dir.into();
+        let entries = Self::load_index(&dir)
+            .log_error("Unable to load watermark cache index")
+            .unwrap_or_default();
+        Self {
+            dir,
+            max_bytes,
+            revalidate_after,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let max_bytes = envmnt::get_parse("WATERMARK_CACHE_MAX_BYTES").unwrap_or(DEFAULT_MAX_BYTES);
+        let revalidate_after = envmnt::get_parse("WATERMARK_CACHE_REVALIDATE_HOURS")
+            .map(Duration::hours)
+            .unwrap_or(DEFAULT_REVALIDATE_AFTER);
+        Self::new(CACHE_DIR, max_bytes, revalidate_after)
+    }
+
+    fn load_index(dir: &std::path::Path) -> Result<HashMap<u64, CacheEntry>, anyhow::Error> {
+        let mut res = HashMap::new();
+        let dir = match std::fs::read_dir(dir) {
+            Ok(d) => d,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(res),
+            Err(err) => return Err(err).context("Unable to read watermark cache dir"),
+        };
+        for e in dir {
+            let path = e?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let Some(key) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| u64::from_str_radix(s, 16).ok())
+            else {
+                continue;
+            };
+            let entry = std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|b| Ok(serde_json::from_slice::<CacheEntry>(&b)?));
+            match entry {
+                Ok(entry) => {
+                    res.insert(key, entry);
+                }
+                Err(err) => {
+                    log::warn!("Dropping broken watermark cache entry {path:?}: {err}");
+                    let _ = std::fs::remove_file(&path);
+                    let _ = std::fs::remove_file(path.with_extension("img"));
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    fn image_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.img"))
+    }
+
+    fn meta_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}.json"))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, CacheEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, key: u64) -> Option<CacheEntry> {
+        let mut entries = self.lock();
+        let entry = entries.get_mut(&key)?;
+        entry.last_access = OffsetDateTime::now_utc();
+        Some(entry.clone())
+    }
+
+    pub fn needs_revalidation(&self, entry: &CacheEntry, now: OffsetDateTime) -> bool {
+        now - entry.checked >= self.revalidate_after
+    }
+
+    pub async fn read(&self, key: u64) -> Option<Vec<u8>> {
+        match tokio::fs::read(self.image_path(key)).await {
+            Ok(b) => Some(b),
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Unable to read cached watermark image {key:016x}: {err}");
+                }
+                self.remove(&[key]).await;
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, key: u64, mut entry: CacheEntry, image: &[u8]) -> CacheEntry {
+        entry.size = image.len() as u64;
+        let res = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(self.image_path(key), image).await?;
+            tokio::fs::write(self.meta_path(key), serde_json::to_vec(&entry)?).await?;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if res.log_error("Unable to write watermark cache entry").is_none() {
+            return entry;
+        }
+        let evicted = {
+            let mut entries = self.lock();
+            entries.insert(key, entry.clone());
+            eviction_candidates(&entries, self.max_bytes)
+        };
+        self.remove(&evicted).await;
+        entry
+    }
+
+    pub async fn touch(&self, key: u64, now: OffsetDateTime) {
+        let entry = {
+            let mut entries = self.lock();
+            let Some(entry) = entries.get_mut(&key) else {
+                return;
+            };
+            entry.checked = now;
+            entry.clone()
+        };
+        if let Ok(meta) = serde_json::to_vec(&entry) {
+            tokio::fs::write(self.meta_path(key), meta)
+                .await
+                .log_error("Unable to update watermark cache entry");
+        }
+    }
+
+    pub async fn remove(&self, keys: &[u64]) {
+        {
+            let mut entries = self.lock();
+            for key in keys {
+                entries.remove(key);
+            }
+        }
+        for key in keys {
+            let _ = tokio::fs::remove_file(self.image_path(*key)).await;
+            let _ = tokio::fs::remove_file(self.meta_path(*key)).await;
+        }
+    }
+
+    pub async fn invalidate(&self, shop_id: IdentityOf<Shop>, watermark: &str) -> usize {
+        let keys: Vec<_> = self
+            .lock()
+            .iter()
+            .filter(|(_, e)| e.uses(shop_id, watermark))
+            .map(|(k, _)| *k)
+            .collect();
+        self.remove(&keys).await;
+        keys.len()
+    }
+}
+
+pub struct WatermarkCacheService {
+    cache: Arc<WatermarkCache>,
+}
+
+impl WatermarkCacheService {
+    pub fn new(cache: Arc<WatermarkCache>) -> Self {
+        Self { cache }
+    }
+}
+
+impl Actor for WatermarkCacheService {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.subscribe_system_async::<WatermarkUpdated>(ctx);
+    }
+}
+
+impl Handler<WatermarkUpdated> for WatermarkCacheService {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(
+        &mut self,
+        WatermarkUpdated { shop_id, from, to }: WatermarkUpdated,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let cache = self.cache.clone();
+        let fut = async move {
+            let mut count = cache.invalidate(shop_id, &from).await;
+            if to != from {
+                count += cache.invalidate(shop_id, &to).await;
+            }
+            if count > 0 {
+                log::info!("Invalidated {count} cached images of watermark {from}");
+            }
+        };
+        Box::pin(fut.into_actor(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64, last_access: i64, layers: &[&str]) -> CacheEntry {
+        let t = OffsetDateTime::UNIX_EPOCH + Duration::seconds(last_access);
+        CacheEntry {
+            shop_id: uuid::Uuid::nil(),
+            url: String::new(),
+            watermark: "42".to_string(),
+            layers: layers.iter().map(ToString::to_string).collect(),
+            format: ImageFormat::default(),
+            size,
+            source_etag: None,
+            source_last_modified: None,
+            created: t,
+            checked: t,
+            last_access: t,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let entries = HashMap::from([
+            (1, entry(40, 30, &["a.png"])),
+            (2, entry(40, 10, &["b.png"])),
+            (3, entry(40, 20, &["a.png"])),
+        ]);
+        assert!(eviction_candidates(&entries, 120).is_empty());
+        assert_eq!(eviction_candidates(&entries, 100), vec![2]);
+        assert_eq!(eviction_candidates(&entries, 40), vec![2, 3]);
+
+        let shop = uuid::Uuid::nil();
+        assert!(entries[&1].uses(shop, "a.png"));
+        assert!(entries[&1].uses(shop, "42"));
+        assert!(!entries[&2].uses(shop, "a.png"));
+        let opts = WatermarkOptions::default();
+        assert_ne!(
+            cache_key(shop, "http://a/1.jpg", "a.png", &opts),
+            cache_key(shop, "http://a/1.jpg", "b.png", &opts)
+        );
+    }
+}
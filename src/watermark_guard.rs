@@ -0,0 +1,293 @@
+use anyhow::{anyhow, Context as AnyhowContext};
+use hmac::Mac;
+use once_cell::sync::Lazy;
+use rt_types::shop::Shop;
+use sha2::Sha256;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use typesafe_repository::IdentityOf;
+use url::{Host, Url};
+
+// Без ключа посилання не підписуються і проксі приймає лише зображення товарів чи дозволених хостів
+pub static WATERMARK_SIGNING_KEY: Lazy<Option<String>> = Lazy::new(|| {
+    let key = envmnt::get_or("WATERMARK_SIGNING_KEY", "");
+    if key.is_empty() {
+        log::warn!("WATERMARK_SIGNING_KEY not set, watermark links are not signed");
+        return None;
+    }
+    Some(key)
+});
+
+fn mac(key: &str, shop_id: IdentityOf<Shop>, image: &str, watermark: &str) -> hmac::Hmac<Sha256> {
+    let mut hasher = hmac::Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    hasher.update(format!("{shop_id}\n{image}\n{watermark}").as_bytes());
+    hasher
+}
+
+pub fn sign(key: &str, shop_id: IdentityOf<Shop>, image: &str, watermark: &str) -> String {
+    format!(
+        "{:x}",
+        mac(key, shop_id, image, watermark).finalize().into_bytes()
+    )
+}
+
+pub fn verify(
+    key: &str,
+    shop_id: IdentityOf<Shop>,
+    image: &str,
+    watermark: &str,
+    signature: &str,
+) -> bool {
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    mac(key, shop_id, image, watermark)
+        .verify_slice(&signature)
+        .is_ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub fn sign_link(shop_id: IdentityOf<Shop>, image: &str, watermark: &str) -> Option<String> {
+    let key = WATERMARK_SIGNING_KEY.as_ref()?;
+    Some(sign(key, shop_id, image, watermark))
+}
+
+pub fn host_allowed(allowed: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    allowed.iter().any(|a| {
+        let a = a.trim().trim_end_matches('.').to_lowercase();
+        !a.is_empty() && (host == a || host.strip_suffix(&a).is_some_and(|sub| sub.ends_with('.')))
+    })
+}
+
+// Експорти з товарів постачальників посилаються на них без підпису
+pub fn source_image_hosts(shop: &Shop) -> Vec<String> {
+    let mut hosts = vec![];
+    for entry in &shop.export_entries {
+        let dt = [
+            &entry.dt_parsing,
+            &entry.op_tuning_parsing,
+            &entry.jgd_parsing,
+            &entry.pl_parsing,
+            &entry.skm_parsing,
+            &entry.maxton_parsing,
+            &entry.dt_tt_parsing,
+        ];
+        if dt.iter().any(|o| o.is_some()) {
+            hosts.push("design-tuning.com");
+        }
+        if entry.tt_parsing.is_some() || entry.dt_tt_parsing.is_some() {
+            hosts.push("tuning-tec.com");
+        }
+        if entry.davi_parsing.is_some() {
+            hosts.push("davi.com.ua");
+        }
+        if entry.ddaudio_api.is_some() {
+            hosts.push("ddaudio.com.ua");
+        }
+    }
+    let links = shop
+        .export_entries
+        .iter()
+        .flat_map(|e| e.links.iter().flatten())
+        .filter_map(|l| Url::parse(&l.link).ok()?.host_str().map(ToString::to_string));
+    let mut hosts = hosts
+        .into_iter()
+        .map(ToString::to_string)
+        .chain(links)
+        .collect::<Vec<_>>();
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+pub fn shop_image_allowed(shop: &Shop, url: &Url) -> bool {
+    url.host_str().is_some_and(|host| {
+        host_allowed(&shop.watermark_hosts, host)
+            || host_allowed(&source_image_hosts(shop), host)
+    })
+}
+
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let first = segments[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // IPv4-compatible ::/96 and IPv4-mapped ::ffff:0:0/96
+                || ip.to_ipv4().is_some()
+                // NAT64 64:ff9b::/96
+                || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+                // 6to4 2002::/16
+                || first == 0x2002
+                // Unique local fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link-local fe80::/10
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // Carrier-grade NAT 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // Reserved 240.0.0.0/4
+        || a >= 240
+        || a == 0
+}
+
+// Адреси фіксуються на час завантаження, щоб хост не перерезолвився на внутрішній
+pub async fn resolve_public(url: &Url) -> Result<(String, Vec<SocketAddr>), anyhow::Error> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("Unsupported scheme {}", url.scheme()));
+    }
+    let port = url.port_or_known_default().ok_or(anyhow!("Unknown port"))?;
+    let (host, addrs) = match url.host().ok_or(anyhow!("Image link has no host"))? {
+        Host::Ipv4(ip) => (ip.to_string(), vec![SocketAddr::new(ip.into(), port)]),
+        Host::Ipv6(ip) => (ip.to_string(), vec![SocketAddr::new(ip.into(), port)]),
+        Host::Domain(domain) => {
+            let addrs = tokio::net::lookup_host((domain, port))
+                .await
+                .with_context(|| format!("Unable to resolve {domain}"))?
+                .collect::<Vec<_>>();
+            (domain.to_string(), addrs)
+        }
+    };
+    if addrs.is_empty() {
+        return Err(anyhow!("{host} has no addresses"));
+    }
+    if addrs.iter().any(|a| is_internal(a.ip())) {
+        return Err(anyhow!("{host} resolves to an internal address"));
+    }
+    Ok((host, addrs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_signatures_hosts_and_addresses() {
+        let shop = uuid::Uuid::nil();
+        let sig = sign("secret", shop, "https://cdn.example.com/1.jpg", "logo.png");
+        assert!(verify(
+            "secret",
+            shop,
+            "https://cdn.example.com/1.jpg",
+            "logo.png",
+            &sig
+        ));
+        assert!(!verify(
+            "secret",
+            shop,
+            "https://cdn.example.com/2.jpg",
+            "logo.png",
+            &sig
+        ));
+        assert!(!verify(
+            "other",
+            shop,
+            "https://cdn.example.com/1.jpg",
+            "logo.png",
+            &sig
+        ));
+        assert!(!verify(
+            "secret",
+            shop,
+            "https://cdn.example.com/1.jpg",
+            "logo.png",
+            "zz"
+        ));
+
+        let hosts = vec!["Example.com".to_string()];
+        assert!(host_allowed(&hosts, "example.com"));
+        assert!(host_allowed(&hosts, "cdn.example.com"));
+        assert!(!host_allowed(&hosts, "badexample.com"));
+        assert!(!host_allowed(&hosts, "example.com.evil.net"));
+
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.1",
+            "169.254.169.254",
+            "::1",
+            "fd00::1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "::ffff:127.0.0.1",
+            "::ffff:93.184.216.34",
+            "::10.0.0.1",
+            "64:ff9b::a00:1",
+            "2002:a00:1::1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{ip}");
+        }
+        assert!(!is_internal("93.184.216.34".parse().unwrap()));
+        assert!(!is_internal("2606:4700::1111".parse().unwrap()));
+    }
+
+    #[test]
+    fn accepts_unsigned_supplier_image_links() {
+        let mut shop = Shop {
+            id: uuid::Uuid::new_v4(),
+            is_suspended: false,
+            name: "shop".to_string(),
+            owner: rt_types::access::Login("owner".to_string()),
+            export_entries: vec![rt_types::shop::ExportEntry {
+                dt_parsing: Some(Default::default()),
+                links: Some(vec![rt_types::shop::ExportEntryLink {
+                    vendor_name: None,
+                    link: "https://feeds.vendor.ua/export.xml".to_string(),
+                    publish: true,
+                    options: None,
+                }]),
+                ..Default::default()
+            }],
+            site_import_entries: vec![],
+            limits: None,
+            default_custom_options: None,
+            image_proxy: false,
+            currency: Default::default(),
+            watermark_hosts: vec![],
+            prom: Default::default(),
+            reviews: Default::default(),
+            nova_poshta: Default::default(),
+            feeds: Default::default(),
+        };
+        let url = |s: &str| Url::parse(s).unwrap();
+        assert!(shop_image_allowed(
+            &shop,
+            &url("https://design-tuning.com/image/cache/1.jpg")
+        ));
+        assert!(shop_image_allowed(&shop, &url("https://feeds.vendor.ua/img/2.jpg")));
+        assert!(!shop_image_allowed(&shop, &url("https://tuning-tec.com/1.jpg")));
+        assert!(!shop_image_allowed(&shop, &url("https://evil.example.com/1.jpg")));
+
+        shop.export_entries[0].tt_parsing = Some(Default::default());
+        shop.watermark_hosts = vec!["cdn.example.com".to_string()];
+        assert!(shop_image_allowed(&shop, &url("https://tuning-tec.com/1.jpg")));
+        assert!(shop_image_allowed(&shop, &url("https://cdn.example.com/1.jpg")));
+    }
+}
@@ -1,19 +1,14 @@
-use itertools::Itertools;
 use lazy_regex::regex;
 use rt_types::category::Category;
 use rt_types::product::Product;
 use rt_types::shop::{Discount, ExportOptions};
 use rt_types::{Availability, DescriptionOptions};
-use rust_decimal::Decimal;
 use rust_xlsxwriter::Workbook;
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
 use std::hash::BuildHasher;
-use std::iter;
 use uuid::Uuid;
 
-static EMPTY_STRING: String = String::new();
-
 pub fn delivery_days_from_params(params: &HashMap<String, String>) -> Option<usize> {
     let keys = ["delivery_days", "DeliveryDays", "deliveryDays"];
     for key in keys {
@@ -29,381 +24,291 @@ pub fn delivery_days_from_params(params: &HashMap<String, String>) -> Option<usi
     None
 }
 
-pub fn write_xlsx_dto_map(
-    path: &str,
-    items: HashMap<ExportOptions, Vec<Product>, impl BuildHasher>,
-    categories: HashSet<Category, impl BuildHasher>,
+pub(crate) type Field<'a> = Box<dyn Fn(&ExportOptions, &Product) -> String + Send + Sync + 'a>;
+
+pub(crate) type Descriptions<'a> =
+    HashMap<&'a ExportOptions, (Option<DescriptionOptions>, Option<DescriptionOptions>)>;
+
+fn read_description(d: &DescriptionOptions, shop_id: &str) -> Option<DescriptionOptions> {
+    match d {
+        DescriptionOptions::Replace(d) => {
+            match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
+                Ok(d) => Some(DescriptionOptions::Replace(d)),
+                Err(err) => {
+                    log::error!("Unable to read description {d}: {err}");
+                    None
+                }
+            }
+        }
+        DescriptionOptions::Append(d) => {
+            match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
+                Ok(d) => Some(DescriptionOptions::Append(d)),
+                Err(err) => {
+                    log::error!("Unable to read description {d}: {err}");
+                    None
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn read_descriptions<'a>(
+    options: impl Iterator<Item = &'a ExportOptions>,
     shop_id: &str,
-) -> Result<(), anyhow::Error> {
-    let items: HashMap<_, _> = items
-        .into_iter()
-        .map(|(o, i)| {
-            (
-                o,
-                i.into_iter()
-                    .filter(|i| matches!(i.available, Availability::OnOrder))
-                    .collect::<Vec<_>>(),
-            )
+) -> Descriptions<'a> {
+    let mut descriptions = Descriptions::new();
+    for o in options {
+        descriptions.entry(o).or_insert_with(|| {
+            let description = o
+                .description
+                .as_ref()
+                .and_then(|d| read_description(d, shop_id));
+            let description_ua = o
+                .description_ua
+                .as_ref()
+                .and_then(|d| read_description(d, shop_id));
+            (description, description_ua)
+        });
+    }
+    descriptions
+}
+
+fn truncate(d: &str, max: usize) -> &str {
+    &d[..d.char_indices().nth(max).map(|(x, _)| x).unwrap_or(d.len())]
+}
+
+fn price_list_description(
+    o: &ExportOptions,
+    d: Option<String>,
+    description_options: Option<&DescriptionOptions>,
+) -> String {
+    let d = match description_options {
+        Some(DescriptionOptions::Replace(d)) => Some(d.clone()),
+        Some(DescriptionOptions::Append(a)) => d.map(|mut d| {
+            d.push_str(a);
+            d
+        }),
+        None => d,
+    };
+    let d = d
+        .map(|d| format_replica(&d))
+        .map(|d| {
+            if o.description.is_none() {
+                trim_images(&d)
+            } else {
+                d
+            }
         })
-        .collect();
-    let mut workbook = Workbook::new();
-    let sheet = workbook.add_worksheet();
+        .unwrap_or_default();
+    truncate(&d, 32_000).replace("\n", "")
+}
+
+fn sale(o: &ExportOptions, p: &Product) -> Option<(String, String, String)> {
+    let Discount { percent, duration } = o.discount.as_ref()?;
+    if !matches!(p.available, Availability::Available | Availability::OnOrder) {
+        return None;
+    }
+    let time: time::OffsetDateTime = time::OffsetDateTime::now_utc() + *duration;
+    let from_time = time::OffsetDateTime::now_utc();
+    let (time, from_time) = match time::format_description::parse("[day].[month].[year]") {
+        Ok(time_format) => match (time.format(&time_format), from_time.format(&time_format)) {
+            (Ok(time), Ok(from_time)) => (time, from_time),
+            (Err(err1), Err(err2)) => {
+                log::error!("Unable to format time: \n{err1:?}\n{err2:?}");
+                ("".to_string(), "".to_string())
+            }
+            _ => {
+                log::error!("Unable to format time");
+                ("".to_string(), "".to_string())
+            }
+        },
+        Err(err) => {
+            log::error!("Unable to parse time format: {err:?}");
+            ("".to_string(), "".to_string())
+        }
+    };
+    Some((format!("{percent}%"), time, from_time))
+}
 
-    let titles: Vec<String> = items
-        .iter()
-        .flat_map(|(opts, products)| {
-            products.iter().map(|p| {
+// Рахуються по товару, щоб не тримати всю колонку в пам'яті
+pub(crate) fn price_list_columns<'a>(
+    descriptions: &'a Descriptions<'a>,
+) -> Vec<(&'static str, Field<'a>)> {
+    vec![
+        (
+            "Код_товара",
+            Box::new(|_, p| truncate(&p.article, 25).to_string()),
+        ),
+        (
+            "Название_позиции",
+            Box::new(|o, p| {
                 let base = if p.title.is_empty() {
                     p.ua_translation
                         .as_ref()
-                        .map(|t| t.title.clone())
+                        .map(|t| t.title.as_str())
                         .unwrap_or_default()
                 } else {
-                    p.title.clone()
+                    p.title.as_str()
                 };
-                build_title(opts, &base, false)
-            })
-        })
-        .collect();
-    let titles = titles.iter();
-    let titles_ua: Vec<String> = items
-        .iter()
-        .flat_map(|(opts, products)| {
-            products.iter().map(|p| {
+                build_title(o, base, false)
+            }),
+        ),
+        (
+            "Название_позиции_укр",
+            Box::new(|o, p| {
                 let title = p
                     .ua_translation
                     .as_ref()
-                    .map(|t| t.title.clone())
+                    .map(|t| t.title.as_str())
                     .filter(|t| !t.is_empty())
-                    .unwrap_or_else(|| p.title.clone());
-                build_title(opts, &title, true)
-            })
-        })
-        .collect();
-    let titles_ua = titles_ua.iter();
-
-    let product_ids = items.values().flatten().map(|Product { id, .. }| id);
-    let codes: Vec<_> = items
-        .values()
-        .flatten()
-        .map(|Product { article, .. }| article.clone())
-        .map(|d| {
-            (
-                d.char_indices().nth(25).map(|(x, _)| x).unwrap_or(d.len()),
-                d,
-            )
-        })
-        .map(|(x, d)| d[..x].to_string())
-        .collect();
-    let descriptions: Vec<String> = items
-        .iter()
-        .flat_map(|(o, i)| {
-            let description_options = o.description.as_ref().and_then(|d| match d {
-                DescriptionOptions::Replace(d) => {
-                    match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
-                        Ok(d) => Some(DescriptionOptions::Replace(d)),
-                        Err(err) => {
-                            log::error!("Unable to read description {d}: {err}");
-                            None
-                        }
-                    }
-                }
-                DescriptionOptions::Append(d) => {
-                    match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
-                        Ok(d) => Some(DescriptionOptions::Append(d)),
-                        Err(err) => {
-                            log::error!("Unable to read description {d}: {err}");
-                            None
-                        }
-                    }
-                }
-            });
-            let i: Vec<String> = i
-                .iter()
-                .map(|i| {
-                    i.description.clone().or_else(|| {
-                        i.ua_translation
-                            .as_ref()
-                            .and_then(|t| t.description.clone())
-                    })
-                })
-                .map(|d| match &description_options {
-                    Some(DescriptionOptions::Replace(d)) => Some(d.clone()),
-                    Some(DescriptionOptions::Append(a)) => d.map(|mut d| {
-                        d.push_str(&a);
-                        d
-                    }),
-                    None => d,
-                })
-                .map(|d| {
-                    d.map(|d| format_replica(&d))
-                        .map(|d| {
-                            if o.description.is_none() {
-                                trim_images(&d)
-                            } else {
-                                d
-                            }
-                        })
-                        .unwrap_or_default()
-                })
-                .collect();
-            i
-        })
-        .map(|d| {
-            (
-                d.char_indices()
-                    .nth(32_000)
-                    .map(|(x, _)| x)
-                    .unwrap_or(d.len()),
-                d,
-            )
-        })
-        .map(|(x, d)| d[..x].to_string())
-        .map(|d| d.replace("\n", ""))
-        .collect();
-    let descriptions_ua: Vec<String> = items
-        .iter()
-        .flat_map(|(o, i)| {
-            let description_options = o.description_ua.as_ref().and_then(|d| match d {
-                DescriptionOptions::Replace(d) => {
-                    match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
-                        Ok(d) => Some(DescriptionOptions::Replace(d)),
-                        Err(err) => {
-                            log::error!("Unable to read description {d}: {err}");
-                            None
-                        }
-                    }
-                }
-                DescriptionOptions::Append(d) => {
-                    match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
-                        Ok(d) => Some(DescriptionOptions::Append(d)),
-                        Err(err) => {
-                            log::error!("Unable to read description {d}: {err}");
-                            None
-                        }
-                    }
-                }
-            });
-            let i: Vec<String> = i
-                .iter()
-                .map(|i| {
-                    i.ua_translation
-                        .clone()
-                        .and_then(|t| t.description)
-                        .or(i.description.clone())
-                })
-                .map(|d| match &description_options {
-                    Some(DescriptionOptions::Replace(d)) => Some(d.clone()),
-                    Some(DescriptionOptions::Append(a)) => d.map(|mut d| {
-                        d.push_str(&a);
-                        d
-                    }),
-                    None => d,
-                })
-                .map(|d| {
-                    d.map(|d| format_replica(&d))
-                        .map(|d| {
-                            if o.description.is_none() {
-                                trim_images(&d)
-                            } else {
-                                d
-                            }
-                        })
-                        .unwrap_or_default()
-                })
-                .collect();
-            i
-        })
-        .map(|d| {
-            (
-                d.char_indices()
-                    .nth(32_000)
-                    .map(|(x, _)| x)
-                    .unwrap_or(d.len()),
-                d,
-            )
-        })
-        .map(|(x, d)| d[..x].to_string())
-        .map(|d| d.replace("\n", ""))
-        .collect();
-    let (images, keywords, currency): (Vec<_>, Vec<_>, Vec<_>) = items
-        .values()
-        .flatten()
-        .map(|p| {
-            let images = crate::normalize_image_urls(&p.images);
-            (
-                itertools::intersperse(images.into_iter().take(10), ",".to_string()).collect(),
-                p.keywords.clone().unwrap_or_default(),
-                p.currency.clone(),
-            )
-        })
-        .multiunzip();
-    let available: Vec<_> = items
-        .iter()
-        .flat_map(|(o, i)| {
-            i.iter().map(|p| match p.available {
+                    .unwrap_or(&p.title);
+                build_title(o, title, true)
+            }),
+        ),
+        ("Идентификатор_товара", Box::new(|_, p| p.id.clone())),
+        ("Цена", Box::new(|_, p| format!("{}", p.price))),
+        ("Валюта", Box::new(|_, p| p.currency.clone())),
+        (
+            "Ссылка_изображения",
+            Box::new(|_, p| {
+                let images = crate::normalize_image_urls(&p.images);
+                itertools::intersperse(images.into_iter().take(10), ",".to_string()).collect()
+            }),
+        ),
+        (
+            "Поисковые_запросы",
+            Box::new(|_, p| p.keywords.clone().unwrap_or_default()),
+        ),
+        ("Единица_измерения", Box::new(|_, _| "шт.".to_string())),
+        (
+            "Наличие",
+            Box::new(|o, p| match p.available {
                 Availability::Available => "!".to_string(),
                 Availability::NotAvailable => "-".to_string(),
                 Availability::OnOrder => delivery_days_from_params(&p.params)
                     .or(o.delivery_time)
                     .map(|t| t.to_string())
                     .unwrap_or_else(|| "0".to_string()),
-            })
-        })
-        .collect();
-    let prices: Vec<_> = items
-        .iter()
-        .flat_map(|(o, i)| {
-            let adjust = o.adjust_price.unwrap_or(Decimal::ONE);
-            i.iter().map(|p| p.price * adjust).collect::<Vec<Decimal>>()
-        })
-        .map(|price| format!("{price}"))
-        .collect();
-    let items_categories: Vec<_> = items
-        .iter()
-        .flat_map(|(_, i)| {
-            i.iter()
-                .map(|p| {
-                    p.category
-                        .as_ref()
-                        .map(Uuid::as_u64_pair)
-                        .map(|(a, _)| a)
+            }),
+        ),
+        (
+            "Количество",
+            Box::new(|_, p| match p.in_stock {
+                Some(q) => format!("{q}"),
+                None => String::new(),
+            }),
+        ),
+        (
+            "Описание",
+            Box::new(|o, p| {
+                let d = p.description.clone().or_else(|| {
+                    p.ua_translation
                         .as_ref()
-                        .map(ToString::to_string)
-                        .unwrap_or_default()
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
-    let in_stock: Vec<_> = items
-        .values()
-        .flatten()
-        .map(|p| match p.in_stock {
-            Some(q) => format!("{q}"),
-            None => String::new(),
-        })
-        .collect();
-    let empty = String::new();
-    let default_unit = String::from("шт.");
-    let unit = vec![&default_unit; prices.len()];
-    let (sale, sale_until, sale_from): (Vec<_>, Vec<_>, Vec<_>) = items
-        .iter()
-        .flat_map(|(o, i)| match o.discount {
-            Some(Discount { percent, duration }) => i
-                .iter()
-                .map(|p| match p.available {
-                    Availability::Available | Availability::OnOrder => {
-                        Some((format!("{percent}%"), duration))
-                    }
-                    _ => None,
-                })
-                .collect::<Vec<Option<(String, std::time::Duration)>>>(),
-            None => i.iter().map(|_| None).collect(),
-        })
-        .map(|s| {
-            if let Some((sale, duration)) = s {
-                let time: time::OffsetDateTime = time::OffsetDateTime::now_utc() + duration;
-                let from_time = time::OffsetDateTime::now_utc();
-                let (time, from_time) =
-                    match time::format_description::parse("[day].[month].[year]") {
-                        Ok(time_format) => {
-                            match (time.format(&time_format), from_time.format(&time_format)) {
-                                (Ok(time), Ok(from_time)) => (time, from_time),
-                                (Err(err1), Err(err2)) => {
-                                    log::error!("Unable to format time: \n{err1:?}\n{err2:?}");
-                                    ("".to_string(), "".to_string())
-                                }
-                                _ => {
-                                    log::error!("Unable to format time");
-                                    ("".to_string(), "".to_string())
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            log::error!("Unable to parse time format: {err:?}");
-                            ("".to_string(), "".to_string())
-                        }
-                    };
-                let sale_until = time;
-                let sale_from = from_time;
-                (sale, sale_until, sale_from)
-            } else {
-                (String::new(), String::new(), String::new())
-            }
+                        .and_then(|t| t.description.clone())
+                });
+                let options = descriptions.get(o).and_then(|(d, _)| d.as_ref());
+                price_list_description(o, d, options)
+            }),
+        ),
+        (
+            "Описание_укр",
+            Box::new(|o, p| {
+                let d = p
+                    .ua_translation
+                    .as_ref()
+                    .and_then(|t| t.description.clone())
+                    .or(p.description.clone());
+                let options = descriptions.get(o).and_then(|(_, d)| d.as_ref());
+                price_list_description(o, d, options)
+            }),
+        ),
+        (
+            "Знижка",
+            Box::new(|o, p| sale(o, p).map(|(s, _, _)| s).unwrap_or_default()),
+        ),
+        (
+            "Идентификатор_группы",
+            Box::new(|_, p| {
+                p.category
+                    .as_ref()
+                    .map(Uuid::as_u64_pair)
+                    .map(|(a, _)| a.to_string())
+                    .unwrap_or_default()
+            }),
+        ),
+        (
+            "Термін_дії_знижки_до",
+            Box::new(|o, p| sale(o, p).map(|(_, s, _)| s).unwrap_or_default()),
+        ),
+        (
+            "Термін_дії_знижки_від",
+            Box::new(|o, p| sale(o, p).map(|(_, _, s)| s).unwrap_or_default()),
+        ),
+        (
+            "Личные_Заметки",
+            Box::new(|o, p| match o.add_vendor {
+                true => p.vendor.clone(),
+                false => String::new(),
+            }),
+        ),
+    ]
+}
+
+pub(crate) fn param_columns<'a>(
+    names: impl IntoIterator<Item = &'a String>,
+) -> Vec<(&'static str, Field<'a>)> {
+    names
+        .into_iter()
+        .flat_map(|name| -> [(&'static str, Field<'a>); 3] {
+            [
+                (
+                    "Название_Характеристики",
+                    Box::new(move |_, _| name.clone()),
+                ),
+                ("Измерение_Характеристики", Box::new(|_, _| String::new())),
+                (
+                    "Значение_Характеристики",
+                    Box::new(move |_, p| p.params.get(name).cloned().unwrap_or_default()),
+                ),
+            ]
         })
-        .multiunzip();
-    let columns: Vec<(_, Box<dyn Iterator<Item = &String>>)> = vec![
-        ("Код_товара", Box::new(codes.iter())),
-        ("Название_позиции", Box::new(titles)),
-        ("Название_позиции_укр", Box::new(titles_ua)),
-        ("Идентификатор_товара", Box::new(product_ids)),
-        ("Цена", Box::new(prices.iter())),
-        ("Валюта", Box::new(currency.iter())),
-        ("Ссылка_изображения", Box::new(images.iter())),
-        ("Поисковые_запросы", Box::new(keywords.iter())),
-        ("Единица_измерения", Box::new(unit.into_iter())),
-        ("Наличие", Box::new(available.iter())),
-        ("Количество", Box::new(in_stock.iter())),
-        ("Описание", Box::new(descriptions.iter())),
-        ("Описание_укр", Box::new(descriptions_ua.iter())),
-        ("Знижка", Box::new(sale.iter())),
-        ("Идентификатор_группы", Box::new(items_categories.iter())),
-        ("Термін_дії_знижки_до", Box::new(sale_until.iter())),
-        ("Термін_дії_знижки_від", Box::new(sale_from.iter())),
-    ];
-    let mut columns: Box<dyn Iterator<Item = (&str, Box<dyn Iterator<Item = &String>>)>> =
-        Box::new(columns.into_iter());
-    let notes: Option<Box<dyn Iterator<Item = &String>>> = Some(Box::new(items.iter().flat_map(
-        |(o, i)| match o.add_vendor {
-            true => i.iter().map(|p| &p.vendor).collect::<Vec<&String>>(),
-            false => i.iter().map(|_| &empty).collect::<Vec<&String>>(),
-        },
-    )));
-    if let Some(n) = notes {
-        columns = Box::new(columns.chain(iter::once(("Личные_Заметки", n))));
-    }
+        .collect()
+}
+
+pub fn write_xlsx_dto_map<'a>(
+    path: &str,
+    items: impl Iterator<Item = (&'a ExportOptions, Product)> + Clone,
+    categories: HashSet<Category, impl BuildHasher>,
+    shop_id: &str,
+) -> Result<(), anyhow::Error> {
+    let items = items.filter(|(_, p)| matches!(p.available, Availability::OnOrder));
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+
+    let descriptions = read_descriptions(items.clone().map(|(o, _)| o), shop_id);
     let params_names = items
-        .iter()
-        .flat_map(|(_, products)| products.iter().flat_map(|p| p.params.keys().dedup()))
+        .clone()
+        .flat_map(|(_, p)| p.params.into_keys())
         .collect::<HashSet<_>>();
-    let len = codes.len();
-    let params = params_names.into_iter().flat_map(|name| {
-        let values = items.iter().flat_map(|(_, p)| p).map(move |i| {
-            i.params
-                .iter()
-                .find(|(k, _)| *k == name)
-                .map(|(_, v)| v)
-                .unwrap_or(&EMPTY_STRING)
-        });
-        let values: Box<dyn Iterator<Item = &String>> = Box::new(values);
-        let names: Box<dyn Iterator<Item = &String>> = Box::new(vec![name; len].into_iter());
-        let measure: Box<dyn Iterator<Item = &String>> =
-            Box::new(vec![&EMPTY_STRING; len].into_iter());
-        [
-            ("Название_Характеристики", names),
-            ("Измерение_Характеристики", measure),
-            ("Значение_Характеристики", values),
-        ]
-    });
-
-    columns = Box::new(columns.chain(params));
-
-    for (i, (name, values)) in columns.enumerate() {
+    let columns = price_list_columns(&descriptions)
+        .into_iter()
+        .chain(param_columns(&params_names));
+
+    // По одній колонці, щоб рядки лишились у порядку таблиці спільних рядків
+    for (i, (name, field)) in columns.enumerate() {
         let mut max_width = name.len();
-        sheet.set_column_width(i as u16, max_width as f64)?;
         sheet.write_string(0, i as u16, name)?;
-        for (e, value) in values.enumerate() {
+        for (e, (o, p)) in items.clone().enumerate() {
+            let value = field(o, &p);
             if value.char_indices().count() > 32_000 {
                 return Err(anyhow::anyhow!(
                     "Column {e} {name} has exceeded excel character limit"
                 ));
             }
-            if value.len() > max_width {
-                max_width = value.len();
-                sheet.set_column_width(i as u16, max_width as f64)?;
-            }
+            max_width = max_width.max(value.len());
             sheet.write_string(e as u32 + 1, i as u16, value)?;
         }
+        sheet.set_column_width(i as u16, max_width as f64)?;
     }
 
     if !categories.is_empty() {
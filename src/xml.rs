@@ -1,6 +1,7 @@
 use crate::xlsx::{format_replica, trim_images};
 use async_zip::tokio::write::ZipFileWriter;
 use async_zip::{Compression, DeflateOption, ZipEntryBuilder};
+use futures::{Stream, StreamExt};
 use quick_xml::escape::escape;
 use quick_xml::events::{
     attributes::Attribute, BytesCData, BytesEnd, BytesStart, BytesText, Event,
@@ -11,34 +12,52 @@ use rt_types::category::Category;
 use rt_types::product::Product;
 use rt_types::shop::ExportOptions;
 use rt_types::{Availability, DescriptionOptions};
-use rust_decimal::Decimal;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::hash::BuildHasher;
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
 use uuid::Uuid;
 
-pub async fn write_dto_map(
+pub async fn write_dto_map<'a, S>(
     path: &str,
-    items: &HashMap<ExportOptions, Vec<Product>, impl BuildHasher>,
+    items: impl Fn() -> S,
     categories: HashSet<Category, impl BuildHasher>,
     shop_id: &str,
-) -> Result<(), anyhow::Error> {
-    let map = HashMap::with_hasher(xxhash_rust::xxh3::Xxh3DefaultBuilder::new());
-    let items: HashMap<_, _, _> = items
-        .into_iter()
-        .map(|(o, i)| {
-            (
-                o,
-                i.into_iter()
-                    .filter(|i| !matches!(i.available, Availability::OnOrder))
-                    .collect::<Vec<_>>(),
-            )
-        })
-        .fold(map, |mut r, (k, v)| {
-            r.insert(k, v);
-            r
-        });
+) -> Result<(), anyhow::Error>
+where
+    S: Stream<Item = (&'a ExportOptions, Product)>,
+{
+    let items = || {
+        items()
+            .filter(|(_, p)| futures::future::ready(!matches!(p.available, Availability::OnOrder)))
+    };
+    let proc_description = |d: &DescriptionOptions| match d {
+        DescriptionOptions::Replace(d) => {
+            match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
+                Ok(d) => Some(DescriptionOptions::Replace(d)),
+                Err(err) => {
+                    log::error!("Unable to read description {d}: {err}");
+                    None
+                }
+            }
+        }
+        DescriptionOptions::Append(d) => {
+            match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
+                Ok(d) => Some(DescriptionOptions::Append(d)),
+                Err(err) => {
+                    log::error!("Unable to read description {d}: {err}");
+                    None
+                }
+            }
+        }
+    };
+    let mut descriptions = HashMap::new();
+    let mut options = std::pin::pin!(items());
+    while let Some((o, _)) = options.next().await {
+        descriptions
+            .entry(o)
+            .or_insert_with(|| o.description.as_ref().and_then(proc_description));
+    }
     let mut res_file = tokio::fs::File::create(&path).await?;
     let mut w = ZipFileWriter::with_tokio(&mut res_file);
     let builder = ZipEntryBuilder::new(
@@ -86,216 +105,187 @@ pub async fn write_dto_map(
         .write_event_async(Event::Start(BytesStart::new("offers")))
         .await?;
 
-    for (o, i) in items {
-        let proc_description = |d: &DescriptionOptions| match d {
-            DescriptionOptions::Replace(d) => {
-                match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
-                    Ok(d) => Some(DescriptionOptions::Replace(d)),
-                    Err(err) => {
-                        log::error!("Unable to read description {d}: {err}");
-                        None
-                    }
-                }
-            }
-            DescriptionOptions::Append(d) => {
-                match std::fs::read_to_string(format!("./description/{shop_id}/{d}")) {
-                    Ok(d) => Some(DescriptionOptions::Append(d)),
-                    Err(err) => {
-                        log::error!("Unable to read description {d}: {err}");
-                        None
-                    }
-                }
-            }
+    let mut items = std::pin::pin!(items());
+    while let Some((o, i)) = items.next().await {
+        let i = &i;
+        let description = descriptions.get(o).and_then(Option::as_ref);
+        let description_ua = description;
+        let available = match i.available {
+            Availability::Available => "true",
+            _ => "false",
         };
-        let description = o.description.as_ref().and_then(proc_description);
-        let description_ua = o.description.as_ref().and_then(proc_description);
-        for i in i {
-            let available = match i.available {
-                Availability::Available => "true",
-                _ => "false",
-            };
-            let id = escape(&i.id);
-            let in_stock = i
-                .in_stock
-                .as_ref()
-                .map(ToString::to_string)
-                .unwrap_or_default();
-            let in_stock = in_stock.as_bytes().into();
-            let attrs = vec![
-                Attribute {
-                    key: QName(b"available"),
-                    value: available.as_bytes().into(),
-                },
-                Attribute {
-                    key: QName(b"id"),
-                    value: id.as_bytes().into(),
-                },
-                Attribute {
-                    key: QName(b"selling_type"),
-                    value: b"r".into(),
-                },
-                Attribute {
-                    key: QName(b"in_stock"),
-                    value: in_stock,
-                },
-            ];
-
-            let title_fn = |s: &str, opts: &ExportOptions, is_ua: bool| -> String {
-                escape(&crate::xlsx::build_title(opts, s, is_ua)).to_string()
-            };
+        let id = escape(&i.id);
+        let in_stock = i
+            .in_stock
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let in_stock = in_stock.as_bytes().into();
+        let attrs = vec![
+            Attribute {
+                key: QName(b"available"),
+                value: available.as_bytes().into(),
+            },
+            Attribute {
+                key: QName(b"id"),
+                value: id.as_bytes().into(),
+            },
+            Attribute {
+                key: QName(b"selling_type"),
+                value: b"r".into(),
+            },
+            Attribute {
+                key: QName(b"in_stock"),
+                value: in_stock,
+            },
+        ];
 
-            let description_fn = format_replica;
-            let description_fn: Box<dyn Fn(&str) -> String> = match o.description {
-                Some(_) => Box::new(move |s| escape(&description_fn(&trim_images(s))).to_string()),
-                None => Box::new(|s| escape(&description_fn(s)).to_string()),
-            };
+        let title_fn = |s: &str, opts: &ExportOptions, is_ua: bool| -> String {
+            escape(&crate::xlsx::build_title(opts, s, is_ua)).to_string()
+        };
 
-            let price_fn = |p| p * o.adjust_price.unwrap_or(Decimal::ONE);
+        let description_fn = format_replica;
+        let description_fn: Box<dyn Fn(&str) -> String> = match o.description {
+            Some(_) => Box::new(move |s| escape(&description_fn(&trim_images(s))).to_string()),
+            None => Box::new(|s| escape(&description_fn(s)).to_string()),
+        };
 
-            writer
-                .create_element("offer")
-                .with_attributes(attrs)
-                .write_inner_content_async::<_, _, quick_xml::Error>(|writer| async {
-                    let base_title_ru = if i.title.is_empty() {
-                        i.ua_translation
-                            .as_ref()
-                            .map(|t| t.title.as_str())
-                            .unwrap_or("")
-                    } else {
-                        &i.title
-                    };
-                    let base_title_ua = i
-                        .ua_translation
+        writer
+            .create_element("offer")
+            .with_attributes(attrs)
+            .write_inner_content_async::<_, _, quick_xml::Error>(|writer| async {
+                let base_title_ru = if i.title.is_empty() {
+                    i.ua_translation
                         .as_ref()
                         .map(|t| t.title.as_str())
-                        .unwrap_or(base_title_ru);
+                        .unwrap_or("")
+                } else {
+                    &i.title
+                };
+                let base_title_ua = i
+                    .ua_translation
+                    .as_ref()
+                    .map(|t| t.title.as_str())
+                    .unwrap_or(base_title_ru);
+                writer
+                    .create_element("name")
+                    .write_text_content_async(BytesText::new(&title_fn(base_title_ru, o, false)))
+                    .await?;
+                writer
+                    .create_element("name_ua")
+                    .write_text_content_async(BytesText::new(&title_fn(base_title_ua, o, true)))
+                    .await?;
+                writer
+                    .create_element("barcode")
+                    .write_text_content_async(BytesText::new(&escape(&i.article)))
+                    .await?;
+                writer
+                    .create_element("price")
+                    .write_text_content_async(BytesText::new(&i.price.to_string()))
+                    .await?;
+                writer
+                    .create_element("currencyId")
+                    .write_text_content_async(BytesText::new(&escape(&i.currency)))
+                    .await?;
+                if let Some(c) = i.category {
                     writer
-                        .create_element("name")
-                        .write_text_content_async(BytesText::new(&title_fn(
-                            base_title_ru,
-                            o,
-                            false,
+                        .create_element("categoryId")
+                        .write_text_content_async(BytesText::new(&escape(
+                            &c.as_u64_pair().0.to_string(),
                         )))
                         .await?;
-                    writer
-                        .create_element("name_ua")
-                        .write_text_content_async(BytesText::new(&title_fn(base_title_ua, o, true)))
-                        .await?;
-                    writer
-                        .create_element("barcode")
-                        .write_text_content_async(BytesText::new(&escape(&i.article)))
-                        .await?;
-                    writer
-                        .create_element("price")
-                        .write_text_content_async(BytesText::new(&price_fn(i.price).to_string()))
-                        .await?;
-                    writer
-                        .create_element("currencyId")
-                        .write_text_content_async(BytesText::new(&escape(&i.currency)))
-                        .await?;
-                    if let Some(c) = i.category {
+                }
+                match description {
+                    Some(DescriptionOptions::Replace(d)) => {
                         writer
-                            .create_element("categoryId")
-                            .write_text_content_async(BytesText::new(&escape(
-                                &c.as_u64_pair().0.to_string(),
-                            )))
+                            .create_element("description")
+                            .write_cdata_content_async(BytesCData::new(description_fn(&d)))
                             .await?;
                     }
-                    match &description {
-                        Some(DescriptionOptions::Replace(d)) => {
+                    Some(DescriptionOptions::Append(a)) => {
+                        if let Some(d) = &i.description {
                             writer
                                 .create_element("description")
-                                .write_cdata_content_async(BytesCData::new(description_fn(&d)))
+                                .write_cdata_content_async(BytesCData::new(description_fn(
+                                    &format!("{d} {a}"),
+                                )))
                                 .await?;
                         }
-                        Some(DescriptionOptions::Append(a)) => {
-                            if let Some(d) = &i.description {
-                                writer
-                                    .create_element("description")
-                                    .write_cdata_content_async(BytesCData::new(description_fn(
-                                        &format!("{d} {a}"),
-                                    )))
-                                    .await?;
-                            }
-                        }
-                        None => {
-                            if let Some(d) = &i.description {
-                                writer
-                                    .create_element("description")
-                                    .write_cdata_content_async(BytesCData::new(description_fn(&d)))
-                                    .await?;
-                            }
-                        }
                     }
-                    match &description_ua {
-                        Some(DescriptionOptions::Replace(d)) => {
+                    None => {
+                        if let Some(d) = &i.description {
                             writer
-                                .create_element("description_ua")
+                                .create_element("description")
                                 .write_cdata_content_async(BytesCData::new(description_fn(&d)))
                                 .await?;
                         }
-                        Some(DescriptionOptions::Append(a)) => {
-                            if let Some(d) = i
-                                .ua_translation
-                                .as_ref()
-                                .and_then(|t| t.description.as_ref())
-                            {
-                                writer
-                                    .create_element("description_ua")
-                                    .write_cdata_content_async(BytesCData::new(description_fn(
-                                        &format!("{d} {a}"),
-                                    )))
-                                    .await?;
-                            }
-                        }
-                        None => {
-                            if let Some(d) = i
-                                .ua_translation
-                                .as_ref()
-                                .and_then(|t| t.description.as_ref())
-                            {
-                                writer
-                                    .create_element("description_ua")
-                                    .write_cdata_content_async(BytesCData::new(description_fn(&d)))
-                                    .await?;
-                            }
-                        }
                     }
-                    if let Some(discount) = &o.discount {
-                        writer
-                            .create_element("discount")
-                            .write_text_content_async(BytesText::new(&format!(
-                                "{}%",
-                                discount.percent
-                            )))
-                            .await?;
-                    }
-                    if let Some(keywords) = &i.keywords {
+                }
+                match description_ua {
+                    Some(DescriptionOptions::Replace(d)) => {
                         writer
-                            .create_element("keywords")
-                            .write_text_content_async(BytesText::new(&escape(keywords)))
+                            .create_element("description_ua")
+                            .write_cdata_content_async(BytesCData::new(description_fn(&d)))
                             .await?;
                     }
-                    for param in &i.params {
-                        writer
-                            .create_element("param")
-                            .with_attribute(Attribute {
-                                key: QName(b"name"),
-                                value: escape(&param.0).as_bytes().into(),
-                            })
-                            .write_text_content_async(BytesText::new(&escape(&param.1)))
-                            .await?;
+                    Some(DescriptionOptions::Append(a)) => {
+                        if let Some(d) = i
+                            .ua_translation
+                            .as_ref()
+                            .and_then(|t| t.description.as_ref())
+                        {
+                            writer
+                                .create_element("description_ua")
+                                .write_cdata_content_async(BytesCData::new(description_fn(
+                                    &format!("{d} {a}"),
+                                )))
+                                .await?;
+                        }
                     }
-                    for image in i.images.iter().take(10) {
-                        writer
-                            .create_element("picture")
-                            .write_text_content_async(BytesText::new(&escape(image)))
-                            .await?;
+                    None => {
+                        if let Some(d) = i
+                            .ua_translation
+                            .as_ref()
+                            .and_then(|t| t.description.as_ref())
+                        {
+                            writer
+                                .create_element("description_ua")
+                                .write_cdata_content_async(BytesCData::new(description_fn(&d)))
+                                .await?;
+                        }
                     }
-                    Ok(writer)
-                })
-                .await?;
-        }
+                }
+                if let Some(discount) = &o.discount {
+                    writer
+                        .create_element("discount")
+                        .write_text_content_async(BytesText::new(&format!("{}%", discount.percent)))
+                        .await?;
+                }
+                if let Some(keywords) = &i.keywords {
+                    writer
+                        .create_element("keywords")
+                        .write_text_content_async(BytesText::new(&escape(keywords)))
+                        .await?;
+                }
+                for param in &i.params {
+                    writer
+                        .create_element("param")
+                        .with_attribute(Attribute {
+                            key: QName(b"name"),
+                            value: escape(&param.0).as_bytes().into(),
+                        })
+                        .write_text_content_async(BytesText::new(&escape(&param.1)))
+                        .await?;
+                }
+                for image in i.images.iter().take(10) {
+                    writer
+                        .create_element("picture")
+                        .write_text_content_async(BytesText::new(&escape(image)))
+                        .await?;
+                }
+                Ok(writer)
+            })
+            .await?;
     }
     writer
         .write_event_async(Event::End(BytesEnd::new("offers")))